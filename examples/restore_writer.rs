@@ -0,0 +1,62 @@
+//! Compares appending rules one by one with writing them through a `RestoreWriter`.
+//!
+//! Run as root, the rules are written to a temporary chain which is removed afterwards:
+//! `cargo run --release --example restore_writer -- 2000`
+
+use iptables::writer::WriterOptions;
+use std::time::Instant;
+
+const CHAIN: &str = "RESTORE-WRITER-BENCH";
+
+fn main() {
+    let count = std::env::args()
+        .nth(1)
+        .map(|count| count.parse().expect("the number of rules"))
+        .unwrap_or(1000);
+    let ipt = iptables::new(false).unwrap();
+    let rules = (0..count)
+        .map(|i| format!("-p tcp --dport {} -j ACCEPT", 10000 + i))
+        .collect::<Vec<_>>();
+
+    ipt.new_chain("filter", CHAIN).unwrap();
+
+    let started = Instant::now();
+    for rule in &rules {
+        ipt.append("filter", CHAIN, rule).unwrap();
+    }
+    report("iptables per rule", count, started);
+    ipt.flush_chain("filter", CHAIN).unwrap();
+
+    for &persistent in &[false, true] {
+        let options = WriterOptions {
+            max_pending: 100,
+            persistent,
+        };
+        let started = Instant::now();
+        let mut writer = ipt.restore_writer(options);
+        for rule in &rules {
+            writer.append("filter", CHAIN, rule).unwrap();
+        }
+        writer.finish().unwrap();
+        let mode = if persistent {
+            "persistent"
+        } else {
+            "one restore per commit"
+        };
+        report(&format!("writer, {}", mode), count, started);
+        ipt.flush_chain("filter", CHAIN).unwrap();
+    }
+
+    ipt.delete_chain("filter", CHAIN).unwrap();
+}
+
+fn report(name: &str, count: usize, started: Instant) {
+    let elapsed = started.elapsed();
+    println!(
+        "{}: {} rules in {:?} ({:.0} rules/s)",
+        name,
+        count,
+        elapsed,
+        count as f64 / elapsed.as_secs_f64()
+    );
+}
@@ -0,0 +1,916 @@
+//! Asynchronous variant of `IPTables` which runs the blocking calls on the blocking thread pool
+//! of tokio.
+
+use crate::batch::Batch;
+use crate::counters::RuleCounters;
+use crate::list::{ChainInfo, ListOptions, ListedChain};
+use crate::parse::ParsedRule;
+use crate::position::Position;
+use crate::privileges::Privileges;
+use crate::rule::{Rule, RuleSpec};
+use crate::snapshot::Snapshot;
+use crate::{as_strs, CallOptions, IPTError, IPTResult, IPTables, Table, Version};
+use std::collections::HashMap;
+use std::process::Output;
+
+/// Mirrors the methods of `IPTables` without blocking the async runtime.
+/// Create it from an `IPTables` instance, e.g. `AsyncIPTables::from(iptables::new(false)?)`.
+///
+/// Each call runs the blocking method on the blocking thread pool of tokio, so it takes the same
+/// serial lock as the blocking calls of all clones of the instance and cannot interleave with them.
+///
+/// The rule, chain and table methods are mirrored. Any other method of `IPTables`, e.g. `apply`,
+/// `set_chain_rules`, `create_managed_chain` or `stats`, runs the same way through `spawn`:
+/// ```no_run
+/// # async fn example() -> iptables::IPTResult<()> {
+/// let ipt = iptables::AsyncIPTables::from(iptables::new(false).unwrap());
+/// let stats = ipt.spawn(|ipt| ipt.stats()).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AsyncIPTables {
+    ipt: IPTables,
+}
+
+impl From<IPTables> for AsyncIPTables {
+    fn from(ipt: IPTables) -> Self {
+        Self { ipt }
+    }
+}
+
+impl AsyncIPTables {
+    /// Returns the underlying blocking `IPTables` instance. Its methods block the calling thread,
+    /// use `spawn` to call them from async code.
+    pub fn blocking(&self) -> &IPTables {
+        &self.ipt
+    }
+
+    /// Returns the version of the binary, detecting it now if the instance was built with
+    /// `IPTablesBuilder::lazy` and no previous command did, see `IPTables::detect`.
+    pub async fn detect(&self) -> IPTResult<Version> {
+        self.spawn(move |ipt| ipt.detect()).await
+    }
+
+    /// Get the default policy for a table/chain.
+    pub async fn get_policy(&self, table: impl Into<Table>, chain: &str) -> IPTResult<String> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.get_policy(table, &chain)).await
+    }
+
+    /// Set the default policy for a table/chain.
+    pub async fn set_policy(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        policy: &str,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let policy = policy.to_string();
+        self.spawn(move |ipt| ipt.set_policy(table, &chain, &policy))
+            .await
+    }
+
+    /// Executes a given `command` on the chain.
+    /// Returns the command output if successful.
+    pub async fn execute(&self, table: impl Into<Table>, command: &str) -> IPTResult<Output> {
+        let table = table.into();
+        let command = command.to_string();
+        self.spawn(move |ipt| ipt.execute(table, &command)).await
+    }
+
+    /// Runs iptables with `args` as is, e.g. to use options which are not wrapped by this crate.
+    /// Returns the command output, also if iptables failed unless `strict` is set.
+    pub async fn execute_args(&self, args: &[&str]) -> IPTResult<Output> {
+        let args = to_strings(args);
+        self.spawn(move |ipt| ipt.execute_args(&as_strs(&args)))
+            .await
+    }
+
+    /// Runs iptables with `args` as is in the table.
+    /// Returns the command output, also if iptables failed unless `strict` is set.
+    pub async fn execute_table(&self, table: impl Into<Table>, args: &[&str]) -> IPTResult<Output> {
+        let table = table.into();
+        let args = to_strings(args);
+        self.spawn(move |ipt| ipt.execute_table(table, &as_strs(&args)))
+            .await
+    }
+
+    /// Runs iptables with `args` as is in the table with the given `options`.
+    /// Returns the command output, also if iptables failed unless `strict` is set.
+    pub async fn execute_table_with(
+        &self,
+        table: impl Into<Table>,
+        args: &[&str],
+        options: &CallOptions,
+    ) -> IPTResult<Output> {
+        let table = table.into();
+        let args = to_strings(args);
+        let options = *options;
+        self.spawn(move |ipt| ipt.execute_table_with(table, &as_strs(&args), &options))
+            .await
+    }
+
+    /// Checks for the existence of the `rule` in the table/chain.
+    /// Returns true if the rule exists, failures other than a missing rule are returned as errors.
+    pub async fn exists(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<bool> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.exists(table, &chain, rule)).await
+    }
+
+    /// Checks for the existence of the `rule` in the table/chain with the given `options`.
+    pub async fn exists_with(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        options: &CallOptions,
+    ) -> IPTResult<bool> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        let options = *options;
+        self.spawn(move |ipt| ipt.exists_with(table, &chain, rule, &options))
+            .await
+    }
+
+    /// Checks for the existence of the `rule` in the table/chain comparing the canonical forms,
+    /// see `IPTables::exists_normalized`.
+    pub async fn exists_normalized(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<bool> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.exists_normalized(table, &chain, rule))
+            .await
+    }
+
+    /// Checks for the existence of each of `rules` in the table/chain with a single listing,
+    /// see `IPTables::exists_many`.
+    pub async fn exists_many<R: Clone + Into<RuleSpec>>(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rules: &[R],
+    ) -> IPTResult<Vec<bool>> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rules: Vec<RuleSpec> = rules.iter().cloned().map(Into::into).collect();
+        self.spawn(move |ipt| ipt.exists_many(table, &chain, &rules))
+            .await
+    }
+
+    /// Checks for the existence of the structured `rule` in the table/chain.
+    /// Returns true if the rule exists.
+    pub async fn exists_rule(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: &Rule,
+    ) -> IPTResult<bool> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule = rule.clone();
+        self.spawn(move |ipt| ipt.exists_rule(table, &chain, &rule))
+            .await
+    }
+
+    /// Checks for the existence of the `chain` in the table.
+    /// Returns true if the chain exists, failures other than a missing chain are returned as errors.
+    pub async fn chain_exists(&self, table: impl Into<Table>, chain: &str) -> IPTResult<bool> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.chain_exists(table, &chain)).await
+    }
+
+    /// Checks if the `table` is available, e.g. `nat` may be missing on stripped down kernels.
+    /// Returns true if the table exists, other failures are returned as errors.
+    pub async fn table_exists(&self, table: impl Into<Table>) -> IPTResult<bool> {
+        let table = table.into();
+        self.spawn(move |ipt| ipt.table_exists(table)).await
+    }
+
+    /// Inserts `rule` in the `position` to the table/chain, which can be counted from the end of
+    /// the chain, see `IPTables::insert_relative`.
+    pub async fn insert_relative(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: Position,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.insert_relative(table, &chain, rule, position))
+            .await
+    }
+
+    /// Returns the position of the first copy of `rule` in the table/chain, compared in the
+    /// canonical form, see `IPTables::position_of`.
+    pub async fn position_of(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<Option<u32>> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.position_of(table, &chain, rule))
+            .await
+    }
+
+    /// Inserts `rule` right before the first copy of `anchor` in the table/chain, see
+    /// `IPTables::insert_before`.
+    pub async fn insert_before(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        anchor: impl Into<RuleSpec>,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let anchor: RuleSpec = anchor.into();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.insert_before(table, &chain, anchor, rule))
+            .await
+    }
+
+    /// Inserts `rule` right after the first copy of `anchor` in the table/chain, see
+    /// `IPTables::insert_after`.
+    pub async fn insert_after(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        anchor: impl Into<RuleSpec>,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let anchor: RuleSpec = anchor.into();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.insert_after(table, &chain, anchor, rule))
+            .await
+    }
+
+    /// Inserts `rule` in the `position` to the table/chain.
+    pub async fn insert(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: i32,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.insert(table, &chain, rule, position))
+            .await
+    }
+
+    /// Inserts `rule` in the `position` to the table/chain with the given `options`.
+    pub async fn insert_with(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: i32,
+        options: &CallOptions,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        let options = *options;
+        self.spawn(move |ipt| ipt.insert_with(table, &chain, rule, position, &options))
+            .await
+    }
+
+    /// Inserts `rule` in the `position` to the table/chain if it does not exist.
+    pub async fn insert_unique(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: i32,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.insert_unique(table, &chain, rule, position))
+            .await
+    }
+
+    /// Replaces `rule` in the `position` to the table/chain.
+    pub async fn replace(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: i32,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.replace(table, &chain, rule, position))
+            .await
+    }
+
+    /// Appends `rule` to the table/chain.
+    pub async fn append(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.append(table, &chain, rule)).await
+    }
+
+    /// Appends `rule` to the table/chain with the given `options`.
+    pub async fn append_with(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        options: &CallOptions,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        let options = *options;
+        self.spawn(move |ipt| ipt.append_with(table, &chain, rule, &options))
+            .await
+    }
+
+    /// Appends `rule` to the table/chain with its packet and byte counters initialized to the given
+    /// values.
+    pub async fn append_with_counters(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        packets: u64,
+        bytes: u64,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.append_with_counters(table, &chain, rule, packets, bytes))
+            .await
+    }
+
+    /// Inserts `rule` in the `position` to the table/chain with its packet and byte counters
+    /// initialized to the given values.
+    pub async fn insert_with_counters(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: i32,
+        packets: u64,
+        bytes: u64,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| {
+            ipt.insert_with_counters(table, &chain, rule, position, packets, bytes)
+        })
+        .await
+    }
+
+    /// Appends `rule` to the table/chain if it does not exist.
+    pub async fn append_unique(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.append_unique(table, &chain, rule))
+            .await
+    }
+
+    /// Appends or replaces `rule` to the table/chain if it does not exist.
+    pub async fn append_replace(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.append_replace(table, &chain, rule))
+            .await
+    }
+
+    /// Deletes `rule` from the table/chain.
+    pub async fn delete(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.delete(table, &chain, rule)).await
+    }
+
+    /// Deletes `rule` from the table/chain with the given `options`.
+    pub async fn delete_with(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        options: &CallOptions,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        let options = *options;
+        self.spawn(move |ipt| ipt.delete_with(table, &chain, rule, &options))
+            .await
+    }
+
+    /// Deletes the rule in the `position` from the table/chain, the first rule has position 1.
+    pub async fn delete_by_position(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        position: u32,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.delete_by_position(table, &chain, position))
+            .await
+    }
+
+    /// Deletes all repetition of the `rule` from the table/chain.
+    pub async fn delete_all(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule: RuleSpec = rule.into();
+        self.spawn(move |ipt| ipt.delete_all(table, &chain, rule))
+            .await
+    }
+
+    /// Appends the structured `rule` to the table/chain.
+    pub async fn append_rule(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: &Rule,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule = rule.clone();
+        self.spawn(move |ipt| ipt.append_rule(table, &chain, &rule))
+            .await
+    }
+
+    /// Inserts the structured `rule` in the `position` to the table/chain.
+    pub async fn insert_rule(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: &Rule,
+        position: i32,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule = rule.clone();
+        self.spawn(move |ipt| ipt.insert_rule(table, &chain, &rule, position))
+            .await
+    }
+
+    /// Replaces the rule in the `position` of the table/chain with the structured `rule`.
+    pub async fn replace_rule(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: &Rule,
+        position: i32,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule = rule.clone();
+        self.spawn(move |ipt| ipt.replace_rule(table, &chain, &rule, position))
+            .await
+    }
+
+    /// Deletes the structured `rule` from the table/chain.
+    pub async fn delete_rule(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: &Rule,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rule = rule.clone();
+        self.spawn(move |ipt| ipt.delete_rule(table, &chain, &rule))
+            .await
+    }
+
+    /// Lists rules in the table/chain.
+    pub async fn list(&self, table: impl Into<Table>, chain: &str) -> IPTResult<Vec<String>> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.list(table, &chain)).await
+    }
+
+    /// Lists rules in the table/chain split into their arguments like a shell would,
+    /// see `IPTables::list_args`.
+    pub async fn list_args(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> IPTResult<Vec<Vec<String>>> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.list_args(table, &chain)).await
+    }
+
+    /// Lists rules in the table/chain paired with their position, the same numbers shown by
+    /// `--line-numbers`. The rules are given without the leading `-A <chain>`.
+    pub async fn list_numbered(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> IPTResult<Vec<(u32, String)>> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.list_numbered(table, &chain))
+            .await
+    }
+
+    /// Deletes the exact duplicates of rules in the table/chain in a single restore transaction
+    /// and returns how many rules were deleted, see `IPTables::dedupe_chain`.
+    pub async fn dedupe_chain(&self, table: impl Into<Table>, chain: &str) -> IPTResult<usize> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.dedupe_chain(table, &chain)).await
+    }
+
+    /// Lists the chains of the table, or only `chain` if given, in the tabular format of
+    /// `iptables -L` with the given `options`, parsed into their structured representation.
+    pub async fn list_with_options(
+        &self,
+        table: impl Into<Table>,
+        chain: Option<&str>,
+        options: &ListOptions,
+    ) -> IPTResult<Vec<ListedChain>> {
+        let table = table.into();
+        let chain = chain.map(str::to_string);
+        let options = *options;
+        self.spawn(move |ipt| ipt.list_with_options(table, chain.as_deref(), &options))
+            .await
+    }
+
+    /// Lists the rules of every table known to iptables, grouped by table name.
+    /// Tables which are not available are left out.
+    pub async fn list_all(&self) -> IPTResult<HashMap<String, Vec<String>>> {
+        self.spawn(move |ipt| ipt.list_all()).await
+    }
+
+    /// Lists the rules of all chains in the table which carry the comment `tag`.
+    pub async fn list_by_comment(
+        &self,
+        table: impl Into<Table>,
+        tag: &str,
+    ) -> IPTResult<Vec<String>> {
+        let table = table.into();
+        let tag = tag.to_string();
+        self.spawn(move |ipt| ipt.list_by_comment(table, &tag))
+            .await
+    }
+
+    /// Deletes every rule of the table/chain which carries the comment `tag`.
+    pub async fn delete_by_comment(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        tag: &str,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let tag = tag.to_string();
+        self.spawn(move |ipt| ipt.delete_by_comment(table, &chain, &tag))
+            .await
+    }
+
+    /// Lists rules in the table.
+    pub async fn list_table(&self, table: impl Into<Table>) -> IPTResult<Vec<String>> {
+        let table = table.into();
+        self.spawn(move |ipt| ipt.list_table(table)).await
+    }
+
+    /// Lists rules in the table/chain together with their packet and byte counters.
+    pub async fn list_with_counters(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> IPTResult<Vec<RuleCounters>> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.list_with_counters(table, &chain))
+            .await
+    }
+
+    /// Returns the packet and byte counters of each rule in the table/chain. Use
+    /// `RuleCounters::key` to identify the rules across calls.
+    pub async fn counters(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> IPTResult<Vec<RuleCounters>> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.counters(table, &chain)).await
+    }
+
+    /// Lists rules in the table/chain together with their packet and byte counters and zeroes the
+    /// counters in the same invocation.
+    pub async fn list_and_zero(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> IPTResult<Vec<RuleCounters>> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.list_and_zero(table, &chain))
+            .await
+    }
+
+    /// Lists rules in the table/chain parsed into their typed representation.
+    pub async fn list_parsed(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> IPTResult<Vec<ParsedRule>> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.list_parsed(table, &chain)).await
+    }
+
+    /// Lists the name of each chain in the table.
+    pub async fn list_chains(&self, table: impl Into<Table>) -> IPTResult<Vec<String>> {
+        let table = table.into();
+        self.spawn(move |ipt| ipt.list_chains(table)).await
+    }
+
+    /// Lists each chain in the table with its policy and policy counters if it is built-in,
+    /// or the number of rules jumping to it if it is user-defined, parsed from `iptables -L`.
+    pub async fn list_chain_info(&self, table: impl Into<Table>) -> IPTResult<Vec<ChainInfo>> {
+        let table = table.into();
+        self.spawn(move |ipt| ipt.list_chain_info(table)).await
+    }
+
+    /// Creates a new user-defined chain.
+    pub async fn new_chain(&self, table: impl Into<Table>, chain: &str) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.new_chain(table, &chain)).await
+    }
+
+    /// Creates a new user-defined chain if it does not exist yet.
+    pub async fn ensure_chain(&self, table: impl Into<Table>, chain: &str) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.ensure_chain(table, &chain)).await
+    }
+
+    /// Flushes (deletes all rules) a chain.
+    pub async fn flush_chain(&self, table: impl Into<Table>, chain: &str) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.flush_chain(table, &chain)).await
+    }
+
+    /// Renames a chain in the table.
+    pub async fn rename_chain(
+        &self,
+        table: impl Into<Table>,
+        old_chain: &str,
+        new_chain: &str,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let old_chain = old_chain.to_string();
+        let new_chain = new_chain.to_string();
+        self.spawn(move |ipt| ipt.rename_chain(table, &old_chain, &new_chain))
+            .await
+    }
+
+    /// Deletes a user-defined chain in the table.
+    pub async fn delete_chain(&self, table: impl Into<Table>, chain: &str) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.delete_chain(table, &chain)).await
+    }
+
+    /// Lists the rules of the table jumping to the user-defined `chain`, as the chain of each
+    /// rule and its position in it, see `IPTables::chain_references`.
+    pub async fn chain_references(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> IPTResult<Vec<(String, u32)>> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.chain_references(table, &chain))
+            .await
+    }
+
+    /// Deletes the user-defined chain in the table after deleting the rules jumping to it and
+    /// flushing it, see `IPTables::delete_chain_recursive`.
+    pub async fn delete_chain_recursive(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.delete_chain_recursive(table, &chain))
+            .await
+    }
+
+    /// Removes the user-defined chain completely: deletes the rules of the `referencing_chains`
+    /// which jump to it, flushes it and deletes it. Does nothing if the chain does not exist.
+    pub async fn teardown_chain(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        referencing_chains: &[&str],
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let referencing_chains = to_strings(referencing_chains);
+        self.spawn(move |ipt| ipt.teardown_chain(table, &chain, &as_strs(&referencing_chains)))
+            .await
+    }
+
+    /// Creates the user-defined `child` chain if it does not exist yet and makes the `parent`
+    /// chain jump to it, see `IPTables::link_chain`.
+    pub async fn link_chain(
+        &self,
+        table: impl Into<Table>,
+        parent: &str,
+        child: &str,
+        position: Option<i32>,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let parent = parent.to_string();
+        let child = child.to_string();
+        self.spawn(move |ipt| ipt.link_chain(table, &parent, &child, position))
+            .await
+    }
+
+    /// Like `link_chain`, but continues processing in the `child` chain with `-g child`, see
+    /// `IPTables::link_chain_goto`.
+    pub async fn link_chain_goto(
+        &self,
+        table: impl Into<Table>,
+        parent: &str,
+        child: &str,
+        position: Option<i32>,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let parent = parent.to_string();
+        let child = child.to_string();
+        self.spawn(move |ipt| ipt.link_chain_goto(table, &parent, &child, position))
+            .await
+    }
+
+    /// Zeroes the packet and byte counters of all rules in a chain.
+    pub async fn zero_chain(&self, table: impl Into<Table>, chain: &str) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        self.spawn(move |ipt| ipt.zero_chain(table, &chain)).await
+    }
+
+    /// Zeroes the packet and byte counters of all chains in a table.
+    pub async fn zero_all(&self, table: impl Into<Table>) -> IPTResult<()> {
+        let table = table.into();
+        self.spawn(move |ipt| ipt.zero_all(table)).await
+    }
+
+    /// Flushes all chains in a table.
+    pub async fn flush_table(&self, table: impl Into<Table>) -> IPTResult<()> {
+        let table = table.into();
+        self.spawn(move |ipt| ipt.flush_table(table)).await
+    }
+
+    /// Dumps the rules of all tables, or only of `table` if given, in the `iptables-save` format.
+    pub async fn save(&self, table: Option<&str>) -> IPTResult<String> {
+        let table = table.map(str::to_string);
+        self.spawn(move |ipt| ipt.save(table.as_deref())).await
+    }
+
+    /// Restores the rules in `data`, given in the `iptables-save` format, using `iptables-restore`.
+    /// The tables contained in `data` are flushed before restoring if `flush` is true.
+    pub async fn restore(&self, data: &str, flush: bool) -> IPTResult<()> {
+        let data = data.to_string();
+        self.spawn(move |ipt| ipt.restore(&data, flush)).await
+    }
+
+    /// Writes the rules of the `chains` of the table with a single `iptables-restore --noflush`
+    /// call, see `IPTables::restore_table`.
+    pub async fn restore_table<C: AsRef<str>, R: AsRef<str>>(
+        &self,
+        table: impl Into<Table>,
+        chains: &[(C, Vec<R>)],
+        flush_managed: bool,
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chains = chains
+            .iter()
+            .map(|(chain, rules)| {
+                let rules = rules.iter().map(|rule| rule.as_ref().to_string());
+                (chain.as_ref().to_string(), rules.collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+        self.spawn(move |ipt| ipt.restore_table(table, &chains, flush_managed))
+            .await
+    }
+
+    /// Detects if the rules can be read and modified, see `IPTables::check_permissions`.
+    pub async fn check_permissions(&self) -> IPTResult<Privileges> {
+        self.spawn(move |ipt| ipt.check_permissions()).await
+    }
+
+    /// Appends all `rules` to the table/chain with a single `iptables-restore --noflush` call,
+    /// see `IPTables::append_many`.
+    pub async fn append_many(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rules: &[&str],
+    ) -> IPTResult<()> {
+        let table = table.into();
+        let chain = chain.to_string();
+        let rules = to_strings(rules);
+        self.spawn(move |ipt| ipt.append_many(table, &chain, &as_strs(&rules)))
+            .await
+    }
+
+    /// Takes a snapshot of the rules of all tables with a single `iptables-save` call.
+    pub async fn snapshot(&self) -> IPTResult<Snapshot> {
+        self.spawn(move |ipt| ipt.snapshot()).await
+    }
+
+    /// Applies all operations queued in `batch` with a single `iptables-restore --noflush` call.
+    /// If applying fails, the touched tables are restored to the state they had before the call.
+    pub async fn commit(&self, batch: &Batch) -> IPTResult<()> {
+        let batch = batch.clone();
+        self.spawn(move |ipt| ipt.commit(&batch)).await
+    }
+
+    /// Runs `call` with a clone of the blocking instance on the blocking thread pool of tokio,
+    /// e.g. to use a method of `IPTables` which is not mirrored. A panic of `call` is resumed in
+    /// the calling task.
+    pub async fn spawn<T, F>(&self, call: F) -> IPTResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&IPTables) -> IPTResult<T> + Send + 'static,
+    {
+        let ipt = self.ipt.clone();
+        match tokio::task::spawn_blocking(move || call(&ipt)).await {
+            Ok(result) => result,
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(err) => Err(IPTError::Other(format!(
+                "the blocking call failed: {}",
+                err
+            ))),
+        }
+    }
+}
+
+fn to_strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
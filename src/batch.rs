@@ -0,0 +1,203 @@
+//! Queues rule operations across tables to apply them with a single `iptables-restore` call.
+
+use crate::rule::{join_quoted, Rule};
+use crate::{IPTError, IPTResult, SplitQuoted};
+
+/// A set of rule operations which are committed together by `IPTables::commit`.
+///
+/// # Example
+/// ```
+/// use iptables::batch::Batch;
+///
+/// let mut batch = Batch::new();
+/// batch.append("filter", "INPUT", "-p tcp --dport 22 -j ACCEPT");
+/// batch.insert("nat", "POSTROUTING", "-o eth0 -j MASQUERADE", 1);
+/// assert_eq!(
+///     batch.to_restore_string(),
+///     "*filter\n-A INPUT -p tcp --dport 22 -j ACCEPT\nCOMMIT\n*nat\n-I POSTROUTING 1 -o eth0 -j MASQUERADE\nCOMMIT\n"
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Batch {
+    // Operations grouped by table, in the order the tables were first used
+    tables: Vec<(String, Vec<Operation>)>,
+    // The number of operations queued, including rejected ones
+    queued: usize,
+    // The index of the first operation rejected because it contains a line break
+    rejected: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Operation {
+    // The position of the operation in the order it was queued
+    index: usize,
+    // The command with its chain and position, e.g. `-I INPUT 1`
+    command: String,
+    args: Vec<String>,
+}
+
+impl Batch {
+    /// Creates an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if no operation has been queued.
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Returns the names of the tables touched by the queued operations.
+    pub fn tables(&self) -> Vec<&str> {
+        self.tables
+            .iter()
+            .map(|(table, _)| table.as_str())
+            .collect()
+    }
+
+    /// Queues appending `rule` to the table/chain.
+    pub fn append(&mut self, table: &str, chain: &str, rule: &str) -> &mut Self {
+        self.push_str(table, format!("-A {}", chain), rule)
+    }
+
+    /// Queues appending each of `rules` to the table/chain, in the given order.
+    pub fn append_many(&mut self, table: &str, chain: &str, rules: &[&str]) -> &mut Self {
+        for rule in rules {
+            self.append(table, chain, rule);
+        }
+        self
+    }
+
+    /// Queues inserting `rule` in the `position` to the table/chain.
+    pub fn insert(&mut self, table: &str, chain: &str, rule: &str, position: i32) -> &mut Self {
+        self.push_str(table, format!("-I {} {}", chain, position), rule)
+    }
+
+    /// Queues deleting `rule` from the table/chain.
+    pub fn delete(&mut self, table: &str, chain: &str, rule: &str) -> &mut Self {
+        self.push_str(table, format!("-D {}", chain), rule)
+    }
+
+    /// Queues appending the structured `rule` to the table/chain.
+    pub fn append_rule(&mut self, table: &str, chain: &str, rule: &Rule) -> &mut Self {
+        self.push(table, format!("-A {}", chain), rule.args())
+    }
+
+    /// Queues inserting the structured `rule` in the `position` to the table/chain.
+    pub fn insert_rule(
+        &mut self,
+        table: &str,
+        chain: &str,
+        rule: &Rule,
+        position: i32,
+    ) -> &mut Self {
+        self.push(table, format!("-I {} {}", chain, position), rule.args())
+    }
+
+    /// Queues deleting the structured `rule` from the table/chain.
+    pub fn delete_rule(&mut self, table: &str, chain: &str, rule: &Rule) -> &mut Self {
+        self.push(table, format!("-D {}", chain), rule.args())
+    }
+
+    /// Fails with `IPTError::RuleFailed`, giving the index of the operation in the order it was
+    /// queued, if the table, chain or rule of an operation contains a line break. Such an
+    /// operation is left out of the batch, as the line break would start another
+    /// `iptables-restore` command.
+    /// `IPTables::commit` checks the batch before applying anything.
+    pub fn check(&self) -> IPTResult<()> {
+        match self.rejected {
+            Some(index) => Err(IPTError::RuleFailed {
+                index,
+                error: Box::new(line_break()),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // Runs `check` on the rule of every operation and fails with `IPTError::RuleFailed` for the
+    // first rejected operation in the order they were queued
+    pub(crate) fn check_rules(&self, check: impl Fn(&[String]) -> IPTResult<()>) -> IPTResult<()> {
+        let mut operations = self
+            .tables
+            .iter()
+            .flat_map(|(_, operations)| operations)
+            .collect::<Vec<_>>();
+        operations.sort_by_key(|operation| operation.index);
+        for operation in operations {
+            check(&operation.args).map_err(|error| IPTError::RuleFailed {
+                index: operation.index,
+                error: Box::new(error),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Renders the queued operations in the format expected by `iptables-restore`.
+    pub fn to_restore_string(&self) -> String {
+        self.render(|args| args)
+    }
+
+    // Renders the queued operations, passing the arguments of every rule through `tag`
+    pub(crate) fn render(&self, tag: impl Fn(Vec<String>) -> Vec<String>) -> String {
+        let mut data = String::new();
+        for (table, operations) in &self.tables {
+            data.push('*');
+            data.push_str(table);
+            data.push('\n');
+            for operation in operations {
+                data.push_str(&operation.command);
+                data.push(' ');
+                data.push_str(&join_quoted(&tag(operation.args.clone())));
+                data.push('\n');
+            }
+            data.push_str("COMMIT\n");
+        }
+        data
+    }
+
+    // Counts an operation which is left out because it contains a line break
+    fn reject(&mut self) -> &mut Self {
+        self.rejected.get_or_insert(self.queued);
+        self.queued += 1;
+        self
+    }
+
+    // Checks the rule before splitting it, which would turn a line break into a separator
+    fn push_str(&mut self, table: &str, command: String, rule: &str) -> &mut Self {
+        match check_line_breaks(&[table, &command, rule]) {
+            Ok(()) => self.push(table, command, rule.split_quoted()),
+            Err(_) => self.reject(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, table: &str, command: String, args: Vec<String>) -> &mut Self {
+        // The table and chain are written into `*table` and the command line like the rule
+        if check_line_breaks(&[table, &command]).is_err() || check_line_breaks(&args).is_err() {
+            return self.reject();
+        }
+        let operation = Operation {
+            index: self.queued,
+            command,
+            args,
+        };
+        self.queued += 1;
+        match self.tables.iter_mut().find(|(name, _)| name == table) {
+            Some((_, operations)) => operations.push(operation),
+            None => self.tables.push((table.to_string(), vec![operation])),
+        }
+        self
+    }
+}
+
+// Fails if an argument contains a line break, which would end the line of the rule and start
+// another command of iptables-restore
+pub(crate) fn check_line_breaks<S: AsRef<str>>(args: &[S]) -> IPTResult<()> {
+    if args.iter().any(|arg| arg.as_ref().contains(['\n', '\r'])) {
+        return Err(line_break());
+    }
+    Ok(())
+}
+
+fn line_break() -> IPTError {
+    IPTError::Parse("rule contains a line break".to_string())
+}
@@ -0,0 +1,530 @@
+//! Options to create an `IPTables` instance with a custom binary or backend.
+
+use crate::lock::{LockPolicy, SerialLock};
+use crate::retry::RetryPolicy;
+use crate::runner::CommandRunner;
+use crate::sanitize::Sanitizer;
+use crate::version::Version;
+use crate::{Backend, IPTError, IPTResult, IPTables, SplitQuoted};
+use std::env;
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The directories searched for the iptables binary after the `PATH`, see
+/// `IPTablesBuilder::search_paths`.
+pub const DEFAULT_SEARCH_PATHS: &[&str] =
+    &["/usr/sbin", "/sbin", "/usr/local/sbin", "/usr/bin", "/bin"];
+
+/// Builds an `IPTables` instance, use `IPTables::builder` to create a new builder.
+///
+/// # Example
+/// ```no_run
+/// let ipt = iptables::IPTables::builder()
+///     .path("/usr/sbin/iptables-legacy")
+///     .ipv6(false)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct IPTablesBuilder {
+    path: Option<String>,
+    search_paths: Option<Vec<PathBuf>>,
+    is_ipv6: bool,
+    backend: Backend,
+    wait_timeout: Option<Duration>,
+    command_timeout: Option<Duration>,
+    wait_interval: Option<Duration>,
+    lock_policy: LockPolicy,
+    owner_tag: Option<String>,
+    dry_run: bool,
+    race_safe_unique: bool,
+    strict: bool,
+    netns: Option<String>,
+    netns_path: Option<PathBuf>,
+    env: Vec<(String, String)>,
+    env_clear: bool,
+    global_args: Vec<String>,
+    modprobe: Option<String>,
+    command_prefix: Vec<String>,
+    retry_policy: RetryPolicy,
+    validate_rules: bool,
+    sanitizer: Option<Sanitizer>,
+    lazy: bool,
+    runner: Option<Arc<dyn CommandRunner>>,
+}
+
+impl fmt::Debug for IPTablesBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IPTablesBuilder")
+            .field("path", &self.path)
+            .field("search_paths", &self.search_paths)
+            .field("is_ipv6", &self.is_ipv6)
+            .field("backend", &self.backend)
+            .field("wait_timeout", &self.wait_timeout)
+            .field("command_timeout", &self.command_timeout)
+            .field("wait_interval", &self.wait_interval)
+            .field("lock_policy", &self.lock_policy)
+            .field("owner_tag", &self.owner_tag)
+            .field("dry_run", &self.dry_run)
+            .field("race_safe_unique", &self.race_safe_unique)
+            .field("strict", &self.strict)
+            .field("netns", &self.netns)
+            .field("netns_path", &self.netns_path)
+            .field("env", &self.env)
+            .field("env_clear", &self.env_clear)
+            .field("global_args", &self.global_args)
+            .field("modprobe", &self.modprobe)
+            .field("command_prefix", &self.command_prefix)
+            .field("retry_policy", &self.retry_policy)
+            .field("validate_rules", &self.validate_rules)
+            .field("sanitizer", &self.sanitizer)
+            .field("lazy", &self.lazy)
+            .field("runner", &self.runner.is_some())
+            .finish()
+    }
+}
+
+impl Default for IPTablesBuilder {
+    fn default() -> Self {
+        Self {
+            path: None,
+            search_paths: None,
+            is_ipv6: false,
+            backend: Backend::Auto,
+            wait_timeout: None,
+            command_timeout: None,
+            wait_interval: None,
+            lock_policy: LockPolicy::default(),
+            owner_tag: None,
+            dry_run: false,
+            race_safe_unique: false,
+            strict: false,
+            netns: None,
+            netns_path: None,
+            env: Vec::new(),
+            env_clear: false,
+            global_args: Vec::new(),
+            modprobe: None,
+            command_prefix: Vec::new(),
+            retry_policy: RetryPolicy::default(),
+            validate_rules: false,
+            sanitizer: None,
+            lazy: false,
+            runner: None,
+        }
+    }
+}
+
+impl IPTablesBuilder {
+    /// Sets the path of the iptables binary, which is otherwise chosen by `ipv6` and `backend`
+    /// and searched, see `search_paths`.
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// Searches the iptables binary in `paths` only, instead of the directories of the `PATH`
+    /// followed by `DEFAULT_SEARCH_PATHS`.
+    ///
+    /// Unless a `path` is set, the binary chosen by `ipv6` and `backend` is searched before it
+    /// is run, e.g. `/usr/sbin/iptables` in minimal images whose `PATH` lacks the sbin
+    /// directories. With `Backend::Auto`, `iptables-nft` and `iptables-legacy` are tried if
+    /// `iptables` is missing. The instance runs the binary by its name if it was found in the
+    /// `PATH`, by its full path otherwise. `build` fails with `IPTError::BinaryNotFound` if no
+    /// candidate exists. Nothing is searched in dry-run mode, with a `runner` or a
+    /// `command_prefix`, which run the binary elsewhere, or with an empty list of `paths`.
+    pub fn search_paths<P: AsRef<Path>>(mut self, paths: &[P]) -> Self {
+        self.search_paths = Some(
+            paths
+                .iter()
+                .map(|path| path.as_ref().to_path_buf())
+                .collect(),
+        );
+        self
+    }
+
+    /// Uses 'ip6tables' instead of 'iptables' if `is_ipv6` is true.
+    pub fn ipv6(mut self, is_ipv6: bool) -> Self {
+        self.is_ipv6 = is_ipv6;
+        self
+    }
+
+    /// Selects the backend of the iptables binary, defaults to `Backend::Auto`.
+    /// If a `path` is given, the binary must use this backend unless it is `Backend::Auto`.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Stops waiting for the xtables lock after `timeout` instead of waiting forever.
+    /// Requires iptables 1.4.22 or later, older versions always wait forever.
+    pub fn wait_timeout(mut self, timeout: Duration) -> Self {
+        self.wait_timeout = Some(timeout);
+        self
+    }
+
+    /// Polls the xtables lock in the given `interval` while waiting for it.
+    /// Requires iptables 1.6.0 or later, older versions use their default interval.
+    pub fn wait_interval(mut self, interval: Duration) -> Self {
+        self.wait_interval = Some(interval);
+        self
+    }
+
+    /// Kills every iptables, iptables-save and iptables-restore process which runs longer than
+    /// `timeout` and fails the call with `IPTError::Timeout`, instead of waiting forever, e.g.
+    /// for an iptables stuck on a lock which is never released. Commands wait for the xtables
+    /// lock, so `timeout` should be longer than the `wait_timeout`. Disabled by default.
+    ///
+    /// Applies to the processes the instance spawns itself, not to a custom `runner` nor to the
+    /// process kept open by a persistent `RestoreWriter`.
+    pub fn command_timeout(mut self, timeout: Duration) -> Self {
+        self.command_timeout = Some(timeout);
+        self
+    }
+
+    /// Controls how the lock is retried if iptables does not have -w (--wait) option.
+    pub fn lock_policy(mut self, policy: LockPolicy) -> Self {
+        self.lock_policy = policy;
+        self
+    }
+
+    /// Adds the comment `tag` (`-m comment --comment`) to every rule created, checked or deleted
+    /// through the instance, see `IPTables::owner_tag`.
+    pub fn owner_tag(mut self, tag: &str) -> Self {
+        self.owner_tag = Some(tag.to_string());
+        self
+    }
+
+    /// Records the commands instead of running them if `dry_run` is true, they can be read with
+    /// `IPTables::recorded_commands`. Every command succeeds with an empty output, so checks
+    /// report existing rules and listings are empty.
+    ///
+    /// The binary is not run to detect its version, instead the latest iptables version is
+    /// assumed and the backend is reported as `Backend::Nft` unless `Backend::Legacy` is requested.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Makes `insert_unique` and `append_unique` safe against other processes adding the same
+    /// rule between the check and the insertion if `race_safe_unique` is true.
+    ///
+    /// After adding the rule, the chain is listed again and duplicate copies are deleted until
+    /// a single copy is left, retrying according to the `lock_policy`. The copies are found by
+    /// comparing the rule with the output of `iptables -S`, so the rule should be given in the
+    /// same form, e.g. `-s 10.0.0.1/32` instead of `-s 10.0.0.1`.
+    pub fn race_safe_unique(mut self, race_safe_unique: bool) -> Self {
+        self.race_safe_unique = race_safe_unique;
+        self
+    }
+
+    /// Makes `execute`, `execute_args` and `execute_table` return `IPTError::CommandFailed` with
+    /// the exit code and standard error if iptables fails, instead of the output of the failed
+    /// command, so failures cannot be ignored by accident. All other methods always return an
+    /// error if iptables fails.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Runs every command inside the network namespace `name` with `ip netns exec`, so the rules
+    /// of e.g. a container can be managed from the host. The namespace must be known to `ip netns`,
+    /// i.e. be mounted in `/run/netns`. The version is also detected inside the namespace, so
+    /// `build` fails if it is missing.
+    pub fn netns(mut self, name: &str) -> Self {
+        self.netns = Some(name.to_string());
+        self
+    }
+
+    /// Runs every command inside the network namespace opened from `path` by calling setns(2) in
+    /// the child process before it executes iptables, which does not need iproute2 unlike `netns`.
+    /// `path` is e.g. `/run/netns/<name>` for a namespace created by `ip netns add`,
+    /// `/proc/<pid>/ns/net` for the namespace of a process or `/proc/self/fd/<fd>` for an open
+    /// namespace descriptor. Entering a namespace requires `CAP_SYS_ADMIN`.
+    ///
+    /// Commands recorded in dry-run mode do not show the namespace. A custom `runner` has to
+    /// implement `CommandRunner::run_in_netns`.
+    pub fn netns_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.netns_path = Some(path.into());
+        self
+    }
+
+    /// Sets the environment variable `key` to `value` for every command. The variables are set
+    /// by running the commands through `env`, so they are also visible to a custom `runner`.
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Runs every command with an empty environment except for the variables set with `env`
+    /// if `env_clear` is true. Without a `PATH` variable the binaries are searched in the default
+    /// path of the C library, which usually lacks `/usr/sbin`, so set `PATH` or give the `path`
+    /// of the binary.
+    pub fn env_clear(mut self, env_clear: bool) -> Self {
+        self.env_clear = env_clear;
+        self
+    }
+
+    /// Passes `arg` to every iptables command in front of the other arguments, e.g. an option
+    /// which is not wrapped by this crate. `iptables-save` and `iptables-restore` do not get it.
+    pub fn global_arg(mut self, arg: &str) -> Self {
+        self.global_args.push(arg.to_string());
+        self
+    }
+
+    /// Loads missing kernel modules with the program `modprobe`, passed as `--modprobe` to
+    /// iptables, `iptables-save` and `iptables-restore`, e.g. on systems where the default
+    /// program is missing or not in the `PATH`.
+    pub fn modprobe(mut self, modprobe: &str) -> Self {
+        self.modprobe = Some(modprobe.to_string());
+        self
+    }
+
+    /// Runs every command through `prefix`, e.g. `sudo -n`, `doas -n` or `pkexec`, so an
+    /// unprivileged process can manage the rules where the policy of the system allows it.
+    /// The prefix is split into arguments like a shell would, e.g. `sudo -n -u 'fw admin'`, and
+    /// comes before the `netns` and `env` wrappers, which therefore run with the privileges.
+    ///
+    /// The prefix must not ask for a password, as the commands have no terminal. Versions of
+    /// iptables without -w (--wait) option still need write access to the xtables lock file.
+    pub fn command_prefix(mut self, prefix: &str) -> Self {
+        self.command_prefix = prefix.split_quoted();
+        self
+    }
+
+    /// Retries iptables commands which failed for a transient reason according to `policy`,
+    /// by default every command runs once.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Checks the rules passed to `append`, `insert` and their `*_with` variants with
+    /// `IPTables::validate_rule` before running iptables, so a typo fails with
+    /// `IPTError::Invalid` describing the mistake instead of the message of iptables.
+    pub fn validate_rules(mut self, validate_rules: bool) -> Self {
+        self.validate_rules = validate_rules;
+        self
+    }
+
+    /// Checks every rule against the allowlist of `sanitizer` before it is run, so rules built
+    /// from untrusted input cannot smuggle options into the command line, see `Sanitizer`.
+    /// Applies to every method which takes a rule: the commands which add, replace, check or
+    /// delete a single rule, string or structured, including `append_handle`, `insert_handle`,
+    /// the `*_verbose` methods, `exists_normalized` and `position_of`, and the methods writing many rules at once:
+    /// `exists_many`, `delete_all`, `append_many`, `restore_table`, `commit`, `apply`,
+    /// `set_chain_rules` and `restore_writer`. Rejected rules fail with `IPTError::Invalid`,
+    /// wrapped in `IPTError::RuleFailed` by `append_many` and `commit`, and nothing is run.
+    /// Disabled by default.
+    pub fn sanitizer(mut self, sanitizer: Sanitizer) -> Self {
+        self.sanitizer = Some(sanitizer);
+        self
+    }
+
+    /// Defers detecting the version and backend of the binary to the first command if `lazy` is
+    /// true, so `build` neither runs nor needs the binary, e.g. in containers which only use
+    /// iptables in some configurations. The detected version is cached for the instance and its
+    /// clones; a failed detection is retried by the next command. See `IPTables::detect`.
+    ///
+    /// Until the version is detected, the public fields like `version` and `has_wait` describe
+    /// the latest iptables version, like in dry-run mode, and keep doing so afterwards. Use the
+    /// methods like `IPTables::supports_wait` instead.
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Runs the commands with `runner` instead of spawning them as child processes, e.g. to test
+    /// an application with a fake runner. The runner is also used to detect the version.
+    pub fn runner<R: CommandRunner + 'static>(mut self, runner: R) -> Self {
+        self.runner = Some(Arc::new(runner));
+        self
+    }
+
+    /// Returns an error because iptables only works on linux
+    #[cfg(not(target_os = "linux"))]
+    pub fn build(self) -> IPTResult<IPTables> {
+        Err(IPTError::Other("iptables only works on Linux".to_string()))
+    }
+
+    /// Detects the version and backend of the iptables binary and creates the `IPTables` instance.
+    #[cfg(target_os = "linux")]
+    pub fn build(self) -> IPTResult<IPTables> {
+        let cmd = match self.path.clone() {
+            Some(path) => path,
+            None => self.locate()?,
+        };
+
+        // Assumes the latest version in dry-run mode, whose options are all supported
+        let version = Version::new(1, 8, 10);
+        let backend = match self.backend {
+            Backend::Legacy => Backend::Legacy,
+            _ => Backend::Nft,
+        };
+        let mut ipt = IPTables {
+            cmd,
+            is_ipv6: self.is_ipv6,
+            backend,
+            version,
+            has_check: version.has_check(),
+            has_wait: version.has_wait(),
+            has_wait_timeout: version.has_wait_timeout(),
+            has_wait_interval: version.has_wait_interval(),
+            wait_timeout: self.wait_timeout,
+            command_timeout: self.command_timeout,
+            wait_interval: self.wait_interval,
+            lock_policy: self.lock_policy,
+            owner_tag: self.owner_tag,
+            dry_run: self.dry_run,
+            race_safe_unique: self.race_safe_unique,
+            strict: self.strict,
+            netns: self.netns,
+            netns_path: self.netns_path,
+            env: self.env,
+            env_clear: self.env_clear,
+            global_args: self.global_args,
+            modprobe: self.modprobe,
+            command_prefix: self.command_prefix,
+            retry_policy: self.retry_policy,
+            validate_rules: self.validate_rules,
+            sanitizer: self.sanitizer.map(Arc::new),
+            recorded: Arc::new(Mutex::new(Vec::new())),
+            serial: Arc::new(SerialLock::default()),
+            lazy: None,
+            runner: self.runner,
+        };
+        if ipt.dry_run {
+            return Ok(ipt);
+        }
+        if self.lazy {
+            ipt.lazy = Some(Arc::new(LazyDetection {
+                requested: self.backend,
+                detected: Mutex::new(None),
+            }));
+            return Ok(ipt);
+        }
+
+        let capabilities = detect(&ipt, self.backend)?;
+        ipt.backend = capabilities.backend;
+        ipt.version = capabilities.version;
+        ipt.has_check = capabilities.has_check;
+        ipt.has_wait = capabilities.has_wait;
+        ipt.has_wait_timeout = capabilities.has_wait_timeout;
+        ipt.has_wait_interval = capabilities.has_wait_interval;
+        Ok(ipt)
+    }
+
+    // Returns the name or path of the first binary found for `ipv6` and `backend`, see
+    // `search_paths`
+    fn locate(&self) -> IPTResult<String> {
+        let family = if self.is_ipv6 {
+            "ip6tables"
+        } else {
+            "iptables"
+        };
+        let variants: &[&str] = match self.backend {
+            Backend::Auto => &["", "-nft", "-legacy"],
+            Backend::Nft => &["-nft"],
+            Backend::Legacy => &["-legacy"],
+        };
+        let names = variants
+            .iter()
+            .map(|variant| format!("{}{}", family, variant))
+            .collect::<Vec<_>>();
+        let skip = self.dry_run || self.runner.is_some() || !self.command_prefix.is_empty();
+        if skip || self.search_paths.as_ref().is_some_and(Vec::is_empty) {
+            return Ok(names[0].clone());
+        }
+
+        let (in_path, dirs) = match &self.search_paths {
+            Some(paths) => (Vec::new(), paths.clone()),
+            None => (
+                env::var_os("PATH")
+                    .map(|path| env::split_paths(&path).collect())
+                    .unwrap_or_default(),
+                DEFAULT_SEARCH_PATHS.iter().map(PathBuf::from).collect(),
+            ),
+        };
+        let mut searched = Vec::new();
+        for name in &names {
+            for dir in &in_path {
+                let candidate = dir.join(name);
+                if is_executable(&candidate) {
+                    return Ok(name.clone());
+                }
+                searched.push(candidate.display().to_string());
+            }
+            for dir in &dirs {
+                let candidate = dir.join(name);
+                if is_executable(&candidate) {
+                    return Ok(candidate.display().to_string());
+                }
+                searched.push(candidate.display().to_string());
+            }
+        }
+
+        // A lazy instance only fails once the binary is used
+        if self.lazy {
+            return Ok(names[0].clone());
+        }
+        Err(IPTError::BinaryNotFound { searched })
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+// The properties of the binary which depend on its version
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Capabilities {
+    pub(crate) backend: Backend,
+    pub(crate) version: Version,
+    pub(crate) has_check: bool,
+    pub(crate) has_wait: bool,
+    pub(crate) has_wait_timeout: bool,
+    pub(crate) has_wait_interval: bool,
+}
+
+// The capabilities of an instance built with `IPTablesBuilder::lazy`, detected on first use
+#[derive(Debug)]
+pub(crate) struct LazyDetection {
+    pub(crate) requested: Backend,
+    pub(crate) detected: Mutex<Option<Capabilities>>,
+}
+
+// Detects the version and backend of the binary of `ipt`, which must use the `requested` backend
+pub(crate) fn detect(ipt: &IPTables, requested: Backend) -> IPTResult<Capabilities> {
+    // The version is detected the same way every other command is run, e.g. in the namespace
+    let mut command = ipt.command(&ipt.cmd);
+    command.arg("--version");
+    let version_output = ipt.spawn(&command, None)?;
+    let version_string = String::from_utf8_lossy(version_output.stdout.as_slice());
+    let version = Version::parse(&version_string)?;
+
+    // Versions before 1.8 only have the legacy backend and do not print it
+    let detected = if version_string.contains("(nf_tables)") {
+        Backend::Nft
+    } else {
+        Backend::Legacy
+    };
+    if requested != Backend::Auto && requested != detected {
+        return Err(IPTError::Other(
+            "iptables binary does not use the requested backend".to_string(),
+        ));
+    }
+
+    Ok(Capabilities {
+        backend: detected,
+        version,
+        has_check: version.has_check(),
+        has_wait: version.has_wait(),
+        has_wait_timeout: version.has_wait_timeout(),
+        has_wait_interval: version.has_wait_interval(),
+    })
+}
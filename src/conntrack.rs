@@ -0,0 +1,268 @@
+//! Typed values of the `conntrack` match module, see `Rule::ctstate` and `Rule::ctstatus`, the
+//! options of the `CT` target, see `Rule::ct`, and a helper bypassing connection tracking.
+
+use crate::rule::Rule;
+use crate::{IPTResult, IPTables};
+use std::fmt;
+
+/// A connection tracking state matched with `--ctstate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CtState {
+    /// The packet could not be associated with a known connection.
+    Invalid,
+    /// The packet starts a new connection.
+    New,
+    /// The packet starts a new connection related to a known one, e.g. an FTP data transfer.
+    Related,
+    /// The packet belongs to a connection which has seen packets in both directions.
+    Established,
+    /// The packet is not tracked, e.g. because of the `NOTRACK` target.
+    Untracked,
+    /// The original source address differs from the reply destination.
+    Snat,
+    /// The original destination address differs from the reply source.
+    Dnat,
+}
+
+impl CtState {
+    /// Returns the state as passed to iptables.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CtState::Invalid => "INVALID",
+            CtState::New => "NEW",
+            CtState::Related => "RELATED",
+            CtState::Established => "ESTABLISHED",
+            CtState::Untracked => "UNTRACKED",
+            CtState::Snat => "SNAT",
+            CtState::Dnat => "DNAT",
+        }
+    }
+}
+
+impl fmt::Display for CtState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A connection tracking status matched with `--ctstatus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CtStatus {
+    /// None of the other statuses.
+    None,
+    /// The connection is an expected connection, e.g. created by a helper.
+    Expected,
+    /// The connection has seen packets in both directions.
+    SeenReply,
+    /// The connection is assured and will not be dropped early.
+    Assured,
+    /// The connection is confirmed, its initial packet has left the box.
+    Confirmed,
+}
+
+impl CtStatus {
+    /// Returns the status as passed to iptables.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CtStatus::None => "NONE",
+            CtStatus::Expected => "EXPECTED",
+            CtStatus::SeenReply => "SEEN_REPLY",
+            CtStatus::Assured => "ASSURED",
+            CtStatus::Confirmed => "CONFIRMED",
+        }
+    }
+}
+
+impl fmt::Display for CtStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A connection tracking event delivered to userspace, set with `CtTarget::ctevents`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CtEvent {
+    /// A connection was created.
+    New,
+    /// A connection related to a known one was created.
+    Related,
+    /// A connection was destroyed.
+    Destroy,
+    /// A connection has seen packets in both directions.
+    Reply,
+    /// A connection was assured.
+    Assured,
+    /// The protocol state of a connection changed, e.g. of TCP.
+    ProtoInfo,
+    /// The helper of a connection changed.
+    Helper,
+    /// The mark of a connection changed.
+    Mark,
+    /// The sequence numbers adjusted by NAT changed.
+    NatSeqInfo,
+    /// The security mark of a connection changed.
+    SecMark,
+}
+
+impl CtEvent {
+    /// Returns the event as passed to iptables.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CtEvent::New => "new",
+            CtEvent::Related => "related",
+            CtEvent::Destroy => "destroy",
+            CtEvent::Reply => "reply",
+            CtEvent::Assured => "assured",
+            CtEvent::ProtoInfo => "protoinfo",
+            CtEvent::Helper => "helper",
+            CtEvent::Mark => "mark",
+            CtEvent::NatSeqInfo => "natseqinfo",
+            CtEvent::SecMark => "secmark",
+        }
+    }
+}
+
+impl fmt::Display for CtEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The options of the `CT` target, which sets up the connection tracking of a packet before
+/// it is tracked and is therefore only valid in the `raw` table. See `Rule::ct`.
+///
+/// # Example
+/// ```
+/// use iptables::conntrack::{CtEvent, CtTarget};
+/// use iptables::Rule;
+///
+/// let target = CtTarget::new().helper("ftp").zone(2).ctevents(&[CtEvent::Destroy, CtEvent::New]);
+/// assert_eq!(
+///     Rule::new().protocol("tcp").dport(21).ct(&target).to_string(),
+///     "-p tcp -m tcp --dport 21 -j CT --helper ftp --ctevents new,destroy --zone 2"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CtTarget {
+    notrack: bool,
+    helper: Option<String>,
+    timeout: Option<String>,
+    ctevents: Vec<CtEvent>,
+    zone: Option<u16>,
+}
+
+impl CtTarget {
+    /// Creates a target without options, which only attaches the default zone.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a target which disables connection tracking for the packet (`--notrack`).
+    pub fn notrack() -> Self {
+        Self {
+            notrack: true,
+            ..Self::default()
+        }
+    }
+
+    /// Assigns the helper `name` to the connection, e.g. `ftp` or `sip` (`--helper`).
+    pub fn helper(mut self, name: &str) -> Self {
+        self.helper = Some(name.to_string());
+        self
+    }
+
+    /// Applies the timeout policy `name` defined with `nfct` to the connection (`--timeout`).
+    pub fn timeout(mut self, name: &str) -> Self {
+        self.timeout = Some(name.to_string());
+        self
+    }
+
+    /// Only delivers the `events` of the connection to userspace (`--ctevents`).
+    pub fn ctevents(mut self, events: &[CtEvent]) -> Self {
+        self.ctevents = events.to_vec();
+        self
+    }
+
+    /// Tracks the connection in the conntrack `zone` (`--zone`), to keep apart connections of
+    /// overlapping networks.
+    pub fn zone(mut self, zone: u16) -> Self {
+        self.zone = Some(zone);
+        self
+    }
+
+    /// Returns the arguments of the target in the order `iptables -S` prints them.
+    pub(crate) fn target_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.notrack {
+            args.push("--notrack".to_string());
+        }
+        if let Some(helper) = &self.helper {
+            args.extend(["--helper".to_string(), helper.clone()]);
+        }
+        if let Some(timeout) = &self.timeout {
+            args.extend(["--timeout".to_string(), timeout.clone()]);
+        }
+        if !self.ctevents.is_empty() {
+            args.extend(["--ctevents".to_string(), join_sorted(&self.ctevents)]);
+        }
+        if let Some(zone) = self.zone {
+            args.extend(["--zone".to_string(), zone.to_string()]);
+        }
+        args
+    }
+}
+
+// The chains of the raw table which see the packets received and sent by the host
+const BYPASS_CHAINS: [&str; 2] = ["PREROUTING", "OUTPUT"];
+
+impl IPTables {
+    /// Disables connection tracking for the packets matched by `selector`, by appending a
+    /// `-j CT --notrack` rule to the `PREROUTING` and `OUTPUT` chains of the `raw` table, each
+    /// unless it already exists. The target of `selector` is replaced.
+    ///
+    /// Tracking costs memory and time for every packet, which matters for flows with many
+    /// packets per second such as DNS or NTP servers. The packets of both directions must be
+    /// untracked, so a server usually calls this for the requests and for the replies. Stateful
+    /// `filter` rules see such packets in the `UNTRACKED` state, see `CtState::Untracked`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use iptables::Rule;
+    ///
+    /// let ipt = iptables::new(false).unwrap();
+    /// ipt.disable_conntrack_for(&Rule::new().protocol("udp").dport(53))
+    ///     .unwrap();
+    /// ipt.disable_conntrack_for(&Rule::new().protocol("udp").sport(53))
+    ///     .unwrap();
+    /// ```
+    pub fn disable_conntrack_for(&self, selector: &Rule) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        let rule = selector.clone().notrack();
+        for chain in BYPASS_CHAINS {
+            self.append_rule_if_missing("raw", chain, &rule)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the rules appended by `disable_conntrack_for` with the same `selector`.
+    pub fn enable_conntrack_for(&self, selector: &Rule) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        let rule = selector.clone().notrack();
+        for chain in BYPASS_CHAINS {
+            self.delete_rule_if_present("raw", chain, &rule)?;
+        }
+        Ok(())
+    }
+}
+
+// Joins the values in the order `iptables -S` prints them, without duplicates
+pub(crate) fn join_sorted<T: Ord + Copy + fmt::Display>(values: &[T]) -> String {
+    let mut values = values.to_vec();
+    values.sort_unstable();
+    values.dedup();
+    values
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,75 @@
+//! Helpers for the chains container runtimes hand over to the administrator, e.g. `DOCKER-USER`,
+//! which end with a `RETURN` rule back into the runtime's own chains.
+
+use crate::{IPTResult, IPTables, RuleSpec, Table};
+
+/// The chain of the `filter` table Docker jumps to from the top of `FORWARD` before its own
+/// chains, the place for rules restricting the traffic of containers.
+pub const DOCKER_USER_CHAIN: &str = "DOCKER-USER";
+
+impl IPTables {
+    /// Inserts `rule` in front of the last unconditional `-j RETURN` of the table/chain, or
+    /// appends it if the chain has none, unless the rule already exists. The chain is created
+    /// if it is missing.
+    ///
+    /// Appending to a chain like `DOCKER-USER` puts the rule behind its `RETURN`, where it is
+    /// never reached.
+    pub fn insert_before_return(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        let _serial = self.serial.lock();
+        if !self.chain_exists(table, chain)? {
+            self.new_chain(table, chain)?;
+        } else if self.exists(table, chain, &rule)? {
+            return Ok(());
+        }
+
+        let prefix = format!("-A {} ", chain);
+        let rules = self
+            .list(table, chain)?
+            .into_iter()
+            .filter_map(|line| line.strip_prefix(&prefix).map(str::to_string))
+            .collect::<Vec<_>>();
+        match rules.iter().rposition(|spec| spec == "-j RETURN") {
+            Some(index) => self.insert(table, chain, &rule, index as i32 + 1),
+            None => self.append(table, chain, &rule),
+        }
+    }
+
+    /// Creates the `DOCKER-USER` chain the way Docker does if it is missing: ending with
+    /// `-j RETURN` and jumped to from the top of `FORWARD`. Docker keeps an existing chain and
+    /// its rules when it starts, so rules can be installed before Docker runs.
+    pub fn ensure_docker_user_chain(&self) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        if !self.chain_exists("filter", DOCKER_USER_CHAIN)? {
+            self.new_chain("filter", DOCKER_USER_CHAIN)?;
+            self.append("filter", DOCKER_USER_CHAIN, "-j RETURN")?;
+        }
+        let jump = ["-j", DOCKER_USER_CHAIN];
+        if !self.exists("filter", "FORWARD", &jump)? {
+            self.insert("filter", "FORWARD", &jump, 1)?;
+        }
+        Ok(())
+    }
+
+    /// Inserts `rule` into `DOCKER-USER` in front of its `RETURN`, unless it already exists,
+    /// creating the chain with `ensure_docker_user_chain` if needed. Rules there see the
+    /// traffic of containers before Docker's own chains accept it.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let ipt = iptables::new(false).unwrap();
+    /// // Only the local network may reach published ports
+    /// ipt.insert_docker_user("-i eth0 ! -s 192.168.1.0/24 -j DROP").unwrap();
+    /// ```
+    pub fn insert_docker_user(&self, rule: impl Into<RuleSpec>) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        self.ensure_docker_user_chain()?;
+        self.insert_before_return("filter", DOCKER_USER_CHAIN, rule)
+    }
+}
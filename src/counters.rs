@@ -0,0 +1,162 @@
+//! Packet and byte counters of rules, as printed by `iptables -S -v`, and a poller which reads
+//! them periodically, e.g. for a metrics exporter.
+
+use crate::rule::quote_arg;
+use crate::{IPTResult, IPTables, SplitQuoted, Table};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A rule of a chain together with its packet and byte counters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuleCounters {
+    /// The chain the rule belongs to.
+    pub chain: String,
+    /// The rule specification without the chain, e.g. `-s 10.0.0.1/32 -j DROP`.
+    pub rule: String,
+    /// The number of packets matched by the rule.
+    pub packets: u64,
+    /// The number of bytes matched by the rule.
+    pub bytes: u64,
+}
+
+impl RuleCounters {
+    /// Parses a single `-A` line of `iptables -S -v` output.
+    /// Returns `None` for other lines such as policies and chain declarations.
+    pub fn parse(line: &str) -> Option<RuleCounters> {
+        let mut args = line.split_quoted();
+        let (mut packets, mut bytes) = (0, 0);
+
+        // iptables-nft and iptables-save print the counters as a `[packets:bytes]` prefix
+        if let Some(prefix) = args.first().filter(|arg| arg.starts_with('[')) {
+            let (p, b) = parse_bracketed(prefix)?;
+            packets = p;
+            bytes = b;
+            args.remove(0);
+        }
+
+        if args.len() < 2 || args[0] != "-A" {
+            return None;
+        }
+        let chain = args[1].clone();
+        let mut spec = args.split_off(2);
+
+        if let Some(index) = (0..spec.len()).find(|&i| {
+            spec[i] == "-c"
+                && spec.get(i + 2).is_some()
+                && spec[i + 1].parse::<u64>().is_ok()
+                && spec[i + 2].parse::<u64>().is_ok()
+        }) {
+            let counters = spec.drain(index..index + 3).collect::<Vec<_>>();
+            packets = counters[1].parse().ok()?;
+            bytes = counters[2].parse().ok()?;
+        }
+
+        Some(RuleCounters {
+            chain,
+            rule: spec
+                .iter()
+                .map(|arg| quote_arg(arg))
+                .collect::<Vec<_>>()
+                .join(" "),
+            packets,
+            bytes,
+        })
+    }
+
+    /// Returns the first comment (`-m comment --comment`) of the rule.
+    pub fn comment(&self) -> Option<String> {
+        self.rule
+            .split_quoted()
+            .windows(2)
+            .find(|pair| pair[0] == "--comment")
+            .map(|pair| pair[1].clone())
+    }
+
+    /// Returns a key identifying the rule in metrics: its comment if it has one, which stays the
+    /// same when the rule is changed, otherwise the rule specification.
+    pub fn key(&self) -> String {
+        self.comment().unwrap_or_else(|| self.rule.clone())
+    }
+}
+
+/// Reads the counters of a set of chains periodically on a background thread, started with
+/// `IPTables::poll_counters`. Polling stops when the poller is stopped or dropped.
+#[derive(Debug)]
+pub struct CounterPoller {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CounterPoller {
+    /// Stops polling and waits for a running poll to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        // Dropping the sender wakes the thread up
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for CounterPoller {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl IPTables {
+    /// Returns the packet and byte counters of each rule in the table/chain. Use
+    /// `RuleCounters::key` to identify the rules across calls.
+    pub fn counters(&self, table: impl Into<Table>, chain: &str) -> IPTResult<Vec<RuleCounters>> {
+        self.list_with_counters(table, chain)
+    }
+
+    /// Reads the counters of each table/chain in `chains` every `interval` on a background
+    /// thread, starting immediately, and passes them to `callback` together with the table and
+    /// chain. The chains are read through a clone of the instance.
+    pub fn poll_counters<F>(
+        &self,
+        chains: &[(&str, &str)],
+        interval: Duration,
+        mut callback: F,
+    ) -> CounterPoller
+    where
+        F: FnMut(&str, &str, IPTResult<Vec<RuleCounters>>) + Send + 'static,
+    {
+        let ipt = self.clone();
+        let chains = chains
+            .iter()
+            .map(|(table, chain)| (table.to_string(), chain.to_string()))
+            .collect::<Vec<_>>();
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let thread = thread::spawn(move || loop {
+            for (table, chain) in &chains {
+                callback(table, chain, ipt.counters(table, chain));
+            }
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                _ => break,
+            }
+        });
+        CounterPoller {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+// Parses the `[packets:bytes]` counters printed by iptables-save and iptables-nft
+pub(crate) fn parse_bracketed(counters: &str) -> Option<(u64, u64)> {
+    let counters = counters.strip_prefix('[')?.strip_suffix(']')?;
+    let mut fields = counters.split(':');
+    let packets = fields.next()?.parse().ok()?;
+    let bytes = fields.next()?.parse().ok()?;
+    Some((packets, bytes))
+}
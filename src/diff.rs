@@ -0,0 +1,529 @@
+//! Computes the operations which converge the live rules of a table to a desired set of rules,
+//! and reports the differences for review.
+
+use crate::batch::Batch;
+use crate::rule::join_quoted;
+use crate::{IPTResult, IPTables, SplitQuoted};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A single rule operation computed by `diff_chain` or `diff_table`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Appends `rule` to the table/chain.
+    Append {
+        /// The table of the chain.
+        table: String,
+        /// The chain the rule is appended to.
+        chain: String,
+        /// The rule specification without the chain.
+        rule: String,
+    },
+    /// Inserts `rule` in the `position` to the table/chain.
+    Insert {
+        /// The table of the chain.
+        table: String,
+        /// The chain the rule is inserted in.
+        chain: String,
+        /// The rule specification without the chain.
+        rule: String,
+        /// The position of the rule once inserted, the first rule has position 1.
+        position: i32,
+    },
+    /// Deletes `rule` from the table/chain.
+    Delete {
+        /// The table of the chain.
+        table: String,
+        /// The chain the rule is deleted from.
+        chain: String,
+        /// The rule specification without the chain.
+        rule: String,
+    },
+}
+
+impl Change {
+    /// Applies the operation with `ipt`.
+    pub fn apply(&self, ipt: &IPTables) -> IPTResult<()> {
+        match self {
+            Change::Append { table, chain, rule } => ipt.append(table, chain, rule),
+            Change::Insert {
+                table,
+                chain,
+                rule,
+                position,
+            } => ipt.insert(table, chain, rule, *position),
+            Change::Delete { table, chain, rule } => ipt.delete(table, chain, rule),
+        }
+    }
+
+    /// Queues the operation in `batch`, to apply several changes atomically.
+    pub fn queue(&self, batch: &mut Batch) {
+        match self {
+            Change::Append { table, chain, rule } => batch.append(table, chain, rule),
+            Change::Insert {
+                table,
+                chain,
+                rule,
+                position,
+            } => batch.insert(table, chain, rule, *position),
+            Change::Delete { table, chain, rule } => batch.delete(table, chain, rule),
+        };
+    }
+}
+
+/// Computes the operations which turn the `current` rules of the table/chain into the `desired`
+/// rules, in the order they have to be applied. Rules are given without the leading `-A <chain>`
+/// and are compared the way `iptables -S` prints them, so the desired rules should use the same
+/// form (e.g. `-s 10.0.0.1/32` instead of `-s 10.0.0.1`).
+///
+/// Rules which are in the same order in both lists are kept, all other current rules are
+/// deleted and the missing rules are inserted in their position.
+///
+/// # Example
+/// ```
+/// use iptables::diff::{diff_chain, Change};
+///
+/// let current = ["-j DROP".to_string()];
+/// let desired = ["-p tcp -j ACCEPT".to_string(), "-j DROP".to_string()];
+/// assert_eq!(
+///     diff_chain("filter", "INPUT", &desired, &current),
+///     vec![Change::Insert {
+///         table: "filter".to_string(),
+///         chain: "INPUT".to_string(),
+///         rule: "-p tcp -j ACCEPT".to_string(),
+///         position: 1,
+///     }]
+/// );
+/// ```
+pub fn diff_chain(table: &str, chain: &str, desired: &[String], current: &[String]) -> Vec<Change> {
+    let desired = desired
+        .iter()
+        .map(|rule| normalize(rule))
+        .collect::<Vec<_>>();
+    let current = current
+        .iter()
+        .map(|rule| normalize(rule))
+        .collect::<Vec<_>>();
+    let (kept_desired, kept_current) = longest_common_subsequence(&desired, &current);
+
+    let mut changes = Vec::new();
+    for (index, rule) in current.iter().enumerate() {
+        if !kept_current[index] {
+            changes.push(Change::Delete {
+                table: table.to_string(),
+                chain: chain.to_string(),
+                rule: rule.clone(),
+            });
+        }
+    }
+
+    // After the deletions the chain holds the kept rules in the desired order, so inserting
+    // each missing rule at its desired position restores the full order
+    let mut len = kept_current.iter().filter(|&&kept| kept).count();
+    for (index, rule) in desired.iter().enumerate() {
+        if kept_desired[index] {
+            continue;
+        }
+        let (table, chain, rule) = (table.to_string(), chain.to_string(), rule.clone());
+        changes.push(if index == len {
+            Change::Append { table, chain, rule }
+        } else {
+            Change::Insert {
+                table,
+                chain,
+                rule,
+                position: index as i32 + 1,
+            }
+        });
+        len += 1;
+    }
+    changes
+}
+
+/// Computes the operations which turn the rules of the table, given as the `current` output of
+/// `IPTables::list_table`, into the `desired` rules of each chain. Chains which are not in
+/// `desired` are left untouched, the operations are sorted by chain name.
+pub fn diff_table(
+    table: &str,
+    desired: &HashMap<String, Vec<String>>,
+    current: &[String],
+) -> Vec<Change> {
+    let mut chains = desired.keys().collect::<Vec<_>>();
+    chains.sort();
+
+    chains
+        .into_iter()
+        .flat_map(|chain| {
+            let prefix = format!("-A {} ", chain);
+            let live = current
+                .iter()
+                .filter_map(|line| line.strip_prefix(&prefix))
+                .map(String::from)
+                .collect::<Vec<_>>();
+            diff_chain(table, chain, &desired[chain], &live)
+        })
+        .collect()
+}
+
+/// The output format of `DiffReport::render`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReportFormat {
+    /// Text resembling a unified diff, one section per chain, also used by `Display`.
+    Text,
+    /// A single JSON object, for audit logs and review tooling, shaped like the `Serialize`
+    /// implementation of `DiffReport`. Available with the `serde` feature.
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+/// The difference of a single rule between the current and the desired rules of a chain.
+/// Positions start at 1, like the numbers shown by `--line-numbers`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "change", rename_all = "lowercase"))]
+pub enum RuleChange {
+    /// The rule is only desired.
+    Added {
+        /// The rule specification without the chain.
+        rule: String,
+        /// The position of the rule in the desired rules.
+        position: usize,
+    },
+    /// The rule is only current.
+    Removed {
+        /// The rule specification without the chain.
+        rule: String,
+        /// The position of the rule in the current rules.
+        position: usize,
+    },
+    /// The rule is in both, but its order relative to the other rules changed.
+    Moved {
+        /// The rule specification without the chain.
+        rule: String,
+        /// The position of the rule in the current rules.
+        from: usize,
+        /// The position of the rule in the desired rules.
+        to: usize,
+    },
+}
+
+/// The differences of a single chain, see `report_chain`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChainReport {
+    /// The table of the chain.
+    pub table: String,
+    /// The name of the chain.
+    pub chain: String,
+    /// The removed rules in their current order, followed by the added and moved rules in
+    /// their desired order.
+    pub changes: Vec<RuleChange>,
+}
+
+/// The differences of several chains, see `report_table`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiffReport {
+    /// The chains with at least one change.
+    pub chains: Vec<ChainReport>,
+}
+
+impl DiffReport {
+    /// Returns true if no chain changed.
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    /// Renders the report in the given `format`.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::diff::{report_table, ReportFormat};
+    /// use std::collections::HashMap;
+    ///
+    /// let mut desired = HashMap::new();
+    /// desired.insert("FW".to_string(), vec!["-j B".to_string(), "-j A".to_string()]);
+    /// let current = ["-A FW -j A".to_string(), "-A FW -j B".to_string(), "-A FW -j C".to_string()];
+    /// let report = report_table("filter", &desired, &current);
+    /// assert_eq!(
+    ///     report.render(ReportFormat::Text),
+    ///     "--- filter FW (current)\n+++ filter FW (desired)\n-3 -j C\n~1>2 -j A\n"
+    /// );
+    /// #[cfg(feature = "serde")]
+    /// assert_eq!(
+    ///     report.render(ReportFormat::Json),
+    ///     r#"{"chains":[{"table":"filter","chain":"FW","changes":[{"change":"removed","rule":"-j C","position":3},{"change":"moved","rule":"-j A","from":1,"to":2}]}]}"#
+    /// );
+    /// ```
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Text => self.to_string(),
+            #[cfg(feature = "serde")]
+            ReportFormat::Json => self.to_json(),
+        }
+    }
+
+    // The JSON has the shape of the `Serialize` implementation
+    #[cfg(feature = "serde")]
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("a report only holds strings and numbers")
+    }
+}
+
+/// Renders a section per chain: added rules start with `+` and their desired position, removed
+/// rules with `-` and their current position, moved rules with `~` and both positions.
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for report in &self.chains {
+            writeln!(f, "--- {} {} (current)", report.table, report.chain)?;
+            writeln!(f, "+++ {} {} (desired)", report.table, report.chain)?;
+            for change in &report.changes {
+                match change {
+                    RuleChange::Added { rule, position } => writeln!(f, "+{} {}", position, rule)?,
+                    RuleChange::Removed { rule, position } => {
+                        writeln!(f, "-{} {}", position, rule)?
+                    }
+                    RuleChange::Moved { rule, from, to } => {
+                        writeln!(f, "~{}>{} {}", from, to, rule)?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reports the differences between the `current` and the `desired` rules of the table/chain,
+/// given and compared like in `diff_chain`. A rule which is removed from one position and added
+/// in another is reported as moved.
+pub fn report_chain(
+    table: &str,
+    chain: &str,
+    desired: &[String],
+    current: &[String],
+) -> ChainReport {
+    let desired = desired
+        .iter()
+        .map(|rule| normalize(rule))
+        .collect::<Vec<_>>();
+    let current = current
+        .iter()
+        .map(|rule| normalize(rule))
+        .collect::<Vec<_>>();
+    let (kept_desired, kept_current) = longest_common_subsequence(&desired, &current);
+
+    let mut removed = current
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !kept_current[*index])
+        .map(|(index, rule)| Some((index + 1, rule)))
+        .collect::<Vec<_>>();
+    let mut added = Vec::new();
+    for (index, rule) in desired.iter().enumerate() {
+        if kept_desired[index] {
+            continue;
+        }
+        // The first removed copy of the rule is the one which moved
+        let from = removed
+            .iter_mut()
+            .find(|entry| entry.is_some_and(|(_, removed)| removed == rule))
+            .and_then(Option::take);
+        added.push(match from {
+            Some((from, _)) => RuleChange::Moved {
+                rule: rule.clone(),
+                from,
+                to: index + 1,
+            },
+            None => RuleChange::Added {
+                rule: rule.clone(),
+                position: index + 1,
+            },
+        });
+    }
+
+    let mut changes = removed
+        .into_iter()
+        .flatten()
+        .map(|(position, rule)| RuleChange::Removed {
+            rule: rule.clone(),
+            position,
+        })
+        .collect::<Vec<_>>();
+    changes.extend(added);
+    ChainReport {
+        table: table.to_string(),
+        chain: chain.to_string(),
+        changes,
+    }
+}
+
+/// Reports the differences between the rules of the table, given as the `current` output of
+/// `IPTables::list_table`, and the `desired` rules of each chain, like `diff_table`. Only the
+/// changed chains are reported, sorted by name.
+pub fn report_table(
+    table: &str,
+    desired: &HashMap<String, Vec<String>>,
+    current: &[String],
+) -> DiffReport {
+    let mut chains = desired.keys().collect::<Vec<_>>();
+    chains.sort();
+
+    let chains = chains
+        .into_iter()
+        .map(|chain| {
+            let prefix = format!("-A {} ", chain);
+            let live = current
+                .iter()
+                .filter_map(|line| line.strip_prefix(&prefix))
+                .map(String::from)
+                .collect::<Vec<_>>();
+            report_chain(table, chain, &desired[chain], &live)
+        })
+        .filter(|report| !report.changes.is_empty())
+        .collect();
+    DiffReport { chains }
+}
+
+pub(crate) fn normalize(rule: &str) -> String {
+    join_quoted(&rule.split_quoted())
+}
+
+// Marks the rules of both lists which are part of their longest common subsequence, using the
+// linear space variant of Myers' diff algorithm. It takes time proportional to the length of
+// the lists times the number of differences, and memory proportional to the length only, so
+// chains of many thousand rules with a few changes are compared quickly.
+pub(crate) fn longest_common_subsequence(a: &[String], b: &[String]) -> (Vec<bool>, Vec<bool>) {
+    // Numbers are cheaper to compare than rules
+    let mut ids = HashMap::new();
+    let a_ids = intern(a, &mut ids);
+    let b_ids = intern(b, &mut ids);
+    let (mut kept_a, mut kept_b) = (vec![false; a.len()], vec![false; b.len()]);
+    mark_common(&a_ids, &b_ids, &mut kept_a, &mut kept_b);
+    (kept_a, kept_b)
+}
+
+// Replaces each rule by a number, equal rules by the same number
+fn intern<'a>(rules: &'a [String], ids: &mut HashMap<&'a str, usize>) -> Vec<usize> {
+    rules
+        .iter()
+        .map(|rule| {
+            let next = ids.len();
+            *ids.entry(rule.as_str()).or_insert(next)
+        })
+        .collect()
+}
+
+// Marks the common prefix and suffix, then splits the rest where the forward and the backward
+// search for the shortest edit meet and marks both parts
+fn mark_common(a: &[usize], b: &[usize], kept_a: &mut [bool], kept_b: &mut [bool]) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_end, b_end) = (a.len() - suffix, b.len() - suffix);
+    for (kept, end) in [(&mut *kept_a, a_end), (&mut *kept_b, b_end)] {
+        kept[..prefix].fill(true);
+        kept[end..].fill(true);
+    }
+
+    let (a, b) = (&a[prefix..a_end], &b[prefix..b_end]);
+    if a.is_empty() || b.is_empty() {
+        return;
+    }
+    if let Some((x, y)) = middle_snake(a, b) {
+        let (a_head, a_tail) = kept_a[prefix..a_end].split_at_mut(x);
+        let (b_head, b_tail) = kept_b[prefix..b_end].split_at_mut(y);
+        mark_common(&a[..x], &b[..y], a_head, b_head);
+        mark_common(&a[x..], &b[y..], a_tail, b_tail);
+    }
+}
+
+// Searches the shortest edit from both ends at once and returns the point where the searches
+// overlap, which lies on a shortest edit, or `None` if the lists have nothing in common. `a`
+// and `b` must differ in their first and in their last element.
+fn middle_snake(a: &[usize], b: &[usize]) -> Option<(usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max_d = (n + m + 1) / 2;
+    let len = 2 * max_d + 2;
+    // The furthest x reached on each diagonal k = x - y, shifted by `max_d`, the backward
+    // search counting from the ends of the lists
+    let mut forward = vec![-1; len as usize];
+    let mut backward = vec![-1; len as usize];
+    forward[max_d as usize + 1] = 0;
+    backward[max_d as usize + 1] = 0;
+    let delta = n - m;
+    // If the difference of the lengths is odd, the forward search hits the backward one first
+    let front = delta % 2 != 0;
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+
+    for d in 0..max_d {
+        let mut k1 = -d + k1_start;
+        while k1 <= d - k1_end {
+            let k1_offset = (max_d + k1) as usize;
+            let mut x1 = if k1 == -d || (k1 != d && forward[k1_offset - 1] < forward[k1_offset + 1])
+            {
+                forward[k1_offset + 1]
+            } else {
+                forward[k1_offset - 1] + 1
+            };
+            let mut y1 = x1 - k1;
+            while x1 < n && y1 < m && a[x1 as usize] == b[y1 as usize] {
+                x1 += 1;
+                y1 += 1;
+            }
+            forward[k1_offset] = x1;
+            if x1 > n {
+                // Ran off the right of the graph
+                k1_end += 2;
+            } else if y1 > m {
+                // Ran off the bottom of the graph
+                k1_start += 2;
+            } else if front {
+                let k2_offset = max_d + delta - k1;
+                if (0..len).contains(&k2_offset) && backward[k2_offset as usize] != -1 {
+                    let x2 = n - backward[k2_offset as usize];
+                    if x1 >= x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2_start;
+        while k2 <= d - k2_end {
+            let k2_offset = (max_d + k2) as usize;
+            let mut x2 =
+                if k2 == -d || (k2 != d && backward[k2_offset - 1] < backward[k2_offset + 1]) {
+                    backward[k2_offset + 1]
+                } else {
+                    backward[k2_offset - 1] + 1
+                };
+            let mut y2 = x2 - k2;
+            while x2 < n && y2 < m && a[(n - x2 - 1) as usize] == b[(m - y2 - 1) as usize] {
+                x2 += 1;
+                y2 += 1;
+            }
+            backward[k2_offset] = x2;
+            if x2 > n {
+                k2_end += 2;
+            } else if y2 > m {
+                k2_start += 2;
+            } else if !front {
+                let k1_offset = max_d + delta - k2;
+                if (0..len).contains(&k1_offset) && forward[k1_offset as usize] != -1 {
+                    let x1 = forward[k1_offset as usize];
+                    let y1 = max_d + x1 - k1_offset;
+                    if x1 >= n - x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k2 += 2;
+        }
+    }
+    None
+}
@@ -0,0 +1,149 @@
+//! Manages rules which have to exist for both IPv4 (iptables) and IPv6 (ip6tables).
+
+use crate::error::DualStackError;
+use crate::rule::Rule;
+use crate::{IPTResult, IPTables, RuleSpec, Table};
+
+/// Holds an `IPTables` instance for each address family and applies operations to both.
+///
+/// The operation is always attempted for both families, even if it fails for one of them.
+/// Failures are returned as a `DualStackError` which holds the error of each family.
+pub struct DualStack {
+    /// The instance using 'iptables'.
+    pub ipv4: IPTables,
+    /// The instance using 'ip6tables'.
+    pub ipv6: IPTables,
+}
+
+impl DualStack {
+    /// Creates a new `DualStack` with the default 'iptables' and 'ip6tables' commands.
+    pub fn new() -> IPTResult<DualStack> {
+        Ok(DualStack {
+            ipv4: crate::new(false)?,
+            ipv6: crate::new(true)?,
+        })
+    }
+
+    /// Appends `v4_rule` to the IPv4 and `v6_rule` to the IPv6 table/chain.
+    pub fn append_both(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        v4_rule: impl Into<RuleSpec>,
+        v6_rule: impl Into<RuleSpec>,
+    ) -> Result<(), DualStackError> {
+        let table: &str = &table.into();
+        combine(
+            self.ipv4.append(table, chain, v4_rule),
+            self.ipv6.append(table, chain, v6_rule),
+        )
+    }
+
+    /// Inserts `v4_rule` to the IPv4 and `v6_rule` to the IPv6 table/chain in the `position`.
+    pub fn insert_both(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        v4_rule: impl Into<RuleSpec>,
+        v6_rule: impl Into<RuleSpec>,
+        position: i32,
+    ) -> Result<(), DualStackError> {
+        let table: &str = &table.into();
+        combine(
+            self.ipv4.insert(table, chain, v4_rule, position),
+            self.ipv6.insert(table, chain, v6_rule, position),
+        )
+    }
+
+    /// Deletes `v4_rule` from the IPv4 and `v6_rule` from the IPv6 table/chain.
+    pub fn delete_both(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        v4_rule: impl Into<RuleSpec>,
+        v6_rule: impl Into<RuleSpec>,
+    ) -> Result<(), DualStackError> {
+        let table: &str = &table.into();
+        combine(
+            self.ipv4.delete(table, chain, v4_rule),
+            self.ipv6.delete(table, chain, v6_rule),
+        )
+    }
+
+    /// Appends the structured `rule`, which must be valid for both families, to the table/chain.
+    pub fn append_rule_both(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: &Rule,
+    ) -> Result<(), DualStackError> {
+        let table: &str = &table.into();
+        combine(
+            self.ipv4.append_rule(table, chain, rule),
+            self.ipv6.append_rule(table, chain, rule),
+        )
+    }
+
+    /// Deletes the structured `rule` from the table/chain of both families.
+    pub fn delete_rule_both(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: &Rule,
+    ) -> Result<(), DualStackError> {
+        let table: &str = &table.into();
+        combine(
+            self.ipv4.delete_rule(table, chain, rule),
+            self.ipv6.delete_rule(table, chain, rule),
+        )
+    }
+
+    /// Creates the user-defined chain for both families if it does not exist yet.
+    pub fn ensure_chain_both(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> Result<(), DualStackError> {
+        let table: &str = &table.into();
+        combine(
+            self.ipv4.ensure_chain(table, chain),
+            self.ipv6.ensure_chain(table, chain),
+        )
+    }
+
+    /// Flushes the chain of both families.
+    pub fn flush_chain_both(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> Result<(), DualStackError> {
+        let table: &str = &table.into();
+        combine(
+            self.ipv4.flush_chain(table, chain),
+            self.ipv6.flush_chain(table, chain),
+        )
+    }
+
+    /// Deletes the user-defined chain of both families.
+    pub fn delete_chain_both(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> Result<(), DualStackError> {
+        let table: &str = &table.into();
+        combine(
+            self.ipv4.delete_chain(table, chain),
+            self.ipv6.delete_chain(table, chain),
+        )
+    }
+}
+
+fn combine(ipv4: IPTResult<()>, ipv6: IPTResult<()>) -> Result<(), DualStackError> {
+    if ipv4.is_ok() && ipv6.is_ok() {
+        return Ok(());
+    }
+    Err(DualStackError {
+        ipv4: ipv4.err(),
+        ipv6: ipv6.err(),
+    })
+}
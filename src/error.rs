@@ -1,12 +1,21 @@
+use crate::validate::ValidationError;
 use std::convert::From;
 use std::error::Error;
 use std::fmt;
+use std::io;
 use std::process::Output;
+use std::string::FromUtf8Error;
+use std::time::Duration;
 
+/// Describes a failed invocation of iptables.
 #[derive(Debug)]
 pub struct IptablesError {
+    /// The exit code of iptables, `-1` if it was terminated by a signal.
     pub code: i32,
+    /// The standard error output of iptables.
     pub msg: String,
+    /// The standard output of iptables.
+    pub stdout: String,
 }
 
 impl fmt::Display for IptablesError {
@@ -20,8 +29,157 @@ impl From<Output> for IptablesError {
         Self {
             code: output.status.code().unwrap_or(-1),
             msg: String::from_utf8_lossy(output.stderr.as_slice()).into(),
+            stdout: String::from_utf8_lossy(output.stdout.as_slice()).into(),
         }
     }
 }
 
 impl Error for IptablesError {}
+
+/// Describes the failures of an operation applied to both address families by `DualStack`.
+#[derive(Debug)]
+pub struct DualStackError {
+    /// The failure of the IPv4 operation, `None` if it succeeded.
+    pub ipv4: Option<IPTError>,
+    /// The failure of the IPv6 operation, `None` if it succeeded.
+    pub ipv6: Option<IPTError>,
+}
+
+impl fmt::Display for DualStackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.ipv4, &self.ipv6) {
+            (Some(ipv4), Some(ipv6)) => write!(f, "ipv4: {}, ipv6: {}", ipv4, ipv6),
+            (Some(ipv4), None) => write!(f, "ipv4: {}", ipv4),
+            (None, Some(ipv6)) => write!(f, "ipv6: {}", ipv6),
+            (None, None) => write!(f, "no failure"),
+        }
+    }
+}
+
+impl Error for DualStackError {}
+
+/// Describes all failures of this crate, see the variants to match on specific kinds of failures.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum IPTError {
+    /// Spawning iptables or communicating with it failed.
+    Io(io::Error),
+    /// The output of iptables is not valid UTF-8.
+    Utf8(FromUtf8Error),
+    /// A rule or the output of iptables could not be parsed.
+    Parse(String),
+    /// A system call failed, e.g. taking the xtables lock.
+    Nix(nix::Error),
+    /// iptables exited with a non-zero status.
+    CommandFailed(IptablesError),
+    /// The requested table, chain or policy was not found.
+    NotFound(String),
+    /// The rule or chain to create already exists.
+    AlreadyExists(String),
+    /// The version of iptables could not be detected.
+    Version(String),
+    /// No iptables binary was found, see `IPTablesBuilder::search_paths`.
+    BinaryNotFound {
+        /// The paths which were tried, in the order they were tried.
+        searched: Vec<String>,
+    },
+    /// The lock of iptables versions without the -w (--wait) option could not be taken
+    /// within the limits of the configured `LockPolicy`.
+    LockTimeout,
+    /// The command ran longer than the `IPTablesBuilder::command_timeout` and was killed.
+    Timeout {
+        /// The command line of the killed command.
+        command: String,
+        /// The configured timeout.
+        timeout: Duration,
+    },
+    /// The rule with the `index` of the rules given to a call like `IPTables::append_many` was
+    /// invalid or rejected by iptables, no rule of the call was applied.
+    RuleFailed {
+        /// The index of the failed rule.
+        index: usize,
+        /// The reason the rule failed.
+        error: Box<IPTError>,
+    },
+    /// The rule was rejected before running iptables, see `validate::validate_rule`.
+    Invalid(ValidationError),
+    /// Any other failure, described by the message.
+    Other(String),
+}
+
+impl fmt::Display for IPTError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IPTError::Io(err) => write!(f, "io error: {}", err),
+            IPTError::Utf8(err) => write!(f, "invalid utf-8 output: {}", err),
+            IPTError::Parse(msg) => write!(f, "parse error: {}", msg),
+            IPTError::Nix(err) => write!(f, "system error: {}", err),
+            IPTError::CommandFailed(err) => write!(f, "iptables failed: {}", err),
+            IPTError::NotFound(msg) => write!(f, "not found: {}", msg),
+            IPTError::AlreadyExists(msg) => write!(f, "already exists: {}", msg),
+            IPTError::Version(msg) => write!(f, "invalid version: {}", msg),
+            IPTError::BinaryNotFound { searched } => {
+                write!(
+                    f,
+                    "iptables binary not found, tried: {}",
+                    searched.join(", ")
+                )
+            }
+            IPTError::LockTimeout => write!(f, "timed out waiting for the xtables lock"),
+            IPTError::Timeout { command, timeout } => {
+                write!(
+                    f,
+                    "{} did not finish within {:?} and was killed",
+                    command, timeout
+                )
+            }
+            IPTError::RuleFailed { index, error } => write!(f, "rule {} failed: {}", index, error),
+            IPTError::Invalid(err) => write!(f, "invalid rule: {}", err),
+            IPTError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl Error for IPTError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            IPTError::Io(err) => Some(err),
+            IPTError::Utf8(err) => Some(err),
+            IPTError::Nix(err) => Some(err),
+            IPTError::CommandFailed(err) => Some(err),
+            IPTError::RuleFailed { error, .. } => Some(error.as_ref()),
+            IPTError::Invalid(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for IPTError {
+    fn from(err: io::Error) -> Self {
+        IPTError::Io(err)
+    }
+}
+
+impl From<FromUtf8Error> for IPTError {
+    fn from(err: FromUtf8Error) -> Self {
+        IPTError::Utf8(err)
+    }
+}
+
+impl From<nix::Error> for IPTError {
+    fn from(err: nix::Error) -> Self {
+        IPTError::Nix(err)
+    }
+}
+
+impl From<IptablesError> for IPTError {
+    fn from(err: IptablesError) -> Self {
+        IPTError::CommandFailed(err)
+    }
+}
+
+impl From<ValidationError> for IPTError {
+    fn from(err: ValidationError) -> Self {
+        IPTError::Invalid(err)
+    }
+}
@@ -0,0 +1,233 @@
+//! Models the chains of a table as a graph of jumps, to find loops and unused chains in rulesets
+//! generated by several tools.
+
+use crate::{IPTResult, IPTables, SplitQuoted, Table};
+use std::collections::{HashMap, HashSet};
+
+/// A rule of `from` jumping to the user-defined chain `to`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Edge {
+    /// The chain holding the rule.
+    pub from: String,
+    /// The chain the rule jumps to.
+    pub to: String,
+    /// The position of the rule in `from`, the first rule has position 1.
+    pub position: u32,
+    /// True if the rule uses `-g` (`--goto`), which does not return to `from`.
+    pub goto: bool,
+}
+
+/// The chains of a table as nodes and the rules jumping between them as edges, created with
+/// `IPTables::graph` or `ChainGraph::from_listing`.
+///
+/// # Example
+/// ```
+/// use iptables::graph::ChainGraph;
+///
+/// let listing = [
+///     "-P INPUT ACCEPT",
+///     "-N A",
+///     "-N B",
+///     "-N UNUSED",
+///     "-A INPUT -j A",
+///     "-A A -p tcp -j B",
+///     "-A B -g A",
+/// ];
+/// let listing = listing.iter().map(|line| line.to_string()).collect::<Vec<_>>();
+/// let graph = ChainGraph::from_listing(&listing);
+/// assert_eq!(graph.jumps_from("A"), vec!["B"]);
+/// assert_eq!(graph.cycles(), vec![vec!["A", "B"]]);
+/// assert_eq!(graph.unreachable(), vec!["UNUSED"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChainGraph {
+    builtin: Vec<String>,
+    user: Vec<String>,
+    edges: Vec<Edge>,
+}
+
+impl ChainGraph {
+    /// Builds the graph from the output of `IPTables::list_table`. Built-in chains are the ones
+    /// with a policy (`-P`), user-defined chains the ones created with `-N`. Jumps to targets
+    /// which are not chains, like `ACCEPT` or `MASQUERADE`, are not edges.
+    pub fn from_listing(lines: &[String]) -> ChainGraph {
+        let mut graph = ChainGraph::default();
+        let mut rules = Vec::new();
+        for args in lines.iter().map(|line| line.split_quoted()) {
+            match (args.first().map(String::as_str), args.get(1)) {
+                (Some("-P"), Some(chain)) => graph.builtin.push(chain.clone()),
+                (Some("-N"), Some(chain)) => graph.user.push(chain.clone()),
+                (Some("-A"), Some(_)) => rules.push(args),
+                _ => {}
+            }
+        }
+
+        let mut positions: HashMap<String, u32> = HashMap::new();
+        for args in rules {
+            let position = positions.entry(args[1].clone()).or_insert(0);
+            *position += 1;
+            let target = args.windows(2).find_map(|pair| match pair[0].as_str() {
+                "-j" | "--jump" => Some((&pair[1], false)),
+                "-g" | "--goto" => Some((&pair[1], true)),
+                _ => None,
+            });
+            if let Some((to, goto)) = target.filter(|(to, _)| graph.is_chain(to)) {
+                graph.edges.push(Edge {
+                    from: args[1].clone(),
+                    to: to.clone(),
+                    position: *position,
+                    goto,
+                });
+            }
+        }
+        graph
+    }
+
+    /// Returns the built-in chains, in the order they were listed.
+    pub fn builtin_chains(&self) -> Vec<&str> {
+        self.builtin.iter().map(String::as_str).collect()
+    }
+
+    /// Returns the user-defined chains, in the order they were listed.
+    pub fn user_chains(&self) -> Vec<&str> {
+        self.user.iter().map(String::as_str).collect()
+    }
+
+    /// Returns all rules jumping to a chain, in the order they were listed.
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// Returns the chains `chain` jumps to, each once, in the order of the rules.
+    pub fn jumps_from(&self, chain: &str) -> Vec<&str> {
+        let mut targets = Vec::new();
+        for edge in self.edges.iter().filter(|edge| edge.from == chain) {
+            if !targets.contains(&edge.to.as_str()) {
+                targets.push(edge.to.as_str());
+            }
+        }
+        targets
+    }
+
+    /// Returns the chains jumping to `chain`, each once, in the order of the rules.
+    pub fn jumps_to(&self, chain: &str) -> Vec<&str> {
+        let mut sources = Vec::new();
+        for edge in self.edges.iter().filter(|edge| edge.to == chain) {
+            if !sources.contains(&edge.from.as_str()) {
+                sources.push(edge.from.as_str());
+            }
+        }
+        sources
+    }
+
+    /// Returns the groups of chains which jump to each other in a loop, each sorted by name. The
+    /// kernel refuses to load such a ruleset, so a loop means the rules were listed while another
+    /// tool was in the middle of changing them, or were built for `iptables-restore` by hand.
+    pub fn cycles(&self) -> Vec<Vec<&str>> {
+        let mut cycles = self
+            .strongly_connected()
+            .into_iter()
+            .filter(|component| {
+                component.len() > 1
+                    || self
+                        .edges
+                        .iter()
+                        .any(|edge| edge.from == component[0] && edge.to == component[0])
+            })
+            .map(|mut component| {
+                component.sort_unstable();
+                component
+            })
+            .collect::<Vec<_>>();
+        cycles.sort();
+        cycles
+    }
+
+    /// Returns the user-defined chains which no built-in chain reaches through jumps, in the
+    /// order they were listed. Such chains never see a packet, e.g. because the tool which
+    /// created them failed to add or already removed its jump.
+    pub fn unreachable(&self) -> Vec<&str> {
+        let mut reached = self
+            .builtin
+            .iter()
+            .map(String::as_str)
+            .collect::<HashSet<_>>();
+        let mut pending = self.builtin_chains();
+        while let Some(chain) = pending.pop() {
+            for target in self.jumps_from(chain) {
+                if reached.insert(target) {
+                    pending.push(target);
+                }
+            }
+        }
+        self.user
+            .iter()
+            .map(String::as_str)
+            .filter(|chain| !reached.contains(chain))
+            .collect()
+    }
+
+    fn is_chain(&self, name: &str) -> bool {
+        self.builtin
+            .iter()
+            .chain(&self.user)
+            .any(|chain| chain == name)
+    }
+
+    // Tarjan's algorithm, the chains of each component in no particular order
+    fn strongly_connected(&self) -> Vec<Vec<&str>> {
+        struct State<'a> {
+            index: HashMap<&'a str, usize>,
+            low: HashMap<&'a str, usize>,
+            stack: Vec<&'a str>,
+            components: Vec<Vec<&'a str>>,
+        }
+
+        fn visit<'a>(graph: &'a ChainGraph, chain: &'a str, state: &mut State<'a>) {
+            let index = state.index.len();
+            state.index.insert(chain, index);
+            state.low.insert(chain, index);
+            state.stack.push(chain);
+            for target in graph.jumps_from(chain) {
+                if !state.index.contains_key(target) {
+                    visit(graph, target, state);
+                    let low = state.low[chain].min(state.low[target]);
+                    state.low.insert(chain, low);
+                } else if state.stack.contains(&target) {
+                    let low = state.low[chain].min(state.index[target]);
+                    state.low.insert(chain, low);
+                }
+            }
+            if state.low[chain] == index {
+                let start = state
+                    .stack
+                    .iter()
+                    .position(|member| *member == chain)
+                    .expect("the chain is on the stack");
+                state.components.push(state.stack.split_off(start));
+            }
+        }
+
+        let mut state = State {
+            index: HashMap::new(),
+            low: HashMap::new(),
+            stack: Vec::new(),
+            components: Vec::new(),
+        };
+        for chain in self.builtin.iter().chain(&self.user) {
+            if !state.index.contains_key(chain.as_str()) {
+                visit(self, chain, &mut state);
+            }
+        }
+        state.components
+    }
+}
+
+impl IPTables {
+    /// Builds the graph of the jumps between the chains of the table, see `ChainGraph`.
+    pub fn graph(&self, table: impl Into<Table>) -> IPTResult<ChainGraph> {
+        Ok(ChainGraph::from_listing(&self.list_table(table)?))
+    }
+}
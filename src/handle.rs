@@ -0,0 +1,499 @@
+//! Handles to rules added through `IPTables`, to delete them later without rendering them again,
+//! and handles scoped to a single table.
+
+use crate::batch::check_line_breaks;
+use crate::rule::{join_quoted, Rule, RuleSpec};
+use crate::MSG_BAD_RULE;
+use crate::{
+    as_strs, jumps_to, output_to_exists, output_to_result, rule_specs, IPTResult, IPTables, Table,
+};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Remembers the table, chain and exact arguments of a rule added with `IPTables::append_handle`
+/// or `IPTables::insert_handle`, including the comment of the `owner_tag`.
+///
+/// # Example
+/// ```no_run
+/// let ipt = iptables::new(false).unwrap();
+/// let handle = ipt.append_handle("filter", "INPUT", "-p tcp --dport 8080 -j ACCEPT").unwrap();
+/// // ...
+/// handle.delete(&ipt).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RuleHandle {
+    table: String,
+    chain: String,
+    args: Vec<String>,
+}
+
+impl RuleHandle {
+    /// Returns the table of the rule.
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Returns the chain of the rule.
+    pub fn chain(&self) -> &str {
+        &self.chain
+    }
+
+    /// Returns the arguments of the rule as passed to iptables.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Returns the rule specification, e.g. `-p tcp --dport 8080 -j ACCEPT`.
+    pub fn rule(&self) -> String {
+        join_quoted(&self.args)
+    }
+
+    /// Checks for the existence of the rule.
+    pub fn exists(&self, ipt: &IPTables) -> IPTResult<bool> {
+        ipt.run(
+            &[
+                &["-t", &self.table, "-C", &self.chain],
+                as_strs(&self.args).as_slice(),
+            ]
+            .concat(),
+        )
+        .and_then(|output| output_to_exists(output, MSG_BAD_RULE))
+    }
+
+    /// Deletes the rule.
+    pub fn delete(&self, ipt: &IPTables) -> IPTResult<()> {
+        ipt.run(
+            &[
+                &["-t", &self.table, "-D", &self.chain],
+                as_strs(&self.args).as_slice(),
+            ]
+            .concat(),
+        )
+        .and_then(output_to_result)
+    }
+}
+
+impl fmt::Display for RuleHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "-t {} -A {} {}", self.table, self.chain, self.rule())
+    }
+}
+
+/// Deletes the registered rules when it is dropped, in the reverse order they were registered,
+/// e.g. to clean up the rules of a test or a short-lived service on every exit path.
+///
+/// Errors while deleting are ignored, use `cleanup` to handle them.
+pub struct RuleGuard {
+    ipt: IPTables,
+    handles: Vec<RuleHandle>,
+}
+
+impl RuleGuard {
+    /// Creates an empty guard which deletes the rules with `ipt`.
+    pub fn new(ipt: &IPTables) -> Self {
+        Self {
+            ipt: ipt.clone(),
+            handles: Vec::new(),
+        }
+    }
+
+    /// Registers `handle` to be deleted.
+    pub fn register(&mut self, handle: RuleHandle) -> &mut Self {
+        self.handles.push(handle);
+        self
+    }
+
+    /// Returns the registered handles.
+    pub fn handles(&self) -> &[RuleHandle] {
+        &self.handles
+    }
+
+    /// Unregisters all rules and returns their handles, so they are kept when the guard is
+    /// dropped.
+    pub fn disarm(&mut self) -> Vec<RuleHandle> {
+        std::mem::take(&mut self.handles)
+    }
+
+    /// Deletes the registered rules now and returns the first error. Rules which could not be
+    /// deleted are unregistered as well.
+    pub fn cleanup(&mut self) -> IPTResult<()> {
+        let mut result = Ok(());
+        while let Some(handle) = self.handles.pop() {
+            if let Err(err) = handle.delete(&self.ipt) {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl fmt::Debug for RuleGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RuleGuard")
+            .field("handles", &self.handles)
+            .finish()
+    }
+}
+
+impl Drop for RuleGuard {
+    fn drop(&mut self) {
+        let _ = self.cleanup();
+    }
+}
+
+/// A user-defined chain created with `IPTables::create_managed_chain` together with the chains
+/// jumping to it, to flush or remove it later.
+///
+/// # Example
+/// ```no_run
+/// let ipt = iptables::new(false).unwrap();
+/// let chain = ipt
+///     .create_managed_chain("filter", "MYAPP", &[("INPUT", 1), ("FORWARD", 1)])
+///     .unwrap();
+/// ipt.append("filter", chain.chain(), "-p tcp --dport 8080 -j ACCEPT").unwrap();
+/// // ...
+/// chain.teardown(&ipt).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ManagedChain {
+    table: String,
+    chain: String,
+    parents: Vec<String>,
+}
+
+impl ManagedChain {
+    /// Returns the table of the chain.
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Returns the name of the chain.
+    pub fn chain(&self) -> &str {
+        &self.chain
+    }
+
+    /// Returns the chains jumping to the chain.
+    pub fn parents(&self) -> &[String] {
+        &self.parents
+    }
+
+    /// Deletes all rules of the chain, the jumps to it are kept.
+    pub fn flush(&self, ipt: &IPTables) -> IPTResult<()> {
+        ipt.flush_chain(self.table.as_str(), &self.chain)
+    }
+
+    /// Deletes the jumps from the parents, flushes and deletes the chain.
+    pub fn teardown(&self, ipt: &IPTables) -> IPTResult<()> {
+        let parents = self.parents.iter().map(String::as_str).collect::<Vec<_>>();
+        ipt.teardown_chain(self.table.as_str(), &self.chain, &parents)
+    }
+}
+
+impl IPTables {
+    /// Returns a handle to the chain and rule methods of `table`, see `TableHandle`.
+    pub fn table(&self, table: impl Into<Table>) -> TableHandle {
+        TableHandle {
+            ipt: self.clone(),
+            table: table.into(),
+            chains: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Creates the user-defined `chain` and inserts a jump to it in each parent chain of
+    /// `wire_from` in the given position, with a single `iptables-restore --noflush` call, so
+    /// the chain is never reachable half-wired. The first rule has position 1.
+    ///
+    /// An existing chain is kept with its rules and jumps which are already in a parent are not
+    /// added again, so the call can be repeated on every start of an application.
+    pub fn create_managed_chain<P: AsRef<str>>(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        wire_from: &[(P, i32)],
+    ) -> IPTResult<ManagedChain> {
+        let table: &str = &table.into();
+        // The names and the jump are written into the input of iptables-restore, where a line
+        // break would start another command
+        let jump = self.tagged(vec!["-j".to_string(), chain.to_string()]);
+        check_line_breaks(&[table, chain])?;
+        check_line_breaks(&jump)?;
+        for (parent, _) in wire_from {
+            check_line_breaks(&[parent])?;
+        }
+        let jump = join_quoted(&jump);
+
+        let _serial = self.serial.lock();
+        let mut data = format!("*{}\n", table);
+        if !self
+            .list_chains(table)?
+            .iter()
+            .any(|existing| existing == chain)
+        {
+            data.push_str(&format!(":{} - [0:0]\n", chain));
+        }
+
+        for (parent, position) in wire_from {
+            let parent = parent.as_ref();
+            let lines = self.list(table, parent)?;
+            if rule_specs(&lines, |args| jumps_to(args, chain)).is_empty() {
+                data.push_str(&format!("-I {} {} {}\n", parent, position, jump));
+            }
+        }
+        data.push_str("COMMIT\n");
+        self.restore(&data, false)?;
+
+        Ok(ManagedChain {
+            table: table.to_string(),
+            chain: chain.to_string(),
+            parents: wire_from
+                .iter()
+                .map(|(parent, _)| parent.as_ref().to_string())
+                .collect(),
+        })
+    }
+
+    /// Appends `rule` to the table/chain and returns a handle to delete it later.
+    pub fn append_handle(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<RuleHandle> {
+        let table: &str = &table.into();
+        let handle = self.handle(table, chain, rule)?;
+        self.run(
+            &[
+                &["-t", table, "-A", chain],
+                as_strs(&handle.args).as_slice(),
+            ]
+            .concat(),
+        )
+        .and_then(output_to_result)?;
+        Ok(handle)
+    }
+
+    /// Inserts `rule` in the `position` to the table/chain and returns a handle to delete it
+    /// later.
+    pub fn insert_handle(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: i32,
+    ) -> IPTResult<RuleHandle> {
+        let table: &str = &table.into();
+        let handle = self.handle(table, chain, rule)?;
+        let position = position.to_string();
+        self.run(
+            &[
+                &["-t", table, "-I", chain, &position],
+                as_strs(&handle.args).as_slice(),
+            ]
+            .concat(),
+        )
+        .and_then(output_to_result)?;
+        Ok(handle)
+    }
+
+    fn handle(&self, table: &str, chain: &str, rule: impl Into<RuleSpec>) -> IPTResult<RuleHandle> {
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        Ok(RuleHandle {
+            table: table.to_string(),
+            chain: chain.to_string(),
+            args: self.tagged(rule.into_args()),
+        })
+    }
+}
+
+/// The chain and rule methods of `IPTables` scoped to a single table, created with
+/// `IPTables::table`, so the table is not repeated in every call.
+///
+/// The names of the chains are cached on the first call which needs them and kept up to date by
+/// the chain methods of the handle. Call `refresh` after the chains were changed by other means.
+/// Clones of the handle share the cache, so the handle can be passed to other threads.
+///
+/// # Example
+/// ```no_run
+/// let ipt = iptables::new(false).unwrap();
+/// let nat = ipt.table("nat");
+/// nat.ensure_chain("MYAPP").unwrap();
+/// nat.append("MYAPP", "-p tcp --dport 8080 -j REDIRECT --to-ports 80").unwrap();
+/// assert!(nat.chain_exists("MYAPP").unwrap());
+/// ```
+#[derive(Clone)]
+pub struct TableHandle {
+    ipt: IPTables,
+    table: Table,
+    chains: Arc<Mutex<Option<Vec<String>>>>,
+}
+
+impl TableHandle {
+    /// Returns the table of the handle.
+    pub fn table(&self) -> &Table {
+        &self.table
+    }
+
+    /// Returns the names of the chains of the table, from the cache if it is filled.
+    pub fn chains(&self) -> IPTResult<Vec<String>> {
+        let mut chains = self.cache();
+        if chains.is_none() {
+            *chains = Some(self.ipt.list_chains(&self.table)?);
+        }
+        Ok(chains.clone().unwrap_or_default())
+    }
+
+    /// Reads the names of the chains of the table again.
+    pub fn refresh(&self) -> IPTResult<()> {
+        let listed = self.ipt.list_chains(&self.table)?;
+        *self.cache() = Some(listed);
+        Ok(())
+    }
+
+    /// Checks for the existence of the chain in the cached names of the chains.
+    pub fn chain_exists(&self, chain: &str) -> IPTResult<bool> {
+        Ok(self.chains()?.iter().any(|name| name == chain))
+    }
+
+    /// Creates a new user-defined chain.
+    pub fn new_chain(&self, chain: &str) -> IPTResult<()> {
+        let _serial = self.ipt.serial.lock();
+        self.ipt.new_chain(&self.table, chain)?;
+        self.update_cache(|chains| chains.push(chain.to_string()));
+        Ok(())
+    }
+
+    /// Creates a new user-defined chain if it does not exist yet.
+    pub fn ensure_chain(&self, chain: &str) -> IPTResult<()> {
+        let _serial = self.ipt.serial.lock();
+        self.ipt.ensure_chain(&self.table, chain)?;
+        self.update_cache(|chains| {
+            if !chains.iter().any(|name| name == chain) {
+                chains.push(chain.to_string());
+            }
+        });
+        Ok(())
+    }
+
+    /// Renames a chain.
+    pub fn rename_chain(&self, old_chain: &str, new_chain: &str) -> IPTResult<()> {
+        let _serial = self.ipt.serial.lock();
+        self.ipt.rename_chain(&self.table, old_chain, new_chain)?;
+        self.update_cache(|chains| {
+            if let Some(name) = chains.iter_mut().find(|name| *name == old_chain) {
+                *name = new_chain.to_string();
+            }
+        });
+        Ok(())
+    }
+
+    /// Deletes a user-defined chain.
+    pub fn delete_chain(&self, chain: &str) -> IPTResult<()> {
+        let _serial = self.ipt.serial.lock();
+        self.ipt.delete_chain(&self.table, chain)?;
+        self.update_cache(|chains| chains.retain(|name| name != chain));
+        Ok(())
+    }
+
+    /// Flushes (deletes all rules) a chain.
+    pub fn flush_chain(&self, chain: &str) -> IPTResult<()> {
+        self.ipt.flush_chain(&self.table, chain)
+    }
+
+    /// Flushes all chains of the table.
+    pub fn flush(&self) -> IPTResult<()> {
+        self.ipt.flush_table(&self.table)
+    }
+
+    /// Returns the policy of the built-in chain.
+    pub fn get_policy(&self, chain: &str) -> IPTResult<String> {
+        self.ipt.get_policy(&self.table, chain)
+    }
+
+    /// Sets the policy of the built-in chain.
+    pub fn set_policy(&self, chain: &str, policy: &str) -> IPTResult<()> {
+        self.ipt.set_policy(&self.table, chain, policy)
+    }
+
+    /// Lists the rules of the chain.
+    pub fn list(&self, chain: &str) -> IPTResult<Vec<String>> {
+        self.ipt.list(&self.table, chain)
+    }
+
+    /// Checks for the existence of `rule` in the chain.
+    pub fn exists(&self, chain: &str, rule: impl Into<RuleSpec>) -> IPTResult<bool> {
+        self.ipt.exists(&self.table, chain, rule)
+    }
+
+    /// Appends `rule` to the chain.
+    pub fn append(&self, chain: &str, rule: impl Into<RuleSpec>) -> IPTResult<()> {
+        self.ipt.append(&self.table, chain, rule)
+    }
+
+    /// Appends `rule` to the chain if it does not exist.
+    pub fn append_unique(&self, chain: &str, rule: impl Into<RuleSpec>) -> IPTResult<()> {
+        self.ipt.append_unique(&self.table, chain, rule)
+    }
+
+    /// Inserts `rule` in the `position` to the chain.
+    pub fn insert(&self, chain: &str, rule: impl Into<RuleSpec>, position: i32) -> IPTResult<()> {
+        self.ipt.insert(&self.table, chain, rule, position)
+    }
+
+    /// Inserts `rule` in the `position` to the chain if it does not exist.
+    pub fn insert_unique(
+        &self,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: i32,
+    ) -> IPTResult<()> {
+        self.ipt.insert_unique(&self.table, chain, rule, position)
+    }
+
+    /// Deletes `rule` from the chain.
+    pub fn delete(&self, chain: &str, rule: impl Into<RuleSpec>) -> IPTResult<()> {
+        self.ipt.delete(&self.table, chain, rule)
+    }
+
+    /// Checks for the existence of the structured `rule` in the chain.
+    pub fn exists_rule(&self, chain: &str, rule: &Rule) -> IPTResult<bool> {
+        self.ipt.exists_rule(&self.table, chain, rule)
+    }
+
+    /// Appends the structured `rule` to the chain.
+    pub fn append_rule(&self, chain: &str, rule: &Rule) -> IPTResult<()> {
+        self.ipt.append_rule(&self.table, chain, rule)
+    }
+
+    /// Inserts the structured `rule` in the `position` to the chain.
+    pub fn insert_rule(&self, chain: &str, rule: &Rule, position: i32) -> IPTResult<()> {
+        self.ipt.insert_rule(&self.table, chain, rule, position)
+    }
+
+    /// Deletes the structured `rule` from the chain.
+    pub fn delete_rule(&self, chain: &str, rule: &Rule) -> IPTResult<()> {
+        self.ipt.delete_rule(&self.table, chain, rule)
+    }
+
+    fn cache(&self) -> MutexGuard<'_, Option<Vec<String>>> {
+        self.chains.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Applies a change of the chains to the cache, unless it was not filled yet
+    fn update_cache<F: FnOnce(&mut Vec<String>)>(&self, update: F) {
+        if let Some(chains) = self.cache().as_mut() {
+            update(chains);
+        }
+    }
+}
+
+impl fmt::Debug for TableHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TableHandle")
+            .field("table", &self.table)
+            .field("chains", &*self.cache())
+            .finish()
+    }
+}
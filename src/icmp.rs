@@ -0,0 +1,207 @@
+//! Typed ICMP and ICMPv6 message types for `Rule::icmp_type`, and a helper allowing the ICMPv6
+//! messages IPv6 needs to function.
+
+use crate::rule::{HopLimit, Rule};
+use crate::{IPTResult, IPTables};
+use std::fmt;
+
+/// The type of an ICMP message of IPv4, matched with `-p icmp -m icmp --icmp-type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IcmpType {
+    /// Type 0, the answer to an `EchoRequest`.
+    EchoReply,
+    /// Type 3, a packet could not be delivered.
+    DestinationUnreachable,
+    /// Type 5, a better route is available.
+    Redirect,
+    /// Type 8, a ping.
+    EchoRequest,
+    /// Type 9, a router announces itself.
+    RouterAdvertisement,
+    /// Type 10, a host asks for routers.
+    RouterSolicitation,
+    /// Type 11, the TTL of a packet expired, e.g. answering a traceroute.
+    TimeExceeded,
+    /// Type 12, the header of a packet is invalid.
+    ParameterProblem,
+    /// Type 13, a request for the time of the receiver.
+    TimestampRequest,
+    /// Type 14, the answer to a `TimestampRequest`.
+    TimestampReply,
+    /// Any other type by its number.
+    Other(u8),
+}
+
+impl IcmpType {
+    /// Returns the number of the type.
+    pub fn number(&self) -> u8 {
+        match self {
+            IcmpType::EchoReply => 0,
+            IcmpType::DestinationUnreachable => 3,
+            IcmpType::Redirect => 5,
+            IcmpType::EchoRequest => 8,
+            IcmpType::RouterAdvertisement => 9,
+            IcmpType::RouterSolicitation => 10,
+            IcmpType::TimeExceeded => 11,
+            IcmpType::ParameterProblem => 12,
+            IcmpType::TimestampRequest => 13,
+            IcmpType::TimestampReply => 14,
+            IcmpType::Other(number) => *number,
+        }
+    }
+}
+
+/// The type of an ICMPv6 message, matched with `-p ipv6-icmp -m icmp6 --icmpv6-type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Icmpv6Type {
+    /// Type 1, a packet could not be delivered.
+    DestinationUnreachable,
+    /// Type 2, a packet is larger than the MTU of the path, needed for path MTU discovery.
+    PacketTooBig,
+    /// Type 3, the hop limit of a packet expired, e.g. answering a traceroute.
+    TimeExceeded,
+    /// Type 4, the header of a packet is invalid.
+    ParameterProblem,
+    /// Type 128, a ping.
+    EchoRequest,
+    /// Type 129, the answer to an `EchoRequest`.
+    EchoReply,
+    /// Type 130, a router asks for multicast listeners.
+    MulticastListenerQuery,
+    /// Type 131, a host reports a multicast group it listens to.
+    MulticastListenerReport,
+    /// Type 132, a host stops listening to a multicast group.
+    MulticastListenerDone,
+    /// Type 133, a host asks for routers.
+    RouterSolicitation,
+    /// Type 134, a router announces itself and the prefixes of the link.
+    RouterAdvertisement,
+    /// Type 135, a host resolves the link-layer address of a neighbor.
+    NeighborSolicitation,
+    /// Type 136, the answer to a `NeighborSolicitation`.
+    NeighborAdvertisement,
+    /// Type 137, a better route is available.
+    Redirect,
+    /// Type 143, a host reports the multicast groups it listens to (MLDv2).
+    MulticastListenerReportV2,
+    /// Any other type by its number.
+    Other(u8),
+}
+
+impl Icmpv6Type {
+    /// Returns the number of the type.
+    pub fn number(&self) -> u8 {
+        match self {
+            Icmpv6Type::DestinationUnreachable => 1,
+            Icmpv6Type::PacketTooBig => 2,
+            Icmpv6Type::TimeExceeded => 3,
+            Icmpv6Type::ParameterProblem => 4,
+            Icmpv6Type::EchoRequest => 128,
+            Icmpv6Type::EchoReply => 129,
+            Icmpv6Type::MulticastListenerQuery => 130,
+            Icmpv6Type::MulticastListenerReport => 131,
+            Icmpv6Type::MulticastListenerDone => 132,
+            Icmpv6Type::RouterSolicitation => 133,
+            Icmpv6Type::RouterAdvertisement => 134,
+            Icmpv6Type::NeighborSolicitation => 135,
+            Icmpv6Type::NeighborAdvertisement => 136,
+            Icmpv6Type::Redirect => 137,
+            Icmpv6Type::MulticastListenerReportV2 => 143,
+            Icmpv6Type::Other(number) => *number,
+        }
+    }
+}
+
+/// An ICMP message type of either family, accepted by `Rule::icmp_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Icmp {
+    /// An ICMP type of IPv4.
+    V4(IcmpType),
+    /// An ICMPv6 type.
+    V6(Icmpv6Type),
+}
+
+impl Icmp {
+    /// Returns the protocol, match module and option which match the type in its family.
+    pub(crate) fn match_args(&self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Icmp::V4(_) => ("icmp", "icmp", "--icmp-type"),
+            Icmp::V6(_) => ("ipv6-icmp", "icmp6", "--icmpv6-type"),
+        }
+    }
+}
+
+impl From<IcmpType> for Icmp {
+    fn from(icmp_type: IcmpType) -> Self {
+        Icmp::V4(icmp_type)
+    }
+}
+
+impl From<Icmpv6Type> for Icmp {
+    fn from(icmp_type: Icmpv6Type) -> Self {
+        Icmp::V6(icmp_type)
+    }
+}
+
+/// Renders the number of the type, the way `iptables -S` prints it.
+impl fmt::Display for Icmp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Icmp::V4(icmp_type) => write!(f, "{}", icmp_type.number()),
+            Icmp::V6(icmp_type) => write!(f, "{}", icmp_type.number()),
+        }
+    }
+}
+
+/// Builds the rules accepting the ICMPv6 messages IPv6 needs to function, following RFC 4890:
+/// error messages, echo requests and replies, and the neighbor discovery messages, which are
+/// only accepted with a hop limit of 255 so they cannot come from outside the link.
+pub fn essential_icmpv6_rules() -> Vec<Rule> {
+    let errors = [
+        Icmpv6Type::DestinationUnreachable,
+        Icmpv6Type::PacketTooBig,
+        Icmpv6Type::TimeExceeded,
+        Icmpv6Type::ParameterProblem,
+        Icmpv6Type::EchoRequest,
+        Icmpv6Type::EchoReply,
+    ];
+    let neighbor_discovery = [
+        Icmpv6Type::RouterSolicitation,
+        Icmpv6Type::RouterAdvertisement,
+        Icmpv6Type::NeighborSolicitation,
+        Icmpv6Type::NeighborAdvertisement,
+    ];
+
+    let mut rules = errors
+        .iter()
+        .map(|&icmp_type| Rule::new().icmp_type(icmp_type).jump("ACCEPT"))
+        .collect::<Vec<_>>();
+    rules.extend(neighbor_discovery.iter().map(|&icmp_type| {
+        Rule::new()
+            .icmp_type(icmp_type)
+            .hop_limit(HopLimit::Eq(255))
+            .jump("ACCEPT")
+    }));
+    rules
+}
+
+impl IPTables {
+    /// Appends `essential_icmpv6_rules` to the `chain` of the `filter` table, each unless it
+    /// already exists, so a restrictive IPv6 firewall does not break path MTU discovery and
+    /// neighbor discovery. Only valid for ip6tables.
+    pub fn allow_essential_icmpv6(&self, chain: &str) -> IPTResult<()> {
+        for rule in essential_icmpv6_rules() {
+            self.append_rule_if_missing("filter", chain, &rule)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the rules appended by `allow_essential_icmpv6` from the `chain` of the `filter`
+    /// table.
+    pub fn remove_essential_icmpv6(&self, chain: &str) -> IPTResult<()> {
+        for rule in essential_icmpv6_rules() {
+            self.delete_rule_if_present("filter", chain, &rule)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+//! Checks interface names for `-i` and `-o`, including the `+` wildcard, and finds the rules
+//! matching an interface for audits.
+
+use crate::validate::ValidationError;
+use crate::{IPTResult, IPTables, SplitQuoted, Table};
+
+/// The longest interface name accepted by the kernel, in bytes (`IFNAMSIZ` without the
+/// terminating zero).
+pub const MAX_INTERFACE_LEN: usize = 15;
+
+/// Checks the interface name given to `-i` or `-o`. The name must not be empty nor longer than
+/// 15 bytes, and must not contain whitespace or `/`. A trailing `+` is a wildcard matching every
+/// interface starting with the rest of the name, e.g. `eth+`, and is not allowed anywhere else.
+///
+/// # Example
+/// ```
+/// use iptables::interface::validate_interface;
+///
+/// assert!(validate_interface("eth0").is_ok());
+/// assert!(validate_interface("veth+").is_ok());
+/// assert!(validate_interface("br+0").is_err());
+/// assert!(validate_interface("a-very-long-interface").is_err());
+/// ```
+pub fn validate_interface(name: &str) -> Result<(), ValidationError> {
+    let invalid = |why: &str| {
+        Err(ValidationError::InvalidInterface(format!(
+            "interface {:?} {}",
+            name, why
+        )))
+    };
+    if name.is_empty() {
+        return invalid("is empty");
+    }
+    if name.len() > MAX_INTERFACE_LEN {
+        return invalid(&format!("is longer than {} bytes", MAX_INTERFACE_LEN));
+    }
+    if name.chars().any(|c| c.is_whitespace() || c == '/') {
+        return invalid("contains whitespace or /");
+    }
+    if name.strip_suffix('+').unwrap_or(name).contains('+') {
+        return invalid("has a + before its end");
+    }
+    Ok(())
+}
+
+/// Returns true if the `-i` or `-o` value `pattern` matches the interface `name`, either equal
+/// or, if `pattern` ends with the `+` wildcard, starting with the rest of it.
+///
+/// # Example
+/// ```
+/// use iptables::interface::interface_matches;
+///
+/// assert!(interface_matches("eth+", "eth0"));
+/// assert!(interface_matches("eth0", "eth0"));
+/// assert!(!interface_matches("eth0", "eth1"));
+/// ```
+pub fn interface_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('+') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+impl IPTables {
+    /// Lists the rules of the table, like `list_table`, whose `-i` or `-o` matches the
+    /// interface `name`, either exactly or through a wildcard like `eth+`. Negated interfaces
+    /// and rules without an interface, which also see the packets of `name`, are left out.
+    pub fn rules_for_interface(
+        &self,
+        table: impl Into<Table>,
+        name: &str,
+    ) -> IPTResult<Vec<String>> {
+        let lines = self.list_table(table)?;
+        Ok(lines
+            .into_iter()
+            .filter(|line| {
+                let args = line.split_quoted();
+                args.first().map(String::as_str) == Some("-A")
+                    && args.windows(2).enumerate().any(|(i, pair)| {
+                        matches!(pair[0].as_str(), "-i" | "-o")
+                            && (i == 0 || args[i - 1] != "!")
+                            && interface_matches(&pair[1], name)
+                    })
+            })
+            .collect())
+    }
+}
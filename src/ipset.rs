@@ -0,0 +1,110 @@
+//! Bindings for the [ipset](https://ipset.netfilter.org/) application, which manages the IP sets
+//! referenced by rules with `-m set --match-set` (see `Rule::match_set`).
+
+use crate::{
+    lines_from_output, output_to_exists, output_to_result, output_to_string, output_with_input,
+    IPTResult,
+};
+use std::ffi::OsStr;
+use std::process::{Command, Output};
+
+// Message printed by ipset if a tested entry is not in the set
+const MSG_NOT_IN_SET: &str = "is NOT in set";
+
+/// Contains the ipset command.
+pub struct IPSet {
+    /// The utility command, 'ipset' or a path to it.
+    pub cmd: String,
+}
+
+impl Default for IPSet {
+    fn default() -> Self {
+        Self {
+            cmd: "ipset".to_string(),
+        }
+    }
+}
+
+impl IPSet {
+    /// Creates a new `IPSet` using the 'ipset' command.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `IPSet` using the ipset binary at `path`.
+    pub fn with_path(path: &str) -> Self {
+        Self {
+            cmd: path.to_string(),
+        }
+    }
+
+    /// Creates the set `name` of the given `set_type`, e.g. `hash:ip` or `hash:net`.
+    /// `options` are passed as is, e.g. `family inet6 timeout 300`.
+    pub fn create(&self, name: &str, set_type: &str, options: &[&str]) -> IPTResult<()> {
+        self.run(&[&["create", name, set_type], options].concat())
+            .and_then(output_to_result)
+    }
+
+    /// Destroys the set `name`, which must not be referenced by any rule.
+    pub fn destroy(&self, name: &str) -> IPTResult<()> {
+        self.run(&["destroy", name]).and_then(output_to_result)
+    }
+
+    /// Removes all entries from the set `name`.
+    pub fn flush(&self, name: &str) -> IPTResult<()> {
+        self.run(&["flush", name]).and_then(output_to_result)
+    }
+
+    /// Adds `entry` to the set `name`.
+    pub fn add(&self, name: &str, entry: &str) -> IPTResult<()> {
+        self.run(&["add", name, entry]).and_then(output_to_result)
+    }
+
+    /// Adds all `entries` to the set `name` with a single `ipset restore` call.
+    /// Entries which are already in the set are ignored.
+    pub fn add_many(&self, name: &str, entries: &[&str]) -> IPTResult<()> {
+        let mut data = String::new();
+        for entry in entries {
+            data.push_str(&format!("add {} {}\n", name, entry));
+        }
+
+        let mut command = Command::new(&self.cmd);
+        command.args(["restore", "-exist"]);
+        output_to_result(output_with_input(command, &data)?)
+    }
+
+    /// Deletes `entry` from the set `name`.
+    pub fn del(&self, name: &str, entry: &str) -> IPTResult<()> {
+        self.run(&["del", name, entry]).and_then(output_to_result)
+    }
+
+    /// Checks if `entry` is in the set `name`.
+    pub fn test(&self, name: &str, entry: &str) -> IPTResult<bool> {
+        self.run(&["test", name, entry])
+            .and_then(|output| output_to_exists(output, MSG_NOT_IN_SET))
+    }
+
+    /// Lists the entries of the set `name`.
+    pub fn list(&self, name: &str) -> IPTResult<Vec<String>> {
+        let output = self.run(&["list", name]).and_then(output_to_string)?;
+        Ok(lines_from_output(&output)
+            .into_iter()
+            .skip_while(|line| line != "Members:")
+            .skip(1)
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+
+    /// Lists the names of all sets.
+    pub fn list_sets(&self) -> IPTResult<Vec<String>> {
+        let output = self.run(&["list", "-n"]).and_then(output_to_string)?;
+        Ok(lines_from_output(&output)
+            .into_iter()
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+
+    fn run<S: AsRef<OsStr>>(&self, args: &[S]) -> IPTResult<Output> {
+        Ok(Command::new(&self.cmd).args(args).output()?)
+    }
+}
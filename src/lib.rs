@@ -13,217 +13,815 @@
 //! assert!(ipt.delete("nat", "NEWCHAINNAME", "-j ACCEPT").is_ok());
 //! assert!(ipt.delete_chain("nat", "NEWCHAINNAME").is_ok());
 //! ```
+//!
+//! # Return values
+//! Methods which change the rules return `IPTResult<()>`, every failure of iptables is returned
+//! as `IPTError::CommandFailed` with the exit code and standard error.
+//! The `exists` family returns `Ok(false)` only if the rule, chain or table is missing, any other
+//! failure is returned as an error.
+//! `execute`, `execute_args` and `execute_table` return the raw `Output` of iptables, also if it
+//! failed, unless `IPTablesBuilder::strict` is set.
+//! `append_verbose`, `insert_verbose` and `delete_verbose` return an `output::OperationOutput`
+//! with the command line, exit code and output of a successful call, e.g. to log its warnings.
+//! The methods changing rules have never returned `bool`, so there is no bool-returning
+//! compatibility layer.
 
+#[cfg(feature = "tokio")]
+pub mod async_iptables;
+pub mod batch;
+pub mod builder;
+pub mod conntrack;
+pub mod container;
+pub mod counters;
+pub mod diff;
+pub mod dual_stack;
 pub mod error;
+pub mod graph;
+pub mod handle;
+pub mod icmp;
+pub mod interface;
+pub mod ipset;
+pub mod limit;
+pub mod list;
+pub mod lock;
+pub mod log;
+pub mod mangle;
+pub mod nat;
+pub mod net;
+pub mod options;
+pub mod output;
+pub mod parse;
+pub mod position;
+pub mod privileges;
+pub mod profiles;
+pub mod protection;
+pub mod queue;
+pub mod retry;
+pub mod rule;
+pub mod ruleset;
+pub mod runner;
+pub mod sanitize;
+pub mod snapshot;
+pub mod stats;
+pub mod table;
+pub mod target;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod tproxy;
+mod trace;
+pub mod validate;
+pub mod version;
+pub mod watch;
+pub mod writer;
 
+use batch::{check_line_breaks, Batch};
+use builder::{Capabilities, IPTablesBuilder, LazyDetection};
+use counters::RuleCounters;
 use error::IptablesError;
-use lazy_static::lazy_static;
-use nix::fcntl::{flock, FlockArg};
-use regex::{Match, Regex};
+use list::{parse_listing, ChainInfo, ListOptions, ListedChain};
+use lock::{LockPolicy, LockRetry, SerialLock};
+use parse::{canonical_rule, ParsedRule};
+use regex::Regex;
+use retry::{Retry, RetryPolicy};
+use rule::join_quoted;
+use runner::{command_line, run_with_timeout, CommandRunner, SystemRunner};
+use sanitize::Sanitizer;
+use std::collections::{HashMap, HashSet};
 use std::convert::From;
-use std::error::Error;
 use std::ffi::OsStr;
-use std::fs::File;
-use std::os::unix::io::AsRawFd;
-use std::process::{Command, Output};
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 
+#[cfg(feature = "tokio")]
+pub use async_iptables::AsyncIPTables;
+pub use dual_stack::DualStack;
+pub use error::IPTError;
+pub use options::CallOptions;
+pub use rule::{Rule, RuleSpec};
+pub use table::{Chain, Table};
+pub use version::Version;
+
+/// The result of all operations of this crate.
+pub type IPTResult<T> = Result<T, IPTError>;
+
+// List of tables taken from: man 8 iptables
+const TABLES: &[&str] = &["filter", "nat", "mangle", "raw", "security"];
+
 // List of built-in chains taken from: man 8 iptables
-const BUILTIN_CHAINS_FILTER: &'static [&'static str] = &["INPUT", "FORWARD", "OUTPUT"];
-const BUILTIN_CHAINS_MANGLE: &'static [&'static str] =
-    &["PREROUTING", "OUTPUT", "INPUT", "FORWARD", "POSTROUTING"];
-const BUILTIN_CHAINS_NAT: &'static [&'static str] = &["PREROUTING", "POSTROUTING", "OUTPUT"];
-const BUILTIN_CHAINS_RAW: &'static [&'static str] = &["PREROUTING", "OUTPUT"];
-const BUILTIN_CHAINS_SECURITY: &'static [&'static str] = &["INPUT", "OUTPUT", "FORWARD"];
-
-lazy_static! {
-    static ref RE_SPLIT: Regex = Regex::new(r#"["'].+?["']|[^ ]+"#).unwrap();
-}
+const BUILTIN_CHAINS_FILTER: &[&str] = &["INPUT", "FORWARD", "OUTPUT"];
+const BUILTIN_CHAINS_MANGLE: &[&str] = &["PREROUTING", "OUTPUT", "INPUT", "FORWARD", "POSTROUTING"];
+const BUILTIN_CHAINS_NAT: &[&str] = &["PREROUTING", "POSTROUTING", "OUTPUT"];
+const BUILTIN_CHAINS_RAW: &[&str] = &["PREROUTING", "OUTPUT"];
+const BUILTIN_CHAINS_SECURITY: &[&str] = &["INPUT", "OUTPUT", "FORWARD"];
+
+// Messages printed by iptables if a checked rule, a listed chain or a table does not exist
+const MSG_BAD_RULE: &str = "Bad rule";
+const MSG_NO_CHAIN: &str = "No chain/target/match by that name";
+const MSG_NO_TABLE: &str = "Table does not exist";
+
+// Message printed by iptables if a created chain already exists
+const MSG_CHAIN_EXISTS: &str = "Chain already exists";
 
 trait SplitQuoted {
-    fn split_quoted(&self) -> Vec<&str>;
+    fn split_quoted(&self) -> Vec<String>;
 }
 
 impl SplitQuoted for str {
-    fn split_quoted(&self) -> Vec<&str> {
-        RE_SPLIT
-            // Iterate over matched segments
-            .find_iter(self)
-            // Get match as str
-            .map(|m| Match::as_str(&m))
-            // Remove any surrounding quotes (they will be reinserted by `Command`)
-            .map(|s| s.trim_matches(|c| c == '"' || c == '\''))
-            // Collect
-            .collect::<Vec<_>>()
+    /// Splits the string into arguments like a POSIX shell would, i.e. whitespace inside single
+    /// or double quotes and whitespace escaped with a backslash does not separate arguments.
+    /// The quotes themselves are removed (they will be reinserted by `Command`).
+    fn split_quoted(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut arg = String::new();
+        // Tracks if an argument was started, to keep empty quoted arguments like `""`
+        let mut in_arg = false;
+        let mut chars = self.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\'' => {
+                    in_arg = true;
+                    arg.extend(chars.by_ref().take_while(|&c| c != '\''));
+                }
+                '"' => {
+                    in_arg = true;
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '"' => break,
+                            '\\' => match chars.next() {
+                                Some(escaped @ '"') | Some(escaped @ '\\') => arg.push(escaped),
+                                Some(other) => {
+                                    arg.push('\\');
+                                    arg.push(other);
+                                }
+                                None => arg.push('\\'),
+                            },
+                            _ => arg.push(c),
+                        }
+                    }
+                }
+                '\\' => {
+                    in_arg = true;
+                    if let Some(escaped) = chars.next() {
+                        arg.push(escaped);
+                    }
+                }
+                c if c.is_whitespace() => {
+                    if in_arg {
+                        args.push(std::mem::take(&mut arg));
+                        in_arg = false;
+                    }
+                }
+                _ => {
+                    in_arg = true;
+                    arg.push(c);
+                }
+            }
+        }
+
+        if in_arg {
+            args.push(arg);
+        }
+        args
+    }
+}
+
+// Splits the `[packets:bytes]` prefix of a rule given to `restore_table` from the rule
+fn split_counters(rule: &str) -> (&str, &str) {
+    let rule = rule.trim_start();
+    match rule.find(']').filter(|_| rule.starts_with('[')) {
+        Some(end) => (&rule[..=end], &rule[end + 1..]),
+        None => ("", rule),
     }
 }
 
-fn error_from_str(msg: &str) -> Box<dyn Error> {
-    msg.into()
+fn as_strs(args: &[String]) -> Vec<&str> {
+    args.iter().map(String::as_str).collect()
 }
 
-fn output_to_result(output: Output) -> Result<(), Box<dyn Error>> {
+fn output_to_result(output: Output) -> IPTResult<()> {
     if !output.status.success() {
-        return Err(Box::new(IptablesError::from(output)));
+        return Err(IptablesError::from(output).into());
     }
     Ok(())
 }
 
-fn get_builtin_chains(table: &str) -> Result<&[&str], Box<dyn Error>> {
+fn output_to_string(output: Output) -> IPTResult<String> {
+    if !output.status.success() {
+        return Err(IptablesError::from(output).into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+// iptables prints `message` if the checked rule, chain or table is missing,
+// any other failure is reported as an error to the caller
+fn output_to_exists(output: Output, message: &str) -> IPTResult<bool> {
+    if output.status.success() {
+        return Ok(true);
+    }
+    if String::from_utf8_lossy(output.stderr.as_slice()).contains(message) {
+        return Ok(false);
+    }
+    Err(IptablesError::from(output).into())
+}
+
+// Creating an existing chain is not an error when it only has to be ensured that it exists
+fn output_to_ensured(output: Output) -> IPTResult<()> {
+    output_to_exists(output, MSG_CHAIN_EXISTS).map(|_| ())
+}
+
+// Runs `command` with `data` written to its standard input
+fn output_with_input(mut command: Command, data: &str) -> IPTResult<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // The handle is dropped at the end of the block, closing stdin so the child can exit
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(data.as_bytes())?;
+    }
+    Ok(child.wait_with_output()?)
+}
+
+fn check_builtin_chain(table: &str, chain: &str, action: &str) -> IPTResult<()> {
+    let builtin_chains = get_builtin_chains(table)?;
+    if !builtin_chains.iter().as_slice().contains(&chain) {
+        return Err(IPTError::Other(format!(
+            "given chain is not a default chain in the given table, {}",
+            action
+        )));
+    }
+    Ok(())
+}
+
+fn policy_from_output(output: &str, chain: &str) -> IPTResult<String> {
+    for item in output.trim().split('\n') {
+        let fields = item.split(' ').collect::<Vec<&str>>();
+        if fields.len() > 2 && fields[0] == "-P" && fields[1] == chain {
+            return Ok(fields[2].to_string());
+        }
+    }
+    Err(IPTError::NotFound(
+        "could not find the default policy for table and chain".to_string(),
+    ))
+}
+
+fn chains_from_output(output: &str) -> Vec<String> {
+    let mut list = Vec::new();
+    for item in output.trim().split('\n') {
+        let fields = item.split(' ').collect::<Vec<&str>>();
+        if fields.len() > 1 && (fields[0] == "-P" || fields[0] == "-N") {
+            list.push(fields[1].to_string());
+        }
+    }
+    list
+}
+
+// Maps the line reported by iptables-restore for the input of `append_many` to the failed rule,
+// the first line declares the table
+fn rule_failed(err: IPTError, rules: usize) -> IPTError {
+    let line = match &err {
+        IPTError::CommandFailed(failed) => Regex::new(r"line:? (\d+)")
+            .ok()
+            .and_then(|re| re.captures(&failed.msg))
+            .and_then(|captures| captures[1].parse::<usize>().ok()),
+        _ => None,
+    };
+    match line {
+        Some(line) if line >= 2 && line - 2 < rules => IPTError::RuleFailed {
+            index: line - 2,
+            error: Box::new(err),
+        },
+        _ => err,
+    }
+}
+
+// Checks if a rule of `chain` in `lines` has the `canonical` form
+fn rule_in_listing(lines: &[String], chain: &str, canonical: &str) -> bool {
+    canonical_listing(lines, chain)
+        .iter()
+        .any(|rule| rule == canonical)
+}
+
+// Returns the canonical form of each rule of `chain` in `lines`, skipping unparsable rules
+fn canonical_listing(lines: &[String], chain: &str) -> Vec<String> {
+    let prefix = format!("-A {} ", chain);
+    lines
+        .iter()
+        .filter_map(|line| line.strip_prefix(&prefix))
+        .filter_map(|rule| canonical_rule(rule).ok())
+        .collect()
+}
+
+// Renders the restore input deleting all but the first copy of each rule in `numbered`, from
+// the last position to the first so the positions stay valid, and counts the deleted rules
+fn dedupe_data(table: &str, chain: &str, numbered: &[(u32, String)]) -> (String, usize) {
+    let mut seen = HashSet::new();
+    let duplicates = numbered
+        .iter()
+        .filter(|(_, rule)| !seen.insert(rule.as_str()))
+        .map(|(position, _)| *position)
+        .collect::<Vec<_>>();
+    let mut data = format!("*{}\n", table);
+    for position in duplicates.iter().rev() {
+        data.push_str(&format!("-D {} {}\n", chain, position));
+    }
+    data.push_str("COMMIT\n");
+    (data, duplicates.len())
+}
+
+fn numbered_from_lines(lines: &[String], chain: &str) -> Vec<(u32, String)> {
+    let prefix = format!("-A {} ", chain);
+    lines
+        .iter()
+        .filter_map(|line| line.strip_prefix(&prefix))
+        .enumerate()
+        .map(|(index, rule)| (index as u32 + 1, rule.to_string()))
+        .collect()
+}
+
+fn has_comment(args: &[String], tag: &str) -> bool {
+    args.windows(2)
+        .any(|pair| pair[0] == "--comment" && pair[1] == tag)
+}
+
+fn jumps_to(args: &[String], chain: &str) -> bool {
+    args.windows(2).any(|pair| {
+        ["-j", "--jump", "-g", "--goto"].contains(&pair[0].as_str()) && pair[1] == chain
+    })
+}
+
+// Returns the chain and position of each rule in `lines` jumping to `chain`
+fn references_from_lines(lines: &[String], chain: &str) -> Vec<(String, u32)> {
+    let mut positions: HashMap<String, u32> = HashMap::new();
+    let mut references = Vec::new();
+    for args in lines.iter().map(|line| line.split_quoted()) {
+        if args.len() < 2 || args[0] != "-A" {
+            continue;
+        }
+        let position = positions.entry(args[1].clone()).or_insert(0);
+        *position += 1;
+        if jumps_to(&args[2..], chain) {
+            references.push((args[1].clone(), *position));
+        }
+    }
+    references
+}
+
+// Returns the specification of each `-A` rule in `lines` which is accepted by `filter`
+fn rule_specs<F: Fn(&[String]) -> bool>(lines: &[String], filter: F) -> Vec<Vec<String>> {
+    lines
+        .iter()
+        .map(|line| line.split_quoted())
+        .filter(|args| args.len() > 2 && args[0] == "-A" && filter(args))
+        .map(|mut args| args.split_off(2))
+        .collect()
+}
+
+fn lines_from_output(output: &str) -> Vec<String> {
+    output.trim().split('\n').map(String::from).collect()
+}
+
+fn get_builtin_chains(table: &str) -> IPTResult<&[&str]> {
     match table {
         "filter" => Ok(BUILTIN_CHAINS_FILTER),
         "mangle" => Ok(BUILTIN_CHAINS_MANGLE),
         "nat" => Ok(BUILTIN_CHAINS_NAT),
         "raw" => Ok(BUILTIN_CHAINS_RAW),
         "security" => Ok(BUILTIN_CHAINS_SECURITY),
-        _ => Err(error_from_str("given table is not supported by iptables")),
+        _ => Err(IPTError::NotFound(
+            "given table is not supported by iptables".to_string(),
+        )),
     }
 }
 
+/// The netfilter backend used by the iptables binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Uses whatever `iptables`/`ip6tables` is installed and detects its backend.
+    Auto,
+    /// The nf_tables based `iptables-nft`/`ip6tables-nft`.
+    Nft,
+    /// The legacy x_tables based `iptables-legacy`/`ip6tables-legacy`.
+    Legacy,
+}
+
 /// Contains the iptables command and shows if it supports -w and -C options.
-/// Use `new` method to create a new instance of this struct.
+/// Use `new` method or `IPTables::builder` to create a new instance of this struct.
+///
+/// The instance can be cloned cheaply to share it between threads. The operations of an instance
+/// and all its clones are serialized, including operations made of several commands like
+/// `append_unique`, so threads of the same process cannot interleave them.
+#[derive(Clone)]
 pub struct IPTables {
-    /// The utility command which must be 'iptables' or 'ip6tables'.
-    pub cmd: &'static str,
+    /// The utility command, e.g. 'iptables', 'ip6tables' or a path like '/usr/sbin/iptables-legacy'.
+    pub cmd: String,
+
+    /// Indicates if the utility command manages IPv6 rules.
+    pub is_ipv6: bool,
+
+    /// The backend used by the utility command, never `Backend::Auto`.
+    pub backend: Backend,
+
+    /// The version of the utility command.
+    pub version: Version,
 
     /// Indicates if iptables has -C (--check) option
     pub has_check: bool,
 
     /// Indicates if iptables has -w (--wait) option
     pub has_wait: bool,
-}
 
-/// Returns `None` because iptables only works on linux
-#[cfg(not(target_os = "linux"))]
-pub fn new(is_ipv6: bool) -> Result<IPTables, Box<dyn Error>> {
-    Err(IPTError::Other("iptables only works on Linux"))
+    /// Indicates if the -w (--wait) option of iptables accepts a timeout in seconds
+    pub has_wait_timeout: bool,
+
+    /// Indicates if iptables has -W (--wait-interval) option
+    pub has_wait_interval: bool,
+
+    /// The maximum time to wait for the xtables lock, waits forever if `None`.
+    /// Rounded up to whole seconds and ignored if `has_wait_timeout` is false.
+    pub wait_timeout: Option<Duration>,
+
+    /// The interval to poll the xtables lock while waiting, ignored if `has_wait_interval` is false.
+    pub wait_interval: Option<Duration>,
+
+    /// The longest a spawned command may run before it is killed, see
+    /// `IPTablesBuilder::command_timeout`.
+    pub command_timeout: Option<Duration>,
+
+    /// Controls how the lock is retried if iptables does not have -w (--wait) option
+    pub lock_policy: LockPolicy,
+
+    /// The comment added to every rule created, checked or deleted by this instance, so the
+    /// rules of an application can be found with `list_by_comment` and `delete_by_comment`.
+    pub owner_tag: Option<String>,
+
+    /// Records the commands instead of running them, see `IPTablesBuilder::dry_run`.
+    pub dry_run: bool,
+
+    /// Checks the chain again after `insert_unique` and `append_unique` to remove copies added
+    /// concurrently by other processes, see `IPTablesBuilder::race_safe_unique`.
+    pub race_safe_unique: bool,
+
+    /// Returns an error from the `execute*` methods if iptables fails, instead of the output of
+    /// the failed command, see `IPTablesBuilder::strict`.
+    pub strict: bool,
+
+    /// The network namespace the commands run in, see `IPTablesBuilder::netns`.
+    pub netns: Option<String>,
+
+    /// The network namespace entered with setns(2) before running the commands, see
+    /// `IPTablesBuilder::netns_path`.
+    pub netns_path: Option<PathBuf>,
+
+    /// The environment variables set for every command, see `IPTablesBuilder::env`.
+    pub env: Vec<(String, String)>,
+
+    /// Runs the commands with an empty environment, see `IPTablesBuilder::env_clear`.
+    pub env_clear: bool,
+
+    /// The arguments passed to every iptables command, see `IPTablesBuilder::global_arg`.
+    pub global_args: Vec<String>,
+
+    /// The program loading kernel modules, see `IPTablesBuilder::modprobe`.
+    pub modprobe: Option<String>,
+
+    /// The command every command is run through, e.g. `sudo -n`, see
+    /// `IPTablesBuilder::command_prefix`.
+    pub command_prefix: Vec<String>,
+
+    /// Controls how commands which failed for a transient reason are retried, see `RetryPolicy`.
+    pub retry_policy: RetryPolicy,
+
+    /// Checks the rules passed to `append` and `insert` before running iptables, see
+    /// `IPTablesBuilder::validate_rules`.
+    pub validate_rules: bool,
+
+    // The command lines recorded in dry-run mode
+    recorded: Arc<Mutex<Vec<Vec<String>>>>,
+
+    // Serializes the operations of the instance and its clones
+    serial: Arc<SerialLock>,
+
+    // Detects the version on first use, see `IPTablesBuilder::lazy`
+    lazy: Option<Arc<LazyDetection>>,
+
+    // Checks the rules of every command, see `IPTablesBuilder::sanitizer`
+    sanitizer: Option<Arc<Sanitizer>>,
+
+    // Runs the commands instead of spawning them directly, see `IPTablesBuilder::runner`
+    runner: Option<Arc<dyn CommandRunner>>,
 }
 
 /// Creates a new `IPTables` Result with the command of 'iptables' if `is_ipv6` is `false`, otherwise the command is 'ip6tables'.
-#[cfg(target_os = "linux")]
-pub fn new(is_ipv6: bool) -> Result<IPTables, Box<dyn Error>> {
-    let cmd = if is_ipv6 { "ip6tables" } else { "iptables" };
-
-    let version_output = Command::new(cmd).arg("--version").output()?;
-    let re = Regex::new(r"v(\d+)\.(\d+)\.(\d+)")?;
-    let version_string = String::from_utf8_lossy(version_output.stdout.as_slice());
-    let versions = re
-        .captures(&version_string)
-        .ok_or("invalid version number")?;
-    let v_major = versions
-        .get(1)
-        .ok_or("unable to get major version number")?
-        .as_str()
-        .parse::<i32>()?;
-    let v_minor = versions
-        .get(2)
-        .ok_or("unable to get minor version number")?
-        .as_str()
-        .parse::<i32>()?;
-    let v_patch = versions
-        .get(3)
-        .ok_or("unable to get patch version number")?
-        .as_str()
-        .parse::<i32>()?;
-
-    Ok(IPTables {
-        cmd: cmd,
-        has_check: (v_major > 1)
-            || (v_major == 1 && v_minor > 4)
-            || (v_major == 1 && v_minor == 4 && v_patch > 10),
-        has_wait: (v_major > 1)
-            || (v_major == 1 && v_minor > 4)
-            || (v_major == 1 && v_minor == 4 && v_patch > 19),
-    })
+pub fn new(is_ipv6: bool) -> IPTResult<IPTables> {
+    new_with_backend(is_ipv6, Backend::Auto)
+}
+
+/// Creates a new `IPTables` Result like `new`, but detects the version of the binary on first
+/// use instead of now, see `IPTablesBuilder::lazy`.
+pub fn new_lazy(is_ipv6: bool) -> IPTResult<IPTables> {
+    IPTables::builder().ipv6(is_ipv6).lazy(true).build()
+}
+
+/// Creates a new `IPTables` Result using the variant of 'iptables' (or 'ip6tables' if `is_ipv6` is `true`)
+/// for the given `backend`. `Backend::Auto` uses the default variant and detects its backend.
+pub fn new_with_backend(is_ipv6: bool, backend: Backend) -> IPTResult<IPTables> {
+    IPTables::builder().ipv6(is_ipv6).backend(backend).build()
 }
 
 impl IPTables {
-    /// Get the default policy for a table/chain.
-    pub fn get_policy(&self, table: &str, chain: &str) -> Result<String, Box<dyn Error>> {
-        let builtin_chains = get_builtin_chains(table)?;
-        if !builtin_chains.iter().as_slice().contains(&chain) {
-            return Err(error_from_str(
-                "given chain is not a default chain in the given table, can't get policy",
-            ));
+    /// Returns a builder to create an `IPTables` instance with custom options.
+    pub fn builder() -> IPTablesBuilder {
+        IPTablesBuilder::default()
+    }
+
+    /// Returns true if -C (--check) option is used to check for rules.
+    pub fn supports_check(&self) -> bool {
+        self.capabilities().has_check
+    }
+
+    /// Returns true if -w (--wait) option is used to wait for the xtables lock.
+    pub fn supports_wait(&self) -> bool {
+        self.capabilities().has_wait
+    }
+
+    /// Returns true if iptables-restore supports -w (--wait) option.
+    pub fn supports_restore_wait(&self) -> bool {
+        self.capabilities().version.has_restore_wait()
+    }
+
+    /// Returns the version of the binary. An instance built with `IPTablesBuilder::lazy` detects
+    /// it now unless a previous command did, so failures to run the binary surface here.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let ipt = iptables::IPTables::builder().lazy(true).build().unwrap();
+    /// match ipt.detect() {
+    ///     Ok(version) => println!("using iptables {}", version),
+    ///     Err(err) => println!("iptables is not available: {}", err),
+    /// }
+    /// ```
+    pub fn detect(&self) -> IPTResult<Version> {
+        match &self.lazy {
+            Some(lazy) => self
+                .detect_lazily(lazy)
+                .map(|capabilities| capabilities.version),
+            None => Ok(self.version),
         }
+    }
 
-        let stdout = self.run(&["-t", table, "-L", chain])?.stdout;
-        let output = String::from_utf8_lossy(stdout.as_slice());
-        for item in output.trim().split('\n') {
-            let fields = item.split(' ').collect::<Vec<&str>>();
-            if fields.len() > 1 && fields[0] == "Chain" && fields[1] == chain {
-                return Ok(fields[3].replace(")", ""));
-            }
+    // Returns the version dependent properties, detecting them first in lazy mode. If the
+    // detection fails the latest version is assumed, the command about to run reports the failure
+    pub(crate) fn capabilities(&self) -> Capabilities {
+        let fields = Capabilities {
+            backend: self.backend,
+            version: self.version,
+            has_check: self.has_check,
+            has_wait: self.has_wait,
+            has_wait_timeout: self.has_wait_timeout,
+            has_wait_interval: self.has_wait_interval,
+        };
+        match &self.lazy {
+            Some(lazy) => self.detect_lazily(lazy).unwrap_or(fields),
+            None => fields,
         }
-        Err(error_from_str(
-            "could not find the default policy for table and chain",
-        ))
     }
 
-    /// Set the default policy for a table/chain.
-    pub fn set_policy(&self, table: &str, chain: &str, policy: &str) -> Result<(), Box<dyn Error>> {
-        let builtin_chains = get_builtin_chains(table)?;
-        if !builtin_chains.iter().as_slice().contains(&chain) {
-            return Err(error_from_str(
-                "given chain is not a default chain in the given table, can't set policy",
-            ));
+    fn detect_lazily(&self, lazy: &LazyDetection) -> IPTResult<Capabilities> {
+        // Detecting runs a command, which takes the serial lock, so it is taken first
+        let _serial = self.serial.lock();
+        let mut detected = lazy
+            .detected
+            .lock()
+            .map_err(|_| IPTError::Other("the version detection panicked".to_string()))?;
+        if let Some(capabilities) = *detected {
+            return Ok(capabilities);
         }
+        let capabilities = builder::detect(self, lazy.requested)?;
+        *detected = Some(capabilities);
+        Ok(capabilities)
+    }
+
+    /// Get the default policy for a table/chain.
+    pub fn get_policy(&self, table: impl Into<Table>, chain: &str) -> IPTResult<String> {
+        let table: &str = &table.into();
+        check_builtin_chain(table, chain, "can't get policy")?;
+
+        self.run(&["-t", table, "-S", chain])
+            .and_then(output_to_string)
+            .and_then(|output| policy_from_output(&output, chain))
+    }
+
+    /// Set the default policy for a table/chain.
+    pub fn set_policy(&self, table: impl Into<Table>, chain: &str, policy: &str) -> IPTResult<()> {
+        let table: &str = &table.into();
+        check_builtin_chain(table, chain, "can't set policy")?;
 
         self.run(&["-t", table, "-P", chain, policy])
             .and_then(output_to_result)
     }
 
     /// Executes a given `command` on the chain.
-    /// Returns the command output if successful.
-    pub fn execute(&self, table: &str, command: &str) -> Result<Output, Box<dyn Error>> {
-        self.run(&[&["-t", table], command.split_quoted().as_slice()].concat())
+    /// Returns the command output, also if iptables failed unless `strict` is set.
+    pub fn execute(&self, table: impl Into<Table>, command: &str) -> IPTResult<Output> {
+        let table: &str = &table.into();
+        self.run(&[&["-t", table], as_strs(&command.split_quoted()).as_slice()].concat())
+            .and_then(|output| self.checked(output))
+    }
+
+    /// Runs iptables with `args` as is, e.g. to use options which are not wrapped by this crate.
+    /// The -w (--wait) option or the lock is added like for every other command.
+    /// Returns the command output, also if iptables failed unless `strict` is set.
+    pub fn execute_args(&self, args: &[&str]) -> IPTResult<Output> {
+        self.run(args).and_then(|output| self.checked(output))
+    }
+
+    /// Runs iptables with `args` as is in the table.
+    /// Returns the command output, also if iptables failed unless `strict` is set.
+    pub fn execute_table(&self, table: impl Into<Table>, args: &[&str]) -> IPTResult<Output> {
+        self.execute_table_with(table, args, &CallOptions::default())
+    }
+
+    /// Runs iptables with `args` as is in the table with the given `options`.
+    /// Returns the command output, also if iptables failed unless `strict` is set.
+    pub fn execute_table_with(
+        &self,
+        table: impl Into<Table>,
+        args: &[&str],
+        options: &CallOptions,
+    ) -> IPTResult<Output> {
+        let table: &str = &table.into();
+        self.run_with(&[&["-t", table], args].concat(), options)
+            .and_then(|output| self.checked(output))
+    }
+
+    // Turns the output of a failed command into an error in strict mode
+    fn checked(&self, output: Output) -> IPTResult<Output> {
+        if self.strict && !output.status.success() {
+            return Err(IptablesError::from(output).into());
+        }
+        Ok(output)
     }
 
     /// Checks for the existence of the `rule` in the table/chain.
-    /// Returns true if the rule exists.
+    /// Returns true if the rule exists, failures other than a missing rule are returned as errors.
     #[cfg(target_os = "linux")]
-    pub fn exists(&self, table: &str, chain: &str, rule: &str) -> Result<bool, Box<dyn Error>> {
-        if !self.has_check {
-            return self.exists_old_version(table, chain, rule);
+    pub fn exists(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<bool> {
+        self.exists_with(table, chain, rule, &CallOptions::default())
+    }
+
+    /// Checks for the existence of the `rule` in the table/chain with the given `options`.
+    #[cfg(target_os = "linux")]
+    pub fn exists_with(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        options: &CallOptions,
+    ) -> IPTResult<bool> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        let args = self.tagged(rule.into_args());
+        if !self.capabilities().has_check {
+            return self.exists_old_version(table, chain, &args, options);
         }
 
-        self.run(&[&["-t", table, "-C", chain], rule.split_quoted().as_slice()].concat())
-            .map(|output| output.status.success())
+        self.run_with(
+            &[&["-t", table, "-C", chain], as_strs(&args).as_slice()].concat(),
+            options,
+        )
+        .and_then(|output| output_to_exists(output, MSG_BAD_RULE))
+    }
+
+    /// Checks for the existence of the `rule` in the table/chain like `exists`, but compares the
+    /// canonical form of the rule with the canonical form of each listed rule, so rules which
+    /// differ cosmetically are found, e.g. `-s 10.0.0.1 --dport 22 -p tcp` and
+    /// `-s 10.0.0.1/32 -p tcp -m tcp --dport 22`. See `parse::canonical_rule`.
+    pub fn exists_normalized(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<bool> {
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        let expected = canonical_rule(&join_quoted(&self.tagged(rule.into_args())))?;
+        Ok(rule_in_listing(&self.list(table, chain)?, chain, &expected))
+    }
+
+    /// Checks for the existence of each of `rules` in the table/chain like `exists_normalized`,
+    /// but lists the chain only once, so checking many rules runs a single iptables command.
+    /// The result has one entry per rule, in the given order.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let ipt = iptables::new(false).unwrap();
+    /// let rules = ["-p tcp --dport 22 -j ACCEPT", "-p tcp --dport 80 -j ACCEPT"];
+    /// let missing = ipt
+    ///     .exists_many("filter", "INPUT", &rules)
+    ///     .unwrap()
+    ///     .iter()
+    ///     .filter(|exists| !**exists)
+    ///     .count();
+    /// ```
+    pub fn exists_many<R: Clone + Into<RuleSpec>>(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rules: &[R],
+    ) -> IPTResult<Vec<bool>> {
+        // The rules are checked before the chain is listed
+        let expected = rules
+            .iter()
+            .map(|rule| {
+                let rule: RuleSpec = rule.clone().into();
+                self.sanitize(rule.args())?;
+                canonical_rule(&join_quoted(&self.tagged(rule.into_args())))
+            })
+            .collect::<IPTResult<Vec<_>>>()?;
+        let listing = canonical_listing(&self.list(table, chain)?, chain);
+        Ok(expected
+            .iter()
+            .map(|expected| listing.contains(expected))
+            .collect())
     }
 
     /// Checks for the existence of the `chain` in the table.
-    /// Returns true if the chain exists.
+    /// Returns true if the chain exists, failures other than a missing chain are returned as errors.
     #[cfg(target_os = "linux")]
-    pub fn chain_exists(&self, table: &str, chain: &str) -> Result<bool, Box<dyn Error>> {
-        self.run(&["-t", table, "-L", chain])
-            .map(|output| output.status.success())
+    pub fn chain_exists(&self, table: impl Into<Table>, chain: &str) -> IPTResult<bool> {
+        let table: &str = &table.into();
+        self.run(&["-t", table, "-S", chain])
+            .and_then(|output| output_to_exists(output, MSG_NO_CHAIN))
+    }
+
+    /// Checks if the `table` is available, e.g. `nat` may be missing on stripped down kernels.
+    /// Returns true if the table exists, other failures are returned as errors.
+    #[cfg(target_os = "linux")]
+    pub fn table_exists(&self, table: impl Into<Table>) -> IPTResult<bool> {
+        let table: &str = &table.into();
+        self.run(&["-t", table, "-S"])
+            .and_then(|output| output_to_exists(output, MSG_NO_TABLE))
     }
 
     fn exists_old_version(
         &self,
         table: &str,
         chain: &str,
-        rule: &str,
-    ) -> Result<bool, Box<dyn Error>> {
-        self.run(&["-t", table, "-S"]).map(|output| {
-            String::from_utf8_lossy(&output.stdout).contains(&format!("-A {} {}", chain, rule))
-        })
+        args: &[String],
+        options: &CallOptions,
+    ) -> IPTResult<bool> {
+        self.run_with(&["-t", table, "-S"], options)
+            .and_then(output_to_string)
+            .map(|output| output.contains(&format!("-A {} {}", chain, join_quoted(args))))
     }
 
     /// Inserts `rule` in the `position` to the table/chain.
     pub fn insert(
         &self,
-        table: &str,
+        table: impl Into<Table>,
         chain: &str,
-        rule: &str,
+        rule: impl Into<RuleSpec>,
         position: i32,
-    ) -> Result<(), Box<dyn Error>> {
-        self.run(
+    ) -> IPTResult<()> {
+        self.insert_with(table, chain, rule, position, &CallOptions::default())
+    }
+
+    /// Inserts `rule` in the `position` to the table/chain with the given `options`.
+    pub fn insert_with(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: i32,
+        options: &CallOptions,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        if self.validate_rules {
+            rule.validate(self.is_ipv6)?;
+        }
+        self.run_with(
             &[
                 &["-t", table, "-I", chain, &position.to_string()],
-                rule.split_quoted().as_slice(),
+                as_strs(&self.tagged(rule.into_args())).as_slice(),
             ]
             .concat(),
+            options,
         )
         .and_then(output_to_result)
     }
@@ -231,30 +829,31 @@ impl IPTables {
     /// Inserts `rule` in the `position` to the table/chain if it does not exist.
     pub fn insert_unique(
         &self,
-        table: &str,
+        table: impl Into<Table>,
         chain: &str,
-        rule: &str,
+        rule: impl Into<RuleSpec>,
         position: i32,
-    ) -> Result<(), Box<dyn Error>> {
-        if self.exists(table, chain, rule)? {
-            return Err(error_from_str("the rule exists in the table/chain"));
-        }
-
-        self.insert(table, chain, rule, position)
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.add_unique(table, chain, &rule, Some(position))
     }
 
     /// Replaces `rule` in the `position` to the table/chain.
     pub fn replace(
         &self,
-        table: &str,
+        table: impl Into<Table>,
         chain: &str,
-        rule: &str,
+        rule: impl Into<RuleSpec>,
         position: i32,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
         self.run(
             &[
                 &["-t", table, "-R", chain, &position.to_string()],
-                rule.split_quoted().as_slice(),
+                as_strs(&self.tagged(rule.into_args())).as_slice(),
             ]
             .concat(),
         )
@@ -262,86 +861,533 @@ impl IPTables {
     }
 
     /// Appends `rule` to the table/chain.
-    pub fn append(&self, table: &str, chain: &str, rule: &str) -> Result<(), Box<dyn Error>> {
-        self.run(&[&["-t", table, "-A", chain], rule.split_quoted().as_slice()].concat())
-            .and_then(output_to_result)
+    pub fn append(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        self.append_with(table, chain, rule, &CallOptions::default())
+    }
+
+    /// Appends `rule` to the table/chain with the given `options`.
+    pub fn append_with(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        options: &CallOptions,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        if self.validate_rules {
+            rule.validate(self.is_ipv6)?;
+        }
+        self.run_with(
+            &[
+                &["-t", table, "-A", chain],
+                as_strs(&self.tagged(rule.into_args())).as_slice(),
+            ]
+            .concat(),
+            options,
+        )
+        .and_then(output_to_result)
+    }
+
+    /// Appends `rule` to the table/chain with its packet and byte counters initialized to the given
+    /// values, e.g. to keep the accounting data of a rule which is moved to another chain.
+    pub fn append_with_counters(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        packets: u64,
+        bytes: u64,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        self.run(
+            &[
+                &[
+                    "-t",
+                    table,
+                    "-A",
+                    chain,
+                    "-c",
+                    &packets.to_string(),
+                    &bytes.to_string(),
+                ],
+                as_strs(&self.tagged(rule.into_args())).as_slice(),
+            ]
+            .concat(),
+        )
+        .and_then(output_to_result)
+    }
+
+    /// Inserts `rule` in the `position` to the table/chain with its packet and byte counters
+    /// initialized to the given values.
+    pub fn insert_with_counters(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: i32,
+        packets: u64,
+        bytes: u64,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        self.run(
+            &[
+                &[
+                    "-t",
+                    table,
+                    "-I",
+                    chain,
+                    &position.to_string(),
+                    "-c",
+                    &packets.to_string(),
+                    &bytes.to_string(),
+                ],
+                as_strs(&self.tagged(rule.into_args())).as_slice(),
+            ]
+            .concat(),
+        )
+        .and_then(output_to_result)
     }
 
     /// Appends `rule` to the table/chain if it does not exist.
     pub fn append_unique(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.add_unique(table, chain, &rule, None)
+    }
+
+    // Inserts the rule in `position` or appends it if `position` is `None`
+    fn add_unique(
         &self,
         table: &str,
         chain: &str,
-        rule: &str,
-    ) -> Result<(), Box<dyn Error>> {
+        rule: &RuleSpec,
+        position: Option<i32>,
+    ) -> IPTResult<()> {
+        let _serial = self.serial.lock();
         if self.exists(table, chain, rule)? {
-            return Err(error_from_str("the rule exists in the table/chain"));
+            return Err(IPTError::AlreadyExists(
+                "the rule exists in the table/chain".to_string(),
+            ));
+        }
+        let add = || match position {
+            Some(position) => self.insert(table, chain, rule, position),
+            None => self.append(table, chain, rule),
+        };
+        add()?;
+        if !self.race_safe_unique {
+            return Ok(());
         }
 
-        self.append(table, chain, rule)
+        // Another process may have added the same rule between the check and the insertion.
+        // One copy is deleted until a single copy is left; if concurrent writers deleted every
+        // copy, the rule is added again
+        // iptables prints rules normalized, e.g. with the implicit `-m tcp`, so both sides are
+        // compared in their canonical form
+        let canonical = canonical_rule(&join_quoted(&self.tagged(rule.args().to_vec())))?;
+        let mut retry = LockRetry::new(self.lock_policy);
+        loop {
+            let copies = self.list(table, chain)?;
+            let copies = canonical_listing(&copies, chain);
+            if copies.iter().filter(|copy| **copy == canonical).count() <= 1 {
+                return Ok(());
+            }
+            self.delete(table, chain, rule)?;
+            std::thread::sleep(retry.next_delay()?);
+            if !self.exists(table, chain, rule)? {
+                add()?;
+            }
+        }
     }
 
     /// Appends or replaces `rule` to the table/chain if it does not exist.
     pub fn append_replace(
         &self,
-        table: &str,
+        table: impl Into<Table>,
         chain: &str,
-        rule: &str,
-    ) -> Result<(), Box<dyn Error>> {
-        if self.exists(table, chain, rule)? {
-            self.delete(table, chain, rule)?;
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        let _serial = self.serial.lock();
+        if self.exists(table, chain, &rule)? {
+            self.delete(table, chain, &rule)?;
         }
 
-        self.append(table, chain, rule)
+        self.append(table, chain, &rule)
     }
 
     /// Deletes `rule` from the table/chain.
-    pub fn delete(&self, table: &str, chain: &str, rule: &str) -> Result<(), Box<dyn Error>> {
-        self.run(&[&["-t", table, "-D", chain], rule.split_quoted().as_slice()].concat())
+    pub fn delete(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        self.delete_with(table, chain, rule, &CallOptions::default())
+    }
+
+    /// Deletes `rule` from the table/chain with the given `options`.
+    pub fn delete_with(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        options: &CallOptions,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        self.run_with(
+            &[
+                &["-t", table, "-D", chain],
+                as_strs(&self.tagged(rule.into_args())).as_slice(),
+            ]
+            .concat(),
+            options,
+        )
+        .and_then(output_to_result)
+    }
+
+    /// Appends the structured `rule` to the table/chain.
+    pub fn append_rule(&self, table: impl Into<Table>, chain: &str, rule: &Rule) -> IPTResult<()> {
+        let table: &str = &table.into();
+        rule.check(self.is_ipv6)?;
+        self.sanitize(&rule.args())?;
+        let args = self.tagged(rule.args());
+        self.run(&[&["-t", table, "-A", chain], as_strs(&args).as_slice()].concat())
+            .and_then(output_to_result)
+    }
+
+    /// Inserts the structured `rule` in the `position` to the table/chain.
+    pub fn insert_rule(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: &Rule,
+        position: i32,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        rule.check(self.is_ipv6)?;
+        self.sanitize(&rule.args())?;
+        let args = self.tagged(rule.args());
+        self.run(
+            &[
+                &["-t", table, "-I", chain, &position.to_string()],
+                as_strs(&args).as_slice(),
+            ]
+            .concat(),
+        )
+        .and_then(output_to_result)
+    }
+
+    /// Replaces the rule in the `position` of the table/chain with the structured `rule`.
+    pub fn replace_rule(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: &Rule,
+        position: i32,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        rule.check(self.is_ipv6)?;
+        self.sanitize(&rule.args())?;
+        let args = self.tagged(rule.args());
+        self.run(
+            &[
+                &["-t", table, "-R", chain, &position.to_string()],
+                as_strs(&args).as_slice(),
+            ]
+            .concat(),
+        )
+        .and_then(output_to_result)
+    }
+
+    /// Deletes the structured `rule` from the table/chain.
+    pub fn delete_rule(&self, table: impl Into<Table>, chain: &str, rule: &Rule) -> IPTResult<()> {
+        let table: &str = &table.into();
+        rule.check(self.is_ipv6)?;
+        self.sanitize(&rule.args())?;
+        let args = self.tagged(rule.args());
+        self.run(&[&["-t", table, "-D", chain], as_strs(&args).as_slice()].concat())
+            .and_then(output_to_result)
+    }
+
+    /// Checks for the existence of the structured `rule` in the table/chain.
+    /// Returns true if the rule exists.
+    pub fn exists_rule(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: &Rule,
+    ) -> IPTResult<bool> {
+        let table: &str = &table.into();
+        rule.check(self.is_ipv6)?;
+        self.sanitize(&rule.args())?;
+        if !self.capabilities().has_check {
+            return self.exists_old_version(
+                table,
+                chain,
+                &self.tagged(rule.args()),
+                &CallOptions::default(),
+            );
+        }
+
+        let args = self.tagged(rule.args());
+        self.run(&[&["-t", table, "-C", chain], as_strs(&args).as_slice()].concat())
+            .and_then(|output| output_to_exists(output, MSG_BAD_RULE))
+    }
+
+    /// Deletes the rule in the `position` from the table/chain, the first rule has position 1.
+    pub fn delete_by_position(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        position: u32,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        self.run(&["-t", table, "-D", chain, &position.to_string()])
             .and_then(output_to_result)
     }
 
     /// Deletes all repetition of the `rule` from the table/chain.
-    pub fn delete_all(&self, table: &str, chain: &str, rule: &str) -> Result<(), Box<dyn Error>> {
-        while self.exists(table, chain, rule)? {
-            self.delete(table, chain, rule)?;
+    pub fn delete_all(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        let _serial = self.serial.lock();
+        // A single listing tells how often the rule is repeated, instead of checking before
+        // every deletion. Each copy is deleted as iptables lists it, which also matches copies
+        // giving their match modules in another order.
+        let expected = canonical_rule(&join_quoted(&self.tagged(rule.args().to_vec())))?;
+        let prefix = format!("-A {} ", chain);
+        let lines = self.list(table, chain)?;
+        let copies = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix(&prefix))
+            .filter(|listed| canonical_rule(listed).ok().as_ref() == Some(&expected));
+        for listed in copies {
+            self.run(
+                &[
+                    &["-t", table, "-D", chain],
+                    as_strs(&listed.split_quoted()).as_slice(),
+                ]
+                .concat(),
+            )
+            .and_then(output_to_result)?;
         }
 
         Ok(())
     }
 
+    /// Deletes the exact duplicates of rules in the table/chain, which naive scripts leave behind
+    /// when they crash before cleaning up, and returns how many rules were deleted. The first
+    /// copy of each rule is kept. The extras are deleted by their position in a single
+    /// `iptables-restore --noflush` transaction, so the chain must not be changed concurrently.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let ipt = iptables::new(false).unwrap();
+    /// let removed = ipt.dedupe_chain("filter", "INPUT").unwrap();
+    /// println!("removed {} duplicate rules", removed);
+    /// ```
+    pub fn dedupe_chain(&self, table: impl Into<Table>, chain: &str) -> IPTResult<usize> {
+        let table: &str = &table.into();
+        let _serial = self.serial.lock();
+        let (data, removed) = dedupe_data(table, chain, &self.list_numbered(table, chain)?);
+        if removed > 0 {
+            self.restore(&data, false)?;
+        }
+        Ok(removed)
+    }
+
     /// Lists rules in the table/chain.
-    pub fn list(&self, table: &str, chain: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    pub fn list(&self, table: impl Into<Table>, chain: &str) -> IPTResult<Vec<String>> {
+        let table: &str = &table.into();
         self.get_list(&["-t", table, "-S", chain])
     }
 
+    /// Lists rules in the table/chain split into their arguments like a shell would, e.g.
+    /// `["-A", "INPUT", "-m", "comment", "--comment", "my app", "-j", "ACCEPT"]`, the form
+    /// needed to pass a listed rule to another command without quoting issues.
+    pub fn list_args(&self, table: impl Into<Table>, chain: &str) -> IPTResult<Vec<Vec<String>>> {
+        let table: &str = &table.into();
+        self.get_list(&["-t", table, "-S", chain])
+            .map(|lines| lines.iter().map(|line| line.split_quoted()).collect())
+    }
+
+    /// Lists rules in the table/chain paired with their position, the same numbers shown by
+    /// `--line-numbers`. The rules are given without the leading `-A <chain>`.
+    pub fn list_numbered(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> IPTResult<Vec<(u32, String)>> {
+        let table: &str = &table.into();
+        self.get_list(&["-t", table, "-S", chain])
+            .map(|lines| numbered_from_lines(&lines, chain))
+    }
+
+    /// Lists the chains of the table, or only `chain` if given, in the tabular format of
+    /// `iptables -L` with the given `options`, parsed into their structured representation.
+    pub fn list_with_options(
+        &self,
+        table: impl Into<Table>,
+        chain: Option<&str>,
+        options: &ListOptions,
+    ) -> IPTResult<Vec<ListedChain>> {
+        let table: &str = &table.into();
+        let mut args = vec!["-t", table, "-L"];
+        args.extend(chain);
+        args.extend(options.args());
+
+        self.run(&args)
+            .and_then(output_to_string)
+            .and_then(|output| parse_listing(&output, options))
+    }
+
+    /// Lists the rules of every table known to iptables, grouped by table name.
+    /// Tables which are not available, e.g. `security` on some kernels, are left out.
+    pub fn list_all(&self) -> IPTResult<HashMap<String, Vec<String>>> {
+        let mut tables = HashMap::new();
+        for &table in TABLES {
+            if self.table_exists(table)? {
+                tables.insert(table.to_string(), self.list_table(table)?);
+            }
+        }
+        Ok(tables)
+    }
+
+    /// Lists the rules of all chains in the table which carry the comment `tag`,
+    /// e.g. the `owner_tag` of an instance.
+    pub fn list_by_comment(&self, table: impl Into<Table>, tag: &str) -> IPTResult<Vec<String>> {
+        let table: &str = &table.into();
+        Ok(self
+            .list_table(table)?
+            .into_iter()
+            .filter(|line| has_comment(&line.split_quoted(), tag))
+            .collect())
+    }
+
+    /// Deletes every rule of the table/chain which carries the comment `tag`.
+    pub fn delete_by_comment(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        tag: &str,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let _serial = self.serial.lock();
+        for args in rule_specs(&self.list(table, chain)?, |args| has_comment(args, tag)) {
+            self.run(&[&["-t", table, "-D", chain], as_strs(&args).as_slice()].concat())
+                .and_then(output_to_result)?;
+        }
+        Ok(())
+    }
+
     /// Lists rules in the table.
-    pub fn list_table(&self, table: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    pub fn list_table(&self, table: impl Into<Table>) -> IPTResult<Vec<String>> {
+        let table: &str = &table.into();
         self.get_list(&["-t", table, "-S"])
     }
 
+    /// Lists rules in the table/chain together with their packet and byte counters.
+    /// See `counters` to identify the rules by their comment.
+    pub fn list_with_counters(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> IPTResult<Vec<RuleCounters>> {
+        let table: &str = &table.into();
+        Ok(self
+            .get_list(&["-t", table, "-S", chain, "-v"])?
+            .iter()
+            .filter_map(|line| RuleCounters::parse(line))
+            .collect())
+    }
+
+    /// Lists rules in the table/chain together with their packet and byte counters and zeroes the
+    /// counters in the same invocation, so no packet is missed between reading and resetting.
+    pub fn list_and_zero(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> IPTResult<Vec<RuleCounters>> {
+        let table: &str = &table.into();
+        Ok(self
+            .get_list(&["-t", table, "-S", chain, "-v", "-Z"])?
+            .iter()
+            .filter_map(|line| RuleCounters::parse(line))
+            .collect())
+    }
+
+    /// Lists rules in the table/chain parsed into their typed representation.
+    pub fn list_parsed(&self, table: impl Into<Table>, chain: &str) -> IPTResult<Vec<ParsedRule>> {
+        let table: &str = &table.into();
+        self.get_list(&["-t", table, "-S", chain])?
+            .iter()
+            .filter(|line| line.starts_with("-A "))
+            .map(|line| ParsedRule::parse(line))
+            .collect()
+    }
+
     /// Lists the name of each chain in the table.
-    pub fn list_chains(&self, table: &str) -> Result<Vec<String>, Box<dyn Error>> {
-        let mut list = Vec::new();
-        let stdout = self.run(&["-t", table, "-S"])?.stdout;
-        let output = String::from_utf8_lossy(stdout.as_slice());
-        for item in output.trim().split('\n') {
-            let fields = item.split(' ').collect::<Vec<&str>>();
-            if fields.len() > 1 && (fields[0] == "-P" || fields[0] == "-N") {
-                list.push(fields[1].to_string());
-            }
-        }
-        Ok(list)
+    /// See `list_chain_info` for the policy and the references of each chain.
+    pub fn list_chains(&self, table: impl Into<Table>) -> IPTResult<Vec<String>> {
+        let table: &str = &table.into();
+        self.run(&["-t", table, "-S"])
+            .and_then(output_to_string)
+            .map(|output| chains_from_output(&output))
+    }
+
+    /// Lists each chain in the table with its policy and policy counters if it is built-in,
+    /// or the number of rules jumping to it if it is user-defined, parsed from `iptables -L`.
+    pub fn list_chain_info(&self, table: impl Into<Table>) -> IPTResult<Vec<ChainInfo>> {
+        let options = ListOptions {
+            verbose: true,
+            ..ListOptions::default()
+        };
+        self.list_with_options(table, None, &options)
+            .map(|chains| chains.iter().map(ChainInfo::from).collect())
     }
 
     /// Creates a new user-defined chain.
-    pub fn new_chain(&self, table: &str, chain: &str) -> Result<(), Box<dyn Error>> {
+    pub fn new_chain(&self, table: impl Into<Table>, chain: &str) -> IPTResult<()> {
+        let table: &str = &table.into();
         self.run(&["-t", table, "-N", chain])
             .and_then(output_to_result)
     }
 
+    /// Creates a new user-defined chain if it does not exist yet.
+    pub fn ensure_chain(&self, table: impl Into<Table>, chain: &str) -> IPTResult<()> {
+        let table: &str = &table.into();
+        self.run(&["-t", table, "-N", chain])
+            .and_then(output_to_ensured)
+    }
+
     /// Flushes (deletes all rules) a chain.
-    pub fn flush_chain(&self, table: &str, chain: &str) -> Result<(), Box<dyn Error>> {
+    pub fn flush_chain(&self, table: impl Into<Table>, chain: &str) -> IPTResult<()> {
+        let table: &str = &table.into();
         self.run(&["-t", table, "-F", chain])
             .and_then(output_to_result)
     }
@@ -349,65 +1395,542 @@ impl IPTables {
     /// Renames a chain in the table.
     pub fn rename_chain(
         &self,
-        table: &str,
+        table: impl Into<Table>,
         old_chain: &str,
         new_chain: &str,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
         self.run(&["-t", table, "-E", old_chain, new_chain])
             .and_then(output_to_result)
     }
 
     /// Deletes a user-defined chain in the table.
-    pub fn delete_chain(&self, table: &str, chain: &str) -> Result<(), Box<dyn Error>> {
+    pub fn delete_chain(&self, table: impl Into<Table>, chain: &str) -> IPTResult<()> {
+        let table: &str = &table.into();
         self.run(&["-t", table, "-X", chain])
             .and_then(output_to_result)
     }
 
+    /// Lists the rules of the table jumping to the user-defined `chain` (`-j chain` or
+    /// `-g chain`), as the chain of each rule and its position in it, the first rule has position
+    /// 1. iptables refuses to delete a chain while it has references.
+    pub fn chain_references(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+    ) -> IPTResult<Vec<(String, u32)>> {
+        let table: &str = &table.into();
+        self.list_table(table)
+            .map(|lines| references_from_lines(&lines, chain))
+    }
+
+    /// Deletes the user-defined chain in the table after deleting the rules jumping to it, see
+    /// `chain_references`, and flushing it.
+    pub fn delete_chain_recursive(&self, table: impl Into<Table>, chain: &str) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let _serial = self.serial.lock();
+        // Deleting from the last position keeps the positions of the other references valid
+        for (parent, position) in self.chain_references(table, chain)?.iter().rev() {
+            self.delete_by_position(table, parent, *position)?;
+        }
+        self.flush_chain(table, chain)?;
+        self.delete_chain(table, chain)
+    }
+
+    /// Creates the user-defined `child` chain if it does not exist yet and makes the `parent`
+    /// chain jump to it (`-j child`), in the `position` or at the end if `position` is `None`.
+    /// Does not add the jump again if the parent chain already has it.
+    pub fn link_chain(
+        &self,
+        table: impl Into<Table>,
+        parent: &str,
+        child: &str,
+        position: Option<i32>,
+    ) -> IPTResult<()> {
+        self.link_chain_with(table, parent, &Rule::new().jump(child), child, position)
+    }
+
+    /// Like `link_chain`, but continues processing in the `child` chain with `-g child`, so
+    /// returning from it skips the rest of the `parent` chain.
+    pub fn link_chain_goto(
+        &self,
+        table: impl Into<Table>,
+        parent: &str,
+        child: &str,
+        position: Option<i32>,
+    ) -> IPTResult<()> {
+        self.link_chain_with(table, parent, &Rule::new().goto(child), child, position)
+    }
+
+    fn link_chain_with(
+        &self,
+        table: impl Into<Table>,
+        parent: &str,
+        link: &Rule,
+        child: &str,
+        position: Option<i32>,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let _serial = self.serial.lock();
+        self.ensure_chain(table, child)?;
+        if self.exists_rule(table, parent, link)? {
+            return Ok(());
+        }
+        match position {
+            Some(position) => self.insert_rule(table, parent, link, position),
+            None => self.append_rule(table, parent, link),
+        }
+    }
+
+    /// Removes the user-defined chain completely: deletes the rules of the `referencing_chains`
+    /// which jump to it, flushes it and deletes it. Does nothing if the chain does not exist.
+    pub fn teardown_chain(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        referencing_chains: &[&str],
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let _serial = self.serial.lock();
+        if !self.chain_exists(table, chain)? {
+            return Ok(());
+        }
+
+        for parent in referencing_chains {
+            for args in rule_specs(&self.list(table, parent)?, |args| jumps_to(args, chain)) {
+                self.run(&[&["-t", table, "-D", parent], as_strs(&args).as_slice()].concat())
+                    .and_then(output_to_result)?;
+            }
+        }
+
+        self.flush_chain(table, chain)?;
+        self.delete_chain(table, chain)
+    }
+
+    /// Zeroes the packet and byte counters of all rules in a chain.
+    pub fn zero_chain(&self, table: impl Into<Table>, chain: &str) -> IPTResult<()> {
+        let table: &str = &table.into();
+        self.run(&["-t", table, "-Z", chain])
+            .and_then(output_to_result)
+    }
+
+    /// Zeroes the packet and byte counters of all chains in a table.
+    pub fn zero_all(&self, table: impl Into<Table>) -> IPTResult<()> {
+        let table: &str = &table.into();
+        self.run(&["-t", table, "-Z"]).and_then(output_to_result)
+    }
+
     /// Flushes all chains in a table.
-    pub fn flush_table(&self, table: &str) -> Result<(), Box<dyn Error>> {
+    pub fn flush_table(&self, table: impl Into<Table>) -> IPTResult<()> {
+        let table: &str = &table.into();
         self.run(&["-t", table, "-F"]).and_then(output_to_result)
     }
 
-    fn get_list<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<Vec<String>, Box<dyn Error>> {
-        let stdout = self.run(args)?.stdout;
-        Ok(String::from_utf8_lossy(stdout.as_slice())
-            .trim()
-            .split('\n')
-            .map(String::from)
-            .collect())
+    /// Dumps the rules of all tables, or only of `table` if given, in the `iptables-save` format.
+    pub fn save(&self, table: Option<&str>) -> IPTResult<String> {
+        let mut command = self.command(&format!("{}-save", self.cmd));
+        if let Some(table) = table {
+            command.args(["-t", table]);
+        }
+
+        output_to_string(self.spawn(&command, None)?)
     }
 
-    fn run<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<Output, Box<dyn Error>> {
-        let mut file_lock = None;
+    // Dumps the rules of all tables, or only of `table` if given, together with the counters of
+    // the chains and rules
+    pub(crate) fn save_with_counters(&self, table: Option<&str>) -> IPTResult<String> {
+        let mut command = self.command(&format!("{}-save", self.cmd));
+        command.arg("-c");
+        if let Some(table) = table {
+            command.args(["-t", table]);
+        }
+        output_to_string(self.spawn(&command, None)?)
+    }
+
+    /// Restores the rules in `data`, given in the `iptables-save` format, using `iptables-restore`.
+    /// The tables contained in `data` are flushed before restoring if `flush` is true.
+    /// iptables-restore waits for the xtables lock, up to the `wait_timeout` if set, when it
+    /// supports -w (--wait), see `supports_restore_wait`.
+    pub fn restore(&self, data: &str, flush: bool) -> IPTResult<()> {
+        self.restore_with_counters(data, flush, false)
+    }
+
+    // Restores `data` like `restore`, also setting the counters given in `[packets:bytes]` in
+    // front of the rules if `counters` is true
+    fn restore_with_counters(&self, data: &str, flush: bool, counters: bool) -> IPTResult<()> {
+        let mut command = self.command(&format!("{}-restore", self.cmd));
+        if !flush {
+            command.arg("--noflush");
+        }
+        if counters {
+            command.arg("--counters");
+        }
+        command.args(self.restore_wait_args());
 
-        let mut output_cmd = Command::new(self.cmd);
-        let output;
+        output_to_result(self.spawn(&command, Some(data))?)
+    }
 
-        if self.has_wait {
-            output = output_cmd.args(args).arg("--wait").output()?;
+    /// Programs whole chains of the table with a single `iptables-restore --noflush` call, which
+    /// is much faster than running iptables for every rule when writing hundreds of rules.
+    ///
+    /// `chains` gives the rules of each chain in their order. If `flush_managed` is true the
+    /// chains are flushed first, so they end up with exactly the given rules, otherwise the rules
+    /// are appended. Missing user-defined chains are created and chains which are not given are
+    /// left untouched. A rule may start with `[packets:bytes]` to initialize its counters.
+    /// Nothing is run if a chain or rule contains a line break, which would start another
+    /// command of iptables-restore.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let ipt = iptables::new(false).unwrap();
+    /// ipt.restore_table(
+    ///     "filter",
+    ///     &[
+    ///         ("MYAPP", vec!["-p tcp --dport 22 -j ACCEPT", "-j DROP"]),
+    ///         ("INPUT", vec!["-j MYAPP"]),
+    ///     ],
+    ///     true,
+    /// )
+    /// .unwrap();
+    /// ```
+    pub fn restore_table<C: AsRef<str>, R: AsRef<str>>(
+        &self,
+        table: impl Into<Table>,
+        chains: &[(C, Vec<R>)],
+        flush_managed: bool,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        for (chain, rules) in chains {
+            check_line_breaks(&[chain])?;
+            check_line_breaks(rules)?;
+            for rule in rules {
+                self.sanitize(&split_counters(rule.as_ref()).1.split_quoted())?;
+            }
+        }
+        let _serial = self.serial.lock();
+        let builtin_chains = get_builtin_chains(table).unwrap_or_default();
+        // Declaring a user-defined chain creates it, but also flushes it if it exists already
+        let existing_chains = if flush_managed {
+            Vec::new()
         } else {
-            file_lock = Some(File::create("/var/run/xtables_old.lock")?);
-
-            let mut need_retry = true;
-            while need_retry {
-                match flock(
-                    file_lock.as_ref().unwrap().as_raw_fd(),
-                    FlockArg::LockExclusiveNonblock,
-                ) {
-                    Ok(_) => need_retry = false,
-                    Err(nix::Error::Sys(en)) if en == nix::errno::Errno::EAGAIN => {
-                        // FIXME: may cause infinite loop
-                        need_retry = true;
-                    }
-                    Err(e) => {
-                        return Err(Box::new(e));
-                    }
+            self.list_chains(table)?
+        };
+
+        let mut data = format!("*{}\n", table);
+        for (chain, _) in chains {
+            let chain = chain.as_ref();
+            if builtin_chains.contains(&chain) {
+                if flush_managed {
+                    data.push_str(&format!("-F {}\n", chain));
+                }
+            } else if !existing_chains.iter().any(|existing| existing == chain) {
+                data.push_str(&format!(":{} - [0:0]\n", chain));
+            }
+        }
+
+        let mut counters = false;
+        for (chain, rules) in chains {
+            for rule in rules {
+                let (prefix, rule) = split_counters(rule.as_ref());
+                if !prefix.is_empty() {
+                    counters = true;
+                    data.push_str(prefix);
+                    data.push(' ');
+                }
+                let args = self.tagged(rule.split_quoted());
+                data.push_str(&format!("-A {} {}\n", chain.as_ref(), join_quoted(&args)));
+            }
+        }
+        data.push_str("COMMIT\n");
+        self.restore_with_counters(&data, false, counters)
+    }
+
+    /// Appends all `rules` to the table/chain in the given order with a single
+    /// `iptables-restore --noflush` call, so either all rules are appended or none.
+    ///
+    /// The rules are checked before running iptables-restore. If a rule is invalid or rejected by
+    /// iptables, `IPTError::RuleFailed` gives its index in `rules`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let ipt = iptables::new(false).unwrap();
+    /// let rules = ["-p tcp --dport 80 -j ACCEPT", "-p tcp --dport 443 -j ACCEPT"];
+    /// match ipt.append_many("filter", "INPUT", &rules) {
+    ///     Err(iptables::IPTError::RuleFailed { index, error }) => {
+    ///         eprintln!("{} was rejected: {}", rules[index], error)
+    ///     }
+    ///     result => result.unwrap(),
+    /// }
+    /// ```
+    pub fn append_many(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rules: &[&str],
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let data = self.append_many_data(table, chain, rules)?;
+        self.restore(&data, false)
+            .map_err(|err| rule_failed(err, rules.len()))
+    }
+
+    // Renders the restore input of `append_many` after checking the rules
+    pub(crate) fn append_many_data(
+        &self,
+        table: &str,
+        chain: &str,
+        rules: &[&str],
+    ) -> IPTResult<String> {
+        let mut data = format!("*{}\n", table);
+        for (index, rule) in rules.iter().enumerate() {
+            let invalid = |msg: &str| IPTError::RuleFailed {
+                index,
+                error: Box::new(IPTError::Parse(msg.to_string())),
+            };
+            check_line_breaks(&[rule]).map_err(|error| IPTError::RuleFailed {
+                index,
+                error: Box::new(error),
+            })?;
+            let args = rule.split_quoted();
+            if args.is_empty() {
+                return Err(invalid("rule is empty"));
+            }
+            self.sanitize(&args).map_err(|error| IPTError::RuleFailed {
+                index,
+                error: Box::new(error),
+            })?;
+            data.push_str(&format!(
+                "-A {} {}\n",
+                chain,
+                join_quoted(&self.tagged(args))
+            ));
+        }
+        data.push_str("COMMIT\n");
+        Ok(data)
+    }
+
+    /// Returns the command lines recorded in dry-run mode, in the order they would have run.
+    /// Each command line starts with the program, e.g. `["iptables", "-t", "filter", "-F", "INPUT", "--wait"]`.
+    pub fn recorded_commands(&self) -> Vec<Vec<String>> {
+        self.recorded
+            .lock()
+            .map(|recorded| recorded.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the command lines recorded in dry-run mode and clears them.
+    pub fn take_recorded_commands(&self) -> Vec<Vec<String>> {
+        self.recorded
+            .lock()
+            .map(|mut recorded| std::mem::take(&mut *recorded))
+            .unwrap_or_default()
+    }
+
+    // Records the command line of `command` and returns a successful output without running it
+    pub(crate) fn record_command(&self, command: &Command) -> Output {
+        let (program, args) = command_line(command);
+        if let Ok(mut recorded) = self.recorded.lock() {
+            recorded.push([vec![program], args].concat());
+        }
+
+        Output {
+            status: ExitStatus::from_raw(0),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        }
+    }
+
+    /// Applies all operations queued in `batch` with a single `iptables-restore --noflush` call.
+    /// If applying fails, the touched tables are restored to the state they had before the call,
+    /// together with the counters of their chains and rules.
+    pub fn commit(&self, batch: &Batch) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        batch.check()?;
+        batch.check_rules(|args| self.sanitize(args))?;
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut backup = String::new();
+        for table in batch.tables() {
+            backup.push_str(&self.save_with_counters(Some(table))?);
+        }
+
+        let data = batch.render(|args| self.tagged(args));
+        if let Err(err) = self.restore(&data, false) {
+            self.restore_with_counters(&backup, true, true)?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Adds the comment of `owner_tag` in front of the target, where `iptables -S` prints it,
+    // unless the rule already carries it
+    pub(crate) fn tagged(&self, mut args: Vec<String>) -> Vec<String> {
+        if let Some(tag) = self
+            .owner_tag
+            .as_deref()
+            .filter(|tag| !has_comment(&args, tag))
+        {
+            let index = args
+                .iter()
+                .position(|arg| ["-j", "--jump", "-g", "--goto"].contains(&arg.as_str()))
+                .unwrap_or(args.len());
+            let comment = ["-m", "comment", "--comment", tag];
+            args.splice(index..index, comment.iter().map(|arg| arg.to_string()));
+        }
+        args
+    }
+
+    // Returns the -w (--wait) option together with the configured timeout and interval, or no
+    // option at all if the call must not wait
+    fn wait_args(&self, options: &CallOptions) -> Vec<String> {
+        let wait_timeout = match options.wait {
+            Some(timeout) if timeout == Duration::ZERO => return Vec::new(),
+            Some(timeout) => Some(timeout),
+            None => self.wait_timeout,
+        };
+        let mut args = vec!["--wait".to_string()];
+        if let Some(timeout) = wait_timeout.filter(|_| self.capabilities().has_wait_timeout) {
+            let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+            args.push(secs.to_string());
+        }
+        if let Some(interval) = self
+            .wait_interval
+            .filter(|_| self.capabilities().has_wait_interval)
+        {
+            args.push("-W".to_string());
+            args.push(interval.as_micros().to_string());
+        }
+        args
+    }
+
+    // Returns the -w (--wait) option of iptables-restore together with the configured timeout,
+    // or no option at all if iptables-restore does not support it
+    pub(crate) fn restore_wait_args(&self) -> Vec<String> {
+        if !self.supports_restore_wait() {
+            return Vec::new();
+        }
+        let mut args = vec!["--wait".to_string()];
+        if let Some(timeout) = self.wait_timeout {
+            let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+            args.push(secs.to_string());
+        }
+        args
+    }
+
+    fn get_list<S: AsRef<OsStr>>(&self, args: &[S]) -> IPTResult<Vec<String>> {
+        self.run(args)
+            .and_then(output_to_string)
+            .map(|output| lines_from_output(&output))
+    }
+
+    /// Returns a clone of the instance which runs the commands inside the network namespace
+    /// `name`, see `IPTablesBuilder::netns`. The version detected for this instance is kept.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let ipt = iptables::new(false).unwrap();
+    /// let container = ipt.in_netns("container1");
+    /// container.append("filter", "INPUT", "-j DROP").unwrap();
+    /// ```
+    pub fn in_netns(&self, name: &str) -> IPTables {
+        IPTables {
+            netns: Some(name.to_string()),
+            ..self.clone()
+        }
+    }
+
+    // Creates the command running `program` in the configured network namespace and environment
+    pub(crate) fn command(&self, program: &str) -> Command {
+        let mut line = as_strs(&self.command_prefix);
+        if let Some(netns) = &self.netns {
+            line.extend(["ip", "netns", "exec", netns]);
+        }
+        let env = self
+            .env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>();
+        if self.env_clear || !env.is_empty() {
+            line.push("env");
+            if self.env_clear {
+                line.push("-i");
+            }
+            line.extend(as_strs(&env));
+        }
+        line.push(program);
+
+        let mut command = Command::new(line[0]);
+        command.args(&line[1..]);
+        if let Some(modprobe) = &self.modprobe {
+            command.arg(format!("--modprobe={}", modprobe));
+        }
+        command
+    }
+
+    // Runs `command` with the configured runner, or only records it in dry-run mode
+    pub(crate) fn spawn(&self, command: &Command, input: Option<&str>) -> IPTResult<Output> {
+        let _serial = self.serial.lock();
+        if self.dry_run {
+            return Ok(self.record_command(command));
+        }
+
+        let (program, args) = command_line(command);
+        let started = Instant::now();
+        let result = match (&self.runner, self.command_timeout) {
+            (None, Some(timeout)) => {
+                run_with_timeout(&program, &args, input, self.netns_path.as_deref(), timeout)
+            }
+            (runner, _) => {
+                let runner = runner.as_deref().unwrap_or(&SystemRunner);
+                match &self.netns_path {
+                    Some(netns) => runner.run_in_netns(netns, &program, &args, input),
+                    None => runner.run(&program, &args, input),
                 }
             }
-            output = output_cmd.args(args).output()?;
+        };
+        trace::command(&program, &args, &result, started.elapsed());
+        result
+    }
+
+    fn run<S: AsRef<OsStr>>(&self, args: &[S]) -> IPTResult<Output> {
+        self.run_with(args, &CallOptions::default())
+    }
+
+    fn run_with<S: AsRef<OsStr>>(&self, args: &[S], options: &CallOptions) -> IPTResult<Output> {
+        let mut retry = Retry::new(self.retry_policy);
+        loop {
+            let output = self.run_once(args, options)?;
+            match retry.next_delay(&output) {
+                Some(delay) => std::thread::sleep(delay),
+                None => return Ok(output),
+            }
+        }
+    }
+
+    fn run_once<S: AsRef<OsStr>>(&self, args: &[S], options: &CallOptions) -> IPTResult<Output> {
+        let mut file_lock = None;
+        if !self.dry_run && !self.capabilities().has_wait {
+            file_lock = Some(lock::acquire(options.lock_policy(self.lock_policy))?);
         }
 
-        file_lock.map(|f| drop(f));
+        let output = self.spawn(&self.iptables_command(args, options), None)?;
+        drop(file_lock);
         Ok(output)
     }
+
+    // Creates the iptables command running `args`, waiting for the xtables lock if supported
+    pub(crate) fn iptables_command<S: AsRef<OsStr>>(
+        &self,
+        args: &[S],
+        options: &CallOptions,
+    ) -> Command {
+        let mut command = self.command(&self.cmd);
+        command.args(&self.global_args).args(args);
+        if self.capabilities().has_wait {
+            command.args(self.wait_args(options));
+        }
+        command
+    }
 }
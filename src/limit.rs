@@ -0,0 +1,247 @@
+//! Typed parameters of the `limit` and `hashlimit` match modules, see `Rule::limit` and
+//! `Rule::hashlimit`.
+
+use std::fmt;
+
+/// The burst which iptables uses if none is given, not printed by `iptables -S`.
+const DEFAULT_BURST: u32 = 5;
+
+/// The time unit of a `Rate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateUnit {
+    /// Per second, rendered as `/sec`.
+    Second,
+    /// Per minute, rendered as `/min`.
+    Minute,
+    /// Per hour, rendered as `/hour`.
+    Hour,
+    /// Per day, rendered as `/day`.
+    Day,
+}
+
+impl RateUnit {
+    /// Returns the unit as printed by `iptables -S`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateUnit::Second => "sec",
+            RateUnit::Minute => "min",
+            RateUnit::Hour => "hour",
+            RateUnit::Day => "day",
+        }
+    }
+}
+
+/// The average rate of matched packets, e.g. `10/sec`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rate {
+    /// The number of packets per `unit`.
+    pub count: u32,
+    /// The time unit.
+    pub unit: RateUnit,
+}
+
+impl Rate {
+    /// Creates a rate of `count` packets per `unit`.
+    pub fn new(count: u32, unit: RateUnit) -> Self {
+        Self { count, unit }
+    }
+
+    /// Creates a rate of `count` packets per second.
+    pub fn per_second(count: u32) -> Self {
+        Self::new(count, RateUnit::Second)
+    }
+
+    /// Creates a rate of `count` packets per minute.
+    pub fn per_minute(count: u32) -> Self {
+        Self::new(count, RateUnit::Minute)
+    }
+
+    /// Creates a rate of `count` packets per hour.
+    pub fn per_hour(count: u32) -> Self {
+        Self::new(count, RateUnit::Hour)
+    }
+
+    /// Creates a rate of `count` packets per day.
+    pub fn per_day(count: u32) -> Self {
+        Self::new(count, RateUnit::Day)
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.count, self.unit.as_str())
+    }
+}
+
+/// The fields of a packet which select the bucket of `hashlimit` (`--hashlimit-mode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashLimitMode {
+    /// The source address, `srcip`.
+    SrcIp,
+    /// The source port, `srcport`.
+    SrcPort,
+    /// The destination address, `dstip`.
+    DstIp,
+    /// The destination port, `dstport`.
+    DstPort,
+}
+
+impl HashLimitMode {
+    /// Returns the mode as passed to iptables.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashLimitMode::SrcIp => "srcip",
+            HashLimitMode::SrcPort => "srcport",
+            HashLimitMode::DstIp => "dstip",
+            HashLimitMode::DstPort => "dstport",
+        }
+    }
+}
+
+/// Options of the `hashlimit` match module, which limits the rate per address or port instead
+/// of for all packets of the rule.
+///
+/// # Example
+/// ```
+/// use iptables::limit::{HashLimit, HashLimitMode, Rate};
+/// use iptables::Rule;
+///
+/// let limit = HashLimit::above("ssh", Rate::per_minute(3))
+///     .burst(10)
+///     .mode(HashLimitMode::SrcIp);
+/// let rule = Rule::new().protocol("tcp").dport(22).hashlimit(&limit).jump("DROP");
+/// assert_eq!(
+///     rule.to_string(),
+///     "-p tcp -m tcp --dport 22 -m hashlimit --hashlimit-above 3/min --hashlimit-burst 10 \
+///      --hashlimit-mode srcip --hashlimit-name ssh -j DROP"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashLimit {
+    name: String,
+    rate: Rate,
+    above: bool,
+    burst: Option<u32>,
+    modes: Vec<HashLimitMode>,
+    srcmask: Option<u8>,
+    dstmask: Option<u8>,
+    expire_ms: Option<u64>,
+}
+
+impl HashLimit {
+    /// Matches packets as long as the rate stays below `rate` (`--hashlimit-upto`).
+    /// `name` names the hash table, shown in `/proc/net/ipt_hashlimit/<name>`.
+    pub fn upto(name: &str, rate: Rate) -> Self {
+        Self {
+            name: name.to_string(),
+            rate,
+            above: false,
+            burst: None,
+            modes: Vec::new(),
+            srcmask: None,
+            dstmask: None,
+            expire_ms: None,
+        }
+    }
+
+    /// Matches packets once the rate exceeds `rate` (`--hashlimit-above`).
+    pub fn above(name: &str, rate: Rate) -> Self {
+        Self {
+            above: true,
+            ..Self::upto(name, rate)
+        }
+    }
+
+    /// Sets the maximum initial number of packets to match (`--hashlimit-burst`).
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = Some(burst);
+        self
+    }
+
+    /// Adds a field to the key of the buckets (`--hashlimit-mode`). Without any mode, all
+    /// packets share one bucket.
+    pub fn mode(mut self, mode: HashLimitMode) -> Self {
+        if !self.modes.contains(&mode) {
+            self.modes.push(mode);
+        }
+        self
+    }
+
+    /// Groups source addresses by the prefix length `mask` (`--hashlimit-srcmask`).
+    pub fn srcmask(mut self, mask: u8) -> Self {
+        self.srcmask = Some(mask);
+        self
+    }
+
+    /// Groups destination addresses by the prefix length `mask` (`--hashlimit-dstmask`).
+    pub fn dstmask(mut self, mask: u8) -> Self {
+        self.dstmask = Some(mask);
+        self
+    }
+
+    /// Removes idle buckets after `expire_ms` milliseconds (`--hashlimit-htable-expire`).
+    pub fn expire_ms(mut self, expire_ms: u64) -> Self {
+        self.expire_ms = Some(expire_ms);
+        self
+    }
+
+    /// Renders the options of the match module, in the order `iptables -S` prints them.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            if self.above {
+                "--hashlimit-above"
+            } else {
+                "--hashlimit-upto"
+            }
+            .to_string(),
+            self.rate.to_string(),
+        ];
+        if let Some(burst) = self.burst.filter(|&burst| burst != DEFAULT_BURST) {
+            args.push("--hashlimit-burst".to_string());
+            args.push(burst.to_string());
+        }
+        if !self.modes.is_empty() {
+            args.push("--hashlimit-mode".to_string());
+            args.push(
+                self.modes
+                    .iter()
+                    .map(HashLimitMode::as_str)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            );
+        }
+        args.push("--hashlimit-name".to_string());
+        args.push(self.name.clone());
+
+        let mut push = |flag: &str, value: Option<String>| {
+            if let Some(value) = value {
+                args.push(flag.to_string());
+                args.push(value);
+            }
+        };
+        push(
+            "--hashlimit-srcmask",
+            self.srcmask.map(|mask| mask.to_string()),
+        );
+        push(
+            "--hashlimit-dstmask",
+            self.dstmask.map(|mask| mask.to_string()),
+        );
+        push(
+            "--hashlimit-htable-expire",
+            self.expire_ms.map(|expire| expire.to_string()),
+        );
+        args
+    }
+}
+
+/// Renders the options of the `limit` match module, leaving out the default burst like
+/// `iptables -S` does.
+pub(crate) fn limit_args(rate: Rate, burst: Option<u32>) -> Vec<String> {
+    let mut args = vec!["--limit".to_string(), rate.to_string()];
+    if let Some(burst) = burst.filter(|&burst| burst != DEFAULT_BURST) {
+        args.push("--limit-burst".to_string());
+        args.push(burst.to_string());
+    }
+    args
+}
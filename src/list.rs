@@ -0,0 +1,215 @@
+//! Structured representation of the tabular listing printed by `iptables -L`.
+
+use crate::{IPTError, IPTResult};
+
+/// Controls the options passed to `iptables -L` by `IPTables::list_with_options`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListOptions {
+    /// Prints addresses and ports numerically (`-n`) instead of resolving them, which avoids slow
+    /// DNS lookups on big rulesets. Enabled by default.
+    pub numeric: bool,
+    /// Prints the interfaces and the packet and byte counters (`-v`). The counters are always
+    /// printed exactly (`-x`) so they can be parsed.
+    pub verbose: bool,
+    /// Prints the position of each rule (`--line-numbers`).
+    pub line_numbers: bool,
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        Self {
+            numeric: true,
+            verbose: false,
+            line_numbers: false,
+        }
+    }
+}
+
+impl ListOptions {
+    /// Returns the options to pass to iptables after `-L`.
+    pub fn args(&self) -> Vec<&'static str> {
+        let mut args = Vec::new();
+        if self.numeric {
+            args.push("-n");
+        }
+        if self.verbose {
+            args.push("-v");
+            args.push("-x");
+        }
+        if self.line_numbers {
+            args.push("--line-numbers");
+        }
+        args
+    }
+}
+
+/// A chain of the `iptables -L` listing together with its rules.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListedChain {
+    /// The name of the chain.
+    pub name: String,
+    /// The policy of a built-in chain.
+    pub policy: Option<String>,
+    /// The number of rules jumping to a user-defined chain.
+    pub references: Option<u32>,
+    /// The packets which reached the policy of a built-in chain, only listed with `verbose`.
+    pub packets: Option<u64>,
+    /// The bytes which reached the policy of a built-in chain, only listed with `verbose`.
+    pub bytes: Option<u64>,
+    /// The rules of the chain.
+    pub rules: Vec<ListedRule>,
+}
+
+/// The summary of a chain returned by `IPTables::list_chain_info`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChainInfo {
+    /// The name of the chain.
+    pub name: String,
+    /// Indicates if the chain is built-in, which is the case if it has a policy.
+    pub builtin: bool,
+    /// The policy of a built-in chain.
+    pub policy: Option<String>,
+    /// The number of rules jumping to a user-defined chain.
+    pub references: Option<u32>,
+    /// The packets which reached the policy of a built-in chain.
+    pub packets: Option<u64>,
+    /// The bytes which reached the policy of a built-in chain.
+    pub bytes: Option<u64>,
+}
+
+impl From<&ListedChain> for ChainInfo {
+    fn from(chain: &ListedChain) -> Self {
+        ChainInfo {
+            name: chain.name.clone(),
+            builtin: chain.policy.is_some(),
+            policy: chain.policy.clone(),
+            references: chain.references,
+            packets: chain.packets,
+            bytes: chain.bytes,
+        }
+    }
+}
+
+/// A rule of the `iptables -L` listing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListedRule {
+    /// The position of the rule, only listed with `line_numbers`.
+    pub number: Option<u32>,
+    /// The packets matched by the rule, only listed with `verbose`.
+    pub packets: Option<u64>,
+    /// The bytes matched by the rule, only listed with `verbose`.
+    pub bytes: Option<u64>,
+    /// The target of the rule, `None` if the rule has no target.
+    pub target: Option<String>,
+    /// The protocol, e.g. `tcp` or `all`.
+    pub protocol: String,
+    /// The fragment option, e.g. `--` or `-f`.
+    pub options: String,
+    /// The input interface, only listed with `verbose`.
+    pub in_interface: Option<String>,
+    /// The output interface, only listed with `verbose`.
+    pub out_interface: Option<String>,
+    /// The source address.
+    pub source: String,
+    /// The destination address.
+    pub destination: String,
+    /// The options of the matches and the target, e.g. `tcp dpt:22`.
+    pub extra: String,
+}
+
+/// Parses the output of `iptables -L` which was run with `options`.
+pub fn parse_listing(output: &str, options: &ListOptions) -> IPTResult<Vec<ListedChain>> {
+    let mut chains: Vec<ListedChain> = Vec::new();
+    let mut header_seen = false;
+
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        if let Some(header) = line.strip_prefix("Chain ") {
+            chains.push(parse_chain_header(header)?);
+            header_seen = false;
+        } else if !header_seen {
+            // The line with the column names follows each chain header
+            header_seen = true;
+        } else {
+            let chain = chains
+                .last_mut()
+                .ok_or_else(|| IPTError::Parse("rule listed before any chain".to_string()))?;
+            chain.rules.push(parse_rule(line, options)?);
+        }
+    }
+    Ok(chains)
+}
+
+// Parses e.g. `INPUT (policy ACCEPT 0 packets, 0 bytes)` or `MYCHAIN (1 references)`
+fn parse_chain_header(header: &str) -> IPTResult<ListedChain> {
+    let invalid = || IPTError::Parse(format!("invalid chain header: {}", header));
+    let (name, details) = header.split_once(" (").ok_or_else(invalid)?;
+    let details = details.strip_suffix(')').ok_or_else(invalid)?;
+    let fields = details
+        .split([' ', ','])
+        .filter(|field| !field.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut chain = ListedChain {
+        name: name.to_string(),
+        ..ListedChain::default()
+    };
+    match fields.as_slice() {
+        ["policy", policy, rest @ ..] => {
+            chain.policy = Some(policy.to_string());
+            if let [packets, "packets", bytes, "bytes"] = rest {
+                chain.packets = Some(parse_number(packets)?);
+                chain.bytes = Some(parse_number(bytes)?);
+            }
+        }
+        [references, "references"] => chain.references = Some(parse_number(references)?),
+        _ => return Err(invalid()),
+    }
+    Ok(chain)
+}
+
+fn parse_rule(line: &str, options: &ListOptions) -> IPTResult<ListedRule> {
+    let invalid = || IPTError::Parse(format!("invalid rule: {}", line));
+    let mut fields = line.split_whitespace();
+    let mut next = || fields.next().ok_or_else(invalid);
+    let mut rule = ListedRule::default();
+
+    if options.line_numbers {
+        rule.number = Some(parse_number(next()?)?);
+    }
+    if options.verbose {
+        rule.packets = Some(parse_number(next()?)?);
+        rule.bytes = Some(parse_number(next()?)?);
+    }
+
+    // The target column is blank for rules without a target, which is detected by the
+    // fragment option following directly after the protocol
+    let first = next()?;
+    let second = next()?;
+    if is_fragment_option(second) {
+        rule.protocol = first.to_string();
+        rule.options = second.to_string();
+    } else {
+        rule.target = Some(first.to_string());
+        rule.protocol = second.to_string();
+        rule.options = next()?.to_string();
+    }
+
+    if options.verbose {
+        rule.in_interface = Some(next()?.to_string());
+        rule.out_interface = Some(next()?.to_string());
+    }
+    rule.source = next()?.to_string();
+    rule.destination = next()?.to_string();
+    rule.extra = fields.collect::<Vec<_>>().join(" ");
+    Ok(rule)
+}
+
+fn is_fragment_option(field: &str) -> bool {
+    matches!(field, "--" | "-f" | "!f")
+}
+
+fn parse_number<T: std::str::FromStr>(field: &str) -> IPTResult<T> {
+    field
+        .parse()
+        .map_err(|_| IPTError::Parse(format!("invalid number: {}", field)))
+}
@@ -0,0 +1,160 @@
+//! The lock shared with iptables versions without the -w (--wait) option.
+
+use crate::{IPTError, IPTResult};
+use nix::fcntl::{flock, FlockArg};
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+pub(crate) const OLD_LOCK_PATH: &str = "/var/run/xtables_old.lock";
+
+/// Controls how the lock of iptables versions without the -w (--wait) option is retried
+/// while it is held by another process.
+///
+/// The delay between two attempts starts at `backoff` and doubles after each failed attempt up to
+/// `max_backoff`. Taking the lock fails with `IPTError::LockTimeout` once `max_attempts` or
+/// `timeout` is exceeded, the default policy retries forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockPolicy {
+    /// The maximum number of attempts to take the lock, unlimited if `None`.
+    pub max_attempts: Option<u32>,
+    /// The delay after the first failed attempt.
+    pub backoff: Duration,
+    /// The upper bound of the delay between two attempts.
+    pub max_backoff: Duration,
+    /// The maximum total time to wait for the lock, unlimited if `None`.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for LockPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: None,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(100),
+            timeout: None,
+        }
+    }
+}
+
+/// Serializes the operations of an `IPTables` instance and its clones within the process.
+///
+/// The lock is reentrant, so an operation which is made of other operations, e.g. checking for a
+/// rule and appending it, holds the lock for all of them.
+#[derive(Debug, Default)]
+pub(crate) struct SerialLock {
+    // The thread holding the lock and how often it has taken it
+    owner: Mutex<Option<(ThreadId, usize)>>,
+    released: Condvar,
+}
+
+/// Releases the `SerialLock` when dropped.
+pub(crate) struct SerialGuard<'a> {
+    lock: &'a SerialLock,
+}
+
+impl SerialLock {
+    pub(crate) fn lock(&self) -> SerialGuard<'_> {
+        let current = thread::current().id();
+        let mut owner = self.owner();
+        loop {
+            match owner.as_mut() {
+                None => {
+                    *owner = Some((current, 1));
+                    break;
+                }
+                Some((thread, count)) if *thread == current => {
+                    *count += 1;
+                    break;
+                }
+                Some(_) => {
+                    owner = self
+                        .released
+                        .wait(owner)
+                        .unwrap_or_else(|err| err.into_inner());
+                }
+            }
+        }
+        SerialGuard { lock: self }
+    }
+
+    // A panic while holding the inner mutex cannot leave the owner inconsistent, so the
+    // poisoning is ignored
+    fn owner(&self) -> MutexGuard<'_, Option<(ThreadId, usize)>> {
+        self.owner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Drop for SerialGuard<'_> {
+    fn drop(&mut self) {
+        let mut owner = self.lock.owner();
+        if let Some((_, count)) = owner.as_mut() {
+            *count -= 1;
+            if *count == 0 {
+                *owner = None;
+                self.lock.released.notify_one();
+            }
+        }
+    }
+}
+
+/// Tracks the attempts to take the lock according to a `LockPolicy`.
+pub(crate) struct LockRetry {
+    policy: LockPolicy,
+    attempts: u32,
+    delay: Duration,
+    started: Instant,
+}
+
+impl LockRetry {
+    pub(crate) fn new(policy: LockPolicy) -> Self {
+        Self {
+            policy,
+            attempts: 0,
+            delay: policy.backoff,
+            started: Instant::now(),
+        }
+    }
+
+    /// Returns the delay to wait before the next attempt after a failed attempt,
+    /// or `IPTError::LockTimeout` if the policy does not allow another attempt.
+    pub(crate) fn next_delay(&mut self) -> Result<Duration, IPTError> {
+        self.attempts += 1;
+        if let Some(max_attempts) = self.policy.max_attempts {
+            if self.attempts >= max_attempts {
+                return Err(IPTError::LockTimeout);
+            }
+        }
+
+        let delay = self.delay;
+        if let Some(timeout) = self.policy.timeout {
+            if self.started.elapsed() + delay > timeout {
+                return Err(IPTError::LockTimeout);
+            }
+        }
+
+        self.delay = (self.delay * 2).min(self.policy.max_backoff);
+        Ok(delay)
+    }
+}
+
+/// Tries to take the lock, returns false if it is held by another process.
+pub(crate) fn try_lock(file: &File) -> IPTResult<bool> {
+    match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+        Ok(_) => Ok(true),
+        Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => Ok(false),
+        Err(e) => Err(IPTError::Nix(e)),
+    }
+}
+
+/// Takes the lock, retrying according to `policy`. The lock is released when the file is dropped.
+pub(crate) fn acquire(policy: LockPolicy) -> IPTResult<File> {
+    let file = File::create(OLD_LOCK_PATH)?;
+    let mut retry = LockRetry::new(policy);
+    while !try_lock(&file)? {
+        std::thread::sleep(retry.next_delay()?);
+    }
+    Ok(file)
+}
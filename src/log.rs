@@ -0,0 +1,78 @@
+//! Typed options of the `LOG` and `NFLOG` targets, see `Rule::log` and `Rule::nflog`, and a
+//! helper logging packets before dropping them.
+
+use crate::rule::Rule;
+use crate::{IPTResult, IPTables};
+use std::fmt;
+
+/// The longest prefix accepted by the `LOG` target, in bytes.
+pub const MAX_LOG_PREFIX: usize = 29;
+
+/// The longest prefix accepted by the `NFLOG` target, in bytes.
+pub const MAX_NFLOG_PREFIX: usize = 63;
+
+/// The syslog level of the messages of the `LOG` target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    /// Level 0, `emerg`.
+    Emergency,
+    /// Level 1, `alert`.
+    Alert,
+    /// Level 2, `crit`.
+    Critical,
+    /// Level 3, `err`.
+    Error,
+    /// Level 4, `warning`, the level iptables uses if none is given.
+    #[default]
+    Warning,
+    /// Level 5, `notice`.
+    Notice,
+    /// Level 6, `info`.
+    Info,
+    /// Level 7, `debug`.
+    Debug,
+}
+
+impl LogLevel {
+    /// Returns the number of the level.
+    pub fn number(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/// Renders the number of the level, the way `iptables -S` prints it.
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.number())
+    }
+}
+
+impl IPTables {
+    /// Logs the packets matched by `selector` with `prefix` and drops them, by appending a `LOG`
+    /// and a `DROP` rule to the `chain` of the `filter` table, each unless it already exists. The
+    /// target of `selector` is replaced.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use iptables::Rule;
+    ///
+    /// let ipt = iptables::new(false).unwrap();
+    /// let telnet = Rule::new().protocol("tcp").dport(23);
+    /// ipt.log_and_drop("INPUT", &telnet, "telnet: ").unwrap();
+    /// ```
+    pub fn log_and_drop(&self, chain: &str, selector: &Rule, prefix: &str) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        // An invalid prefix fails the LOG rule before the DROP rule is appended
+        let log = selector.clone().log(prefix, LogLevel::default());
+        self.append_rule_if_missing("filter", chain, &log)?;
+        self.append_rule_if_missing("filter", chain, &selector.clone().jump("DROP"))
+    }
+
+    /// Removes the rules appended by `log_and_drop` with the same arguments.
+    pub fn remove_log_and_drop(&self, chain: &str, selector: &Rule, prefix: &str) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        let log = selector.clone().log(prefix, LogLevel::default());
+        self.delete_rule_if_present("filter", chain, &log)?;
+        self.delete_rule_if_present("filter", chain, &selector.clone().jump("DROP"))
+    }
+}
@@ -0,0 +1,67 @@
+//! High-level helpers for marking packets and setting their DSCP or TOS field in the `mangle`
+//! table, e.g. for traffic shaping.
+
+use crate::rule::Rule;
+use crate::{IPTResult, IPTables, Table};
+
+impl IPTables {
+    /// Marks the packets matched by `selector` with `mark` in the bits of `mask` by appending a
+    /// `MARK` rule to the table/chain, unless the rule already exists. The target of `selector`
+    /// is replaced. Remove the mark with `delete_rule` and `selector.clone().set_mark(mark, mask)`.
+    pub fn set_mark(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        selector: &Rule,
+        mark: u32,
+        mask: Option<u32>,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule = selector.clone().set_mark(mark, mask);
+        self.append_rule_if_missing(table, chain, &rule)
+    }
+
+    /// Marks the connections of the packets matched by `selector` with `mark` in the bits of
+    /// `mask` by appending a `CONNMARK` rule to the table/chain, unless the rule already exists.
+    pub fn set_connmark(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        selector: &Rule,
+        mark: u32,
+        mask: Option<u32>,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule = selector.clone().set_connmark(mark, mask);
+        self.append_rule_if_missing(table, chain, &rule)
+    }
+
+    /// Sets the DSCP field of the packets matched by `selector` to `dscp` by appending a `DSCP`
+    /// rule to the table/chain, unless the rule already exists.
+    pub fn set_dscp(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        selector: &Rule,
+        dscp: u8,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule = selector.clone().set_dscp(dscp);
+        self.append_rule_if_missing(table, chain, &rule)
+    }
+
+    /// Sets the TOS field of the packets matched by `selector` to `tos` in the bits of `mask` by
+    /// appending a `TOS` rule to the table/chain, unless the rule already exists.
+    pub fn set_tos(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        selector: &Rule,
+        tos: u8,
+        mask: Option<u8>,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule = selector.clone().set_tos(tos, mask);
+        self.append_rule_if_missing(table, chain, &rule)
+    }
+}
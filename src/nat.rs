@@ -0,0 +1,278 @@
+//! High-level helpers for common router-style setups in the `nat` table.
+
+use crate::rule::Rule;
+use crate::{IPTResult, IPTables};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+
+/// The addresses and ports packets are translated to by `Dnat` and `Snat`, rendered as
+/// `--to-destination`/`--to-source` like `iptables -S` prints it, e.g. `10.0.0.1-10.0.0.5:80-90`
+/// or `[fd00::1]:80`.
+///
+/// # Example
+/// ```
+/// use iptables::nat::NatRange;
+/// use std::net::SocketAddr;
+///
+/// let to = NatRange::from("10.0.0.1:8080".parse::<SocketAddr>().unwrap());
+/// assert_eq!(to.to_string(), "10.0.0.1:8080");
+/// let to = NatRange {
+///     addresses: Some("fd00::1".parse().unwrap()..="fd00::5".parse().unwrap()),
+///     ports: Some(1000..=2000),
+/// };
+/// assert_eq!(to.to_string(), "[fd00::1-fd00::5]:1000-2000");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NatRange {
+    /// The addresses to translate to, the address is kept if `None`.
+    pub addresses: Option<RangeInclusive<IpAddr>>,
+    /// The ports to translate to, the port is kept if `None`. Requires a protocol with ports.
+    pub ports: Option<RangeInclusive<u16>>,
+}
+
+impl From<IpAddr> for NatRange {
+    fn from(address: IpAddr) -> Self {
+        NatRange {
+            addresses: Some(address..=address),
+            ports: None,
+        }
+    }
+}
+
+impl From<SocketAddr> for NatRange {
+    fn from(address: SocketAddr) -> Self {
+        NatRange {
+            addresses: Some(address.ip()..=address.ip()),
+            ports: Some(address.port()..=address.port()),
+        }
+    }
+}
+
+impl fmt::Display for NatRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(addresses) = &self.addresses {
+            let mut range = addresses.start().to_string();
+            if addresses.start() != addresses.end() {
+                range = format!("{}-{}", range, addresses.end());
+            }
+            // IPv6 addresses have to be enclosed in brackets to be followed by a port
+            if addresses.start().is_ipv6() && self.ports.is_some() {
+                range = format!("[{}]", range);
+            }
+            write!(f, "{}", range)?;
+        }
+        if let Some(ports) = &self.ports {
+            write!(f, ":{}", port_range(ports))?;
+        }
+        Ok(())
+    }
+}
+
+/// Translates the destination of the packets (`-j DNAT --to-destination`), only valid in the
+/// `PREROUTING` and `OUTPUT` chains of the `nat` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dnat {
+    /// The destination addresses and ports.
+    pub to: NatRange,
+}
+
+/// Translates the source of the packets (`-j SNAT --to-source`), only valid in the
+/// `POSTROUTING` and `INPUT` chains of the `nat` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snat {
+    /// The source addresses and ports.
+    pub to: NatRange,
+}
+
+/// Redirects the packets to a port of the local host (`-j REDIRECT`), e.g. to a transparent
+/// proxy. Only valid in the `PREROUTING` and `OUTPUT` chains of the `nat` table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Redirect {
+    /// The local ports (`--to-ports`), the destination port is kept if `None`.
+    pub to_ports: Option<RangeInclusive<u16>>,
+}
+
+/// Translates the source of the packets to the address of the outgoing interface
+/// (`-j MASQUERADE`), only valid in the `POSTROUTING` chain of the `nat` table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Masquerade {
+    /// The source ports (`--to-ports`), the source port is kept if possible if `None`.
+    pub to_ports: Option<RangeInclusive<u16>>,
+    /// Randomizes the source ports (`--random`).
+    pub random: bool,
+}
+
+/// A NAT target of a rule, set with `Rule::nat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NatTarget {
+    /// See `Dnat`.
+    Dnat(Dnat),
+    /// See `Snat`.
+    Snat(Snat),
+    /// See `Redirect`.
+    Redirect(Redirect),
+    /// See `Masquerade`.
+    Masquerade(Masquerade),
+}
+
+impl NatTarget {
+    /// Returns the name of the target and its options, in the order `iptables -S` prints them.
+    pub(crate) fn target_args(&self) -> (&'static str, Vec<String>) {
+        let to_ports = |ports: &Option<RangeInclusive<u16>>| match ports {
+            Some(ports) => vec!["--to-ports".to_string(), port_range(ports)],
+            None => Vec::new(),
+        };
+        match self {
+            NatTarget::Dnat(dnat) => (
+                "DNAT",
+                vec!["--to-destination".to_string(), dnat.to.to_string()],
+            ),
+            NatTarget::Snat(snat) => ("SNAT", vec!["--to-source".to_string(), snat.to.to_string()]),
+            NatTarget::Redirect(redirect) => ("REDIRECT", to_ports(&redirect.to_ports)),
+            NatTarget::Masquerade(masquerade) => {
+                let mut args = to_ports(&masquerade.to_ports);
+                if masquerade.random {
+                    args.push("--random".to_string());
+                }
+                ("MASQUERADE", args)
+            }
+        }
+    }
+}
+
+impl From<Dnat> for NatTarget {
+    fn from(dnat: Dnat) -> Self {
+        NatTarget::Dnat(dnat)
+    }
+}
+
+impl From<Snat> for NatTarget {
+    fn from(snat: Snat) -> Self {
+        NatTarget::Snat(snat)
+    }
+}
+
+impl From<Redirect> for NatTarget {
+    fn from(redirect: Redirect) -> Self {
+        NatTarget::Redirect(redirect)
+    }
+}
+
+impl From<Masquerade> for NatTarget {
+    fn from(masquerade: Masquerade) -> Self {
+        NatTarget::Masquerade(masquerade)
+    }
+}
+
+// A single port is rendered without the range
+fn port_range(ports: &RangeInclusive<u16>) -> String {
+    if ports.start() == ports.end() {
+        ports.start().to_string()
+    } else {
+        format!("{}-{}", ports.start(), ports.end())
+    }
+}
+
+/// Builds the `PREROUTING` rule which forwards `ext_port` of the protocol `proto` (`tcp` or `udp`)
+/// to `dest_port` of `dest_ip`, optionally only for packets received on `iface`.
+pub fn forward_port_rule(
+    proto: &str,
+    ext_port: u16,
+    dest_ip: &str,
+    dest_port: u16,
+    iface: Option<&str>,
+) -> Rule {
+    // IPv6 addresses have to be enclosed in brackets to be followed by a port
+    let destination = if dest_ip.contains(':') {
+        format!("[{}]:{}", dest_ip, dest_port)
+    } else {
+        format!("{}:{}", dest_ip, dest_port)
+    };
+
+    let mut rule = Rule::new();
+    if let Some(iface) = iface {
+        rule = rule.in_interface(iface);
+    }
+    rule.protocol(proto)
+        .dport(ext_port)
+        .jump_with("DNAT", &["--to-destination", &destination])
+}
+
+/// Builds the `POSTROUTING` rule which masquerades packets sent on `out_iface`,
+/// optionally only those from `source_cidr`.
+pub fn masquerade_rule(out_iface: &str, source_cidr: Option<&str>) -> Rule {
+    let mut rule = Rule::new();
+    if let Some(source_cidr) = source_cidr {
+        rule = rule.source(source_cidr);
+    }
+    rule.out_interface(out_iface).jump("MASQUERADE")
+}
+
+impl IPTables {
+    /// Forwards `ext_port` of the protocol `proto` to `dest_port` of `dest_ip` by appending a `DNAT`
+    /// rule to the `PREROUTING` chain of the `nat` table, unless the rule already exists.
+    pub fn forward_port(
+        &self,
+        proto: &str,
+        ext_port: u16,
+        dest_ip: &str,
+        dest_port: u16,
+        iface: Option<&str>,
+    ) -> IPTResult<()> {
+        let rule = forward_port_rule(proto, ext_port, dest_ip, dest_port, iface);
+        self.append_rule_if_missing("nat", "PREROUTING", &rule)
+    }
+
+    /// Removes a port forwarding installed by `forward_port` with the same arguments.
+    pub fn remove_forward_port(
+        &self,
+        proto: &str,
+        ext_port: u16,
+        dest_ip: &str,
+        dest_port: u16,
+        iface: Option<&str>,
+    ) -> IPTResult<()> {
+        let rule = forward_port_rule(proto, ext_port, dest_ip, dest_port, iface);
+        self.delete_rule_if_present("nat", "PREROUTING", &rule)
+    }
+
+    /// Masquerades packets sent on `out_iface` by appending a `MASQUERADE` rule to the
+    /// `POSTROUTING` chain of the `nat` table, unless the rule already exists.
+    pub fn masquerade(&self, out_iface: &str, source_cidr: Option<&str>) -> IPTResult<()> {
+        let rule = masquerade_rule(out_iface, source_cidr);
+        self.append_rule_if_missing("nat", "POSTROUTING", &rule)
+    }
+
+    /// Removes a masquerading installed by `masquerade` with the same arguments.
+    pub fn remove_masquerade(&self, out_iface: &str, source_cidr: Option<&str>) -> IPTResult<()> {
+        let rule = masquerade_rule(out_iface, source_cidr);
+        self.delete_rule_if_present("nat", "POSTROUTING", &rule)
+    }
+
+    pub(crate) fn append_rule_if_missing(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &Rule,
+    ) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        if self.exists_rule(table, chain, rule)? {
+            return Ok(());
+        }
+        self.append_rule(table, chain, rule)
+    }
+
+    pub(crate) fn delete_rule_if_present(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &Rule,
+    ) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        if !self.exists_rule(table, chain, rule)? {
+            return Ok(());
+        }
+        self.delete_rule(table, chain, rule)
+    }
+}
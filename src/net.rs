@@ -0,0 +1,109 @@
+//! Typed networks for the source and destination of a rule, see `Rule::source_net` and
+//! `Rule::destination_net`.
+
+use crate::validate::ValidationError;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 network in CIDR notation, e.g. `10.0.0.0/8`.
+///
+/// The host bits of the address are cleared like iptables does, so the network renders the way
+/// `iptables -S` prints it. A single address converts to a network with the longest prefix.
+///
+/// # Example
+/// ```
+/// use iptables::net::Cidr;
+/// use std::net::Ipv4Addr;
+///
+/// let net: Cidr = "10.1.2.3/8".parse().unwrap();
+/// assert_eq!(net.to_string(), "10.0.0.0/8");
+/// assert_eq!(Cidr::from(Ipv4Addr::LOCALHOST).to_string(), "127.0.0.1/32");
+/// assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cidr {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Creates the network of `address` with the `prefix` length, which must not be longer than
+    /// 32 bits for IPv4 or 128 bits for IPv6.
+    pub fn new(address: IpAddr, prefix: u8) -> Result<Cidr, ValidationError> {
+        let address = match address {
+            IpAddr::V4(v4) if prefix <= 32 => {
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask))
+            }
+            IpAddr::V6(v6) if prefix <= 128 => {
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask))
+            }
+            _ => {
+                return Err(ValidationError::InvalidAddress(format!(
+                    "prefix /{} is too long for {}",
+                    prefix, address
+                )))
+            }
+        };
+        Ok(Cidr { address, prefix })
+    }
+
+    /// Returns the address of the network, without host bits.
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    /// Returns the length of the prefix in bits.
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Indicates if this is an IPv6 network.
+    pub fn is_ipv6(&self) -> bool {
+        self.address.is_ipv6()
+    }
+}
+
+impl From<IpAddr> for Cidr {
+    fn from(address: IpAddr) -> Self {
+        let prefix = if address.is_ipv6() { 128 } else { 32 };
+        Cidr { address, prefix }
+    }
+}
+
+impl From<Ipv4Addr> for Cidr {
+    fn from(address: Ipv4Addr) -> Self {
+        Cidr::from(IpAddr::V4(address))
+    }
+}
+
+impl From<Ipv6Addr> for Cidr {
+    fn from(address: Ipv6Addr) -> Self {
+        Cidr::from(IpAddr::V6(address))
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ValidationError;
+
+    /// Parses a network like `10.0.0.0/8` or a single address like `::1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ValidationError::InvalidAddress(format!("invalid network {}", s));
+        match s.split_once('/') {
+            Some((address, prefix)) => Cidr::new(
+                address.parse().map_err(|_| invalid())?,
+                prefix.parse().map_err(|_| invalid())?,
+            ),
+            None => s.parse::<IpAddr>().map(Cidr::from).map_err(|_| invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
@@ -0,0 +1,61 @@
+//! Options which override the defaults of an `IPTables` instance for a single call.
+
+use crate::lock::LockPolicy;
+use std::time::Duration;
+
+/// Overrides the defaults of an `IPTables` instance for a single call of the `*_with` methods,
+/// e.g. to probe for a rule without blocking while other calls wait for the xtables lock.
+///
+/// # Example
+/// ```no_run
+/// use iptables::CallOptions;
+/// use std::time::Duration;
+///
+/// let ipt = iptables::new(false).unwrap();
+/// let probe = CallOptions::no_wait();
+/// if !ipt.exists_with("filter", "INPUT", "-j ACCEPT", &probe).unwrap() {
+///     let options = CallOptions::wait(Duration::from_secs(10));
+///     ipt.append_with("filter", "INPUT", "-j ACCEPT", &options).unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallOptions {
+    /// The maximum time to wait for the xtables lock, `IPTables::wait_timeout` is used if
+    /// `None`. With `Some(Duration::ZERO)` the call fails right away if the lock is held.
+    ///
+    /// A timeout is rounded up to whole seconds and waits forever if iptables does not support
+    /// a timeout for the -w (--wait) option. Versions without the -w option retry the lock
+    /// according to `IPTables::lock_policy` within the timeout.
+    pub wait: Option<Duration>,
+}
+
+impl CallOptions {
+    /// Fails right away if the xtables lock is held by another process.
+    pub fn no_wait() -> Self {
+        Self {
+            wait: Some(Duration::ZERO),
+        }
+    }
+
+    /// Waits at most `timeout` for the xtables lock.
+    pub fn wait(timeout: Duration) -> Self {
+        Self {
+            wait: Some(timeout),
+        }
+    }
+
+    // The lock policy of iptables versions without the -w (--wait) option
+    pub(crate) fn lock_policy(&self, policy: LockPolicy) -> LockPolicy {
+        match self.wait {
+            Some(timeout) if timeout == Duration::ZERO => LockPolicy {
+                max_attempts: Some(1),
+                ..policy
+            },
+            Some(timeout) => LockPolicy {
+                timeout: Some(timeout),
+                ..policy
+            },
+            None => policy,
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! The complete output of an iptables command, returned by the `*_verbose` methods of `IPTables`
+//! so automation can log what iptables printed, also when it succeeded.
+
+use crate::error::IptablesError;
+use crate::runner::command_line;
+use crate::{as_strs, CallOptions, IPTResult, IPTables, RuleSpec, Table};
+use std::fmt;
+use std::process::Output;
+
+/// The command line of a successful iptables command together with everything it printed,
+/// e.g. warnings about rules which are incompatible with nf_tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OperationOutput {
+    /// The command line which was run, starting with the program, e.g.
+    /// `["iptables", "-t", "filter", "-A", "INPUT", "-j", "ACCEPT", "--wait"]`.
+    pub argv: Vec<String>,
+    /// The exit code of iptables.
+    pub status: i32,
+    /// The standard output of iptables.
+    pub stdout: String,
+    /// The standard error output of iptables.
+    pub stderr: String,
+}
+
+impl OperationOutput {
+    /// Indicates if iptables printed anything to its standard error output.
+    pub fn has_warnings(&self) -> bool {
+        !self.stderr.trim().is_empty()
+    }
+}
+
+impl fmt::Display for OperationOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (code: {})", self.argv.join(" "), self.status)?;
+        for line in self.stdout.lines().chain(self.stderr.lines()) {
+            write!(f, "\n{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl IPTables {
+    /// Appends `rule` to the table/chain like `append` and returns the command line and output
+    /// of iptables.
+    pub fn append_verbose(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<OperationOutput> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        if self.validate_rules {
+            rule.validate(self.is_ipv6)?;
+        }
+        let args = self.tagged(rule.into_args());
+        self.run_verbose(&[&["-t", table, "-A", chain], as_strs(&args).as_slice()].concat())
+    }
+
+    /// Inserts `rule` in the `position` to the table/chain like `insert` and returns the command
+    /// line and output of iptables.
+    pub fn insert_verbose(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: i32,
+    ) -> IPTResult<OperationOutput> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        if self.validate_rules {
+            rule.validate(self.is_ipv6)?;
+        }
+        let args = self.tagged(rule.into_args());
+        self.run_verbose(
+            &[
+                &["-t", table, "-I", chain, &position.to_string()],
+                as_strs(&args).as_slice(),
+            ]
+            .concat(),
+        )
+    }
+
+    /// Deletes `rule` from the table/chain like `delete` and returns the command line and output
+    /// of iptables. Like `delete`, the rule is sanitized but not validated, see
+    /// `IPTablesBuilder::validate_rules`.
+    pub fn delete_verbose(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<OperationOutput> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        let args = self.tagged(rule.into_args());
+        self.run_verbose(&[&["-t", table, "-D", chain], as_strs(&args).as_slice()].concat())
+    }
+
+    // Runs iptables like `run` and keeps its command line, failures are returned as errors
+    fn run_verbose(&self, args: &[&str]) -> IPTResult<OperationOutput> {
+        let options = CallOptions::default();
+        let (program, rest) = command_line(&self.iptables_command(args, &options));
+        let output = self.run_with(args, &options)?;
+        operation_output([vec![program], rest].concat(), output)
+    }
+}
+
+fn operation_output(argv: Vec<String>, output: Output) -> IPTResult<OperationOutput> {
+    if !output.status.success() {
+        return Err(IptablesError::from(output).into());
+    }
+    Ok(OperationOutput {
+        argv,
+        status: output.status.code().unwrap_or(-1),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
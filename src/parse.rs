@@ -0,0 +1,313 @@
+//! Typed representation of the rules listed by `iptables -S`.
+
+use crate::rule::join_quoted;
+use crate::{IPTError, IPTResult, SplitQuoted};
+use std::iter::Peekable;
+use std::net::IpAddr;
+use std::vec::IntoIter;
+
+/// A match module (`-m <name>`) of a parsed rule together with its options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParsedMatch {
+    /// The name of the match module, e.g. `tcp` or `comment`.
+    pub name: String,
+    /// The options given to the match module, e.g. `["--dport", "80"]`.
+    pub args: Vec<String>,
+}
+
+/// A rule parsed from a single `-A` line of `iptables -S` output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParsedRule {
+    /// The chain the rule belongs to.
+    pub chain: String,
+    /// The protocol matched by `-p`.
+    pub protocol: Option<String>,
+    /// The source matched by `-s`.
+    pub source: Option<String>,
+    /// The destination matched by `-d`.
+    pub destination: Option<String>,
+    /// The input interface matched by `-i`.
+    pub in_interface: Option<String>,
+    /// The output interface matched by `-o`.
+    pub out_interface: Option<String>,
+    /// The basic options negated with `!`, in their short form, e.g. `["-s"]` for
+    /// `! -s 10.0.0.0/8`. Negated options of match modules keep their `!` in `args`.
+    pub negated: Vec<String>,
+    /// The match modules loaded by `-m`, in the order they appear in the rule.
+    pub matches: Vec<ParsedMatch>,
+    /// The target given by `-j` or `-g`.
+    pub target: Option<String>,
+    /// The options given to the target, e.g. `["--to-destination", "10.0.0.1"]`.
+    pub target_args: Vec<String>,
+    /// Indicates if the target is a `-g` (goto) instead of a `-j` (jump).
+    pub goto: bool,
+}
+
+impl ParsedRule {
+    /// Parses a single `-A` line of `iptables -S` output.
+    pub fn parse(line: &str) -> IPTResult<ParsedRule> {
+        let args = line.split_quoted();
+        if args.len() < 2 || args[0] != "-A" {
+            return Err(IPTError::Parse(
+                "rule does not start with -A <chain>".to_string(),
+            ));
+        }
+
+        let mut rule = ParsedRule {
+            chain: args[1].clone(),
+            ..ParsedRule::default()
+        };
+        let mut args = args.into_iter().skip(2);
+        let mut in_target = false;
+        let mut negated = false;
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| IPTError::Parse("option is missing a value".to_string()))
+            };
+
+            let basic = matches!(
+                arg.as_str(),
+                "-p" | "--protocol"
+                    | "-s"
+                    | "--source"
+                    | "-d"
+                    | "--destination"
+                    | "-i"
+                    | "--in-interface"
+                    | "-o"
+                    | "--out-interface"
+            );
+            if std::mem::take(&mut negated) && basic {
+                rule.negated.push(short_option(&arg));
+            }
+
+            match arg.as_str() {
+                "-p" | "--protocol" => rule.protocol = Some(value()?),
+                "-s" | "--source" => rule.source = Some(value()?),
+                "-d" | "--destination" => rule.destination = Some(value()?),
+                "-i" | "--in-interface" => rule.in_interface = Some(value()?),
+                "-o" | "--out-interface" => rule.out_interface = Some(value()?),
+                "-m" | "--match" => {
+                    in_target = false;
+                    rule.matches.push(ParsedMatch {
+                        name: value()?,
+                        args: Vec::new(),
+                    });
+                }
+                "-j" | "--jump" | "-g" | "--goto" => {
+                    in_target = true;
+                    rule.goto = arg == "-g" || arg == "--goto";
+                    rule.target = Some(value()?);
+                }
+                "-c" | "--set-counters" => {
+                    value()?;
+                    value()?;
+                }
+                "!" if !in_target && rule.matches.is_empty() => negated = true,
+                _ if in_target => rule.target_args.push(arg),
+                _ => match rule.matches.last_mut() {
+                    Some(m) => m.args.push(arg),
+                    None => {
+                        return Err(IPTError::Parse(
+                            "option given before any match module".to_string(),
+                        ))
+                    }
+                },
+            }
+        }
+
+        Ok(rule)
+    }
+
+    /// Returns true if the basic option `option` is negated, given in its short form like
+    /// `-s` or `-i`.
+    pub fn is_negated(&self, option: &str) -> bool {
+        self.negated.iter().any(|negated| negated == option)
+    }
+
+    /// Returns the match module `name` if the rule loads it.
+    pub fn get_match(&self, name: &str) -> Option<&ParsedMatch> {
+        self.matches.iter().find(|m| m.name == name)
+    }
+}
+
+/// Returns the canonical form of `rule`, given without the leading `-A <chain>`, so rules which
+/// only differ cosmetically compare equal, see `IPTables::exists_normalized`.
+///
+/// Long options are replaced by their short form, addresses get their prefix length (`/32` or
+/// `/128` if missing) and host bits are cleared, options of the protocol given without `-m` are
+/// moved to the implicit match module of the protocol, and the match modules as well as the
+/// options of each module and of the target are sorted.
+///
+/// # Example
+/// ```
+/// use iptables::parse::canonical_rule;
+///
+/// assert_eq!(
+///     canonical_rule("--protocol TCP --dport 22 -s 10.0.0.1 -j ACCEPT").unwrap(),
+///     canonical_rule("-s 10.0.0.1/32 -p tcp -m tcp --dport 22 -j ACCEPT").unwrap(),
+/// );
+/// ```
+pub fn canonical_rule(rule: &str) -> IPTResult<String> {
+    let mut basic: Vec<Vec<String>> = Vec::new();
+    let mut matches: Vec<(String, Vec<Vec<String>>)> = Vec::new();
+    let mut target: Vec<String> = Vec::new();
+    let mut target_args: Vec<Vec<String>> = Vec::new();
+    // Options of the protocol given before or without `-m <protocol>`
+    let mut loose: Vec<Vec<String>> = Vec::new();
+    let mut protocol = None;
+    let mut current_match = None;
+    let mut in_target = false;
+    let mut negated = false;
+
+    let mut args = rule.split_quoted().into_iter().peekable();
+    while let Some(arg) = args.next() {
+        if arg == "!" {
+            negated = true;
+            continue;
+        }
+        let mut group = Vec::new();
+        if std::mem::take(&mut negated) {
+            group.push("!".to_string());
+        }
+
+        match arg.as_str() {
+            "-p" | "--protocol" => {
+                let name = option_value(&mut args, &arg)?.to_lowercase();
+                protocol = Some(name.clone());
+                group.extend(["-p".to_string(), name]);
+                basic.push(group);
+            }
+            "-s" | "--source" | "-d" | "--destination" => {
+                let address = canonical_address(&option_value(&mut args, &arg)?);
+                group.extend([short_option(&arg), address]);
+                basic.push(group);
+            }
+            "-i" | "--in-interface" | "-o" | "--out-interface" => {
+                group.extend([short_option(&arg), option_value(&mut args, &arg)?]);
+                basic.push(group);
+            }
+            "-f" | "--fragment" => {
+                group.push("-f".to_string());
+                basic.push(group);
+            }
+            "-m" | "--match" => {
+                in_target = false;
+                matches.push((option_value(&mut args, &arg)?, Vec::new()));
+                current_match = Some(matches.len() - 1);
+            }
+            "-j" | "--jump" | "-g" | "--goto" => {
+                in_target = true;
+                target = vec![short_option(&arg), option_value(&mut args, &arg)?];
+            }
+            "-c" | "--set-counters" => {
+                option_value(&mut args, &arg)?;
+                option_value(&mut args, &arg)?;
+            }
+            _ => {
+                group.push(
+                    match arg.as_str() {
+                        "--destination-port" => "--dport",
+                        "--source-port" => "--sport",
+                        option => option,
+                    }
+                    .to_string(),
+                );
+                while let Some(value) = args.next_if(|next| !is_option(next)) {
+                    group.push(value);
+                }
+                if in_target {
+                    target_args.push(group);
+                } else if let Some(index) = current_match {
+                    matches[index].1.push(group);
+                } else {
+                    loose.push(group);
+                }
+            }
+        }
+    }
+
+    if !loose.is_empty() {
+        let protocol = protocol
+            .ok_or_else(|| IPTError::Parse("option given before any match module".to_string()))?;
+        match matches.iter_mut().find(|(name, _)| *name == protocol) {
+            Some((_, options)) => options.extend(loose),
+            None => matches.push((protocol, loose)),
+        }
+    }
+
+    basic.sort();
+    for (_, options) in &mut matches {
+        options.sort();
+    }
+    matches.sort();
+    target_args.sort();
+
+    let mut canonical = basic.concat();
+    for (name, options) in matches {
+        canonical.extend(["-m".to_string(), name]);
+        canonical.extend(options.concat());
+    }
+    canonical.extend(target);
+    canonical.extend(target_args.concat());
+    Ok(join_quoted(&canonical))
+}
+
+fn option_value(args: &mut Peekable<IntoIter<String>>, option: &str) -> IPTResult<String> {
+    args.next()
+        .ok_or_else(|| IPTError::Parse(format!("option {} is missing a value", option)))
+}
+
+fn short_option(option: &str) -> String {
+    match option {
+        "--source" => "-s",
+        "--destination" => "-d",
+        "--in-interface" => "-i",
+        "--out-interface" => "-o",
+        "--protocol" => "-p",
+        "--jump" => "-j",
+        "--goto" => "-g",
+        option => option,
+    }
+    .to_string()
+}
+
+// Values never look like options, except for negative numbers which are not used by iptables
+fn is_option(arg: &str) -> bool {
+    arg == "!" || (arg.starts_with('-') && arg.len() > 1)
+}
+
+// Formats an address with its prefix length and without host bits, the way `iptables -S` prints
+// it. Hostnames and address lists are kept as given.
+fn canonical_address(address: &str) -> String {
+    let (ip, prefix) = match address.split_once('/') {
+        Some((ip, prefix)) => (ip, Some(prefix)),
+        None => (address, None),
+    };
+    let ip = match ip.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => return address.to_string(),
+    };
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix.map(str::parse::<u32>) {
+        None => max,
+        Some(Ok(prefix)) if prefix <= max => prefix,
+        Some(_) => return address.to_string(),
+    };
+
+    let network = match ip {
+        IpAddr::V4(ip) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            IpAddr::from((u32::from(ip) & mask).to_be_bytes())
+        }
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            IpAddr::from((u128::from(ip) & mask).to_be_bytes())
+        }
+    };
+    format!("{}/{}", network, prefix)
+}
@@ -0,0 +1,151 @@
+//! Positions of rules relative to the start or the end of a chain.
+
+use crate::parse::canonical_rule;
+use crate::rule::join_quoted;
+use crate::{IPTError, IPTResult, IPTables, RuleSpec, Table};
+
+/// The position of a rule inserted with `IPTables::insert_relative`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Position {
+    /// The position counted from the start of the chain, the first rule has position 1.
+    FromStart(u32),
+    /// The number of rules which follow the inserted rule, so `FromEnd(0)` appends the rule and
+    /// `FromEnd(1)` inserts it in front of the last rule. Positions before the first rule insert
+    /// the rule at the start of the chain.
+    FromEnd(u32),
+}
+
+impl Position {
+    /// Returns the absolute position in a chain of `len` rules, the first rule has position 1.
+    pub fn resolve(&self, len: u32) -> u32 {
+        match *self {
+            Position::FromStart(position) => position.max(1),
+            Position::FromEnd(offset) => len.saturating_sub(offset) + 1,
+        }
+    }
+}
+
+impl IPTables {
+    /// Inserts `rule` in the `position` to the table/chain, which can be counted from the end of
+    /// the chain, e.g. `Position::FromEnd(1)` to insert the rule just before a final `REJECT`.
+    /// The rules are counted right before inserting, with other operations of the instance
+    /// waiting in between.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use iptables::position::Position;
+    ///
+    /// let ipt = iptables::new(false).unwrap();
+    /// ipt.insert_relative("filter", "INPUT", "-p tcp --dport 80 -j ACCEPT", Position::FromEnd(1))
+    ///     .unwrap();
+    /// ```
+    pub fn insert_relative(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: Position,
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        let _serial = self.serial.lock();
+        let len = self.list_numbered(table, chain)?.len() as u32;
+        let position = position.resolve(len);
+        if position > len {
+            return self.append(table, chain, &rule);
+        }
+        self.insert(table, chain, &rule, position as i32)
+    }
+
+    /// Returns the position of the first copy of `rule` in the table/chain, the first rule has
+    /// position 1, or `None` if the chain does not contain the rule. The rules are compared in
+    /// their canonical form like `exists_normalized`, so the position can be used to insert
+    /// another rule right before or after it.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let ipt = iptables::new(false).unwrap();
+    /// if let Some(position) = ipt
+    ///     .position_of("filter", "INPUT", "-m conntrack --ctstate ESTABLISHED -j ACCEPT")
+    ///     .unwrap()
+    /// {
+    ///     ipt.insert("filter", "INPUT", "-p tcp --dport 22 -j ACCEPT", position as i32 + 1)
+    ///         .unwrap();
+    /// }
+    /// ```
+    pub fn position_of(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<Option<u32>> {
+        let table: &str = &table.into();
+        let rule: RuleSpec = rule.into();
+        self.sanitize(rule.args())?;
+        let expected = canonical_rule(&join_quoted(&self.tagged(rule.into_args())))?;
+        Ok(position_in(&self.list_numbered(table, chain)?, &expected))
+    }
+
+    /// Inserts `rule` right before the first copy of `anchor` in the table/chain, found like
+    /// `position_of`. Fails with `IPTError::NotFound` if the chain does not contain `anchor`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let ipt = iptables::new(false).unwrap();
+    /// ipt.insert_before("filter", "INPUT", "-j DROP", "-p tcp --dport 22 -j ACCEPT")
+    ///     .unwrap();
+    /// ```
+    pub fn insert_before(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        anchor: impl Into<RuleSpec>,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        self.insert_next_to(&table.into(), chain, anchor.into(), rule.into(), 0)
+    }
+
+    /// Inserts `rule` right after the first copy of `anchor` in the table/chain, found like
+    /// `position_of`. Fails with `IPTError::NotFound` if the chain does not contain `anchor`.
+    pub fn insert_after(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        anchor: impl Into<RuleSpec>,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        self.insert_next_to(&table.into(), chain, anchor.into(), rule.into(), 1)
+    }
+
+    // Inserts `rule` at the position of `anchor` plus `offset`, with other operations of the
+    // instance waiting in between
+    fn insert_next_to(
+        &self,
+        table: &str,
+        chain: &str,
+        anchor: RuleSpec,
+        rule: RuleSpec,
+        offset: u32,
+    ) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        match self.position_of(table, chain, &anchor)? {
+            Some(position) => self.insert(table, chain, &rule, (position + offset) as i32),
+            None => Err(anchor_missing(table, chain, &anchor)),
+        }
+    }
+}
+
+pub(crate) fn anchor_missing(table: &str, chain: &str, anchor: &RuleSpec) -> IPTError {
+    IPTError::NotFound(format!(
+        "the rule \"{}\" is not in the table/chain {}/{}",
+        anchor, table, chain
+    ))
+}
+
+// Returns the position of the first rule of `numbered` with the `canonical` form
+pub(crate) fn position_in(numbered: &[(u32, String)], canonical: &str) -> Option<u32> {
+    numbered
+        .iter()
+        .find(|(_, rule)| canonical_rule(rule).is_ok_and(|rule| rule == canonical))
+        .map(|(position, _)| *position)
+}
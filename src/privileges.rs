@@ -0,0 +1,83 @@
+//! Detection of the privileges needed to read and modify the rules.
+
+use crate::error::IptablesError;
+use crate::{IPTError, IPTResult, IPTables};
+use std::fs;
+use std::io;
+use std::process::Output;
+
+// The bit of CAP_NET_ADMIN in the capability sets, see capabilities(7)
+const CAP_NET_ADMIN: u32 = 12;
+
+// Messages printed by iptables if the process lacks the privileges
+const MSG_PERMISSION_DENIED: &str = "Permission denied";
+const MSG_NOT_PERMITTED: &str = "Operation not permitted";
+
+/// The privileges of an `IPTables` instance, returned by `IPTables::check_permissions`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Privileges {
+    /// Indicates if the rules can be listed.
+    pub read: bool,
+    /// Indicates if the rules can be modified.
+    pub write: bool,
+}
+
+impl IPTables {
+    /// Detects if the rules can be read and modified, so an application can fail fast with a
+    /// clear message instead of failing on its first change.
+    ///
+    /// Reading is probed by listing the `filter` table. Writing is assumed if reading works and
+    /// the commands run through a `command_prefix` like `sudo`, or the process has the
+    /// `CAP_NET_ADMIN` capability, as nothing is changed to probe it. Failures which are not
+    /// caused by missing privileges, e.g. a missing binary, are returned as errors.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let ipt = iptables::new(false).unwrap();
+    /// if !ipt.check_permissions().unwrap().write {
+    ///     eprintln!("run as root or grant CAP_NET_ADMIN to manage the firewall");
+    /// }
+    /// ```
+    pub fn check_permissions(&self) -> IPTResult<Privileges> {
+        self.privileges(self.execute_table("filter", &["-S"]))
+    }
+
+    // Interprets the result of the probe listing the filter table
+    pub(crate) fn privileges(&self, probe: IPTResult<Output>) -> IPTResult<Privileges> {
+        let read = match probe {
+            Ok(output) if output.status.success() => true,
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(output.stderr.as_slice());
+                if !stderr.contains(MSG_PERMISSION_DENIED) && !stderr.contains(MSG_NOT_PERMITTED) {
+                    return Err(IptablesError::from(output).into());
+                }
+                false
+            }
+            // Raised by `netns_path` if the namespace cannot be entered
+            Err(IPTError::Io(err)) if err.kind() == io::ErrorKind::PermissionDenied => false,
+            Err(IPTError::CommandFailed(err))
+                if err.msg.contains(MSG_PERMISSION_DENIED)
+                    || err.msg.contains(MSG_NOT_PERMITTED) =>
+            {
+                false
+            }
+            Err(err) => return Err(err),
+        };
+
+        let write = read && (self.dry_run || !self.command_prefix.is_empty() || has_net_admin());
+        Ok(Privileges { read, write })
+    }
+}
+
+// Checks the effective capabilities of the process for CAP_NET_ADMIN
+fn has_net_admin() -> bool {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("CapEff:"))
+                .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        })
+        .is_some_and(|caps| caps & (1 << CAP_NET_ADMIN) != 0)
+}
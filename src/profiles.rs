@@ -0,0 +1,154 @@
+//! Ready-made firewall profiles built from rule sets, e.g. an allowlist-based host firewall.
+
+use crate::conntrack::CtState;
+use crate::icmp::{essential_icmpv6_rules, IcmpType};
+use crate::rule::Rule;
+use crate::ruleset::RuleSet;
+use crate::{IPTResult, IPTables};
+
+/// The chain of the `filter` table holding the `INPUT` rules of a `HostFirewall`.
+pub const INPUT_CHAIN: &str = "HOSTFW-INPUT";
+
+/// The chain of the `filter` table holding the `FORWARD` rules of a `HostFirewall`.
+pub const FORWARD_CHAIN: &str = "HOSTFW-FORWARD";
+
+/// A minimal firewall for a host which does not route: incoming packets on the loopback
+/// interface and of known connections are accepted, invalid packets are dropped and new
+/// connections are only accepted to the allowed services. For ip6tables the ICMPv6 messages
+/// IPv6 needs are accepted as well, see `icmp::essential_icmpv6_rules`.
+///
+/// The rules live in their own chains, `HOSTFW-INPUT` and `HOSTFW-FORWARD`, which are jumped to
+/// from the top of `INPUT` and `FORWARD`, so the firewall can be torn down without touching
+/// other rules. The policies of the built-in chains are not changed.
+///
+/// # Example
+/// ```no_run
+/// use iptables::profiles::HostFirewall;
+///
+/// let ipt = iptables::new(false).unwrap();
+/// let firewall = HostFirewall::new().allow_tcp(22).allow_tcp(443).default_drop();
+/// firewall.apply(&ipt).unwrap();
+/// firewall.teardown(&ipt).unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostFirewall {
+    allowed: Vec<Rule>,
+    allow_ping: bool,
+    default_drop: bool,
+}
+
+impl HostFirewall {
+    /// Creates a firewall which accepts everything, until services are allowed and
+    /// `default_drop` is set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts new TCP connections to `port`.
+    pub fn allow_tcp(self, port: u16) -> Self {
+        self.allow(Rule::new().protocol("tcp").dport(port))
+    }
+
+    /// Accepts UDP packets to `port`.
+    pub fn allow_udp(self, port: u16) -> Self {
+        self.allow(Rule::new().protocol("udp").dport(port))
+    }
+
+    /// Accepts the incoming packets matched by `selector`, e.g. SSH from a management network.
+    /// The target of `selector` is replaced.
+    pub fn allow(mut self, selector: Rule) -> Self {
+        self.allowed.push(selector.jump("ACCEPT"));
+        self
+    }
+
+    /// Accepts echo requests of IPv4. Echo requests of IPv6 are always accepted.
+    pub fn allow_ping(mut self) -> Self {
+        self.allow_ping = true;
+        self
+    }
+
+    /// Drops the incoming and forwarded packets which are not accepted by the firewall.
+    pub fn default_drop(mut self) -> Self {
+        self.default_drop = true;
+        self
+    }
+
+    /// Returns the chains of the firewall for the address family, IPv6 if `is_ipv6` is set.
+    /// The jumps from the built-in chains are not part of it, see `apply`.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::profiles::HostFirewall;
+    ///
+    /// let ruleset = HostFirewall::new().allow_tcp(22).default_drop().ruleset(false);
+    /// assert_eq!(
+    ///     ruleset.rules("filter", "HOSTFW-INPUT"),
+    ///     [
+    ///         "-i lo -j ACCEPT",
+    ///         "-m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT",
+    ///         "-m conntrack --ctstate INVALID -j DROP",
+    ///         "-p tcp -m tcp --dport 22 -j ACCEPT",
+    ///         "-j DROP",
+    ///     ]
+    /// );
+    /// ```
+    pub fn ruleset(&self, is_ipv6: bool) -> RuleSet {
+        let established = Rule::new()
+            .ctstate(&[CtState::Established, CtState::Related])
+            .jump("ACCEPT");
+        let mut input = vec![
+            Rule::new().in_interface("lo").jump("ACCEPT"),
+            established.clone(),
+            Rule::new().ctstate(&[CtState::Invalid]).jump("DROP"),
+        ];
+        if is_ipv6 {
+            input.extend(essential_icmpv6_rules());
+        } else if self.allow_ping {
+            input.push(Rule::new().icmp_type(IcmpType::EchoRequest).jump("ACCEPT"));
+        }
+        input.extend(self.allowed.iter().cloned());
+        let mut forward = vec![established];
+        if self.default_drop {
+            input.push(Rule::new().jump("DROP"));
+            forward.push(Rule::new().jump("DROP"));
+        }
+
+        let mut ruleset = RuleSet::new();
+        for (chain, rules) in [(INPUT_CHAIN, input), (FORWARD_CHAIN, forward)] {
+            ruleset.chain("filter", chain);
+            for rule in &rules {
+                ruleset.append_rule("filter", chain, rule);
+            }
+        }
+        ruleset
+    }
+
+    /// Applies the firewall: converges its chains to `ruleset` with `IPTables::apply` and
+    /// inserts the jumps to them at the top of `INPUT` and `FORWARD`, unless they exist.
+    /// Applying a changed firewall again only changes the differing rules.
+    pub fn apply(&self, ipt: &IPTables) -> IPTResult<()> {
+        let _serial = ipt.serial.lock();
+        ipt.apply(&self.ruleset(ipt.is_ipv6))?;
+        for (builtin, chain) in [("INPUT", INPUT_CHAIN), ("FORWARD", FORWARD_CHAIN)] {
+            let jump = Rule::new().jump(chain);
+            if !ipt.exists_rule("filter", builtin, &jump)? {
+                ipt.insert_rule("filter", builtin, &jump, 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tears the firewall down: deletes the jumps from `INPUT` and `FORWARD` and the chains of
+    /// the firewall, if they exist.
+    pub fn teardown(&self, ipt: &IPTables) -> IPTResult<()> {
+        let _serial = ipt.serial.lock();
+        for (builtin, chain) in [("INPUT", INPUT_CHAIN), ("FORWARD", FORWARD_CHAIN)] {
+            ipt.delete_rule_if_present("filter", builtin, &Rule::new().jump(chain))?;
+            if ipt.chain_exists("filter", chain)? {
+                ipt.flush_chain("filter", chain)?;
+                ipt.delete_chain("filter", chain)?;
+            }
+        }
+        Ok(())
+    }
+}
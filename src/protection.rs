@@ -0,0 +1,65 @@
+//! High-level helpers protecting a host from floods in the `filter` table.
+
+use crate::limit::Rate;
+use crate::rule::Rule;
+use crate::{IPTResult, IPTables};
+
+/// Builds the rules of the chain installed by `IPTables::syn_flood_protection`: connection
+/// attempts up to the average `rate`, allowing `burst` at once, return to the calling chain and
+/// the others are dropped.
+pub fn syn_flood_rules(rate: Rate, burst: u32) -> Vec<Rule> {
+    vec![
+        Rule::new().limit(rate, Some(burst)).jump("RETURN"),
+        Rule::new().jump("DROP"),
+    ]
+}
+
+/// Builds the `INPUT` rule which sends the packets opening a TCP connection to `chain`.
+pub fn syn_flood_jump_rule(chain: &str) -> Rule {
+    Rule::new().protocol("tcp").syn().jump(chain)
+}
+
+impl IPTables {
+    /// Limits the rate of incoming TCP connection attempts to `rate`, allowing `burst` at once,
+    /// and drops the attempts above it. The user-defined `chain` of the `filter` table is created
+    /// or flushed and filled with `syn_flood_rules`, and the packets opening a connection are
+    /// sent to it from the top of the `INPUT` chain, unless the jump already exists.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use iptables::limit::Rate;
+    ///
+    /// let ipt = iptables::new(false).unwrap();
+    /// ipt.syn_flood_protection("SYN_FLOOD", Rate::per_second(25), 50)
+    ///     .unwrap();
+    /// ```
+    pub fn syn_flood_protection(&self, chain: &str, rate: Rate, burst: u32) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        if self.chain_exists("filter", chain)? {
+            self.flush_chain("filter", chain)?;
+        } else {
+            self.new_chain("filter", chain)?;
+        }
+        for rule in syn_flood_rules(rate, burst) {
+            self.append_rule("filter", chain, &rule)?;
+        }
+
+        let jump = syn_flood_jump_rule(chain);
+        if !self.exists_rule("filter", "INPUT", &jump)? {
+            self.insert_rule("filter", "INPUT", &jump, 1)?;
+        }
+        Ok(())
+    }
+
+    /// Removes a protection installed by `syn_flood_protection` with the same `chain`: the jump
+    /// from the `INPUT` chain and the chain itself, if they exist.
+    pub fn remove_syn_flood_protection(&self, chain: &str) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        self.delete_rule_if_present("filter", "INPUT", &syn_flood_jump_rule(chain))?;
+        if self.chain_exists("filter", chain)? {
+            self.flush_chain("filter", chain)?;
+            self.delete_chain("filter", chain)?;
+        }
+        Ok(())
+    }
+}
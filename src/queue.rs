@@ -0,0 +1,144 @@
+//! Options of the `NFQUEUE` target, see `Rule::nfqueue`, and a helper passing traffic to the
+//! userspace queues of an IDS or IPS.
+
+use crate::batch::check_line_breaks;
+use crate::rule::{join_quoted, Rule};
+use crate::{IPTResult, IPTables};
+use std::ops::RangeInclusive;
+
+/// The netfilter queues the `NFQUEUE` target passes packets to, read by a userspace program
+/// with libnetfilter_queue, which decides whether each packet is accepted or dropped.
+///
+/// # Example
+/// ```
+/// use iptables::queue::NfQueue;
+/// use iptables::Rule;
+///
+/// let queue = NfQueue {
+///     queues: 0..=3,
+///     bypass: true,
+///     cpu_fanout: true,
+/// };
+/// assert_eq!(
+///     Rule::new().protocol("tcp").nfqueue(&queue).to_string(),
+///     "-p tcp -j NFQUEUE --queue-balance 0:3 --queue-bypass --queue-cpu-fanout"
+/// );
+/// assert_eq!(
+///     Rule::new().nfqueue(&NfQueue::new(5)).to_string(),
+///     "-j NFQUEUE --queue-num 5"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NfQueue {
+    /// The queues to use, a single one (`--queue-num`) or several which share the packets by
+    /// their connection (`--queue-balance first:last`). An empty range like `5..=2` is rejected
+    /// by `Rule::check`.
+    pub queues: RangeInclusive<u16>,
+    /// Accepts the packets instead of dropping them while no program listens on the queue
+    /// (`--queue-bypass`).
+    pub bypass: bool,
+    /// Picks the queue of several by the CPU handling the packet instead of by its
+    /// connection (`--queue-cpu-fanout`).
+    pub cpu_fanout: bool,
+}
+
+impl NfQueue {
+    /// Uses the single queue `num` without any flag.
+    pub fn new(num: u16) -> Self {
+        NfQueue {
+            queues: num..=num,
+            bypass: false,
+            cpu_fanout: false,
+        }
+    }
+
+    /// Returns the options of the target in the order `iptables -S` prints them.
+    pub(crate) fn target_args(&self) -> Vec<String> {
+        // An empty range is kept as given so `Rule::check` rejects it
+        let mut args = if self.queues.start() == self.queues.end() {
+            vec!["--queue-num".to_string(), self.queues.start().to_string()]
+        } else {
+            vec![
+                "--queue-balance".to_string(),
+                format!("{}:{}", self.queues.start(), self.queues.end()),
+            ]
+        };
+        if self.bypass {
+            args.push("--queue-bypass".to_string());
+        }
+        if self.cpu_fanout {
+            args.push("--queue-cpu-fanout".to_string());
+        }
+        args
+    }
+}
+
+/// The chains of the `filter` table `IPTables::divert_to_queue` inserts its rules to: the
+/// packets received by the host, forwarded by it and sent by it.
+pub const QUEUE_CHAINS: [&str; 3] = ["INPUT", "FORWARD", "OUTPUT"];
+
+/// Builds the rules installed by `IPTables::divert_to_queue`, each with its chain of the
+/// `filter` table. The target of `selector` is replaced.
+pub fn queue_rules(selector: &Rule, queue: &NfQueue) -> Vec<(&'static str, Rule)> {
+    QUEUE_CHAINS
+        .iter()
+        .map(|&chain| (chain, selector.clone().nfqueue(queue)))
+        .collect()
+}
+
+impl IPTables {
+    /// Passes the packets matched by `selector` to the userspace `queue`, e.g. of Suricata or
+    /// Snort running inline, by inserting a rule at the top of the `INPUT`, `FORWARD` and
+    /// `OUTPUT` chains of the `filter` table, each unless it already exists. The rules come
+    /// first so the program sees the packets before other rules accept them. The missing rules
+    /// are inserted with a single `iptables-restore --noflush` call, so either all of them are
+    /// inserted or none.
+    ///
+    /// Without `NfQueue::bypass` the matched packets are dropped while the program is not
+    /// running, which blocks the host if `selector` matches everything.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use iptables::queue::NfQueue;
+    /// use iptables::Rule;
+    ///
+    /// let ipt = iptables::new(false).unwrap();
+    /// let queue = NfQueue {
+    ///     bypass: true,
+    ///     ..NfQueue::new(0)
+    /// };
+    /// ipt.divert_to_queue(&Rule::new().protocol("tcp"), &queue)
+    ///     .unwrap();
+    /// ```
+    pub fn divert_to_queue(&self, selector: &Rule, queue: &NfQueue) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        // The missing rules are inserted with a single `iptables-restore --noflush` call, so a
+        // failure does not leave the traffic of some chains diverted
+        let mut data = String::from("*filter\n");
+        let mut missing = false;
+        for (chain, rule) in queue_rules(selector, queue) {
+            if self.exists_rule("filter", chain, &rule)? {
+                continue;
+            }
+            self.sanitize(&rule.args())?;
+            let args = self.tagged(rule.args());
+            check_line_breaks(&args)?;
+            data.push_str(&format!("-I {} 1 {}\n", chain, join_quoted(&args)));
+            missing = true;
+        }
+        if !missing {
+            return Ok(());
+        }
+        data.push_str("COMMIT\n");
+        self.restore(&data, false)
+    }
+
+    /// Removes the rules inserted by `divert_to_queue` with the same arguments.
+    pub fn remove_queue_diversion(&self, selector: &Rule, queue: &NfQueue) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        for (chain, rule) in queue_rules(selector, queue) {
+            self.delete_rule_if_present("filter", chain, &rule)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+//! Retrying iptables commands which failed for transient reasons.
+
+use std::process::Output;
+use std::time::Duration;
+
+// Messages printed by iptables if a command failed for a transient reason
+const MSG_TEMPORARILY_UNAVAILABLE: &str = "Resource temporarily unavailable";
+const MSG_LOCK_HELD: &str = "holding the xtables lock";
+
+/// Controls how iptables commands which failed for a transient reason are retried, e.g. when the
+/// kernel reports `Resource temporarily unavailable` or the xtables lock could not be taken
+/// within the wait timeout.
+///
+/// The delay between two attempts starts at `backoff` and doubles after each failed attempt up to
+/// `max_backoff`. A command is retried while `retryable` returns true for its output and
+/// `max_attempts` is not reached. The default policy runs every command once.
+///
+/// A command which failed this way did not change the rules, so retrying is also safe for
+/// commands like `append`. `iptables-save` and `iptables-restore` are not retried.
+///
+/// # Example
+/// ```no_run
+/// use iptables::retry::RetryPolicy;
+///
+/// let ipt = iptables::IPTables::builder()
+///     .retry_policy(RetryPolicy::new(5))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// The maximum number of times a command is run, including the first attempt.
+    pub max_attempts: u32,
+    /// The delay after the first failed attempt.
+    pub backoff: Duration,
+    /// The upper bound of the delay between two attempts.
+    pub max_backoff: Duration,
+    /// Decides if the output of a failed command is worth another attempt, `is_transient` by
+    /// default.
+    pub retryable: fn(&Output) -> bool,
+}
+
+impl RetryPolicy {
+    /// Creates a policy which runs a command up to `max_attempts` times if it fails for a
+    /// transient reason, waiting 10ms after the first failure and at most 1s between attempts.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            retryable: is_transient,
+        }
+    }
+}
+
+/// Indicates if iptables failed for a transient reason: the kernel reported
+/// `Resource temporarily unavailable` or another application held the xtables lock.
+pub fn is_transient(output: &Output) -> bool {
+    let stderr = String::from_utf8_lossy(output.stderr.as_slice());
+    !output.status.success()
+        && (stderr.contains(MSG_TEMPORARILY_UNAVAILABLE) || stderr.contains(MSG_LOCK_HELD))
+}
+
+/// Tracks the attempts to run a command according to a `RetryPolicy`.
+pub(crate) struct Retry {
+    policy: RetryPolicy,
+    attempts: u32,
+    delay: Duration,
+}
+
+impl Retry {
+    pub(crate) fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            attempts: 1,
+            delay: policy.backoff,
+        }
+    }
+
+    /// Returns the delay to wait before running the command again after it returned `output`,
+    /// or `None` if the output is final.
+    pub(crate) fn next_delay(&mut self, output: &Output) -> Option<Duration> {
+        if output.status.success()
+            || self.attempts >= self.policy.max_attempts
+            || !(self.policy.retryable)(output)
+        {
+            return None;
+        }
+
+        self.attempts += 1;
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(self.policy.max_backoff);
+        Some(delay)
+    }
+}
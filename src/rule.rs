@@ -0,0 +1,1239 @@
+//! Structured rule builder which renders to iptables arguments without hand-written option strings.
+
+use crate::batch::check_line_breaks;
+use crate::conntrack::{join_sorted, CtState, CtStatus, CtTarget};
+use crate::icmp::Icmp;
+use crate::interface::validate_interface;
+use crate::limit::{limit_args, HashLimit, Rate};
+use crate::log::{LogLevel, MAX_LOG_PREFIX, MAX_NFLOG_PREFIX};
+use crate::nat::NatTarget;
+use crate::net::Cidr;
+use crate::queue::NfQueue;
+use crate::target::{reject_with_mismatch, Target};
+use crate::validate::{validate_rule, ValidationError};
+use crate::{IPTError, IPTResult, SplitQuoted};
+use std::fmt;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+
+/// A match module (`-m <name>`) together with its options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct MatchModule {
+    name: String,
+    args: Vec<String>,
+}
+
+/// Compares the hop limit of an IPv6 packet, used by `Rule::hop_limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HopLimit {
+    /// Matches packets whose hop limit equals the value (`--hl-eq`).
+    Eq(u8),
+    /// Matches packets whose hop limit is below the value (`--hl-lt`).
+    Lt(u8),
+    /// Matches packets whose hop limit is above the value (`--hl-gt`).
+    Gt(u8),
+}
+
+/// A flag of the TCP header, used by `Rule::tcp_flags`. Flags are rendered in the order
+/// `iptables -S` prints them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TcpFlag {
+    /// `FIN`, the sender finished sending.
+    Fin,
+    /// `SYN`, the sender opens a connection.
+    Syn,
+    /// `RST`, the sender resets the connection.
+    Rst,
+    /// `PSH`, the receiver should pass the data on immediately.
+    Psh,
+    /// `ACK`, the acknowledgment number is valid.
+    Ack,
+    /// `URG`, the urgent pointer is valid.
+    Urg,
+}
+
+impl TcpFlag {
+    /// All flags, which iptables accepts as `ALL`.
+    pub const ALL: [TcpFlag; 6] = [
+        TcpFlag::Fin,
+        TcpFlag::Syn,
+        TcpFlag::Rst,
+        TcpFlag::Psh,
+        TcpFlag::Ack,
+        TcpFlag::Urg,
+    ];
+}
+
+impl fmt::Display for TcpFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = match self {
+            TcpFlag::Fin => "FIN",
+            TcpFlag::Syn => "SYN",
+            TcpFlag::Rst => "RST",
+            TcpFlag::Psh => "PSH",
+            TcpFlag::Ack => "ACK",
+            TcpFlag::Urg => "URG",
+        };
+        write!(f, "{}", flag)
+    }
+}
+
+// Match modules and protocols which only exist in one of the address families
+const IPV4_ONLY_MATCHES: &[&str] = &["icmp", "ttl"];
+const IPV6_ONLY_MATCHES: &[&str] = &[
+    "icmp6",
+    "hl",
+    "rt",
+    "frag",
+    "hbh",
+    "dst",
+    "ipv6header",
+    "mh",
+];
+// The protocols with ports, which are matched by their match module or by `multiport`
+const PORT_PROTOCOLS: &[&str] = &["tcp", "udp", "sctp", "dccp"];
+const MULTIPORT_PROTOCOLS: &[&str] = &["tcp", "udp", "udplite", "sctp", "dccp"];
+const MAX_MULTIPORT: usize = 15;
+
+// The options of the owner match module in the order `iptables -S` prints them
+const OWNER_OPTIONS: &[&str] = &[
+    "--socket-exists",
+    "--uid-owner",
+    "--gid-owner",
+    "--suppl-groups",
+];
+const IPV4_ONLY_PROTOCOLS: &[&str] = &["icmp"];
+const IPV6_ONLY_PROTOCOLS: &[&str] = &["ipv6-icmp", "icmpv6", "icmp6"];
+
+/// Describes a rule specification which can be passed to the `*_rule` methods of `IPTables`.
+///
+/// The arguments are rendered in the same order `iptables -S` prints them, so a rendered rule can
+/// be compared with the listing of a chain.
+///
+/// # Example
+/// ```
+/// use iptables::Rule;
+///
+/// let rule = Rule::new().protocol("tcp").dport(80).jump("ACCEPT");
+/// assert_eq!(rule.to_string(), "-p tcp -m tcp --dport 80 -j ACCEPT");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Rule {
+    source: Option<String>,
+    destination: Option<String>,
+    in_interface: Option<String>,
+    out_interface: Option<String>,
+    protocol: Option<String>,
+    sport: Option<String>,
+    dport: Option<String>,
+    tcp_flags: Option<(String, String)>,
+    sports: Vec<u16>,
+    dports: Vec<u16>,
+    matches: Vec<MatchModule>,
+    target: Option<String>,
+    target_args: Vec<String>,
+    goto: bool,
+    // The basic options and ports negated with `!`, e.g. `-s` or `--dport`
+    negated: Vec<String>,
+    // Set by `not` until the next match is added
+    #[cfg_attr(feature = "serde", serde(skip))]
+    negate_next: bool,
+}
+
+impl Rule {
+    /// Creates an empty rule which matches every packet and has no target.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Negates the match added by the next call (`!`), which must be one of the addresses,
+    /// interfaces, the protocol, the ports, `tcp_flags`, `ctstate`, `ctstatus` or a match
+    /// module added with `matching`, e.g. `mark`, `match_set` or `icmp_type`. A `not` without
+    /// such a call is rejected by `check`.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::Rule;
+    ///
+    /// let rule = Rule::new()
+    ///     .not()
+    ///     .source("10.0.0.0/8")
+    ///     .protocol("tcp")
+    ///     .not()
+    ///     .dport(22)
+    ///     .jump("DROP");
+    /// assert_eq!(rule.to_string(), "! -s 10.0.0.0/8 -p tcp -m tcp ! --dport 22 -j DROP");
+    /// ```
+    // Named like the `!` of iptables, `Not` would negate the whole rule
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(mut self) -> Self {
+        self.negate_next = true;
+        self
+    }
+
+    // Records whether the basic option or port `option` is negated, consuming a pending `not`
+    fn negate(&mut self, option: &str) {
+        self.negated.retain(|negated| negated != option);
+        if std::mem::take(&mut self.negate_next) {
+            self.negated.push(option.to_string());
+        }
+    }
+
+    fn is_negated(&self, option: &str) -> bool {
+        self.negated.iter().any(|negated| negated == option)
+    }
+
+    /// Matches the source address, network or hostname (`-s`).
+    pub fn source(mut self, source: &str) -> Self {
+        self.negate("-s");
+        self.source = Some(source.to_string());
+        self
+    }
+
+    /// Matches the destination address, network or hostname (`-d`).
+    pub fn destination(mut self, destination: &str) -> Self {
+        self.negate("-d");
+        self.destination = Some(destination.to_string());
+        self
+    }
+
+    /// Matches the source network or address (`-s`), e.g. `"10.0.0.0/8".parse::<Cidr>()?` or
+    /// an `IpAddr`. Rendered with its prefix like `iptables -S` prints it.
+    pub fn source_net(mut self, net: impl Into<Cidr>) -> Self {
+        self.negate("-s");
+        self.source = Some(net.into().to_string());
+        self
+    }
+
+    /// Matches the destination network or address (`-d`), see `source_net`.
+    pub fn destination_net(mut self, net: impl Into<Cidr>) -> Self {
+        self.negate("-d");
+        self.destination = Some(net.into().to_string());
+        self
+    }
+
+    /// Matches the interface a packet was received on (`-i`). A trailing `+` matches every
+    /// interface starting with the rest of the name, e.g. `eth+`. The name is checked by
+    /// `check`, see `validate_interface`.
+    pub fn in_interface(mut self, interface: &str) -> Self {
+        self.negate("-i");
+        self.in_interface = Some(interface.to_string());
+        self
+    }
+
+    /// Matches the interface a packet is going to be sent on (`-o`), see `in_interface`.
+    pub fn out_interface(mut self, interface: &str) -> Self {
+        self.negate("-o");
+        self.out_interface = Some(interface.to_string());
+        self
+    }
+
+    /// Matches the protocol of the packet (`-p`).
+    pub fn protocol(mut self, protocol: &str) -> Self {
+        self.negate("-p");
+        self.protocol = Some(protocol.to_string());
+        self
+    }
+
+    /// Matches the source port (`--sport`), requires a protocol to be set.
+    pub fn sport(mut self, port: u16) -> Self {
+        self.negate("--sport");
+        self.sport = Some(port.to_string());
+        self
+    }
+
+    /// Matches the destination port (`--dport`), requires a protocol to be set.
+    pub fn dport(mut self, port: u16) -> Self {
+        self.negate("--dport");
+        self.dport = Some(port.to_string());
+        self
+    }
+
+    /// Matches TCP packets which have the flags of `set` set among the flags of `mask`
+    /// (`--tcp-flags`), requires the protocol `tcp`. An empty list renders as `NONE`.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::rule::{Rule, TcpFlag};
+    ///
+    /// let rule = Rule::new()
+    ///     .protocol("tcp")
+    ///     .tcp_flags(&TcpFlag::ALL, &[])
+    ///     .jump("DROP");
+    /// assert_eq!(
+    ///     rule.to_string(),
+    ///     "-p tcp -m tcp --tcp-flags FIN,SYN,RST,PSH,ACK,URG NONE -j DROP"
+    /// );
+    /// ```
+    pub fn tcp_flags(mut self, mask: &[TcpFlag], set: &[TcpFlag]) -> Self {
+        let join = |flags: &[TcpFlag]| match join_sorted(flags) {
+            flags if flags.is_empty() => "NONE".to_string(),
+            flags => flags,
+        };
+        self.negate("--tcp-flags");
+        self.tcp_flags = Some((join(mask), join(set)));
+        self
+    }
+
+    /// Matches TCP packets which open a connection (`--syn`), requires the protocol `tcp`.
+    /// Rendered as `--tcp-flags FIN,SYN,RST,ACK SYN` like `iptables -S` prints it.
+    pub fn syn(self) -> Self {
+        self.tcp_flags(
+            &[TcpFlag::Fin, TcpFlag::Syn, TcpFlag::Rst, TcpFlag::Ack],
+            &[TcpFlag::Syn],
+        )
+    }
+
+    /// Matches the source ports in `range` (`--sport first:last`), requires a protocol to be set.
+    pub fn sport_range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.negate("--sport");
+        self.sport = Some(port_range(range));
+        self
+    }
+
+    /// Matches the destination ports in `range` (`--dport first:last`), requires a protocol to
+    /// be set.
+    pub fn dport_range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.negate("--dport");
+        self.dport = Some(port_range(range));
+        self
+    }
+
+    /// Matches any of the source `ports` (`-m multiport --sports`), requires a protocol to be
+    /// set. At most 15 ports can be given.
+    pub fn sports(mut self, ports: &[u16]) -> Self {
+        self.negate("--sports");
+        self.sports = ports.to_vec();
+        self
+    }
+
+    /// Matches any of the destination `ports` (`-m multiport --dports`), requires a protocol to
+    /// be set. At most 15 ports can be given.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::Rule;
+    ///
+    /// let rule = Rule::new().protocol("tcp").dports(&[80, 443, 8080]).jump("ACCEPT");
+    /// assert_eq!(rule.to_string(), "-p tcp -m multiport --dports 80,443,8080 -j ACCEPT");
+    /// ```
+    pub fn dports(mut self, ports: &[u16]) -> Self {
+        self.negate("--dports");
+        self.dports = ports.to_vec();
+        self
+    }
+
+    /// Loads the match module `name` (`-m`) with the given options.
+    pub fn matching(mut self, name: &str, args: &[&str]) -> Self {
+        let negation = self.take_negation();
+        self.matches.push(MatchModule {
+            name: name.to_string(),
+            args: negation
+                .into_iter()
+                .chain(args.iter().map(|arg| arg.to_string()))
+                .collect(),
+        });
+        self
+    }
+
+    /// Attaches a comment to the rule (`-m comment --comment`).
+    pub fn comment(self, comment: &str) -> Self {
+        self.matching("comment", &["--comment", comment])
+    }
+
+    /// Matches packets whose address is in the ipset `set` (`-m set --match-set`).
+    /// `flags` selects the addresses to look up, e.g. `src` or `dst,dst`.
+    pub fn match_set(self, set: &str, flags: &str) -> Self {
+        self.matching("set", &["--match-set", set, flags])
+    }
+
+    /// Matches packets whose connection is in one of the `states` (`-m conntrack --ctstate`),
+    /// e.g. `&[CtState::Established, CtState::Related]`.
+    ///
+    /// The states are rendered in the order `iptables -S` prints them. Together with `ctstatus`
+    /// a single `conntrack` match is used, placed where the first of them was called.
+    pub fn ctstate(mut self, states: &[CtState]) -> Self {
+        let args = self
+            .take_negation()
+            .into_iter()
+            .chain(["--ctstate".to_string(), join_sorted(states)]);
+        // --ctstate is printed before --ctstatus
+        self.conntrack().args.splice(0..0, args);
+        self
+    }
+
+    /// Matches packets whose connection has one of the `statuses` (`-m conntrack --ctstatus`).
+    pub fn ctstatus(mut self, statuses: &[CtStatus]) -> Self {
+        let args = self
+            .take_negation()
+            .into_iter()
+            .chain(["--ctstatus".to_string(), join_sorted(statuses)]);
+        self.conntrack().args.extend(args);
+        self
+    }
+
+    // Returns the `!` of a pending `not` for the options of a match module
+    fn take_negation(&mut self) -> Option<String> {
+        if std::mem::take(&mut self.negate_next) {
+            Some("!".to_string())
+        } else {
+            None
+        }
+    }
+
+    // Returns the `conntrack` match module, adding it if it is missing
+    fn conntrack(&mut self) -> &mut MatchModule {
+        let index = match self.matches.iter().position(|m| m.name == "conntrack") {
+            Some(index) => index,
+            None => {
+                self.matches.push(MatchModule {
+                    name: "conntrack".to_string(),
+                    args: Vec::new(),
+                });
+                self.matches.len() - 1
+            }
+        };
+        &mut self.matches[index]
+    }
+
+    /// Matches packets up to the average `rate` (`-m limit --limit`), allowing `burst` packets
+    /// at once (`--limit-burst`, 5 if `None`).
+    pub fn limit(mut self, rate: Rate, burst: Option<u32>) -> Self {
+        self.matches.push(MatchModule {
+            name: "limit".to_string(),
+            args: limit_args(rate, burst),
+        });
+        self
+    }
+
+    /// Limits the rate per address or port with the `hashlimit` match module (`-m hashlimit`).
+    pub fn hashlimit(mut self, limit: &HashLimit) -> Self {
+        self.matches.push(MatchModule {
+            name: "hashlimit".to_string(),
+            args: limit.args(),
+        });
+        self
+    }
+
+    /// Matches packets whose mark equals `mark` in the bits of `mask` (`-m mark --mark`),
+    /// all bits if `mask` is `None`.
+    pub fn mark(self, mark: u32, mask: Option<u32>) -> Self {
+        let mark = match mask.filter(|&mask| mask != u32::MAX) {
+            Some(mask) => format!("{:#x}/{:#x}", mark, mask),
+            None => format!("{:#x}", mark),
+        };
+        self.matching("mark", &["--mark", &mark])
+    }
+
+    /// Sets the mark of the packet to `mark` in the bits of `mask` (`-j MARK --set-mark`),
+    /// all bits if `mask` is `None`. Rendered as `--set-xmark` like `iptables -S` prints it.
+    pub fn set_mark(self, mark: u32, mask: Option<u32>) -> Self {
+        self.jump_with("MARK", &["--set-xmark", &xmark(mark, mask)])
+    }
+
+    /// Sets the mark of the connection to `mark` in the bits of `mask`
+    /// (`-j CONNMARK --set-mark`), all bits if `mask` is `None`.
+    pub fn set_connmark(self, mark: u32, mask: Option<u32>) -> Self {
+        self.jump_with("CONNMARK", &["--set-xmark", &xmark(mark, mask)])
+    }
+
+    /// Copies the mark of the packet to its connection (`-j CONNMARK --save-mark`).
+    pub fn save_connmark(self) -> Self {
+        let mask = format!("{:#x}", u32::MAX);
+        self.jump_with(
+            "CONNMARK",
+            &["--save-mark", "--nfmask", &mask, "--ctmask", &mask],
+        )
+    }
+
+    /// Copies the mark of the connection to the packet (`-j CONNMARK --restore-mark`).
+    pub fn restore_connmark(self) -> Self {
+        let mask = format!("{:#x}", u32::MAX);
+        self.jump_with(
+            "CONNMARK",
+            &["--restore-mark", "--nfmask", &mask, "--ctmask", &mask],
+        )
+    }
+
+    /// Labels the packet with the SELinux security context `context`
+    /// (`-j SECMARK --selctx`), e.g. `system_u:object_r:http_packet_t:s0`. Only valid in the
+    /// `security` and `mangle` tables.
+    pub fn set_secmark(self, context: &str) -> Self {
+        self.jump_with("SECMARK", &["--selctx", context])
+    }
+
+    /// Copies the security label of the packet to its connection (`-j CONNSECMARK --save`).
+    pub fn save_connsecmark(self) -> Self {
+        self.jump_with("CONNSECMARK", &["--save"])
+    }
+
+    /// Copies the security label of the connection to the packet
+    /// (`-j CONNSECMARK --restore`).
+    pub fn restore_connsecmark(self) -> Self {
+        self.jump_with("CONNSECMARK", &["--restore"])
+    }
+
+    /// Sets the typed NAT `target`, a `Dnat`, `Snat`, `Redirect` or `Masquerade`.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::nat::{Dnat, Masquerade};
+    /// use iptables::Rule;
+    ///
+    /// let dnat = Dnat {
+    ///     to: "192.168.1.10:80".parse::<std::net::SocketAddr>().unwrap().into(),
+    /// };
+    /// let rule = Rule::new().protocol("tcp").dport(8080).nat(dnat);
+    /// assert_eq!(
+    ///     rule.to_string(),
+    ///     "-p tcp -m tcp --dport 8080 -j DNAT --to-destination 192.168.1.10:80"
+    /// );
+    ///
+    /// let masquerade = Masquerade {
+    ///     to_ports: Some(1024..=65535),
+    ///     random: true,
+    /// };
+    /// assert_eq!(
+    ///     Rule::new().protocol("udp").nat(masquerade).to_string(),
+    ///     "-p udp -j MASQUERADE --to-ports 1024-65535 --random"
+    /// );
+    /// ```
+    pub fn nat(self, target: impl Into<NatTarget>) -> Self {
+        let (name, args) = target.into().target_args();
+        self.jump_with(name, &args.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    /// Matches packets which belong to a local socket (`-m socket`), also to a non-transparent
+    /// one unless `transparent` is set (`--transparent`).
+    pub fn socket(self, transparent: bool) -> Self {
+        if transparent {
+            self.matching("socket", &["--transparent"])
+        } else {
+            self.matching("socket", &[])
+        }
+    }
+
+    /// Sends the packet to the local transparent proxy listening on `on_port` and `on_ip`
+    /// (`-j TPROXY`), the address the packet was received on if `None`, and sets its mark to
+    /// `mark` in the bits of `mask` (`--tproxy-mark`), all bits if `mask` is `None`. Only valid
+    /// in the `PREROUTING` chain of the `mangle` table, for TCP or UDP packets.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::Rule;
+    ///
+    /// let rule = Rule::new().protocol("tcp").tproxy(50080, None, 1, Some(1));
+    /// assert_eq!(
+    ///     rule.to_string(),
+    ///     "-p tcp -j TPROXY --on-port 50080 --tproxy-mark 0x1/0x1"
+    /// );
+    /// ```
+    pub fn tproxy(self, on_port: u16, on_ip: Option<IpAddr>, mark: u32, mask: Option<u32>) -> Self {
+        let on_port = on_port.to_string();
+        let on_ip = on_ip.map(|ip| ip.to_string());
+        let mark = format!("{:#x}/{:#x}", mark, mask.unwrap_or(u32::MAX));
+        let mut args = vec!["--on-port", &on_port];
+        if let Some(on_ip) = &on_ip {
+            args.extend(["--on-ip", on_ip]);
+        }
+        args.extend(["--tproxy-mark", &mark]);
+        self.jump_with("TPROXY", &args)
+    }
+
+    /// Logs the packet to the kernel log with `prefix` at `level` (`-j LOG`) and continues
+    /// with the next rule. An empty prefix and the default level are omitted like `iptables -S`
+    /// does. The prefix must not be longer than 29 bytes, see `check`.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::log::LogLevel;
+    /// use iptables::Rule;
+    ///
+    /// let rule = Rule::new().protocol("tcp").dport(22).log("ssh: ", LogLevel::Info);
+    /// assert_eq!(
+    ///     rule.to_string(),
+    ///     "-p tcp -m tcp --dport 22 -j LOG --log-prefix \"ssh: \" --log-level 6"
+    /// );
+    /// ```
+    pub fn log(self, prefix: &str, level: LogLevel) -> Self {
+        let level = level.to_string();
+        let mut args = Vec::new();
+        if !prefix.is_empty() {
+            args.extend(["--log-prefix", prefix]);
+        }
+        if level != LogLevel::default().to_string() {
+            args.extend(["--log-level", &level]);
+        }
+        self.jump_with("LOG", &args)
+    }
+
+    /// Passes the packet to the userspace logger listening on the netlink `group`, e.g.
+    /// ulogd, with `prefix` (`-j NFLOG`), and continues with the next rule. An empty prefix and
+    /// the group 0 are omitted like `iptables -S` does. The prefix must not be longer than 63
+    /// bytes, see `check`.
+    pub fn nflog(self, group: u16, prefix: &str) -> Self {
+        let group = group.to_string();
+        let mut args = Vec::new();
+        if !prefix.is_empty() {
+            args.extend(["--nflog-prefix", prefix]);
+        }
+        if group != "0" {
+            args.extend(["--nflog-group", &group]);
+        }
+        self.jump_with("NFLOG", &args)
+    }
+
+    /// Sets up the connection tracking of the packet with the options of `target` (`-j CT`).
+    /// Only valid in the `raw` table.
+    pub fn ct(self, target: &CtTarget) -> Self {
+        let args = target.target_args();
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        self.jump_with("CT", &args)
+    }
+
+    /// Disables connection tracking for the packet (`-j CT --notrack`), e.g. for flows with
+    /// many packets per second. Only valid in the `raw` table, see
+    /// `IPTables::disable_conntrack_for`.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::Rule;
+    ///
+    /// let rule = Rule::new().protocol("udp").dport(53).notrack();
+    /// assert_eq!(rule.to_string(), "-p udp -m udp --dport 53 -j CT --notrack");
+    /// ```
+    pub fn notrack(self) -> Self {
+        self.ct(&CtTarget::notrack())
+    }
+
+    /// Passes the packet to the userspace program listening on the netfilter `queue`
+    /// (`-j NFQUEUE`), which decides whether it is accepted or dropped, see `NfQueue`.
+    pub fn nfqueue(self, queue: &NfQueue) -> Self {
+        let args = queue.target_args();
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        self.jump_with("NFQUEUE", &args)
+    }
+
+    /// Sets the DSCP field of the packet to `dscp`, which must be below 64 (`-j DSCP --set-dscp`).
+    pub fn set_dscp(self, dscp: u8) -> Self {
+        self.jump_with("DSCP", &["--set-dscp", &format!("{:#04x}", dscp)])
+    }
+
+    /// Sets the TOS field of the packet to `tos` in the bits of `mask` (`-j TOS --set-tos`),
+    /// all bits if `mask` is `None`.
+    pub fn set_tos(self, tos: u8, mask: Option<u8>) -> Self {
+        let tos = format!("{:#04x}/{:#04x}", tos, mask.unwrap_or(u8::MAX));
+        self.jump_with("TOS", &["--set-tos", &tos])
+    }
+
+    /// Matches ICMP packets of `icmp_type` with the syntax of its family, e.g.
+    /// `-p icmp -m icmp --icmp-type 8` for `IcmpType::EchoRequest` and
+    /// `-p ipv6-icmp -m icmp6 --icmpv6-type 128` for `Icmpv6Type::EchoRequest`. The type is
+    /// rendered as a number like `iptables -S` prints it.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::icmp::{IcmpType, Icmpv6Type};
+    /// use iptables::Rule;
+    ///
+    /// let ping = Rule::new().icmp_type(IcmpType::EchoRequest).jump("ACCEPT");
+    /// assert_eq!(ping.to_string(), "-p icmp -m icmp --icmp-type 8 -j ACCEPT");
+    /// assert!(ping.check_family(true).is_err());
+    ///
+    /// let ping6 = Rule::new().icmp_type(Icmpv6Type::EchoRequest).jump("ACCEPT");
+    /// assert_eq!(ping6.to_string(), "-p ipv6-icmp -m icmp6 --icmpv6-type 128 -j ACCEPT");
+    /// ```
+    pub fn icmp_type(mut self, icmp_type: impl Into<Icmp>) -> Self {
+        let icmp_type = icmp_type.into();
+        let (protocol, name, option) = icmp_type.match_args();
+        self.protocol = Some(protocol.to_string());
+        self.matching(name, &[option, &icmp_type.to_string()])
+    }
+
+    /// Matches ICMPv6 packets of `icmpv6_type`, e.g. `echo-request` or `128/0`
+    /// (`-p ipv6-icmp -m icmp6 --icmpv6-type`). Only valid for ip6tables.
+    pub fn icmpv6_type(mut self, icmpv6_type: &str) -> Self {
+        self.protocol = Some("ipv6-icmp".to_string());
+        self.matching("icmp6", &["--icmpv6-type", icmpv6_type])
+    }
+
+    /// Matches the hop limit of the packet (`-m hl`). Only valid for ip6tables.
+    pub fn hop_limit(self, limit: HopLimit) -> Self {
+        let (option, value) = match limit {
+            HopLimit::Eq(value) => ("--hl-eq", value),
+            HopLimit::Lt(value) => ("--hl-lt", value),
+            HopLimit::Gt(value) => ("--hl-gt", value),
+        };
+        self.matching("hl", &[option, &value.to_string()])
+    }
+
+    /// Matches packets with a routing header of `rt_type` (`-m rt --rt-type`).
+    /// Only valid for ip6tables.
+    pub fn routing_type(self, rt_type: u8) -> Self {
+        self.matching("rt", &["--rt-type", &rt_type.to_string()])
+    }
+
+    /// Matches packets created by a local process running as `uid`
+    /// (`-m owner --uid-owner`), e.g. to filter the egress traffic of a user. Only valid in the
+    /// `OUTPUT` and `POSTROUTING` chains.
+    ///
+    /// Matching by process id (`--pid-owner`) was removed from the kernel, so processes have to
+    /// be run as a dedicated user or group instead.
+    pub fn uid_owner(self, uid: u32) -> Self {
+        self.owner_option("--uid-owner", Some(uid.to_string()))
+    }
+
+    /// Matches packets created by a local process running as a user in `first..=last`
+    /// (`-m owner --uid-owner first-last`).
+    pub fn uid_owner_range(self, first: u32, last: u32) -> Self {
+        self.owner_option("--uid-owner", Some(id_range(first, last)))
+    }
+
+    /// Matches packets created by a local process running with the group `gid`
+    /// (`-m owner --gid-owner`). Only valid in the `OUTPUT` and `POSTROUTING` chains.
+    pub fn gid_owner(self, gid: u32) -> Self {
+        self.owner_option("--gid-owner", Some(gid.to_string()))
+    }
+
+    /// Matches packets created by a local process running with a group in `first..=last`
+    /// (`-m owner --gid-owner first-last`).
+    pub fn gid_owner_range(self, first: u32, last: u32) -> Self {
+        self.owner_option("--gid-owner", Some(id_range(first, last)))
+    }
+
+    /// Makes `gid_owner` also match the supplementary groups of the process
+    /// (`-m owner --suppl-groups`).
+    pub fn suppl_groups(self) -> Self {
+        self.owner_option("--suppl-groups", None)
+    }
+
+    /// Matches packets which are associated with a local socket (`-m owner --socket-exists`).
+    pub fn socket_exists(self) -> Self {
+        self.owner_option("--socket-exists", None)
+    }
+
+    // Adds the option to the `owner` match module, in the order `iptables -S` prints them.
+    // All owner options share a single match module, so `uid_owner` and `gid_owner` together
+    // match processes running as the user and the group
+    fn owner_option(mut self, option: &str, value: Option<String>) -> Self {
+        let rank = |option: &str| OWNER_OPTIONS.iter().position(|o| *o == option);
+        let owner = match self.matches.iter().position(|m| m.name == "owner") {
+            Some(index) => &mut self.matches[index],
+            None => {
+                self.matches.push(MatchModule {
+                    name: "owner".to_string(),
+                    args: Vec::new(),
+                });
+                self.matches.last_mut().unwrap()
+            }
+        };
+        let index = owner
+            .args
+            .iter()
+            .position(|arg| rank(arg) > rank(option))
+            .unwrap_or(owner.args.len());
+        owner.args.splice(
+            index..index,
+            std::iter::once(option.to_string()).chain(value),
+        );
+        self
+    }
+
+    /// Sets the typed `target`, e.g. `Target::Return` or `Target::Reject`, see `Target`.
+    pub fn target(self, target: impl Into<Target>) -> Self {
+        let (name, args, goto) = target.into().target_args();
+        if goto {
+            return self.goto(&name);
+        }
+        self.jump_with(&name, &args.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    // Returns the reply of a `REJECT` target
+    fn reject_with(&self) -> Option<&str> {
+        if self.target.as_deref() != Some("REJECT") {
+            return None;
+        }
+        self.target_args
+            .windows(2)
+            .find(|pair| pair[0] == "--reject-with")
+            .map(|pair| pair[1].as_str())
+    }
+
+    /// Sets the target of the rule (`-j`).
+    pub fn jump(self, target: &str) -> Self {
+        self.jump_with(target, &[])
+    }
+
+    /// Sets the target of the rule (`-j`) together with the options of the target.
+    pub fn jump_with(mut self, target: &str, args: &[&str]) -> Self {
+        self.target = Some(target.to_string());
+        self.target_args = args.iter().map(|arg| arg.to_string()).collect();
+        self.goto = false;
+        self
+    }
+
+    /// Continues processing in the user-defined `chain` (`-g`). Unlike `jump`, returning from
+    /// the chain continues in the chain which jumped to the current one.
+    pub fn goto(mut self, chain: &str) -> Self {
+        self.target = Some(chain.to_string());
+        self.target_args = Vec::new();
+        self.goto = true;
+        self
+    }
+
+    /// Checks the rule for mistakes iptables would reject: ports without a protocol which
+    /// supports them, TCP flags without the protocol `tcp`, log prefixes which are too long,
+    /// invalid interface names, lists of more than 15 multiport ports, reversed port and
+    /// `NFQUEUE` queue ranges, DSCP values of 64 and above and options of the other address
+    /// family, see `check_family`.
+    ///
+    /// The `*_rule` methods of `IPTables` run this check with the family of the handle.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::Rule;
+    ///
+    /// assert!(Rule::new().protocol("udp").dport_range(5000..=5100).check(false).is_ok());
+    /// assert!(Rule::new().dport(22).check(false).is_err());
+    /// assert!(Rule::new().protocol("tcp").dports(&[1; 16]).check(false).is_err());
+    /// ```
+    pub fn check(&self, is_ipv6: bool) -> IPTResult<()> {
+        if self.negate_next {
+            return Err(IPTError::Invalid(ValidationError::MissingValue(
+                "!".to_string(),
+            )));
+        }
+        let is_tcp =
+            matches!(&self.protocol, Some(protocol) if protocol.eq_ignore_ascii_case("tcp"));
+        if self.tcp_flags.is_some() && !is_tcp {
+            return Err(IPTError::Invalid(ValidationError::UnknownOption(
+                "--tcp-flags".to_string(),
+            )));
+        }
+        for (option, max) in [
+            ("--log-prefix", MAX_LOG_PREFIX),
+            ("--nflog-prefix", MAX_NFLOG_PREFIX),
+        ] {
+            let prefix = self.target_args.windows(2).find(|pair| pair[0] == option);
+            if matches!(prefix, Some(pair) if pair[1].len() > max) {
+                return Err(IPTError::Invalid(ValidationError::TooLong {
+                    option: option.to_string(),
+                    max,
+                }));
+            }
+        }
+        let queues = self
+            .target_args
+            .windows(2)
+            .find(|pair| pair[0] == "--queue-balance");
+        if let Some((first, last)) = queues.and_then(|pair| pair[1].split_once(':')) {
+            if first.parse::<u16>().ok() > last.parse::<u16>().ok() {
+                return Err(IPTError::Invalid(ValidationError::InvalidRange(format!(
+                    "queue range {}:{} is reversed",
+                    first, last
+                ))));
+            }
+        }
+        let dscp = self
+            .target_args
+            .windows(2)
+            .find(|pair| pair[0] == "--set-dscp")
+            .map(|pair| pair[1].as_str());
+        if let Some(value) = dscp {
+            let parsed = match value.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => value.parse::<u32>(),
+            };
+            if matches!(parsed, Ok(dscp) if dscp >= 64) {
+                return Err(IPTError::Invalid(ValidationError::InvalidRange(format!(
+                    "DSCP value {} is not below 64",
+                    value
+                ))));
+            }
+        }
+        for interface in self.in_interface.iter().chain(&self.out_interface) {
+            validate_interface(interface)?;
+        }
+        self.check_ports()
+            .map_err(|err| IPTError::Invalid(ValidationError::InvalidPorts(err)))?;
+        self.check_family(is_ipv6)
+    }
+
+    fn check_ports(&self) -> Result<(), String> {
+        let multiport = [("--sports", &self.sports), ("--dports", &self.dports)];
+        let has_ports = self.sport.is_some() || self.dport.is_some();
+        let has_multiport = multiport.iter().any(|(_, ports)| !ports.is_empty());
+        if !has_ports && !has_multiport {
+            return Ok(());
+        }
+
+        let protocol = self.protocol.as_deref().map(str::to_lowercase);
+        let protocols = if has_ports {
+            PORT_PROTOCOLS
+        } else {
+            MULTIPORT_PROTOCOLS
+        };
+        match protocol {
+            Some(protocol) if protocols.contains(&protocol.as_str()) => {}
+            Some(protocol) => return Err(format!("protocol {} has no ports", protocol)),
+            None => {
+                return Err(format!(
+                    "ports require one of the protocols {}",
+                    protocols.join(", ")
+                ))
+            }
+        }
+
+        for (option, ports) in multiport {
+            if ports.len() > MAX_MULTIPORT {
+                return Err(format!(
+                    "{} accepts at most {} ports, got {}",
+                    option,
+                    MAX_MULTIPORT,
+                    ports.len()
+                ));
+            }
+        }
+        for range in self.sport.iter().chain(&self.dport) {
+            if let Some((first, last)) = range.split_once(':') {
+                if first.parse::<u16>().ok() > last.parse::<u16>().ok() {
+                    return Err(format!("port range {} is reversed", range));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the rule only uses options of one address family, IPv6 if `is_ipv6` is set.
+    /// Literal addresses, protocols, match modules and `REJECT` replies which only exist in the
+    /// other family are rejected, e.g. `-m icmp` or `--reject-with icmp-host-prohibited` for
+    /// ip6tables, or `-m hl` for iptables.
+    ///
+    /// This check is part of `check`, which the `*_rule` methods of `IPTables` run.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::rule::{HopLimit, Rule};
+    ///
+    /// let rule = Rule::new().hop_limit(HopLimit::Eq(255)).jump("ACCEPT");
+    /// assert!(rule.check_family(true).is_ok());
+    /// assert!(rule.check_family(false).is_err());
+    /// ```
+    pub fn check_family(&self, is_ipv6: bool) -> IPTResult<()> {
+        match family_mismatch(
+            is_ipv6,
+            self.source
+                .iter()
+                .chain(&self.destination)
+                .map(String::as_str),
+            self.protocol.as_deref(),
+            self.matches.iter().map(|m| m.name.as_str()),
+            self.reject_with(),
+        ) {
+            Some(mismatch) => Err(IPTError::Invalid(ValidationError::WrongFamily(mismatch))),
+            None => Ok(()),
+        }
+    }
+
+    /// Renders the rule to a list of arguments which can be passed to iptables as is.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut push = |flag: &str, value: &Option<String>| {
+            if let Some(value) = value {
+                if self.is_negated(flag) {
+                    args.push("!".to_string());
+                }
+                args.push(flag.to_string());
+                args.push(value.clone());
+            }
+        };
+
+        push("-s", &self.source);
+        push("-d", &self.destination);
+        push("-i", &self.in_interface);
+        push("-o", &self.out_interface);
+        push("-p", &self.protocol);
+
+        if self.sport.is_some() || self.dport.is_some() || self.tcp_flags.is_some() {
+            if let Some(protocol) = &self.protocol {
+                args.push("-m".to_string());
+                args.push(protocol.clone());
+            }
+            for (option, port) in [("--sport", &self.sport), ("--dport", &self.dport)] {
+                if let Some(port) = port {
+                    if self.is_negated(option) {
+                        args.push("!".to_string());
+                    }
+                    args.push(option.to_string());
+                    args.push(port.clone());
+                }
+            }
+            if let Some((mask, set)) = &self.tcp_flags {
+                if self.is_negated("--tcp-flags") {
+                    args.push("!".to_string());
+                }
+                args.push("--tcp-flags".to_string());
+                args.push(mask.clone());
+                args.push(set.clone());
+            }
+        }
+
+        // A multiport match takes either source or destination ports
+        for (option, ports) in [("--sports", &self.sports), ("--dports", &self.dports)] {
+            if !ports.is_empty() {
+                let ports = ports.iter().map(u16::to_string).collect::<Vec<_>>();
+                args.extend(["-m".to_string(), "multiport".to_string()]);
+                if self.is_negated(option) {
+                    args.push("!".to_string());
+                }
+                args.extend([option.to_string(), ports.join(",")]);
+            }
+        }
+
+        for m in &self.matches {
+            args.push("-m".to_string());
+            args.push(m.name.clone());
+            args.extend(m.args.iter().cloned());
+        }
+
+        if let Some(target) = &self.target {
+            args.push(if self.goto { "-g" } else { "-j" }.to_string());
+            args.push(target.clone());
+            args.extend(self.target_args.iter().cloned());
+        }
+
+        args
+    }
+}
+
+// Renders `--set-mark` of MARK and CONNMARK the way iptables stores it: the bits of `mask` are
+// cleared and `mark` is xored in, so the bits of `mark` are part of the mask
+fn xmark(mark: u32, mask: Option<u32>) -> String {
+    format!("{:#x}/{:#x}", mark, mark | mask.unwrap_or(u32::MAX))
+}
+
+// Renders the range the way `iptables -S` prints it, a single port without the colon
+fn port_range(range: RangeInclusive<u16>) -> String {
+    if range.start() == range.end() {
+        range.start().to_string()
+    } else {
+        format!("{}:{}", range.start(), range.end())
+    }
+}
+
+fn id_range(first: u32, last: u32) -> String {
+    if first == last {
+        first.to_string()
+    } else {
+        format!("{}-{}", first, last)
+    }
+}
+
+/// Describes the first address, protocol, match module or `--reject-with` reply which is only available in the other
+/// address family, IPv6 if `is_ipv6` is set. Hostnames are not checked.
+pub(crate) fn family_mismatch<'a>(
+    is_ipv6: bool,
+    mut addresses: impl Iterator<Item = &'a str>,
+    protocol: Option<&str>,
+    mut matches: impl Iterator<Item = &'a str>,
+    reject_with: Option<&str>,
+) -> Option<String> {
+    let (family, other_matches, other_protocols) = if is_ipv6 {
+        ("IPv6", IPV4_ONLY_MATCHES, IPV4_ONLY_PROTOCOLS)
+    } else {
+        ("IPv4", IPV6_ONLY_MATCHES, IPV6_ONLY_PROTOCOLS)
+    };
+
+    let what = addresses
+        .find(|address| {
+            // Networks are checked by their address part
+            let ip = address.split('/').next().unwrap_or_default();
+            matches!(ip.parse::<IpAddr>(), Ok(ip) if ip.is_ipv6() != is_ipv6)
+        })
+        .map(|address| format!("address {}", address))
+        .or_else(|| {
+            protocol
+                .filter(|protocol| other_protocols.contains(&protocol.to_lowercase().as_str()))
+                .map(|protocol| format!("protocol {}", protocol))
+        })
+        .or_else(|| {
+            matches
+                .find(|name| other_matches.contains(name))
+                .map(|name| format!("match {}", name))
+        })
+        .or_else(|| reject_with.and_then(|value| reject_with_mismatch(value, is_ipv6)))?;
+    Some(format!("{} is not available for {}", what, family))
+}
+
+/// The specification of a rule without the leading `-A <chain>`, accepted by every method of
+/// `IPTables` which takes a rule.
+///
+/// It is converted from a string, which is split into arguments like a shell would, from a slice
+/// of arguments, which are passed to iptables as is, or from a structured `Rule`, so arguments
+/// containing whitespace or quotes do not have to be quoted by hand.
+///
+/// # Example
+/// ```
+/// use iptables::{Rule, RuleSpec};
+///
+/// let from_str = RuleSpec::from("-m comment --comment 'my app' -j ACCEPT");
+/// let from_args = RuleSpec::from(&["-m", "comment", "--comment", "my app", "-j", "ACCEPT"]);
+/// let from_rule = RuleSpec::from(Rule::new().comment("my app").jump("ACCEPT"));
+/// assert_eq!(from_str.args(), from_args.args());
+/// assert_eq!(from_args.args(), from_rule.args());
+/// assert_eq!(from_rule.to_string(), "-m comment --comment \"my app\" -j ACCEPT");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleSpec {
+    args: Vec<String>,
+    // The string the rule was given as, to validate its quotes
+    text: Option<String>,
+}
+
+impl RuleSpec {
+    /// Returns the arguments of the rule.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Returns the arguments of the rule, consuming it.
+    pub fn into_args(self) -> Vec<String> {
+        self.args
+    }
+
+    // Fails if the rule contains a line break, checking the string the rule was given as, as
+    // splitting it turns a line break into a separator
+    pub(crate) fn check_line_breaks(&self) -> IPTResult<()> {
+        match &self.text {
+            Some(text) => check_line_breaks(&[text]),
+            None => check_line_breaks(&self.args),
+        }
+    }
+
+    // Validates the rule as given, see `validate::validate_rule`
+    pub(crate) fn validate(&self, is_ipv6: bool) -> Result<(), ValidationError> {
+        match &self.text {
+            Some(text) => validate_rule(text, is_ipv6),
+            None => validate_rule(&join_quoted(&self.args), is_ipv6),
+        }
+    }
+}
+
+impl fmt::Display for RuleSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", join_quoted(&self.args))
+    }
+}
+
+impl From<&str> for RuleSpec {
+    fn from(rule: &str) -> Self {
+        RuleSpec {
+            args: rule.split_quoted(),
+            text: Some(rule.to_string()),
+        }
+    }
+}
+
+impl From<&String> for RuleSpec {
+    fn from(rule: &String) -> Self {
+        RuleSpec::from(rule.as_str())
+    }
+}
+
+impl From<String> for RuleSpec {
+    fn from(rule: String) -> Self {
+        RuleSpec::from(rule.as_str())
+    }
+}
+
+impl From<Vec<String>> for RuleSpec {
+    fn from(args: Vec<String>) -> Self {
+        RuleSpec { args, text: None }
+    }
+}
+
+impl From<&Vec<String>> for RuleSpec {
+    fn from(args: &Vec<String>) -> Self {
+        RuleSpec::from(args.clone())
+    }
+}
+
+impl From<&[String]> for RuleSpec {
+    fn from(args: &[String]) -> Self {
+        RuleSpec::from(args.to_vec())
+    }
+}
+
+impl From<&[&str]> for RuleSpec {
+    fn from(args: &[&str]) -> Self {
+        RuleSpec::from(args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+}
+
+impl From<Vec<&str>> for RuleSpec {
+    fn from(args: Vec<&str>) -> Self {
+        RuleSpec::from(args.as_slice())
+    }
+}
+
+impl<const N: usize> From<&[&str; N]> for RuleSpec {
+    fn from(args: &[&str; N]) -> Self {
+        RuleSpec::from(&args[..])
+    }
+}
+
+impl From<&Rule> for RuleSpec {
+    fn from(rule: &Rule) -> Self {
+        RuleSpec::from(rule.args())
+    }
+}
+
+impl From<Rule> for RuleSpec {
+    fn from(rule: Rule) -> Self {
+        RuleSpec::from(&rule)
+    }
+}
+
+impl From<&RuleSpec> for RuleSpec {
+    fn from(rule: &RuleSpec) -> Self {
+        rule.clone()
+    }
+}
+
+/// Quotes `arg` the way `iptables -S` does if it is empty or contains whitespace or quotes.
+/// Backslashes are quoted too, as `split_quoted` would take them as escapes otherwise.
+pub(crate) fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty()
+        && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\')
+    {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    for c in arg.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Joins `args` to a single line, quoting each argument like `quote_arg`.
+pub(crate) fn join_quoted(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| quote_arg(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", join_quoted(&self.args()))
+    }
+}
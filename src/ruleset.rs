@@ -0,0 +1,408 @@
+//! Declarative description of the rules an application wants, applied with `IPTables::apply`.
+
+use crate::batch::check_line_breaks;
+use crate::diff::{longest_common_subsequence, normalize};
+use crate::parse::canonical_rule;
+use crate::rule::{join_quoted, Rule};
+use crate::snapshot::Snapshot;
+use crate::{get_builtin_chains, has_comment, IPTResult, IPTables, RuleSpec, SplitQuoted, Table};
+use std::collections::HashMap;
+
+/// The desired rules of each table/chain.
+///
+/// # Example
+/// ```
+/// use iptables::ruleset::RuleSet;
+///
+/// let mut ruleset = RuleSet::new();
+/// ruleset
+///     .chain("filter", "MYAPP")
+///     .append("filter", "MYAPP", "-p tcp -m tcp --dport 22 -j ACCEPT")
+///     .append("filter", "INPUT", "-j MYAPP");
+/// assert_eq!(ruleset.chains("filter"), vec!["INPUT", "MYAPP"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuleSet {
+    tables: HashMap<String, HashMap<String, Vec<String>>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    policies: HashMap<String, HashMap<String, String>>,
+}
+
+/// Parses the output of `iptables-save` into a `RuleSet`, e.g. to diff it against the live rules
+/// or to apply it with `IPTables::apply`. The chains, the policies of built-in chains and the
+/// rules are kept, counters are dropped.
+///
+/// # Example
+/// ```
+/// let ruleset = iptables::ruleset::parse_save(
+///     "*filter\n:INPUT DROP [0:0]\n:MYAPP - [0:0]\n[3:180] -A INPUT -j MYAPP\nCOMMIT\n",
+/// )
+/// .unwrap();
+/// assert_eq!(ruleset.chains("filter"), vec!["INPUT", "MYAPP"]);
+/// assert_eq!(ruleset.policy("filter", "INPUT"), Some("DROP"));
+/// assert_eq!(ruleset.rules("filter", "INPUT"), ["-j MYAPP"]);
+/// ```
+pub fn parse_save(data: &str) -> IPTResult<RuleSet> {
+    let snapshot = Snapshot::parse(data)?;
+    let mut ruleset = RuleSet::new();
+    for table in snapshot.tables() {
+        for chain in snapshot.chains(table) {
+            ruleset.chain(table, chain.name());
+            if let Some(policy) = chain.policy() {
+                ruleset.set_policy(table, chain.name(), policy);
+            }
+            for rule in chain.rules() {
+                ruleset.append(table, chain.name(), rule.spec());
+            }
+        }
+    }
+    Ok(ruleset)
+}
+
+impl RuleSet {
+    /// Creates an empty rule set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares the table/chain, which is created by `IPTables::apply` if it is missing.
+    /// Chains which have rules are declared implicitly.
+    pub fn chain(&mut self, table: &str, chain: &str) -> &mut Self {
+        self.tables
+            .entry(table.to_string())
+            .or_default()
+            .entry(chain.to_string())
+            .or_default();
+        self
+    }
+
+    /// Sets the desired policy of the built-in table/chain, which is set by `IPTables::apply`.
+    pub fn set_policy(&mut self, table: &str, chain: &str, policy: &str) -> &mut Self {
+        self.chain(table, chain);
+        self.policies
+            .entry(table.to_string())
+            .or_default()
+            .insert(chain.to_string(), policy.to_string());
+        self
+    }
+
+    /// Returns the desired policy of the table/chain if one was set.
+    pub fn policy(&self, table: &str, chain: &str) -> Option<&str> {
+        self.policies
+            .get(table)
+            .and_then(|chains| chains.get(chain))
+            .map(String::as_str)
+    }
+
+    /// Appends `rule` to the desired rules of the table/chain.
+    pub fn append(&mut self, table: &str, chain: &str, rule: &str) -> &mut Self {
+        self.tables
+            .entry(table.to_string())
+            .or_default()
+            .entry(chain.to_string())
+            .or_default()
+            .push(rule.to_string());
+        self
+    }
+
+    /// Appends the structured `rule` to the desired rules of the table/chain.
+    pub fn append_rule(&mut self, table: &str, chain: &str, rule: &Rule) -> &mut Self {
+        self.append(table, chain, &rule.to_string())
+    }
+
+    /// Returns the names of the tables, sorted by name.
+    pub fn tables(&self) -> Vec<&str> {
+        let mut tables = self.tables.keys().map(String::as_str).collect::<Vec<_>>();
+        tables.sort_unstable();
+        tables
+    }
+
+    /// Returns the names of the chains of the table, sorted by name.
+    pub fn chains(&self, table: &str) -> Vec<&str> {
+        let mut chains = self
+            .tables
+            .get(table)
+            .map(|chains| chains.keys().map(String::as_str).collect::<Vec<_>>())
+            .unwrap_or_default();
+        chains.sort_unstable();
+        chains
+    }
+
+    /// Returns the desired rules of the table/chain.
+    pub fn rules(&self, table: &str, chain: &str) -> &[String] {
+        self.tables
+            .get(table)
+            .and_then(|chains| chains.get(chain))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns the desired rules of each chain of the table, e.g. to pass them to
+    /// `diff::diff_table`.
+    pub fn table(&self, table: &str) -> Option<&HashMap<String, Vec<String>>> {
+        self.tables.get(table)
+    }
+
+    /// Renders the rule set in the `iptables-save` format, which can be passed to
+    /// `IPTables::restore`. Tables and chains are sorted by name, chains without a policy are
+    /// declared with `-` and the counters of all chains start at zero.
+    ///
+    /// # Example
+    /// ```
+    /// use iptables::ruleset::RuleSet;
+    ///
+    /// let mut ruleset = RuleSet::new();
+    /// ruleset
+    ///     .set_policy("filter", "INPUT", "DROP")
+    ///     .append("filter", "INPUT", "-m comment --comment 'my app' -j ACCEPT");
+    /// assert_eq!(
+    ///     ruleset.to_restore_string(),
+    ///     "*filter\n:INPUT DROP [0:0]\n-A INPUT -m comment --comment \"my app\" -j ACCEPT\nCOMMIT\n"
+    /// );
+    /// ```
+    pub fn to_restore_string(&self) -> String {
+        let mut data = String::new();
+        for table in self.tables() {
+            data.push_str(&format!("*{}\n", table));
+            for chain in self.chains(table) {
+                let policy = self.policy(table, chain).unwrap_or("-");
+                data.push_str(&format!(":{} {} [0:0]\n", chain, policy));
+            }
+            for chain in self.chains(table) {
+                for rule in self.rules(table, chain) {
+                    // iptables-restore only understands double quotes
+                    data.push_str(&format!("-A {} {}\n", chain, normalize(rule)));
+                }
+            }
+            data.push_str("COMMIT\n");
+        }
+        data
+    }
+}
+
+impl IPTables {
+    /// Converges the live rules to `ruleset`: creates the missing chains, sets the policies,
+    /// deletes the stale rules and inserts the missing rules in their position, leaving rules
+    /// which are already in place untouched.
+    ///
+    /// If an `owner_tag` is set, only the rules carrying it are managed and the desired rules
+    /// are tagged, so rules of other applications in the same chains are kept. Otherwise the
+    /// chains of `ruleset` are managed completely.
+    pub fn apply(&self, ruleset: &RuleSet) -> IPTResult<()> {
+        // The rules are checked before any chain is changed
+        for table in ruleset.tables() {
+            for chain in ruleset.chains(table) {
+                for rule in ruleset.rules(table, chain) {
+                    self.sanitize(&rule.split_quoted())?;
+                }
+            }
+        }
+
+        let _serial = self.serial.lock();
+        for table in ruleset.tables() {
+            let builtin_chains = get_builtin_chains(table)?;
+            for chain in ruleset.chains(table) {
+                if !builtin_chains.contains(&chain) {
+                    self.ensure_chain(table, chain)?;
+                } else if let Some(policy) = ruleset.policy(table, chain) {
+                    self.set_policy(table, chain, policy)?;
+                }
+            }
+        }
+
+        for table in ruleset.tables() {
+            for chain in ruleset.chains(table) {
+                self.apply_chain(table, chain, ruleset.rules(table, chain))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the rules of the table/chain exactly equal `rules`, in the given order. Rules which
+    /// are already in place, compared in their canonical form (see `parse::canonical_rule`), are
+    /// kept with their counters, all other rules are deleted and the missing rules are inserted
+    /// in their position. All changes are applied in a single `iptables-restore --noflush`
+    /// transaction, so the chain never holds a partially ordered list. A missing user-defined
+    /// chain is created.
+    ///
+    /// Unlike `apply`, the whole chain is managed even if an `owner_tag` is set; the tag is only
+    /// added to `rules`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let ipt = iptables::new(false).unwrap();
+    /// ipt.set_chain_rules(
+    ///     "filter",
+    ///     "MYAPP",
+    ///     &["-p tcp --dport 22 -j ACCEPT", "-p tcp --dport 443 -j ACCEPT", "-j DROP"],
+    /// )
+    /// .unwrap();
+    /// ```
+    pub fn set_chain_rules<R: Clone + Into<RuleSpec>>(
+        &self,
+        table: impl Into<Table>,
+        chain: &str,
+        rules: &[R],
+    ) -> IPTResult<()> {
+        let table: &str = &table.into();
+        // The chain and the rules are written into the input of iptables-restore, where a line
+        // break would start another command
+        check_line_breaks(&[table, chain])?;
+        let desired = rules
+            .iter()
+            .map(|rule| {
+                let rule: RuleSpec = rule.clone().into();
+                rule.check_line_breaks()?;
+                self.sanitize(rule.args())?;
+                if self.validate_rules {
+                    rule.validate(self.is_ipv6)?;
+                }
+                let args = self.tagged(rule.into_args());
+                check_line_breaks(&args)?;
+                Ok(join_quoted(&args))
+            })
+            .collect::<IPTResult<Vec<_>>>()?;
+
+        let _serial = self.serial.lock();
+        if !get_builtin_chains(table)
+            .unwrap_or_default()
+            .contains(&chain)
+        {
+            self.ensure_chain(table, chain)?;
+        }
+        let current = self
+            .list_numbered(table, chain)?
+            .into_iter()
+            .map(|(_, rule)| rule)
+            .collect::<Vec<_>>();
+        if let Some(data) = chain_rules_data(table, chain, &desired, &current) {
+            self.restore(&data, false)?;
+        }
+        Ok(())
+    }
+
+    fn apply_chain(&self, table: &str, chain: &str, rules: &[String]) -> IPTResult<()> {
+        let desired = rules
+            .iter()
+            .map(|rule| join_quoted(&self.tagged(rule.split_quoted())))
+            .collect::<Vec<_>>();
+        let desired_keys = desired
+            .iter()
+            .map(|rule| rule_key(rule))
+            .collect::<Vec<_>>();
+
+        // The canonical form of every rule of the chain, kept in sync with the changes below
+        let prefix = format!("-A {} ", chain);
+        let listed = self
+            .list(table, chain)?
+            .iter()
+            .filter_map(|line| line.strip_prefix(&prefix))
+            .map(normalize)
+            .collect::<Vec<_>>();
+        let mut live = listed.iter().map(|rule| rule_key(rule)).collect::<Vec<_>>();
+        let owned = listed
+            .iter()
+            .filter(|rule| match &self.owner_tag {
+                Some(tag) => has_comment(&rule.split_quoted(), tag),
+                None => true,
+            })
+            .map(|rule| (rule, rule_key(rule)))
+            .collect::<Vec<_>>();
+        let owned_keys = owned.iter().map(|(_, key)| key.clone()).collect::<Vec<_>>();
+
+        let (kept_desired, kept_owned) = longest_common_subsequence(&desired_keys, &owned_keys);
+        for ((rule, key), _) in owned.iter().zip(kept_owned).filter(|(_, kept)| !kept) {
+            self.delete(table, chain, rule.as_str())?;
+            if let Some(index) = live.iter().position(|live_key| live_key == key) {
+                live.remove(index);
+            }
+        }
+
+        // Missing rules are inserted in front of the next desired rule which is already in place,
+        // so rules of other applications keep their relative order
+        let mut cursor = 0;
+        for (index, rule) in desired.iter().enumerate() {
+            if kept_desired[index] {
+                cursor += live[cursor..]
+                    .iter()
+                    .position(|live_key| *live_key == desired_keys[index])
+                    .map_or(0, |offset| offset + 1);
+                continue;
+            }
+
+            let next_kept = (index + 1..desired.len()).find(|&next| kept_desired[next]);
+            let position = next_kept
+                .and_then(|next| {
+                    live[cursor..]
+                        .iter()
+                        .position(|live_key| *live_key == desired_keys[next])
+                })
+                .map_or(live.len(), |offset| cursor + offset);
+
+            if position == live.len() {
+                self.append(table, chain, rule)?;
+            } else {
+                self.insert(table, chain, rule, position as i32 + 1)?;
+            }
+            live.insert(position, desired_keys[index].clone());
+            cursor = position + 1;
+        }
+        Ok(())
+    }
+}
+
+// Renders the restore input turning the `current` rules of the table/chain into the `desired`
+// rules, or returns `None` if they are equal. Stale rules are deleted by their position from the
+// last to the first, so the positions stay valid, then the missing rules are inserted like
+// `diff::diff_chain` does
+fn chain_rules_data(
+    table: &str,
+    chain: &str,
+    desired: &[String],
+    current: &[String],
+) -> Option<String> {
+    let (kept_desired, kept_current) = longest_common_subsequence(
+        &desired
+            .iter()
+            .map(|rule| rule_key(rule))
+            .collect::<Vec<_>>(),
+        &current
+            .iter()
+            .map(|rule| rule_key(rule))
+            .collect::<Vec<_>>(),
+    );
+    if kept_desired.iter().chain(&kept_current).all(|&kept| kept) {
+        return None;
+    }
+
+    let mut data = format!("*{}\n", table);
+    for (index, _) in kept_current
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, kept)| !**kept)
+    {
+        data.push_str(&format!("-D {} {}\n", chain, index + 1));
+    }
+    let mut len = kept_current.iter().filter(|&&kept| kept).count();
+    for (index, rule) in desired.iter().enumerate() {
+        if kept_desired[index] {
+            continue;
+        }
+        if index == len {
+            data.push_str(&format!("-A {} {}\n", chain, rule));
+        } else {
+            data.push_str(&format!("-I {} {} {}\n", chain, index + 1, rule));
+        }
+        len += 1;
+    }
+    data.push_str("COMMIT\n");
+    Some(data)
+}
+
+// Compares rules the way iptables prints them, e.g. with the implicit `-m tcp`, so a rule which
+// is written differently than `iptables -S` prints it still matches its live copy
+fn rule_key(rule: &str) -> String {
+    canonical_rule(rule).unwrap_or_else(|_| normalize(rule))
+}
@@ -0,0 +1,228 @@
+//! Execution of the iptables binaries, which can be replaced to test applications without root.
+
+use crate::{output_with_input, IPTError, IPTResult};
+use nix::sched::{setns, CloneFlags};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::panic::RefUnwindSafe;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs the commands built by `IPTables`, including `iptables --version`, `iptables-save` and
+/// `iptables-restore`. Use `IPTablesBuilder::runner` to replace the default `SystemRunner`,
+/// e.g. with a fake which checks the generated arguments and returns canned outputs.
+///
+/// # Example
+/// ```
+/// use iptables::runner::CommandRunner;
+/// use iptables::IPTResult;
+/// use std::os::unix::process::ExitStatusExt;
+/// use std::process::{ExitStatus, Output};
+///
+/// struct FakeRunner;
+///
+/// impl CommandRunner for FakeRunner {
+///     fn run(&self, _program: &str, args: &[String], _input: Option<&str>) -> IPTResult<Output> {
+///         let stdout = match args.first().map(String::as_str) {
+///             Some("--version") => "iptables v1.8.7 (nf_tables)\n",
+///             _ => "",
+///         };
+///         Ok(Output {
+///             status: ExitStatus::from_raw(0),
+///             stdout: stdout.as_bytes().to_vec(),
+///             stderr: Vec::new(),
+///         })
+///     }
+/// }
+///
+/// let ipt = iptables::IPTables::builder().runner(FakeRunner).build().unwrap();
+/// assert!(ipt.flush_chain("filter", "INPUT").is_ok());
+/// ```
+pub trait CommandRunner: Send + Sync + RefUnwindSafe {
+    /// Runs `program` with `args` and returns its output. `input` is written to the standard
+    /// input of the program if given.
+    fn run(&self, program: &str, args: &[String], input: Option<&str>) -> IPTResult<Output>;
+
+    /// Runs `program` like `run`, but inside the network namespace opened from `netns`, see
+    /// `IPTablesBuilder::netns_path`. Runners which cannot enter namespaces return an error.
+    fn run_in_netns(
+        &self,
+        netns: &Path,
+        program: &str,
+        args: &[String],
+        input: Option<&str>,
+    ) -> IPTResult<Output> {
+        let _ = (program, args, input);
+        Err(IPTError::Other(format!(
+            "runner cannot enter the network namespace {}",
+            netns.display()
+        )))
+    }
+}
+
+// Allows the caller to keep a handle to the runner, e.g. to inspect a fake after the calls
+impl<R: CommandRunner + ?Sized> CommandRunner for Arc<R> {
+    fn run(&self, program: &str, args: &[String], input: Option<&str>) -> IPTResult<Output> {
+        (**self).run(program, args, input)
+    }
+
+    fn run_in_netns(
+        &self,
+        netns: &Path,
+        program: &str,
+        args: &[String],
+        input: Option<&str>,
+    ) -> IPTResult<Output> {
+        (**self).run_in_netns(netns, program, args, input)
+    }
+}
+
+/// Runs the commands as child processes, the default `CommandRunner`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[String], input: Option<&str>) -> IPTResult<Output> {
+        let mut command = Command::new(program);
+        command.args(args);
+        match input {
+            Some(data) => output_with_input(command, data),
+            None => Ok(command.output()?),
+        }
+    }
+
+    fn run_in_netns(
+        &self,
+        netns: &Path,
+        program: &str,
+        args: &[String],
+        input: Option<&str>,
+    ) -> IPTResult<Output> {
+        let mut command = Command::new(program);
+        command.args(args);
+        enter_netns(&mut command, netns)?;
+        match input {
+            Some(data) => output_with_input(command, data),
+            None => Ok(command.output()?),
+        }
+    }
+}
+
+// Makes the child process of `command` enter the network namespace opened from `netns` with
+// setns(2) right before it executes the program
+pub(crate) fn enter_netns(command: &mut Command, netns: &Path) -> IPTResult<()> {
+    let file = File::open(netns)?;
+    // The closure owns the file, so the descriptor stays open until the command is dropped.
+    // setns is async-signal-safe and the error is read from errno without allocating.
+    unsafe {
+        command.pre_exec(move || {
+            setns(file.as_raw_fd(), CloneFlags::CLONE_NEWNET)
+                .map_err(|_| io::Error::last_os_error())
+        });
+    }
+    Ok(())
+}
+
+// How often a command with a timeout is checked for having exited
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// Runs the command like `SystemRunner`, but kills it once it runs longer than `timeout`. The
+// pipes are served by threads, so a child which neither reads its input nor exits cannot block
+// the caller. The threads are not joined after a kill, as a process the command started, e.g.
+// through `sudo`, may keep the pipes open.
+pub(crate) fn run_with_timeout(
+    program: &str,
+    args: &[String],
+    input: Option<&str>,
+    netns: Option<&Path>,
+    timeout: Duration,
+) -> IPTResult<Output> {
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(netns) = netns {
+        enter_netns(&mut command, netns)?;
+    }
+    let mut child = command.spawn()?;
+
+    let writer = match (child.stdin.take(), input) {
+        (Some(mut stdin), Some(data)) => {
+            let data = data.to_string();
+            // Dropping the handle at the end of the thread closes stdin so the child can exit
+            Some(thread::spawn(move || stdin.write_all(data.as_bytes())))
+        }
+        _ => None,
+    };
+    let read = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut buffer = Vec::new();
+            if let Some(mut pipe) = pipe {
+                pipe.read_to_end(&mut buffer).map(|_| buffer)
+            } else {
+                Ok(buffer)
+            }
+        })
+    };
+    let stdout = read(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+    let stderr = read(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            let mut line = vec![program.to_string()];
+            line.extend(args.iter().cloned());
+            return Err(IPTError::Timeout {
+                command: line.join(" "),
+                timeout,
+            });
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    };
+
+    let join = |handle: thread::JoinHandle<io::Result<Vec<u8>>>| {
+        handle
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("reader panicked")))
+    };
+    let output = Output {
+        status,
+        stdout: join(stdout)?,
+        stderr: join(stderr)?,
+    };
+    if let Some(writer) = writer {
+        match writer.join() {
+            Ok(Err(err)) if output.status.success() => return Err(err.into()),
+            _ => {}
+        }
+    }
+    Ok(output)
+}
+
+// Splits the command line of `command` into the program and its arguments
+pub(crate) fn command_line(command: &Command) -> (String, Vec<String>) {
+    (
+        command.get_program().to_string_lossy().into_owned(),
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect(),
+    )
+}
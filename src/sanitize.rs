@@ -0,0 +1,373 @@
+//! Strict checking of rules built from untrusted input, see `Sanitizer`.
+//!
+//! A rule given as a string is split into arguments which are passed to iptables as they are,
+//! so whoever controls part of the string controls the command line: `-j ACCEPT -t nat` moves
+//! the rule to another table, an abbreviated `--mod=/tmp/x` makes iptables run a program to
+//! load kernel modules, and a newline ends the line of an `iptables-restore` input early and
+//! starts a new command. `validate::validate_rule` only looks for mistakes and lets all of them
+//! through. The sanitizer instead accepts a rule only if every option, match module and target
+//! is on its allowlist, so fragments of user input cannot add anything the caller did not
+//! expect.
+//!
+//! The sanitizer does not check the values of options, so the caller still has to make sure a
+//! rule only matches the traffic the user may control, e.g. by building it with `Rule` and
+//! passing user input as values only.
+
+use crate::validate::ValidationError;
+use crate::{IPTResult, IPTables, SplitQuoted};
+use std::collections::HashMap;
+
+// The options of the rule itself, allowed in every rule
+const BASIC_OPTIONS: &[&str] = &[
+    "-p",
+    "--protocol",
+    "-s",
+    "--source",
+    "-d",
+    "--destination",
+    "-i",
+    "--in-interface",
+    "-o",
+    "--out-interface",
+    "-f",
+    "--fragment",
+];
+
+const PORT_OPTIONS: &[&str] = &["--sport", "--source-port", "--dport", "--destination-port"];
+
+const MATCH_OPTIONS: &[(&str, &[&str])] = &[
+    (
+        "addrtype",
+        &[
+            "--src-type",
+            "--dst-type",
+            "--limit-iface-in",
+            "--limit-iface-out",
+        ],
+    ),
+    ("comment", &["--comment"]),
+    ("connmark", &["--mark"]),
+    (
+        "conntrack",
+        &[
+            "--ctstate",
+            "--ctstatus",
+            "--ctproto",
+            "--ctorigsrc",
+            "--ctorigdst",
+            "--ctreplsrc",
+            "--ctrepldst",
+            "--ctorigsrcport",
+            "--ctorigdstport",
+            "--ctreplsrcport",
+            "--ctrepldstport",
+            "--ctexpire",
+            "--ctdir",
+        ],
+    ),
+    ("hl", &["--hl-eq", "--hl-lt", "--hl-gt"]),
+    ("icmp", &["--icmp-type"]),
+    ("icmp6", &["--icmpv6-type"]),
+    ("iprange", &["--src-range", "--dst-range"]),
+    ("length", &["--length"]),
+    ("limit", &["--limit", "--limit-burst"]),
+    ("mac", &["--mac-source"]),
+    ("mark", &["--mark"]),
+    (
+        "multiport",
+        &[
+            "--sports",
+            "--source-ports",
+            "--dports",
+            "--destination-ports",
+            "--ports",
+        ],
+    ),
+    (
+        "owner",
+        &[
+            "--uid-owner",
+            "--gid-owner",
+            "--socket-exists",
+            "--suppl-groups",
+        ],
+    ),
+    ("pkttype", &["--pkt-type"]),
+    ("set", &["--match-set", "--return-nomatch"]),
+    ("state", &["--state"]),
+    (
+        "tcp",
+        &[
+            "--sport",
+            "--source-port",
+            "--dport",
+            "--destination-port",
+            "--tcp-flags",
+            "--syn",
+        ],
+    ),
+    ("ttl", &["--ttl-eq", "--ttl-lt", "--ttl-gt"]),
+    (
+        "udp",
+        &["--sport", "--source-port", "--dport", "--destination-port"],
+    ),
+];
+
+const TARGET_OPTIONS: &[(&str, &[&str])] = &[
+    ("ACCEPT", &[]),
+    ("DROP", &[]),
+    ("RETURN", &[]),
+    ("REJECT", &["--reject-with"]),
+    (
+        "LOG",
+        &[
+            "--log-prefix",
+            "--log-level",
+            "--log-tcp-sequence",
+            "--log-tcp-options",
+            "--log-ip-options",
+            "--log-uid",
+        ],
+    ),
+    (
+        "NFLOG",
+        &[
+            "--nflog-group",
+            "--nflog-prefix",
+            "--nflog-threshold",
+            "--nflog-size",
+        ],
+    ),
+    (
+        "NFQUEUE",
+        &[
+            "--queue-num",
+            "--queue-balance",
+            "--queue-bypass",
+            "--queue-cpu-fanout",
+        ],
+    ),
+    ("DNAT", &["--to-destination", "--random", "--persistent"]),
+    (
+        "SNAT",
+        &["--to-source", "--random", "--random-fully", "--persistent"],
+    ),
+    ("MASQUERADE", &["--to-ports", "--random", "--random-fully"]),
+    ("REDIRECT", &["--to-ports", "--random"]),
+    (
+        "MARK",
+        &[
+            "--set-xmark",
+            "--set-mark",
+            "--and-mark",
+            "--or-mark",
+            "--xor-mark",
+        ],
+    ),
+    (
+        "CONNMARK",
+        &[
+            "--set-xmark",
+            "--set-mark",
+            "--save-mark",
+            "--restore-mark",
+            "--nfmask",
+            "--ctmask",
+            "--mask",
+        ],
+    ),
+    (
+        "CT",
+        &[
+            "--notrack",
+            "--helper",
+            "--timeout",
+            "--ctevents",
+            "--expevents",
+            "--zone",
+        ],
+    ),
+    ("DSCP", &["--set-dscp", "--set-dscp-class"]),
+    ("TOS", &["--set-tos"]),
+    ("TCPMSS", &["--set-mss", "--clamp-mss-to-pmtu"]),
+];
+
+/// An allowlist of the match modules and targets a rule may use, together with their options,
+/// enabled with `IPTablesBuilder::sanitizer`. See the module documentation for the threat
+/// model.
+///
+/// A rule is rejected with `ValidationError::NotAllowed` if an argument contains a control
+/// character, if it loads a match module (`-m`) or uses a target (`-j`, `-g`) which is not
+/// allowed, or if it gives an option which is neither a basic option like `-s` nor allowed for
+/// the match module or target it follows. Options of the protocol are allowed after `-p` like
+/// iptables does. Options must be spelled out, the abbreviations and the `--option=value`
+/// form iptables accepts are rejected, and so are protocols, match modules and targets
+/// starting with `-`. Every argument is checked for control characters, the values of
+/// options included.
+///
+/// The default allowlist holds the common match modules and the targets the crate renders,
+/// but no user-defined chain.
+///
+/// # Example
+/// ```
+/// use iptables::sanitize::Sanitizer;
+///
+/// let sanitizer = Sanitizer::new().allow_chain("MYAPP");
+/// assert!(sanitizer.check_rule("-p tcp --dport 22 -j MYAPP").is_ok());
+/// assert!(sanitizer.check_rule("-j ACCEPT -t nat").is_err());
+/// assert!(sanitizer.check_rule("-j ACCEPT --mod=/tmp/x").is_err());
+/// assert!(sanitizer.check_rule("-m comment --comment \"a\nCOMMIT\" -j DROP").is_err());
+/// assert!(sanitizer.check_rule("-j OTHER").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sanitizer {
+    matches: HashMap<String, Vec<String>>,
+    targets: HashMap<String, Vec<String>>,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        let table = |entries: &[(&str, &[&str])]| {
+            entries
+                .iter()
+                .map(|(name, options)| {
+                    let options = options.iter().map(|option| option.to_string()).collect();
+                    (name.to_string(), options)
+                })
+                .collect()
+        };
+        Self {
+            matches: table(MATCH_OPTIONS),
+            targets: table(TARGET_OPTIONS),
+        }
+    }
+}
+
+impl Sanitizer {
+    /// Creates a sanitizer with the default allowlist.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a sanitizer which allows no match module and no target, only the basic options.
+    pub fn empty() -> Self {
+        Self {
+            matches: HashMap::new(),
+            targets: HashMap::new(),
+        }
+    }
+
+    /// Allows the match module `name` with `options`, in addition to the options it is
+    /// already allowed.
+    pub fn allow_match(mut self, name: &str, options: &[&str]) -> Self {
+        let allowed = self.matches.entry(name.to_string()).or_default();
+        allowed.extend(options.iter().map(|option| option.to_string()));
+        self
+    }
+
+    /// Allows the target `name` with `options`, in addition to the options it is already
+    /// allowed.
+    pub fn allow_target(mut self, name: &str, options: &[&str]) -> Self {
+        let allowed = self.targets.entry(name.to_string()).or_default();
+        allowed.extend(options.iter().map(|option| option.to_string()));
+        self
+    }
+
+    /// Allows jumping to the user-defined chain `name`.
+    pub fn allow_chain(self, name: &str) -> Self {
+        self.allow_target(name, &[])
+    }
+
+    /// Checks the rule given as a string, without the leading `-A <chain>`, after splitting it
+    /// like `RuleSpec` does.
+    pub fn check_rule(&self, rule: &str) -> Result<(), ValidationError> {
+        if let Some(c) = rule.chars().find(|c| c.is_control()) {
+            return Err(control_character(c));
+        }
+        self.check_args(&rule.split_quoted())
+    }
+
+    /// Checks the rule given as arguments, without the leading `-A <chain>`.
+    pub fn check_args(&self, args: &[String]) -> Result<(), ValidationError> {
+        let not_allowed = |what: String| Err(ValidationError::NotAllowed(what));
+        // Values are checked too, as a line break in any of them ends an iptables-restore line
+        if let Some(c) = args
+            .iter()
+            .flat_map(|arg| arg.chars())
+            .find(|c| c.is_control())
+        {
+            return Err(control_character(c));
+        }
+        let mut protocol: Option<String> = None;
+        // The options allowed after the last match module or target
+        let mut current: Option<&[String]> = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-p" | "--protocol" => {
+                    protocol = Some(name_after(arg, args.next())?.to_lowercase());
+                }
+                "-m" | "--match" => {
+                    let name = name_after(arg, args.next())?;
+                    match self.matches.get(name) {
+                        Some(options) => current = Some(options),
+                        None => return not_allowed(format!("match module {:?}", name)),
+                    }
+                }
+                "-j" | "--jump" | "-g" | "--goto" => {
+                    let name = name_after(arg, args.next())?;
+                    match self.targets.get(name) {
+                        Some(options) => current = Some(options),
+                        None => return not_allowed(format!("target {:?}", name)),
+                    }
+                }
+                option if BASIC_OPTIONS.contains(&option) => {}
+                option if option.starts_with('-') => {
+                    let allowed = current
+                        .or_else(|| {
+                            protocol
+                                .as_deref()
+                                .and_then(|protocol| self.matches.get(protocol))
+                                .map(Vec::as_slice)
+                        })
+                        .unwrap_or_default();
+                    // Ports are allowed after the protocol even if its module is not listed
+                    let port = protocol.is_some() && PORT_OPTIONS.contains(&option);
+                    if !port && !allowed.iter().any(|allowed| allowed == option) {
+                        return not_allowed(format!("option {:?}", option));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// Returns the protocol, match module or target given after `option`, which must not look like
+// an option, or iptables would read the next option as the name
+fn name_after<'a>(option: &str, name: Option<&'a String>) -> Result<&'a str, ValidationError> {
+    match name {
+        Some(name) if name.starts_with('-') => Err(ValidationError::NotAllowed(format!(
+            "value {:?} of {}",
+            name, option
+        ))),
+        Some(name) => Ok(name),
+        None => Err(ValidationError::MissingValue(option.to_string())),
+    }
+}
+
+fn control_character(c: char) -> ValidationError {
+    ValidationError::NotAllowed(format!("control character {:?}", c))
+}
+
+impl IPTables {
+    // Checks the arguments of a rule with the configured sanitizer, if any
+    pub(crate) fn sanitize(&self, args: &[String]) -> IPTResult<()> {
+        match &self.sanitizer {
+            Some(sanitizer) => Ok(sanitizer.check_args(args)?),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! Read-only snapshot of the rules of all tables, taken once with `iptables-save` and queried
+//! without running iptables again.
+
+use crate::counters::{parse_bracketed, RuleCounters};
+use crate::diff::normalize;
+use crate::parse::ParsedRule;
+use crate::{has_comment, IPTError, IPTResult, IPTables, SplitQuoted};
+use std::collections::HashMap;
+
+/// The rules of all tables at the time `IPTables::snapshot` was called, parsed from the output
+/// of `iptables-save`.
+///
+/// # Example
+/// ```
+/// use iptables::snapshot::Snapshot;
+///
+/// let snapshot = Snapshot::parse(
+///     "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -s 10.0.0.1/32 -j DROP\nCOMMIT\n",
+/// )
+/// .unwrap();
+/// assert_eq!(snapshot.chain("filter", "INPUT").unwrap().rules().len(), 1);
+/// assert!(snapshot.find(|rule| rule.target() == "DROP").is_some());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    chains: Vec<SnapshotChain>,
+    // The position of each table/chain in `chains`
+    index: HashMap<(String, String), usize>,
+}
+
+/// A chain of a `Snapshot` together with its rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotChain {
+    table: String,
+    name: String,
+    policy: Option<String>,
+    packets: u64,
+    bytes: u64,
+    rules: Vec<SnapshotRule>,
+}
+
+/// A rule of a `Snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotRule {
+    table: String,
+    chain: String,
+    spec: String,
+    args: Vec<String>,
+    packets: u64,
+    bytes: u64,
+}
+
+impl Snapshot {
+    /// Parses the output of `iptables-save`.
+    pub fn parse(output: &str) -> IPTResult<Snapshot> {
+        let mut snapshot = Snapshot::default();
+        let mut table = None;
+
+        for line in output.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('*') {
+                table = Some(name.to_string());
+            } else if line == "COMMIT" {
+                table = None;
+            } else {
+                let table = table
+                    .clone()
+                    .ok_or_else(|| IPTError::Parse(format!("line outside of a table: {}", line)))?;
+                if let Some(declaration) = line.strip_prefix(':') {
+                    let mut fields = declaration.split_whitespace();
+                    let name = fields.next().unwrap_or_default().to_string();
+                    let policy = fields.next().filter(|&policy| policy != "-");
+                    let (packets, bytes) = match fields.next() {
+                        Some(counters) => parse_bracketed(counters).ok_or_else(|| {
+                            IPTError::Parse(format!("invalid chain counters: {}", line))
+                        })?,
+                        None => (0, 0),
+                    };
+                    let chain = snapshot.chain_mut(&table, &name);
+                    chain.policy = policy.map(String::from);
+                    chain.packets = packets;
+                    chain.bytes = bytes;
+                } else {
+                    let counters = RuleCounters::parse(line)
+                        .ok_or_else(|| IPTError::Parse(format!("invalid rule: {}", line)))?;
+                    let rule = SnapshotRule {
+                        table: table.clone(),
+                        chain: counters.chain.clone(),
+                        args: counters.rule.split_quoted(),
+                        spec: counters.rule,
+                        packets: counters.packets,
+                        bytes: counters.bytes,
+                    };
+                    snapshot.chain_mut(&table, &counters.chain).rules.push(rule);
+                }
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Returns the names of the tables, in the order they were saved.
+    pub fn tables(&self) -> Vec<&str> {
+        let mut tables: Vec<&str> = Vec::new();
+        for chain in &self.chains {
+            if !tables.contains(&chain.table.as_str()) {
+                tables.push(&chain.table);
+            }
+        }
+        tables
+    }
+
+    /// Returns the chains of the table, in the order they were saved.
+    pub fn chains(&self, table: &str) -> Vec<&SnapshotChain> {
+        self.chains
+            .iter()
+            .filter(|chain| chain.table == table)
+            .collect()
+    }
+
+    /// Returns the table/chain if it existed.
+    pub fn chain(&self, table: &str, chain: &str) -> Option<&SnapshotChain> {
+        self.index
+            .get(&(table.to_string(), chain.to_string()))
+            .map(|&index| &self.chains[index])
+    }
+
+    /// Returns the rules of all tables and chains.
+    pub fn rules(&self) -> impl Iterator<Item = &SnapshotRule> {
+        self.chains.iter().flat_map(|chain| chain.rules.iter())
+    }
+
+    /// Returns the first rule accepted by `predicate`.
+    pub fn find<P: Fn(&SnapshotRule) -> bool>(&self, predicate: P) -> Option<&SnapshotRule> {
+        self.rules().find(|rule| predicate(rule))
+    }
+
+    /// Returns all rules accepted by `predicate`.
+    pub fn filter<P: Fn(&SnapshotRule) -> bool>(&self, predicate: P) -> Vec<&SnapshotRule> {
+        self.rules().filter(|rule| predicate(rule)).collect()
+    }
+
+    /// Checks for the existence of `rule` in the table/chain. The rule is compared with the
+    /// saved rules, so it should be given in the form `iptables -S` prints it.
+    pub fn contains(&self, table: &str, chain: &str, rule: &str) -> bool {
+        self.chain(table, chain)
+            .is_some_and(|chain| chain.contains(rule))
+    }
+
+    fn chain_mut(&mut self, table: &str, name: &str) -> &mut SnapshotChain {
+        let key = (table.to_string(), name.to_string());
+        let chains = &mut self.chains;
+        let index = *self.index.entry(key).or_insert_with(|| {
+            chains.push(SnapshotChain {
+                table: table.to_string(),
+                name: name.to_string(),
+                policy: None,
+                packets: 0,
+                bytes: 0,
+                rules: Vec::new(),
+            });
+            chains.len() - 1
+        });
+        &mut self.chains[index]
+    }
+}
+
+impl SnapshotChain {
+    /// Returns the table of the chain.
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Returns the name of the chain.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the policy of a built-in chain.
+    pub fn policy(&self) -> Option<&str> {
+        self.policy.as_deref()
+    }
+
+    /// Returns the packets which reached the policy of a built-in chain, zero unless the
+    /// counters were saved.
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// Returns the bytes which reached the policy of a built-in chain, zero unless the counters
+    /// were saved.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Returns the rules of the chain, in their order.
+    pub fn rules(&self) -> &[SnapshotRule] {
+        &self.rules
+    }
+
+    /// Checks for the existence of `rule`, given in the form `iptables -S` prints it.
+    pub fn contains(&self, rule: &str) -> bool {
+        let rule = normalize(rule);
+        self.rules.iter().any(|saved| saved.spec == rule)
+    }
+}
+
+impl SnapshotRule {
+    /// Returns the table of the rule.
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Returns the chain of the rule.
+    pub fn chain(&self) -> &str {
+        &self.chain
+    }
+
+    /// Returns the rule specification without the chain, e.g. `-s 10.0.0.1/32 -j DROP`.
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    /// Returns the arguments of the rule specification.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Returns the target given by `-j` or `-g`, or an empty string if the rule has no target.
+    pub fn target(&self) -> &str {
+        self.args
+            .windows(2)
+            .find(|pair| ["-j", "--jump", "-g", "--goto"].contains(&pair[0].as_str()))
+            .map_or("", |pair| pair[1].as_str())
+    }
+
+    /// Returns the first comment (`-m comment --comment`) of the rule.
+    pub fn comment(&self) -> Option<&str> {
+        self.args
+            .windows(2)
+            .find(|pair| pair[0] == "--comment")
+            .map(|pair| pair[1].as_str())
+    }
+
+    /// Indicates if the rule carries the comment `tag`.
+    pub fn has_comment(&self, tag: &str) -> bool {
+        has_comment(&self.args, tag)
+    }
+
+    /// Returns the packets matched by the rule, zero unless the counters were saved.
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// Returns the bytes matched by the rule, zero unless the counters were saved.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Parses the rule into its typed representation.
+    pub fn parse(&self) -> IPTResult<ParsedRule> {
+        ParsedRule::parse(&format!("-A {} {}", self.chain, self.spec))
+    }
+}
+
+impl IPTables {
+    /// Takes a snapshot of the rules of all tables with a single `iptables-save` call.
+    pub fn snapshot(&self) -> IPTResult<Snapshot> {
+        Snapshot::parse(&self.save(None)?)
+    }
+}
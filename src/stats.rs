@@ -0,0 +1,175 @@
+//! Flat statistics of the packet and byte counters of all tables, collected with a single
+//! `iptables-save -c` call, e.g. to be scraped by a metrics exporter.
+
+use crate::snapshot::Snapshot;
+use crate::{IPTResult, IPTables};
+use std::fmt::Write;
+
+/// The counters of the policy of a built-in chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChainStats {
+    /// The table of the chain.
+    pub table: String,
+    /// The name of the chain.
+    pub chain: String,
+    /// The policy of the chain, e.g. `ACCEPT`.
+    pub policy: String,
+    /// The packets which reached the policy.
+    pub packets: u64,
+    /// The bytes which reached the policy.
+    pub bytes: u64,
+}
+
+/// The counters of a rule, labeled with the values a metric is usually labeled with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuleStats {
+    /// The table of the rule.
+    pub table: String,
+    /// The chain of the rule.
+    pub chain: String,
+    /// The position of the rule in its chain, the first rule has position 1.
+    pub position: u32,
+    /// The first comment of the rule, which identifies it across changes of the chain.
+    pub comment: Option<String>,
+    /// The target given by `-j` or `-g`.
+    pub target: Option<String>,
+    /// The packets matched by the rule.
+    pub packets: u64,
+    /// The bytes matched by the rule.
+    pub bytes: u64,
+}
+
+/// The counters of all built-in chains and rules, returned by `IPTables::stats`.
+///
+/// # Example
+/// ```
+/// use iptables::stats::Stats;
+///
+/// let stats = Stats::parse(
+///     "*filter\n:INPUT DROP [12:720]\n[3:180] -A INPUT -m comment --comment ssh -j ACCEPT\nCOMMIT\n",
+/// )
+/// .unwrap();
+/// assert_eq!(stats.chains[0].packets, 12);
+/// assert_eq!(stats.rules[0].comment.as_deref(), Some("ssh"));
+///
+/// let mut metrics = String::new();
+/// stats.write_prometheus(&mut metrics);
+/// assert!(metrics.contains(
+///     "iptables_rule_packets_total{table=\"filter\",chain=\"INPUT\",position=\"1\",\
+///      comment=\"ssh\",target=\"ACCEPT\"} 3\n"
+/// ));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stats {
+    /// The counters of the policies of the built-in chains, in the order they were saved.
+    pub chains: Vec<ChainStats>,
+    /// The counters of the rules, in the order they were saved.
+    pub rules: Vec<RuleStats>,
+}
+
+impl Stats {
+    /// Parses the output of `iptables-save -c`.
+    pub fn parse(output: &str) -> IPTResult<Stats> {
+        let mut stats = Stats::default();
+        stats.parse_into(output)?;
+        Ok(stats)
+    }
+
+    // Replaces the statistics with the ones of `output`, keeping the allocated capacity
+    fn parse_into(&mut self, output: &str) -> IPTResult<()> {
+        let snapshot = Snapshot::parse(output)?;
+        self.chains.clear();
+        self.rules.clear();
+
+        for table in snapshot.tables() {
+            for chain in snapshot.chains(table) {
+                if let Some(policy) = chain.policy() {
+                    self.chains.push(ChainStats {
+                        table: table.to_string(),
+                        chain: chain.name().to_string(),
+                        policy: policy.to_string(),
+                        packets: chain.packets(),
+                        bytes: chain.bytes(),
+                    });
+                }
+                for (index, rule) in chain.rules().iter().enumerate() {
+                    self.rules.push(RuleStats {
+                        table: table.to_string(),
+                        chain: chain.name().to_string(),
+                        position: index as u32 + 1,
+                        comment: rule.comment().map(String::from),
+                        target: Some(rule.target())
+                            .filter(|target| !target.is_empty())
+                            .map(String::from),
+                        packets: rule.packets(),
+                        bytes: rule.bytes(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the statistics to `out` in the Prometheus text format, as the counters
+    /// `iptables_chain_policy_packets_total`, `iptables_chain_policy_bytes_total`,
+    /// `iptables_rule_packets_total` and `iptables_rule_bytes_total`. Missing comments and
+    /// targets are rendered as empty labels.
+    pub fn write_prometheus(&self, out: &mut String) {
+        for (name, bytes) in [("packets", false), ("bytes", true)] {
+            let _ = writeln!(out, "# TYPE iptables_chain_policy_{}_total counter", name);
+            for chain in &self.chains {
+                let _ = writeln!(
+                    out,
+                    "iptables_chain_policy_{}_total{{table=\"{}\",chain=\"{}\",policy=\"{}\"}} {}",
+                    name,
+                    escape_label(&chain.table),
+                    escape_label(&chain.chain),
+                    escape_label(&chain.policy),
+                    if bytes { chain.bytes } else { chain.packets },
+                );
+            }
+        }
+        for (name, bytes) in [("packets", false), ("bytes", true)] {
+            let _ = writeln!(out, "# TYPE iptables_rule_{}_total counter", name);
+            for rule in &self.rules {
+                let _ = writeln!(
+                    out,
+                    "iptables_rule_{}_total{{table=\"{}\",chain=\"{}\",position=\"{}\",\
+                     comment=\"{}\",target=\"{}\"}} {}",
+                    name,
+                    escape_label(&rule.table),
+                    escape_label(&rule.chain),
+                    rule.position,
+                    escape_label(rule.comment.as_deref().unwrap_or_default()),
+                    escape_label(rule.target.as_deref().unwrap_or_default()),
+                    if bytes { rule.bytes } else { rule.packets },
+                );
+            }
+        }
+    }
+}
+
+// Label values escape backslashes, double quotes and line feeds
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl IPTables {
+    /// Collects the counters of the policies of all built-in chains and of all rules with a
+    /// single `iptables-save -c` call.
+    pub fn stats(&self) -> IPTResult<Stats> {
+        Stats::parse(&self.save_with_counters(None)?)
+    }
+
+    /// Collects the counters like `stats` into `stats`, reusing its allocated vectors, so a
+    /// metrics exporter polling often can keep a single `Stats` around.
+    pub fn stats_into(&self, stats: &mut Stats) -> IPTResult<()> {
+        stats.parse_into(&self.save_with_counters(None)?)
+    }
+}
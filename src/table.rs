@@ -0,0 +1,168 @@
+//! Typed names of the tables and built-in chains of iptables.
+
+use std::fmt;
+use std::ops::Deref;
+
+/// A table of iptables, accepted by every method which takes a table.
+///
+/// Strings are converted to the matching variant, so `"nat"` and `Table::Nat` are equivalent.
+/// Using the variants catches misspelled table names at compile time.
+///
+/// With the `serde` feature, tables are serialized as their name, e.g. `"nat"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "String", into = "String"))]
+pub enum Table {
+    /// The `filter` table, the default table of iptables.
+    Filter,
+    /// The `nat` table.
+    Nat,
+    /// The `mangle` table.
+    Mangle,
+    /// The `raw` table.
+    Raw,
+    /// The `security` table.
+    Security,
+    /// Any other table, e.g. one added by a kernel module.
+    Custom(String),
+}
+
+impl Table {
+    /// Returns the name of the table as passed to iptables.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Table::Filter => "filter",
+            Table::Nat => "nat",
+            Table::Mangle => "mangle",
+            Table::Raw => "raw",
+            Table::Security => "security",
+            Table::Custom(name) => name,
+        }
+    }
+}
+
+impl Deref for Table {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&str> for Table {
+    fn from(name: &str) -> Self {
+        match name {
+            "filter" => Table::Filter,
+            "nat" => Table::Nat,
+            "mangle" => Table::Mangle,
+            "raw" => Table::Raw,
+            "security" => Table::Security,
+            _ => Table::Custom(name.to_string()),
+        }
+    }
+}
+
+impl From<&String> for Table {
+    fn from(name: &String) -> Self {
+        Table::from(name.as_str())
+    }
+}
+
+impl From<String> for Table {
+    fn from(name: String) -> Self {
+        Table::from(name.as_str())
+    }
+}
+
+impl From<&Table> for Table {
+    fn from(table: &Table) -> Self {
+        table.clone()
+    }
+}
+
+impl From<Table> for String {
+    fn from(table: Table) -> Self {
+        table.as_str().to_string()
+    }
+}
+
+/// A chain of iptables. Dereferences to the name of the chain, so `&Chain::Input` can be passed
+/// to every method which takes a chain.
+///
+/// With the `serde` feature, chains are serialized as their name, e.g. `"INPUT"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "String", into = "String"))]
+pub enum Chain {
+    /// The built-in `INPUT` chain.
+    Input,
+    /// The built-in `OUTPUT` chain.
+    Output,
+    /// The built-in `FORWARD` chain.
+    Forward,
+    /// The built-in `PREROUTING` chain.
+    Prerouting,
+    /// The built-in `POSTROUTING` chain.
+    Postrouting,
+    /// A user-defined chain.
+    Custom(String),
+}
+
+impl Chain {
+    /// Returns the name of the chain as passed to iptables.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Chain::Input => "INPUT",
+            Chain::Output => "OUTPUT",
+            Chain::Forward => "FORWARD",
+            Chain::Prerouting => "PREROUTING",
+            Chain::Postrouting => "POSTROUTING",
+            Chain::Custom(name) => name,
+        }
+    }
+}
+
+impl Deref for Chain {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&str> for Chain {
+    fn from(name: &str) -> Self {
+        match name {
+            "INPUT" => Chain::Input,
+            "OUTPUT" => Chain::Output,
+            "FORWARD" => Chain::Forward,
+            "PREROUTING" => Chain::Prerouting,
+            "POSTROUTING" => Chain::Postrouting,
+            _ => Chain::Custom(name.to_string()),
+        }
+    }
+}
+
+impl From<String> for Chain {
+    fn from(name: String) -> Self {
+        Chain::from(name.as_str())
+    }
+}
+
+impl From<Chain> for String {
+    fn from(chain: Chain) -> Self {
+        chain.as_str().to_string()
+    }
+}
@@ -0,0 +1,171 @@
+//! Typed targets of a rule, set with `Rule::target`, including the ICMP replies of `REJECT`.
+
+use crate::nat::NatTarget;
+use crate::queue::NfQueue;
+use std::fmt;
+
+/// The reply sent by the `REJECT` target (`--reject-with`). The ICMP replies only exist in one
+/// address family, which `Rule::check` verifies, `TcpReset` in both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejectWith {
+    /// `icmp-net-unreachable`, IPv4 only.
+    IcmpNetUnreachable,
+    /// `icmp-host-unreachable`, IPv4 only.
+    IcmpHostUnreachable,
+    /// `icmp-port-unreachable`, the default of iptables.
+    IcmpPortUnreachable,
+    /// `icmp-proto-unreachable`, IPv4 only.
+    IcmpProtoUnreachable,
+    /// `icmp-net-prohibited`, IPv4 only.
+    IcmpNetProhibited,
+    /// `icmp-host-prohibited`, IPv4 only.
+    IcmpHostProhibited,
+    /// `icmp-admin-prohibited`, IPv4 only.
+    IcmpAdminProhibited,
+    /// `icmp6-no-route`, IPv6 only.
+    Icmp6NoRoute,
+    /// `icmp6-adm-prohibited`, IPv6 only.
+    Icmp6AdmProhibited,
+    /// `icmp6-addr-unreachable`, IPv6 only.
+    Icmp6AddrUnreachable,
+    /// `icmp6-port-unreachable`, the default of ip6tables.
+    Icmp6PortUnreachable,
+    /// `icmp6-policy-fail`, IPv6 only.
+    Icmp6PolicyFail,
+    /// `icmp6-reject-route`, IPv6 only.
+    Icmp6RejectRoute,
+    /// `tcp-reset`, answers TCP packets with a reset in both families. Requires the protocol
+    /// `tcp`.
+    TcpReset,
+}
+
+impl RejectWith {
+    /// Returns the port unreachable reply of the family, the default of iptables and
+    /// ip6tables.
+    pub fn port_unreachable(is_ipv6: bool) -> Self {
+        if is_ipv6 {
+            RejectWith::Icmp6PortUnreachable
+        } else {
+            RejectWith::IcmpPortUnreachable
+        }
+    }
+
+    /// Returns the reply as passed to iptables.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectWith::IcmpNetUnreachable => "icmp-net-unreachable",
+            RejectWith::IcmpHostUnreachable => "icmp-host-unreachable",
+            RejectWith::IcmpPortUnreachable => "icmp-port-unreachable",
+            RejectWith::IcmpProtoUnreachable => "icmp-proto-unreachable",
+            RejectWith::IcmpNetProhibited => "icmp-net-prohibited",
+            RejectWith::IcmpHostProhibited => "icmp-host-prohibited",
+            RejectWith::IcmpAdminProhibited => "icmp-admin-prohibited",
+            RejectWith::Icmp6NoRoute => "icmp6-no-route",
+            RejectWith::Icmp6AdmProhibited => "icmp6-adm-prohibited",
+            RejectWith::Icmp6AddrUnreachable => "icmp6-addr-unreachable",
+            RejectWith::Icmp6PortUnreachable => "icmp6-port-unreachable",
+            RejectWith::Icmp6PolicyFail => "icmp6-policy-fail",
+            RejectWith::Icmp6RejectRoute => "icmp6-reject-route",
+            RejectWith::TcpReset => "tcp-reset",
+        }
+    }
+}
+
+impl fmt::Display for RejectWith {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The target of a rule, set with `Rule::target`.
+///
+/// # Example
+/// ```
+/// use iptables::target::{RejectWith, Target};
+/// use iptables::Rule;
+///
+/// let rule = Rule::new().protocol("tcp").target(Target::Reject {
+///     with: RejectWith::TcpReset,
+/// });
+/// assert_eq!(rule.to_string(), "-p tcp -j REJECT --reject-with tcp-reset");
+///
+/// let rule = Rule::new().target(Target::Reject {
+///     with: RejectWith::IcmpHostProhibited,
+/// });
+/// assert!(rule.check(false).is_ok());
+/// assert!(rule.check(true).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Accepts the packet (`-j ACCEPT`).
+    Accept,
+    /// Drops the packet silently (`-j DROP`).
+    Drop,
+    /// Returns to the chain which jumped to the current one, or applies the policy of a
+    /// built-in chain (`-j RETURN`).
+    Return,
+    /// Drops the packet and answers it with `with` (`-j REJECT --reject-with`).
+    Reject {
+        /// The reply, which must exist in the family of the rule.
+        with: RejectWith,
+    },
+    /// Passes the packet to the userspace program listening on the queue `num`
+    /// (`-j NFQUEUE --queue-num`), see `Rule::nfqueue` for the other options.
+    Queue {
+        /// The number of the netfilter queue.
+        num: u16,
+    },
+    /// Jumps to the user-defined chain (`-j`).
+    Jump(String),
+    /// Continues in the user-defined chain without returning (`-g`), see `Rule::goto`.
+    Goto(String),
+    /// A NAT target, see `Rule::nat`.
+    Nat(NatTarget),
+}
+
+impl Target {
+    /// Returns the name of the target, its options in the order `iptables -S` prints them and
+    /// whether it is given with `-g`.
+    pub(crate) fn target_args(&self) -> (String, Vec<String>, bool) {
+        let simple = |name: &str| (name.to_string(), Vec::new(), false);
+        match self {
+            Target::Accept => simple("ACCEPT"),
+            Target::Drop => simple("DROP"),
+            Target::Return => simple("RETURN"),
+            Target::Reject { with } => (
+                "REJECT".to_string(),
+                vec!["--reject-with".to_string(), with.to_string()],
+                false,
+            ),
+            Target::Queue { num } => (
+                "NFQUEUE".to_string(),
+                NfQueue::new(*num).target_args(),
+                false,
+            ),
+            Target::Jump(chain) => simple(chain),
+            Target::Goto(chain) => (chain.clone(), Vec::new(), true),
+            Target::Nat(nat) => {
+                let (name, args) = nat.target_args();
+                (name.to_string(), args, false)
+            }
+        }
+    }
+}
+
+impl From<NatTarget> for Target {
+    fn from(nat: NatTarget) -> Self {
+        Target::Nat(nat)
+    }
+}
+
+/// Describes a `--reject-with` value which only exists in the other address family, IPv6 if
+/// `is_ipv6` is set. The ICMP replies are told apart by their `icmp-` or `icmp6-` prefix, the
+/// abbreviations iptables accepts are not checked.
+pub(crate) fn reject_with_mismatch(value: &str, is_ipv6: bool) -> Option<String> {
+    let other = if is_ipv6 { "icmp-" } else { "icmp6-" };
+    if value.starts_with(other) {
+        Some(format!("reject-with {}", value))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,92 @@
+//! Disposable network namespaces for tests which run the real iptables binaries, available with
+//! the `testing` feature.
+
+use crate::builder::IPTablesBuilder;
+use crate::error::IptablesError;
+use crate::{IPTResult, IPTables};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Tells the namespaces of the tests running in parallel apart
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary network namespace created with `ip netns add`, which is deleted with all its
+/// rules when the sandbox is dropped, also if a test panics. Creating it needs root and iproute2.
+///
+/// # Example
+/// ```no_run
+/// use iptables::testing::NetnsSandbox;
+///
+/// let exists = NetnsSandbox::run(false, |ipt| {
+///     ipt.new_chain("filter", "TEST").unwrap();
+///     ipt.chain_exists("filter", "TEST").unwrap()
+/// })
+/// .unwrap();
+/// assert!(exists);
+/// ```
+#[derive(Debug)]
+pub struct NetnsSandbox {
+    name: String,
+    path: PathBuf,
+}
+
+impl NetnsSandbox {
+    /// Creates a new empty network namespace.
+    pub fn new() -> IPTResult<NetnsSandbox> {
+        let name = format!(
+            "iptables-sandbox-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        );
+        ip_netns(&["add", &name])?;
+        Ok(NetnsSandbox {
+            path: Path::new("/run/netns").join(&name),
+            name,
+        })
+    }
+
+    /// Creates a sandbox, runs `f` with a handle of the address family, IPv6 if `is_ipv6` is
+    /// set, bound to it and deletes the sandbox afterwards.
+    pub fn run<T>(is_ipv6: bool, f: impl FnOnce(&IPTables) -> T) -> IPTResult<T> {
+        let sandbox = NetnsSandbox::new()?;
+        let ipt = sandbox.iptables(is_ipv6)?;
+        Ok(f(&ipt))
+    }
+
+    /// Returns the name of the namespace as known to `ip netns`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the path the namespace is mounted at.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns a builder whose handles run every command inside the namespace, see
+    /// `IPTablesBuilder::netns_path`.
+    pub fn builder(&self) -> IPTablesBuilder {
+        IPTables::builder().netns_path(&self.path)
+    }
+
+    /// Creates a handle of the address family, IPv6 if `is_ipv6` is set, bound to the
+    /// namespace.
+    pub fn iptables(&self, is_ipv6: bool) -> IPTResult<IPTables> {
+        self.builder().ipv6(is_ipv6).build()
+    }
+}
+
+impl Drop for NetnsSandbox {
+    fn drop(&mut self) {
+        let _ = ip_netns(&["delete", &self.name]);
+    }
+}
+
+fn ip_netns(args: &[&str]) -> IPTResult<()> {
+    let output = Command::new("ip").arg("netns").args(args).output()?;
+    if !output.status.success() {
+        return Err(IptablesError::from(output).into());
+    }
+    Ok(())
+}
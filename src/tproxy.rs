@@ -0,0 +1,70 @@
+//! High-level helpers diverting traffic to a transparent proxy with the `TPROXY` target of the
+//! `mangle` table.
+
+use crate::rule::Rule;
+use crate::{IPTResult, IPTables};
+
+/// The chain of the `mangle` table which marks and accepts the packets of connections the proxy
+/// already owns, created by `IPTables::setup_transparent_proxy`.
+pub const DIVERT_CHAIN: &str = "DIVERT";
+
+/// Builds the rules installed by `IPTables::setup_transparent_proxy`, each with its chain of the
+/// `mangle` table, in the order they are appended.
+pub fn transparent_proxy_rules(port: u16, mark: u32) -> Vec<(&'static str, Rule)> {
+    vec![
+        (DIVERT_CHAIN, Rule::new().set_mark(mark, None)),
+        (DIVERT_CHAIN, Rule::new().jump("ACCEPT")),
+        (
+            "PREROUTING",
+            Rule::new().protocol("tcp").socket(false).jump(DIVERT_CHAIN),
+        ),
+        (
+            "PREROUTING",
+            Rule::new()
+                .protocol("tcp")
+                .tproxy(port, None, mark, Some(mark)),
+        ),
+    ]
+}
+
+impl IPTables {
+    /// Diverts incoming TCP connections to the transparent proxy listening on `port` with
+    /// `IP_TRANSPARENT`: packets of connections with a local socket are marked with `mark` in
+    /// the `DIVERT` chain of the `mangle` table, the others are sent to the proxy with `TPROXY`
+    /// and marked as well. The chain is created if it is missing, each rule is appended unless
+    /// it already exists.
+    ///
+    /// The marked packets have to be routed to the local host by the caller, e.g. with
+    /// `ip rule add fwmark 1 lookup 100` and `ip route add local 0.0.0.0/0 dev lo table 100`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let ipt = iptables::new(false).unwrap();
+    /// ipt.setup_transparent_proxy(50080, 1).unwrap();
+    /// ```
+    pub fn setup_transparent_proxy(&self, port: u16, mark: u32) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        if !self.chain_exists("mangle", DIVERT_CHAIN)? {
+            self.new_chain("mangle", DIVERT_CHAIN)?;
+        }
+        for (chain, rule) in transparent_proxy_rules(port, mark) {
+            self.append_rule_if_missing("mangle", chain, &rule)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the rules installed by `setup_transparent_proxy` with the same arguments, and the
+    /// `DIVERT` chain if it is empty then.
+    pub fn remove_transparent_proxy(&self, port: u16, mark: u32) -> IPTResult<()> {
+        let _serial = self.serial.lock();
+        for (chain, rule) in transparent_proxy_rules(port, mark).iter().rev() {
+            self.delete_rule_if_present("mangle", chain, rule)?;
+        }
+        if self.chain_exists("mangle", DIVERT_CHAIN)?
+            && self.list("mangle", DIVERT_CHAIN)?.len() <= 1
+        {
+            self.delete_chain("mangle", DIVERT_CHAIN)?;
+        }
+        Ok(())
+    }
+}
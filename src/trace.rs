@@ -0,0 +1,67 @@
+//! Logs every executed command with the `log` or `tracing` feature.
+//!
+//! Completed commands are logged at the debug level, commands which could not be run at the
+//! warn level. If both features are enabled, only `tracing` is used.
+
+use crate::IPTResult;
+use std::process::Output;
+use std::time::Duration;
+
+// The maximum number of characters of the standard error which are logged
+#[cfg(any(feature = "log", feature = "tracing"))]
+const MAX_STDERR: usize = 256;
+
+#[cfg(not(any(feature = "log", feature = "tracing")))]
+pub(crate) fn command(
+    _program: &str,
+    _args: &[String],
+    _result: &IPTResult<Output>,
+    _elapsed: Duration,
+) {
+}
+
+/// Logs the run of `program` with `args` which took `elapsed`.
+#[cfg(any(feature = "log", feature = "tracing"))]
+pub(crate) fn command(
+    program: &str,
+    args: &[String],
+    result: &IPTResult<Output>,
+    elapsed: Duration,
+) {
+    let argv = crate::rule::join_quoted(args);
+    let duration_ms = elapsed.as_secs_f64() * 1000.0;
+    match result {
+        Ok(output) => {
+            let code = output.status.code().unwrap_or(-1);
+            let stderr = truncated(&output.stderr);
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!(program, argv = %argv, code, duration_ms, stderr = %stderr, "ran iptables command");
+            #[cfg(not(feature = "tracing"))]
+            log::debug!(
+                "ran `{} {}`: exit code {}, {:.1} ms, stderr: {:?}",
+                program,
+                argv,
+                code,
+                duration_ms,
+                stderr
+            );
+        }
+        Err(err) => {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(program, argv = %argv, duration_ms, error = %err, "failed to run iptables command");
+            #[cfg(not(feature = "tracing"))]
+            log::warn!("failed to run `{} {}`: {}", program, argv, err);
+        }
+    }
+}
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+fn truncated(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let stderr = stderr.trim_end();
+    match stderr.char_indices().nth(MAX_STDERR) {
+        Some((end, _)) => format!("{}...", &stderr[..end]),
+        None => stderr.to_string(),
+    }
+}
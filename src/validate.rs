@@ -0,0 +1,217 @@
+//! Checks rules for mistakes before they are passed to iptables, see `IPTables::validate_rule`.
+
+use crate::interface::validate_interface;
+use crate::net::Cidr;
+use crate::rule::family_mismatch;
+use crate::{IPTables, SplitQuoted};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+
+/// Describes why a rule was rejected by `validate_rule`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValidationError {
+    /// The rule is empty.
+    Empty,
+    /// A single or double quote of the rule is not closed.
+    UnbalancedQuotes,
+    /// The option is not known to iptables, e.g. a misspelled `-dport`, or it is given before the
+    /// match module or protocol which provides it.
+    UnknownOption(String),
+    /// The option is given without its value.
+    MissingValue(String),
+    /// `-j` or `-g` is given without a target.
+    MissingTarget,
+    /// The address or network is invalid, e.g. its prefix is too long.
+    InvalidAddress(String),
+    /// The value of the option is longer than `max` bytes, e.g. a `--log-prefix`.
+    TooLong { option: String, max: usize },
+    /// The ports of a structured rule are invalid, e.g. given without a protocol.
+    InvalidPorts(String),
+    /// The option is only available in the other address family, e.g. `-m icmp` for ip6tables.
+    WrongFamily(String),
+    /// The interface name of `-i` or `-o` is invalid, see `validate_interface`.
+    InvalidInterface(String),
+    /// A range of a structured rule is reversed, e.g. the queues of `--queue-balance`.
+    InvalidRange(String),
+    /// The rule uses an option, match module or target which the `Sanitizer` does not allow,
+    /// or contains a control character.
+    NotAllowed(String),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Empty => write!(f, "the rule is empty"),
+            ValidationError::UnbalancedQuotes => write!(f, "the rule has an unclosed quote"),
+            ValidationError::UnknownOption(option) => write!(f, "unknown option {}", option),
+            ValidationError::MissingValue(option) => {
+                write!(f, "option {} is missing a value", option)
+            }
+            ValidationError::MissingTarget => write!(f, "-j or -g is missing the target"),
+            ValidationError::NotAllowed(what) => write!(f, "{} is not allowed", what),
+            ValidationError::TooLong { option, max } => {
+                write!(f, "the value of {} is longer than {} bytes", option, max)
+            }
+            ValidationError::InvalidAddress(what)
+            | ValidationError::InvalidPorts(what)
+            | ValidationError::WrongFamily(what)
+            | ValidationError::InvalidInterface(what)
+            | ValidationError::InvalidRange(what) => {
+                write!(f, "{}", what)
+            }
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Checks `rule`, given without the leading `-A <chain>`, for mistakes iptables would reject with
+/// a cryptic message: unclosed quotes, unknown basic options, options given before the match
+/// module providing them, `-j` without a target, options missing their value, network prefixes
+/// which are too long, invalid interface names and options or `--reject-with` replies of the
+/// other address family, IPv6 if `is_ipv6` is set.
+///
+/// Options of match modules and targets are not known to the crate, so they are only checked to
+/// be given after `-m` or `-j`.
+///
+/// # Example
+/// ```
+/// use iptables::validate::{validate_rule, ValidationError};
+///
+/// assert!(validate_rule("-p tcp --dport 22 -j ACCEPT", false).is_ok());
+/// assert_eq!(
+///     validate_rule("-p tcp -dport 22 -j ACCEPT", false),
+///     Err(ValidationError::UnknownOption("-dport".to_string()))
+/// );
+/// assert_eq!(
+///     validate_rule("-m comment --comment 'ssh -j ACCEPT", false),
+///     Err(ValidationError::UnbalancedQuotes)
+/// );
+/// ```
+pub fn validate_rule(rule: &str, is_ipv6: bool) -> Result<(), ValidationError> {
+    if has_unclosed_quote(rule) {
+        return Err(ValidationError::UnbalancedQuotes);
+    }
+    let args = rule.split_quoted();
+    if args.is_empty() {
+        return Err(ValidationError::Empty);
+    }
+
+    let mut addresses = Vec::new();
+    let mut protocol = None;
+    let mut matches = Vec::new();
+    let mut in_target = false;
+    let mut fragment = false;
+    let mut reject_with = None;
+
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next_if(|next| !next.starts_with('-') && next != "!")
+                .ok_or_else(|| ValidationError::MissingValue(arg.clone()))
+        };
+
+        match arg.as_str() {
+            "!" => {}
+            "-p" | "--protocol" => protocol = Some(value()?),
+            "-s" | "--source" | "-d" | "--destination" => addresses.push(value()?),
+            "-i" | "--in-interface" | "-o" | "--out-interface" => validate_interface(&value()?)?,
+            "-f" | "--fragment" => fragment = true,
+            "-m" | "--match" => {
+                in_target = false;
+                matches.push(value()?);
+            }
+            "-j" | "--jump" | "-g" | "--goto" => {
+                in_target = true;
+                value().map_err(|_| ValidationError::MissingTarget)?;
+            }
+            "-c" | "--set-counters" => {
+                value()?;
+                value()?;
+            }
+            option if option.starts_with("--") && option.len() > 2 => {
+                // Options of the protocol may be given without loading its match module
+                if !in_target && matches.is_empty() && protocol.is_none() {
+                    return Err(ValidationError::UnknownOption(arg));
+                }
+                if in_target && option == "--reject-with" {
+                    reject_with = args.next_if(|next| !next.starts_with('-'));
+                }
+                while args.next_if(|next| !next.starts_with('-')).is_some() {}
+            }
+            option if option.starts_with('-') => {
+                return Err(ValidationError::UnknownOption(arg));
+            }
+            // Values of the options of match modules and targets are skipped above, so anything
+            // else is a stray word
+            _ => return Err(ValidationError::UnknownOption(arg)),
+        }
+    }
+
+    for address in &addresses {
+        check_prefix(address)?;
+    }
+    if fragment && is_ipv6 {
+        return Err(ValidationError::WrongFamily(
+            "option -f is not available for IPv6".to_string(),
+        ));
+    }
+    match family_mismatch(
+        is_ipv6,
+        addresses.iter().map(String::as_str),
+        protocol.as_deref(),
+        matches.iter().map(String::as_str),
+        reject_with.as_deref(),
+    ) {
+        Some(mismatch) => Err(ValidationError::WrongFamily(mismatch)),
+        None => Ok(()),
+    }
+}
+
+// Checks the prefix length of a literal network, netmasks like `/255.0.0.0` and hostnames are
+// left to iptables
+fn check_prefix(address: &str) -> Result<(), ValidationError> {
+    if let Some((ip, prefix)) = address.split_once('/') {
+        if let (Ok(ip), Ok(prefix)) = (ip.parse::<IpAddr>(), prefix.parse::<u32>()) {
+            Cidr::new(ip, u8::try_from(prefix).unwrap_or(u8::MAX))?;
+        }
+    }
+    Ok(())
+}
+
+// Follows the quoting rules of `split_quoted`, which silently closes unclosed quotes
+fn has_unclosed_quote(rule: &str) -> bool {
+    let mut chars = rule.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' if !chars.by_ref().any(|c| c == '\'') => return true,
+            '"' => loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => {
+                        chars.next();
+                    }
+                    Some(_) => {}
+                    None => return true,
+                }
+            },
+            '\\' => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+impl IPTables {
+    /// Checks `rule` for mistakes with the address family of this instance, see `validate_rule`.
+    /// Rules passed to `append` and `insert` are checked automatically if
+    /// `IPTablesBuilder::validate_rules` is set.
+    pub fn validate_rule(&self, rule: &str) -> Result<(), ValidationError> {
+        validate_rule(rule, self.is_ipv6)
+    }
+}
@@ -0,0 +1,75 @@
+//! The version of the iptables binary and the options it supports.
+
+use crate::{IPTError, IPTResult};
+use regex::Regex;
+use std::fmt;
+
+/// The version of an iptables binary, as printed by `iptables --version`.
+///
+/// Versions are ordered, so a feature can be checked with e.g. `version >= Version::new(1, 6, 2)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    /// The major version, e.g. 1 in `v1.8.7`.
+    pub major: u32,
+    /// The minor version, e.g. 8 in `v1.8.7`.
+    pub minor: u32,
+    /// The patch version, e.g. 7 in `v1.8.7`.
+    pub patch: u32,
+}
+
+impl Version {
+    /// Creates a version from its parts.
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses the first `v<major>.<minor>.<patch>` in `output`, e.g. `iptables v1.8.7 (nf_tables)`.
+    pub fn parse(output: &str) -> IPTResult<Version> {
+        let re = Regex::new(r"v(\d+)\.(\d+)\.(\d+)").map_err(|e| IPTError::Other(e.to_string()))?;
+        let invalid = || IPTError::Version(output.trim().to_string());
+        let versions = re.captures(output).ok_or_else(invalid)?;
+        let part = |index: usize| {
+            versions
+                .get(index)
+                .and_then(|part| part.as_str().parse::<u32>().ok())
+                .ok_or_else(invalid)
+        };
+
+        Ok(Version::new(part(1)?, part(2)?, part(3)?))
+    }
+
+    /// Indicates if iptables has -C (--check) option, added in 1.4.11.
+    pub fn has_check(&self) -> bool {
+        *self >= Version::new(1, 4, 11)
+    }
+
+    /// Indicates if iptables has -w (--wait) option, added in 1.4.20.
+    pub fn has_wait(&self) -> bool {
+        *self >= Version::new(1, 4, 20)
+    }
+
+    /// Indicates if the -w (--wait) option accepts a timeout in seconds, added in 1.4.22.
+    pub fn has_wait_timeout(&self) -> bool {
+        *self >= Version::new(1, 4, 22)
+    }
+
+    /// Indicates if iptables has -W (--wait-interval) option, added in 1.6.0.
+    pub fn has_wait_interval(&self) -> bool {
+        *self >= Version::new(1, 6, 0)
+    }
+
+    /// Indicates if iptables-restore has -w (--wait) option, added in 1.6.2.
+    pub fn has_restore_wait(&self) -> bool {
+        *self >= Version::new(1, 6, 2)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
@@ -0,0 +1,266 @@
+//! Watches the rules for changes made by other processes, by comparing `iptables-save` snapshots
+//! taken periodically on a background thread.
+
+use crate::snapshot::{Snapshot, SnapshotChain, SnapshotRule};
+use crate::{IPTResult, IPTables};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A change of the rules detected by a `Watcher` or `changes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WatchEvent {
+    /// The table/chain was created.
+    ChainAdded { table: String, chain: String },
+    /// The table/chain was deleted.
+    ChainRemoved { table: String, chain: String },
+    /// The policy of the built-in table/chain was changed.
+    PolicyChanged {
+        table: String,
+        chain: String,
+        policy: String,
+    },
+    /// `rule` was added to the table/chain.
+    RuleAdded {
+        table: String,
+        chain: String,
+        rule: String,
+    },
+    /// `rule` was removed from the table/chain.
+    RuleRemoved {
+        table: String,
+        chain: String,
+        rule: String,
+    },
+    /// `rule` of the table/chain matched `packets` packets and `bytes` bytes since the previous
+    /// snapshot. Only reported if `WatchOptions::counters` is set.
+    CountersChanged {
+        table: String,
+        chain: String,
+        rule: String,
+        packets: u64,
+        bytes: u64,
+    },
+}
+
+/// Controls how `IPTables::watch` polls the rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchOptions {
+    /// The time between two snapshots, one second by default.
+    pub interval: Duration,
+    /// Saves the counters with the rules (`iptables-save -c`) and reports
+    /// `WatchEvent::CountersChanged`. Disabled by default, as counters change with every packet.
+    pub counters: bool,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            counters: false,
+        }
+    }
+}
+
+/// Receives the changes of the rules detected on a background thread, started with
+/// `IPTables::watch`. Watching stops when the watcher is stopped or dropped.
+#[derive(Debug)]
+pub struct Watcher {
+    events: Receiver<IPTResult<WatchEvent>>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watcher {
+    /// Returns the channel the changes are sent to, in the order they were detected. Failures
+    /// to take a snapshot are sent as errors, watching continues with the next snapshot.
+    pub fn events(&self) -> &Receiver<IPTResult<WatchEvent>> {
+        &self.events
+    }
+
+    /// Stops watching and waits for a running poll to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        // Dropping the sender wakes the thread up
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Computes the changes which turned the `old` snapshot into the `new` one: added and removed
+/// chains, changed policies, added and removed rules and, if `counters` is set, the packets and
+/// bytes each remaining rule matched in between. Counters which were zeroed are reported with
+/// their new values.
+///
+/// Rules are compared by their specification, so moving a rule within its chain is not a change
+/// and replacing a rule is reported as a removal and an addition.
+///
+/// # Example
+/// ```
+/// use iptables::snapshot::Snapshot;
+/// use iptables::watch::{changes, WatchEvent};
+///
+/// let old = Snapshot::parse("*filter\n:INPUT ACCEPT [0:0]\nCOMMIT\n").unwrap();
+/// let new = Snapshot::parse("*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j DROP\nCOMMIT\n").unwrap();
+/// assert_eq!(
+///     changes(&old, &new, false),
+///     vec![WatchEvent::RuleAdded {
+///         table: "filter".to_string(),
+///         chain: "INPUT".to_string(),
+///         rule: "-j DROP".to_string(),
+///     }]
+/// );
+/// ```
+pub fn changes(old: &Snapshot, new: &Snapshot, counters: bool) -> Vec<WatchEvent> {
+    let mut events = Vec::new();
+
+    for chain in all_chains(old) {
+        if new.chain(chain.table(), chain.name()).is_none() {
+            events.extend(chain_changes(chain, None, counters));
+            events.push(WatchEvent::ChainRemoved {
+                table: chain.table().to_string(),
+                chain: chain.name().to_string(),
+            });
+        }
+    }
+    for chain in all_chains(new) {
+        let (table, name) = (chain.table().to_string(), chain.name().to_string());
+        match old.chain(&table, &name) {
+            Some(old_chain) => {
+                if let Some(policy) = chain.policy().filter(|&p| Some(p) != old_chain.policy()) {
+                    events.push(WatchEvent::PolicyChanged {
+                        table,
+                        chain: name,
+                        policy: policy.to_string(),
+                    });
+                }
+                events.extend(chain_changes(old_chain, Some(chain), counters));
+            }
+            None => {
+                events.push(WatchEvent::ChainAdded { table, chain: name });
+                events.extend(chain.rules().iter().map(rule_added));
+            }
+        }
+    }
+    events
+}
+
+fn all_chains(snapshot: &Snapshot) -> Vec<&SnapshotChain> {
+    snapshot
+        .tables()
+        .into_iter()
+        .flat_map(|table| snapshot.chains(table))
+        .collect()
+}
+
+// Pairs the rules of both versions of a chain by their specification, in their order
+fn chain_changes(
+    old: &SnapshotChain,
+    new: Option<&SnapshotChain>,
+    counters: bool,
+) -> Vec<WatchEvent> {
+    let mut remaining = new
+        .map(|new| new.rules().iter().collect::<Vec<_>>())
+        .unwrap_or_default();
+    let mut events = Vec::new();
+
+    for rule in old.rules() {
+        match remaining
+            .iter()
+            .position(|new_rule| new_rule.spec() == rule.spec())
+        {
+            Some(index) => {
+                let new_rule = remaining.remove(index);
+                if counters {
+                    events.extend(counter_change(rule, new_rule));
+                }
+            }
+            None => events.push(WatchEvent::RuleRemoved {
+                table: rule.table().to_string(),
+                chain: rule.chain().to_string(),
+                rule: rule.spec().to_string(),
+            }),
+        }
+    }
+    events.extend(remaining.into_iter().map(rule_added));
+    events
+}
+
+fn rule_added(rule: &SnapshotRule) -> WatchEvent {
+    WatchEvent::RuleAdded {
+        table: rule.table().to_string(),
+        chain: rule.chain().to_string(),
+        rule: rule.spec().to_string(),
+    }
+}
+
+fn counter_change(old: &SnapshotRule, new: &SnapshotRule) -> Option<WatchEvent> {
+    if (old.packets(), old.bytes()) == (new.packets(), new.bytes()) {
+        return None;
+    }
+    // Counters which went down were zeroed in between
+    let delta = |old: u64, new: u64| if new >= old { new - old } else { new };
+    Some(WatchEvent::CountersChanged {
+        table: new.table().to_string(),
+        chain: new.chain().to_string(),
+        rule: new.spec().to_string(),
+        packets: delta(old.packets(), new.packets()),
+        bytes: delta(old.bytes(), new.bytes()),
+    })
+}
+
+impl IPTables {
+    /// Watches the rules of all tables for changes made by other processes, e.g. so a security
+    /// daemon can restore its rules. A first snapshot is taken before returning, then a snapshot
+    /// is taken every `options.interval` on a background thread through a clone of the instance,
+    /// and its differences to the previous one are sent to `Watcher::events`.
+    ///
+    /// Changes made and reverted between two snapshots are not detected.
+    pub fn watch(&self, options: WatchOptions) -> IPTResult<Watcher> {
+        let ipt = self.clone();
+        let mut previous = ipt.watch_snapshot(options.counters)?;
+        let (events, receiver) = mpsc::channel();
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(options.interval) {
+                // The receiver lives as long as the thread, so sending cannot fail
+                match ipt.watch_snapshot(options.counters) {
+                    Ok(snapshot) => {
+                        for event in changes(&previous, &snapshot, options.counters) {
+                            let _ = events.send(Ok(event));
+                        }
+                        previous = snapshot;
+                    }
+                    Err(err) => {
+                        let _ = events.send(Err(err));
+                    }
+                }
+            }
+        });
+        Ok(Watcher {
+            events: receiver,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    fn watch_snapshot(&self, counters: bool) -> IPTResult<Snapshot> {
+        if !counters {
+            return self.snapshot();
+        }
+        Snapshot::parse(&self.save_with_counters(None)?)
+    }
+}
@@ -0,0 +1,258 @@
+//! Writes rules in large batches with `iptables-restore --noflush`, optionally through a single
+//! `iptables-restore` process which stays alive for the whole sync.
+
+use crate::batch::Batch;
+use crate::error::IptablesError;
+use crate::runner::enter_netns;
+use crate::{output_to_result, IPTError, IPTResult, IPTables, RuleSpec, Table};
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread::{self, JoinHandle};
+
+/// Controls how `IPTables::restore_writer` writes the queued rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriterOptions {
+    /// Commits automatically once this many operations are queued, 1000 by default. Zero only
+    /// commits when `RestoreWriter::commit` is called.
+    pub max_pending: usize,
+    /// Keeps a single `iptables-restore --noflush` process open and writes every commit to its
+    /// standard input, instead of running `iptables-restore` for every commit. Disabled by
+    /// default. Ignored in dry-run mode and with a custom `CommandRunner`, which cannot keep a
+    /// process open.
+    pub persistent: bool,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        Self {
+            max_pending: 1000,
+            persistent: false,
+        }
+    }
+}
+
+/// Queues rule operations and writes them with `iptables-restore --noflush`, created with
+/// `IPTables::restore_writer`.
+///
+/// Running iptables once per rule dominates the time of large syncs, as every call loads and
+/// replaces the whole table. The writer instead sends the queued operations of each commit in a
+/// single transaction per table, so syncing thousands of rules costs a few restores. The
+/// `examples/restore_writer.rs` example measures the difference on the local host.
+///
+/// In persistent mode iptables-restore reports a failed commit by exiting, which the writer
+/// only notices on the next write, so an error may be returned by the commit after the failed
+/// one or by `finish`. Call `finish` to learn whether the last commit was applied. Operations
+/// which are still queued when the writer is dropped are discarded.
+///
+/// # Example
+/// ```no_run
+/// use iptables::writer::WriterOptions;
+///
+/// let ipt = iptables::new(false).unwrap();
+/// let options = WriterOptions {
+///     persistent: true,
+///     ..WriterOptions::default()
+/// };
+/// let mut writer = ipt.restore_writer(options);
+/// for port in 10000..20000 {
+///     writer
+///         .append("filter", "INPUT", format!("-p tcp --dport {} -j ACCEPT", port))
+///         .unwrap();
+/// }
+/// writer.finish().unwrap();
+/// ```
+pub struct RestoreWriter {
+    ipt: IPTables,
+    options: WriterOptions,
+    pending: Batch,
+    pending_count: usize,
+    child: Option<Child>,
+    // Reads the standard error of the child, so a failing child cannot block on a full pipe
+    stderr: Option<JoinHandle<Vec<u8>>>,
+}
+
+impl RestoreWriter {
+    /// Queues appending `rule` to the table/chain.
+    pub fn append(
+        &mut self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let args = self.render(rule)?;
+        self.queue(&table.into(), format!("-A {}", chain), args)
+    }
+
+    /// Queues inserting `rule` in the `position` to the table/chain.
+    pub fn insert(
+        &mut self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+        position: i32,
+    ) -> IPTResult<()> {
+        let args = self.render(rule)?;
+        self.queue(&table.into(), format!("-I {} {}", chain, position), args)
+    }
+
+    /// Queues deleting `rule` from the table/chain.
+    pub fn delete(
+        &mut self,
+        table: impl Into<Table>,
+        chain: &str,
+        rule: impl Into<RuleSpec>,
+    ) -> IPTResult<()> {
+        let rule: RuleSpec = rule.into();
+        rule.check_line_breaks()?;
+        self.ipt.sanitize(rule.args())?;
+        let args = self.ipt.tagged(rule.into_args());
+        self.queue(&table.into(), format!("-D {}", chain), args)
+    }
+
+    /// Returns the number of operations queued since the last commit.
+    pub fn pending(&self) -> usize {
+        self.pending_count
+    }
+
+    /// Writes the queued operations. Each table is changed in a single transaction, but tables
+    /// committed together are not rolled back if a later table fails.
+    pub fn commit(&mut self) -> IPTResult<()> {
+        let pending = std::mem::take(&mut self.pending);
+        self.pending_count = 0;
+        pending.check()?;
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let data = pending.to_restore_string();
+
+        if !self.is_persistent() {
+            return output_to_result(self.ipt.spawn(&self.restore_command(), Some(&data))?);
+        }
+        if self.child.is_none() {
+            self.child = Some(self.spawn_child()?);
+        }
+        let child = self.child.as_mut().expect("the child was spawned above");
+        if let Ok(Some(_)) = child.try_wait() {
+            return Err(self.close());
+        }
+        let written = match child.stdin.as_mut() {
+            Some(stdin) => stdin.write_all(data.as_bytes()).and_then(|_| stdin.flush()),
+            None => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+        };
+        match written {
+            Ok(()) => Ok(()),
+            // The process exited, its output explains why
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Err(self.close()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Commits the queued operations and, in persistent mode, closes iptables-restore and waits
+    /// for it to apply the last commit.
+    pub fn finish(mut self) -> IPTResult<()> {
+        self.commit()?;
+        self.wait()
+    }
+
+    fn is_persistent(&self) -> bool {
+        self.options.persistent && !self.ipt.dry_run && self.ipt.runner.is_none()
+    }
+
+    // Checks the rule and returns its arguments carrying the owner tag. A line break is
+    // rejected even without a sanitizer, as it would start another command of the
+    // iptables-restore process, which in persistent mode applies every later commit too.
+    fn render(&self, rule: impl Into<RuleSpec>) -> IPTResult<Vec<String>> {
+        let rule: RuleSpec = rule.into();
+        rule.check_line_breaks()?;
+        self.ipt.sanitize(rule.args())?;
+        if self.ipt.validate_rules {
+            rule.validate(self.ipt.is_ipv6)?;
+        }
+        Ok(self.ipt.tagged(rule.into_args()))
+    }
+
+    fn queue(&mut self, table: &str, command: String, args: Vec<String>) -> IPTResult<()> {
+        self.pending.push(table, command, args);
+        self.pending_count += 1;
+        if self.options.max_pending > 0 && self.pending_count >= self.options.max_pending {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn restore_command(&self) -> Command {
+        let mut command = self.ipt.command(&format!("{}-restore", self.ipt.cmd));
+        command.arg("--noflush");
+        command.args(self.ipt.restore_wait_args());
+        command
+    }
+
+    fn spawn_child(&mut self) -> IPTResult<Child> {
+        let mut command = self.restore_command();
+        if let Some(netns) = &self.ipt.netns_path {
+            enter_netns(&mut command, netns)?;
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        self.stderr = child.stderr.take().map(|mut stderr| {
+            thread::spawn(move || {
+                let mut buffer = Vec::new();
+                let _ = stderr.read_to_end(&mut buffer);
+                buffer
+            })
+        });
+        Ok(child)
+    }
+
+    // Waits for the exited child and returns why it failed
+    fn close(&mut self) -> IPTError {
+        match self.wait() {
+            Err(err) => err,
+            Ok(()) => IPTError::Other("iptables-restore exited before the commit".to_string()),
+        }
+    }
+
+    // Closes the standard input of the child, so it applies the last commit and exits
+    fn wait(&mut self) -> IPTResult<()> {
+        let mut child = match self.child.take() {
+            Some(child) => child,
+            None => return Ok(()),
+        };
+        drop(child.stdin.take());
+        let status = child.wait()?;
+        let stderr = self.stderr.take().and_then(|stderr| stderr.join().ok());
+        let output = Output {
+            status,
+            stdout: Vec::new(),
+            stderr: stderr.unwrap_or_default(),
+        };
+        if !output.status.success() {
+            return Err(IptablesError::from(output).into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RestoreWriter {
+    fn drop(&mut self) {
+        let _ = self.wait();
+    }
+}
+
+impl IPTables {
+    /// Creates a writer which queues rule operations and writes them with
+    /// `iptables-restore --noflush`, see `RestoreWriter`.
+    pub fn restore_writer(&self, options: WriterOptions) -> RestoreWriter {
+        RestoreWriter {
+            ipt: self.clone(),
+            options,
+            pending: Batch::new(),
+            pending_count: 0,
+            child: None,
+            stderr: None,
+        }
+    }
+}
@@ -10,24 +10,26 @@ fn test_new() {
 
 #[test]
 fn test_old() {
-    nat(
-        iptables::IPTables {
-            cmd: "iptables",
-            has_wait: false,
-            has_check: false,
-        },
-        "NATOLD",
-        "NATOLD2",
-    );
+    nat(old_iptables(), "NATOLD", "NATOLD2");
+    filter(old_iptables(), "FILTEROLD");
+}
 
-    filter(
-        iptables::IPTables {
-            cmd: "iptables",
-            has_wait: false,
-            has_check: false,
-        },
-        "FILTEROLD",
-    );
+// Simulates an iptables version without the -C (--check) and -w (--wait) options
+fn old_iptables() -> iptables::IPTables {
+    let mut ipt = iptables::new(false).unwrap();
+    ipt.has_wait = false;
+    ipt.has_check = false;
+    ipt
+}
+
+#[test]
+fn test_backend() {
+    let ipt = iptables::new(false).unwrap();
+    assert_ne!(ipt.backend, iptables::Backend::Auto);
+
+    // Forcing the detected backend has to select a binary which uses it
+    let forced = iptables::new_with_backend(false, ipt.backend).unwrap();
+    assert_eq!(forced.backend, ipt.backend);
 }
 
 fn nat(ipt: iptables::IPTables, old_name: &str, new_name: &str) {
@@ -81,10 +83,22 @@ fn nat(ipt: iptables::IPTables, old_name: &str, new_name: &str) {
 
 fn filter(ipt: iptables::IPTables, name: &str) {
     assert!(ipt.new_chain("filter", name).is_ok());
+    assert!(ipt.new_chain("filter", name).is_err());
+    assert!(ipt.ensure_chain("filter", name).is_ok());
     assert!(ipt.insert("filter", name, "-j ACCEPT", 1).is_ok());
     assert!(ipt.replace("filter", name, "-j DROP", 1).is_ok());
     assert!(ipt.exists("filter", name, "-j DROP").unwrap());
     assert!(!ipt.exists("filter", name, "-j ACCEPT").unwrap());
+    assert_eq!(
+        ipt.list_with_counters("filter", name).unwrap()[0].rule,
+        "-j DROP"
+    );
+    assert!(ipt.delete("filter", name, "-j DROP").is_ok());
+    assert!(ipt
+        .append_with_counters("filter", name, "-j DROP", 10, 840)
+        .is_ok());
+    let counters = &ipt.list_with_counters("filter", name).unwrap()[0];
+    assert_eq!((counters.packets, counters.bytes), (10, 840));
     assert!(ipt.delete("filter", name, "-j DROP").is_ok());
     assert_eq!(ipt.list("filter", name).unwrap().len(), 1);
     assert!(ipt
@@ -121,12 +135,79 @@ fn filter(ipt: iptables::IPTables, name: &str) {
             "-m comment --comment \"single-quoted comment\" -j ACCEPT"
         )
         .unwrap(),);
+    let rule = iptables::Rule::new()
+        .protocol("tcp")
+        .dport(22)
+        .comment("structured rule")
+        .jump("ACCEPT");
+    assert!(ipt.append_rule("filter", name, &rule).is_ok());
+    assert!(ipt.exists_rule("filter", name, &rule).unwrap());
+    assert!(ipt.delete_rule("filter", name, &rule).is_ok());
+    assert!(!ipt.exists_rule("filter", name, &rule).unwrap());
+    assert!(ipt.insert("filter", name, "-j DROP", 1).is_ok());
+    assert_eq!(
+        ipt.list_numbered("filter", name).unwrap()[..2],
+        [(1, "-j DROP".to_string()), (2, "-j ACCEPT".to_string())]
+    );
+    assert!(ipt.delete_by_position("filter", name, 1).is_ok());
+    assert!(!ipt.exists("filter", name, "-j DROP").unwrap());
     assert!(ipt.flush_chain("filter", name).is_ok());
     assert!(ipt.chain_exists("filter", name).unwrap());
     assert!(ipt.delete_chain("filter", name).is_ok());
     assert!(!ipt.chain_exists("filter", name).unwrap());
 }
 
+#[test]
+fn test_rule_render() {
+    let rule = iptables::Rule::new()
+        .source("10.0.0.0/8")
+        .in_interface("eth0")
+        .protocol("tcp")
+        .dport(22)
+        .comment("ssh \"admin\" access")
+        .jump("ACCEPT");
+    assert_eq!(
+        rule.args(),
+        vec![
+            "-s",
+            "10.0.0.0/8",
+            "-i",
+            "eth0",
+            "-p",
+            "tcp",
+            "-m",
+            "tcp",
+            "--dport",
+            "22",
+            "-m",
+            "comment",
+            "--comment",
+            "ssh \"admin\" access",
+            "-j",
+            "ACCEPT",
+        ]
+    );
+    assert_eq!(
+        rule.to_string(),
+        "-s 10.0.0.0/8 -i eth0 -p tcp -m tcp --dport 22 -m comment --comment \"ssh \\\"admin\\\" access\" -j ACCEPT"
+    );
+
+    let dnat = iptables::Rule::new()
+        .protocol("udp")
+        .jump_with("DNAT", &["--to-destination", "192.168.1.2:53"]);
+    assert_eq!(
+        dnat.to_string(),
+        "-p udp -j DNAT --to-destination 192.168.1.2:53"
+    );
+}
+
+#[test]
+fn test_table_exists() {
+    let ipt = iptables::new(false).unwrap();
+    assert!(ipt.table_exists("filter").unwrap());
+    assert!(!ipt.table_exists("not_existant").unwrap());
+}
+
 #[test]
 fn test_get_policy() {
     let ipt = iptables::new(false).unwrap();
@@ -182,3 +263,4424 @@ fn test_set_policy() {
     // "Rethrow" a potential caught panic
     assert!(result.is_ok());
 }
+
+#[test]
+fn test_save_restore() {
+    let ipt = iptables::new(false).unwrap();
+    assert!(ipt.new_chain("filter", "SAVERESTORE").is_ok());
+    assert!(ipt.append("filter", "SAVERESTORE", "-j ACCEPT").is_ok());
+
+    let saved = ipt.save(Some("filter")).unwrap();
+    assert!(saved.contains("-A SAVERESTORE -j ACCEPT"));
+
+    assert!(ipt.flush_chain("filter", "SAVERESTORE").is_ok());
+    assert!(!ipt.exists("filter", "SAVERESTORE", "-j ACCEPT").unwrap());
+
+    // Restoring without flushing only appends the rules missing since the snapshot
+    let restore = "*filter\n-A SAVERESTORE -j ACCEPT\nCOMMIT\n";
+    assert!(ipt.restore(restore, false).is_ok());
+    assert!(ipt.exists("filter", "SAVERESTORE", "-j ACCEPT").unwrap());
+
+    assert!(ipt.flush_chain("filter", "SAVERESTORE").is_ok());
+    assert!(ipt.delete_chain("filter", "SAVERESTORE").is_ok());
+}
+
+#[test]
+fn test_batch() {
+    let ipt = iptables::new(false).unwrap();
+    assert!(ipt.new_chain("filter", "BATCH").is_ok());
+
+    let mut batch = iptables::batch::Batch::new();
+    batch.append("filter", "BATCH", "-j ACCEPT").append(
+        "filter",
+        "BATCH",
+        "-m comment --comment 'batch rule' -j DROP",
+    );
+    assert!(ipt.commit(&batch).is_ok());
+    assert!(ipt.exists("filter", "BATCH", "-j ACCEPT").unwrap());
+    assert!(ipt
+        .exists(
+            "filter",
+            "BATCH",
+            "-m comment --comment \"batch rule\" -j DROP"
+        )
+        .unwrap());
+
+    // A failing operation must leave the chain as it was before the commit
+    let mut failing = iptables::batch::Batch::new();
+    failing
+        .delete("filter", "BATCH", "-j ACCEPT")
+        .append("filter", "BATCH_MISSING", "-j ACCEPT");
+    assert!(ipt.commit(&failing).is_err());
+    assert!(ipt.exists("filter", "BATCH", "-j ACCEPT").unwrap());
+
+    assert!(ipt.flush_chain("filter", "BATCH").is_ok());
+    assert!(ipt.delete_chain("filter", "BATCH").is_ok());
+}
+
+#[test]
+fn test_command_error() {
+    let ipt = iptables::new(false).unwrap();
+
+    // Checking a rule of a missing chain is a failure, not a missing rule
+    assert!(ipt.exists("filter", "ERRMISSING", "-j ACCEPT").is_err());
+
+    let err = ipt.append("filter", "ERRMISSING", "-j ACCEPT").unwrap_err();
+    let err = match err {
+        iptables::IPTError::CommandFailed(err) => err,
+        other => panic!("unexpected error: {}", other),
+    };
+    assert_ne!(err.code, 0);
+    assert!(!err.msg.is_empty());
+}
+
+#[test]
+fn test_split_quoted_rules() {
+    let mut batch = iptables::batch::Batch::new();
+    batch
+        .append(
+            "filter",
+            "A",
+            "-m comment --comment \"my app rule\" -j ACCEPT",
+        )
+        .append(
+            "filter",
+            "A",
+            "-m comment --comment 'single quoted' -j ACCEPT",
+        )
+        .append(
+            "filter",
+            "A",
+            "-m comment --comment=\"say \\\"hi\\\"\" -j ACCEPT",
+        )
+        .append(
+            "filter",
+            "A",
+            "-m comment --comment escaped\\ space -j ACCEPT",
+        )
+        .append("filter", "A", "-m comment --comment \"\"  -j ACCEPT");
+    assert_eq!(
+        batch.to_restore_string(),
+        "*filter\n\
+         -A A -m comment --comment \"my app rule\" -j ACCEPT\n\
+         -A A -m comment --comment \"single quoted\" -j ACCEPT\n\
+         -A A -m comment \"--comment=say \\\"hi\\\"\" -j ACCEPT\n\
+         -A A -m comment --comment \"escaped space\" -j ACCEPT\n\
+         -A A -m comment --comment \"\" -j ACCEPT\n\
+         COMMIT\n"
+    );
+
+    // A backslash is quoted, so splitting the rendered rule gives the argument back
+    for comment in ["a\\b", "\\", "c:\\x\\"] {
+        let rule = iptables::Rule::new().comment(comment).jump("ACCEPT");
+        let rendered = rule.to_string();
+        assert_eq!(
+            iptables::RuleSpec::from(rendered.as_str()).args(),
+            rule.args()
+        );
+    }
+    assert_eq!(
+        iptables::Rule::new().comment("a\\b").to_string(),
+        "-m comment --comment \"a\\\\b\""
+    );
+}
+
+#[test]
+fn test_parse_counters() {
+    use iptables::counters::RuleCounters;
+
+    let rule = RuleCounters::parse(
+        "-A INPUT -s 10.0.0.1/32 -c 12 3456 -m comment --comment \"a -c 1 2\" -j DROP",
+    )
+    .unwrap();
+    assert_eq!(rule.chain, "INPUT");
+    assert_eq!(
+        rule.rule,
+        "-s 10.0.0.1/32 -m comment --comment \"a -c 1 2\" -j DROP"
+    );
+    assert_eq!((rule.packets, rule.bytes), (12, 3456));
+
+    let rule = RuleCounters::parse("[7:420] -A FORWARD -j ACCEPT").unwrap();
+    assert_eq!(rule.chain, "FORWARD");
+    assert_eq!(rule.rule, "-j ACCEPT");
+    assert_eq!((rule.packets, rule.bytes), (7, 420));
+
+    assert!(RuleCounters::parse("-P INPUT ACCEPT -c 0 0").is_none());
+    assert!(RuleCounters::parse("-N CUSTOM").is_none());
+}
+
+#[test]
+fn test_parse_rule() {
+    use iptables::parse::ParsedRule;
+
+    let rule = ParsedRule::parse(
+        "-A INPUT -s 10.0.0.0/8 -i eth0 -p tcp -m tcp --dport 22 -m comment --comment \"ssh access\" -j ACCEPT",
+    )
+    .unwrap();
+    assert_eq!(rule.chain, "INPUT");
+    assert_eq!(rule.source.as_deref(), Some("10.0.0.0/8"));
+    assert_eq!(rule.destination, None);
+    assert_eq!(rule.in_interface.as_deref(), Some("eth0"));
+    assert_eq!(rule.protocol.as_deref(), Some("tcp"));
+    assert_eq!(rule.get_match("tcp").unwrap().args, vec!["--dport", "22"]);
+    assert_eq!(
+        rule.get_match("comment").unwrap().args,
+        vec!["--comment", "ssh access"]
+    );
+    assert_eq!(rule.target.as_deref(), Some("ACCEPT"));
+    assert!(!rule.goto);
+
+    let rule =
+        ParsedRule::parse("-A PREROUTING -p udp -j DNAT --to-destination 10.0.0.2:53").unwrap();
+    assert_eq!(rule.target.as_deref(), Some("DNAT"));
+    assert_eq!(rule.target_args, vec!["--to-destination", "10.0.0.2:53"]);
+
+    let rule = ParsedRule::parse("-A FORWARD -g CUSTOM").unwrap();
+    assert_eq!(rule.target.as_deref(), Some("CUSTOM"));
+    assert!(rule.goto);
+
+    assert!(ParsedRule::parse("-P INPUT ACCEPT").is_err());
+    assert!(ParsedRule::parse("-A INPUT -s").is_err());
+}
+
+#[test]
+fn test_negation() {
+    use iptables::conntrack::CtState;
+    use iptables::parse::{canonical_rule, ParsedRule};
+
+    let rule = iptables::Rule::new()
+        .not()
+        .source("10.0.0.0/8")
+        .not()
+        .in_interface("lo")
+        .protocol("tcp")
+        .not()
+        .dports(&[22, 80])
+        .not()
+        .ctstate(&[CtState::Established])
+        .not()
+        .mark(1, None)
+        .jump("DROP");
+    assert_eq!(
+        rule.to_string(),
+        "! -s 10.0.0.0/8 ! -i lo -p tcp -m multiport ! --dports 22,80 \
+         -m conntrack ! --ctstate ESTABLISHED -m mark ! --mark 0x1 -j DROP"
+    );
+    assert!(rule.check(false).is_ok());
+    assert_eq!(
+        iptables::Rule::new()
+            .protocol("tcp")
+            .not()
+            .sport(22)
+            .dport(80)
+            .not()
+            .syn()
+            .to_string(),
+        "-p tcp -m tcp ! --sport 22 --dport 80 ! --tcp-flags FIN,SYN,RST,ACK SYN"
+    );
+    // Setting an option again without `not` clears its negation
+    assert_eq!(
+        iptables::Rule::new()
+            .not()
+            .destination("192.0.2.1")
+            .destination("192.0.2.2")
+            .to_string(),
+        "-d 192.0.2.2"
+    );
+    assert!(iptables::Rule::new()
+        .jump("ACCEPT")
+        .not()
+        .check(false)
+        .is_err());
+
+    let parsed =
+        ParsedRule::parse("-A INPUT ! -s 10.0.0.0/8 -p tcp -m tcp ! --dport 22 -j DROP").unwrap();
+    assert_eq!(parsed.source.as_deref(), Some("10.0.0.0/8"));
+    assert_eq!(parsed.negated, ["-s"]);
+    assert!(parsed.is_negated("-s"));
+    assert!(!parsed.is_negated("-p"));
+    assert_eq!(
+        parsed.get_match("tcp").unwrap().args,
+        ["!", "--dport", "22"]
+    );
+    let parsed = ParsedRule::parse("-A FORWARD ! --in-interface eth0 -j ACCEPT").unwrap();
+    assert_eq!(parsed.negated, ["-i"]);
+
+    assert_eq!(
+        canonical_rule("! --source 10.0.0.1 -p tcp ! --dport 22 -j DROP").unwrap(),
+        canonical_rule(
+            &iptables::Rule::new()
+                .not()
+                .source("10.0.0.1/32")
+                .protocol("tcp")
+                .not()
+                .dport(22)
+                .jump("DROP")
+                .to_string()
+        )
+        .unwrap()
+    );
+    assert_ne!(
+        canonical_rule("! -s 10.0.0.1 -j DROP").unwrap(),
+        canonical_rule("-s 10.0.0.1 -j DROP").unwrap()
+    );
+    assert_ne!(
+        canonical_rule("-p tcp ! --dport 22 -j DROP").unwrap(),
+        canonical_rule("-p tcp --dport 22 -j DROP").unwrap()
+    );
+
+    let runner = FakeRunner::new("-A INPUT ! -s 10.0.0.0/8 -p tcp -m tcp --dport 22 -j DROP\n");
+    let ipt = runner.build();
+    assert!(ipt
+        .exists_normalized(
+            "filter",
+            "INPUT",
+            "! -s 10.0.0.0/8 -p tcp --dport 22 -j DROP"
+        )
+        .unwrap());
+    assert!(!ipt
+        .exists_normalized("filter", "INPUT", "-s 10.0.0.0/8 -p tcp --dport 22 -j DROP")
+        .unwrap());
+}
+
+#[test]
+fn test_builder() {
+    let ipt = iptables::IPTables::builder()
+        .path("/usr/sbin/iptables")
+        .build()
+        .unwrap();
+    assert_eq!(ipt.cmd, "/usr/sbin/iptables");
+    assert!(!ipt.is_ipv6);
+
+    assert!(iptables::IPTables::builder()
+        .path("/nonexistent/iptables")
+        .build()
+        .is_err());
+}
+
+#[test]
+fn test_dry_run() {
+    let ipt = iptables::IPTables::builder()
+        .path("/nonexistent/iptables")
+        .dry_run(true)
+        .build()
+        .unwrap();
+
+    let rule = iptables::Rule::new()
+        .protocol("tcp")
+        .dport(22)
+        .comment("allow ssh")
+        .jump("ACCEPT");
+    assert!(ipt.new_chain("filter", "DRYCHAIN").is_ok());
+    assert!(ipt.append_rule("filter", "DRYCHAIN", &rule).is_ok());
+    assert!(ipt.flush_chain("filter", "DRYCHAIN").is_ok());
+    assert_eq!(ipt.save(Some("filter")).unwrap(), "");
+
+    let expected: Vec<Vec<&str>> = vec![
+        vec![
+            "/nonexistent/iptables",
+            "-t",
+            "filter",
+            "-N",
+            "DRYCHAIN",
+            "--wait",
+        ],
+        vec![
+            "/nonexistent/iptables",
+            "-t",
+            "filter",
+            "-A",
+            "DRYCHAIN",
+            "-p",
+            "tcp",
+            "-m",
+            "tcp",
+            "--dport",
+            "22",
+            "-m",
+            "comment",
+            "--comment",
+            "allow ssh",
+            "-j",
+            "ACCEPT",
+            "--wait",
+        ],
+        vec![
+            "/nonexistent/iptables",
+            "-t",
+            "filter",
+            "-F",
+            "DRYCHAIN",
+            "--wait",
+        ],
+        vec!["/nonexistent/iptables-save", "-t", "filter"],
+    ];
+    assert_eq!(ipt.take_recorded_commands(), expected);
+    assert!(ipt.recorded_commands().is_empty());
+}
+
+#[test]
+fn test_table_chain_enums() {
+    use iptables::{Chain, Table};
+
+    assert_eq!(Table::from("nat"), Table::Nat);
+    assert_eq!(Table::from("custom"), Table::Custom("custom".to_string()));
+    assert_eq!(Chain::from("POSTROUTING"), Chain::Postrouting);
+    assert_eq!(Chain::Custom("MYCHAIN".to_string()).to_string(), "MYCHAIN");
+
+    let ipt = iptables::IPTables::builder().dry_run(true).build().unwrap();
+    assert!(ipt
+        .append(Table::Nat, &Chain::Postrouting, "-j MASQUERADE")
+        .is_ok());
+    assert!(ipt.flush_chain(&Table::Mangle, "MYCHAIN").is_ok());
+    assert!(ipt.flush_chain(String::from("raw"), "MYCHAIN").is_ok());
+
+    let commands = ipt.take_recorded_commands();
+    assert_eq!(commands[0][1..5], ["-t", "nat", "-A", "POSTROUTING"]);
+    assert_eq!(commands[1][1..3], ["-t", "mangle"]);
+    assert_eq!(commands[2][1..3], ["-t", "raw"]);
+}
+
+#[test]
+fn test_owner_tag() {
+    let ipt = iptables::IPTables::builder()
+        .owner_tag("myapp")
+        .dry_run(true)
+        .build()
+        .unwrap();
+
+    assert!(ipt.append("filter", "INPUT", "-p tcp -j ACCEPT").is_ok());
+    assert!(ipt
+        .delete_rule(
+            "filter",
+            "INPUT",
+            &iptables::Rule::new().comment("myapp").jump("DROP")
+        )
+        .is_ok());
+    assert!(ipt.exists("filter", "INPUT", "-s 10.0.0.1").unwrap());
+
+    let commands = ipt.take_recorded_commands();
+    assert_eq!(
+        commands[0][3..],
+        [
+            "-A",
+            "INPUT",
+            "-p",
+            "tcp",
+            "-m",
+            "comment",
+            "--comment",
+            "myapp",
+            "-j",
+            "ACCEPT",
+            "--wait"
+        ]
+    );
+    assert_eq!(
+        commands[1][3..],
+        [
+            "-D",
+            "INPUT",
+            "-m",
+            "comment",
+            "--comment",
+            "myapp",
+            "-j",
+            "DROP",
+            "--wait"
+        ]
+    );
+    assert_eq!(
+        commands[2][3..],
+        [
+            "-C",
+            "INPUT",
+            "-s",
+            "10.0.0.1",
+            "-m",
+            "comment",
+            "--comment",
+            "myapp",
+            "--wait"
+        ]
+    );
+}
+
+// Answers every command with `stdout`, except for `--version` and commands using the chain MISSING
+struct FakeRunner {
+    stdout: &'static str,
+    commands: std::sync::Mutex<Vec<Vec<String>>>,
+}
+
+impl FakeRunner {
+    fn new(stdout: &'static str) -> std::sync::Arc<FakeRunner> {
+        std::sync::Arc::new(FakeRunner {
+            stdout,
+            commands: std::sync::Mutex::new(Vec::new()),
+        })
+    }
+
+    fn builder(self: &std::sync::Arc<Self>) -> iptables::builder::IPTablesBuilder {
+        iptables::IPTables::builder().runner(self.clone())
+    }
+
+    fn build(self: &std::sync::Arc<Self>) -> iptables::IPTables {
+        self.builder().build().unwrap()
+    }
+
+    // Returns the arguments of the commands run after the version check, without `--wait`
+    fn take_args(&self) -> Vec<Vec<String>> {
+        let mut commands = self.commands.lock().unwrap();
+        commands
+            .drain(..)
+            .filter(|command| command[1] != "--version")
+            .map(|command| command[1..command.len() - 1].to_vec())
+            .collect()
+    }
+}
+
+impl iptables::runner::CommandRunner for FakeRunner {
+    fn run(
+        &self,
+        program: &str,
+        args: &[String],
+        _input: Option<&str>,
+    ) -> iptables::IPTResult<std::process::Output> {
+        use std::os::unix::process::ExitStatusExt;
+
+        self.commands
+            .lock()
+            .unwrap()
+            .push([&[program.to_string()], args].concat());
+        let (code, stdout, stderr) = match args.last().map(String::as_str) {
+            Some("--version") => (0, "iptables v1.8.7 (legacy)\n", ""),
+            _ if args.iter().any(|arg| arg == "MISSING") => {
+                (1, "", "iptables: No chain/target/match by that name.\n")
+            }
+            _ => (0, self.stdout, ""),
+        };
+        Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        })
+    }
+
+    // Records the namespace in front of the program
+    fn run_in_netns(
+        &self,
+        netns: &std::path::Path,
+        program: &str,
+        args: &[String],
+        input: Option<&str>,
+    ) -> iptables::IPTResult<std::process::Output> {
+        self.run(&format!("{}:{}", netns.display(), program), args, input)
+    }
+}
+
+#[test]
+fn test_runner() {
+    let runner = FakeRunner::new("-P INPUT ACCEPT\n-A INPUT -j DROP\n");
+
+    let ipt = runner.build();
+    assert_eq!(ipt.backend, iptables::Backend::Legacy);
+    assert_eq!(ipt.version, iptables::Version::new(1, 8, 7));
+    assert!(ipt.supports_check() && ipt.supports_wait() && ipt.supports_restore_wait());
+
+    assert_eq!(ipt.get_policy("filter", "INPUT").unwrap(), "ACCEPT");
+    assert_eq!(
+        ipt.list("filter", "INPUT").unwrap(),
+        vec!["-P INPUT ACCEPT", "-A INPUT -j DROP"]
+    );
+    assert!(!ipt.chain_exists("filter", "MISSING").unwrap());
+    match ipt.append("filter", "MISSING", "-j ACCEPT").unwrap_err() {
+        iptables::IPTError::CommandFailed(err) => assert_eq!(err.code, 1),
+        other => panic!("unexpected error: {}", other),
+    }
+
+    let output = ipt
+        .execute_table(
+            "filter",
+            &["-A", "INPUT", "-m", "statistic", "--mode", "random"],
+        )
+        .unwrap();
+    assert!(output.status.success());
+    assert!(ipt.execute_args(&["-L", "-n"]).is_ok());
+
+    let commands = runner.commands.lock().unwrap();
+    assert_eq!(commands[0], vec!["iptables", "--version"]);
+    assert_eq!(
+        commands[1],
+        vec!["iptables", "-t", "filter", "-S", "INPUT", "--wait"]
+    );
+    assert_eq!(
+        commands[4],
+        vec!["iptables", "-t", "filter", "-A", "MISSING", "-j", "ACCEPT", "--wait"]
+    );
+    assert_eq!(commands[6], vec!["iptables", "-L", "-n", "--wait"]);
+    drop(commands);
+
+    let all = ipt.list_all().unwrap();
+    assert_eq!(all.len(), 5);
+    assert_eq!(all["raw"], vec!["-P INPUT ACCEPT", "-A INPUT -j DROP"]);
+}
+
+#[test]
+fn test_diff() {
+    use iptables::diff::{diff_chain, diff_table, Change};
+
+    let rules = |rules: &[&str]| {
+        rules
+            .iter()
+            .map(|rule| rule.to_string())
+            .collect::<Vec<_>>()
+    };
+    let current = rules(&["-j A", "-j B", "-j C", "-j D"]);
+    let desired = rules(&["-j B", "-j X", "-j D", "-j 'Y Z'"]);
+    let change = |kind: &str, rule: &str, position: i32| {
+        let (table, chain, rule) = ("filter".to_string(), "FW".to_string(), rule.to_string());
+        match kind {
+            "A" => Change::Append { table, chain, rule },
+            "I" => Change::Insert {
+                table,
+                chain,
+                rule,
+                position,
+            },
+            _ => Change::Delete { table, chain, rule },
+        }
+    };
+
+    assert_eq!(
+        diff_chain("filter", "FW", &desired, &current),
+        vec![
+            change("D", "-j A", 0),
+            change("D", "-j C", 0),
+            change("I", "-j X", 2),
+            change("A", "-j \"Y Z\"", 0),
+        ]
+    );
+    assert!(diff_chain("filter", "FW", &desired, &desired).is_empty());
+
+    let mut desired_table = std::collections::HashMap::new();
+    desired_table.insert("FW".to_string(), rules(&["-j B"]));
+    let live = rules(&[
+        "-P INPUT ACCEPT",
+        "-N FW",
+        "-A FW -j A",
+        "-A FW -j B",
+        "-A INPUT -j FW",
+    ]);
+    assert_eq!(
+        diff_table("filter", &desired_table, &live),
+        vec![change("D", "-j A", 0)]
+    );
+
+    let mut batch = iptables::batch::Batch::new();
+    change("I", "-j X", 2).queue(&mut batch);
+    assert_eq!(batch.to_restore_string(), "*filter\n-I FW 2 -j X\nCOMMIT\n");
+}
+
+#[test]
+fn test_diff_large_chain() {
+    use iptables::diff::{diff_chain, Change};
+
+    // A quadratic table of two chains of 50000 rules would not fit in memory
+    let current = (0..50000)
+        .map(|port| format!("-p tcp --dport {} -j ACCEPT", port))
+        .collect::<Vec<_>>();
+    let mut desired = current.clone();
+    desired.remove(100);
+    desired.insert(40000, "-j DROP".to_string());
+    let changes = diff_chain("filter", "FW", &desired, &current);
+    assert_eq!(
+        changes,
+        vec![
+            Change::Delete {
+                table: "filter".to_string(),
+                chain: "FW".to_string(),
+                rule: "-p tcp --dport 100 -j ACCEPT".to_string(),
+            },
+            Change::Insert {
+                table: "filter".to_string(),
+                chain: "FW".to_string(),
+                rule: "-j DROP".to_string(),
+                position: 40001,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_diff_report() {
+    use iptables::diff::{report_chain, report_table, ReportFormat, RuleChange};
+
+    let rules = |rules: &[&str]| {
+        rules
+            .iter()
+            .map(|rule| rule.to_string())
+            .collect::<Vec<_>>()
+    };
+    let current = rules(&["-j A", "-j B", "-j C", "-j D"]);
+    let desired = rules(&[
+        "-j D",
+        "-j B",
+        "-j X",
+        "-m comment --comment 'a \"b\"' -j Y",
+    ]);
+    let report = report_chain("filter", "FW", &desired, &current);
+    assert_eq!(
+        report.changes,
+        vec![
+            RuleChange::Removed {
+                rule: "-j A".to_string(),
+                position: 1
+            },
+            RuleChange::Removed {
+                rule: "-j C".to_string(),
+                position: 3
+            },
+            RuleChange::Moved {
+                rule: "-j D".to_string(),
+                from: 4,
+                to: 1
+            },
+            RuleChange::Added {
+                rule: "-j X".to_string(),
+                position: 3
+            },
+            RuleChange::Added {
+                rule: "-m comment --comment \"a \\\"b\\\"\" -j Y".to_string(),
+                position: 4
+            },
+        ]
+    );
+
+    let mut desired_table = std::collections::HashMap::new();
+    desired_table.insert("FW".to_string(), desired);
+    desired_table.insert("UNCHANGED".to_string(), rules(&["-j ACCEPT"]));
+    let live = rules(&[
+        "-N FW",
+        "-N UNCHANGED",
+        "-A FW -j A",
+        "-A FW -j B",
+        "-A FW -j C",
+        "-A FW -j D",
+        "-A UNCHANGED -j ACCEPT",
+    ]);
+    let report = report_table("filter", &desired_table, &live);
+    assert_eq!(report.chains.len(), 1);
+    assert_eq!(
+        report.render(ReportFormat::Text),
+        "--- filter FW (current)\n+++ filter FW (desired)\n-1 -j A\n-3 -j C\n~4>1 -j D\n\
+         +3 -j X\n+4 -m comment --comment \"a \\\"b\\\"\" -j Y\n"
+    );
+    #[cfg(feature = "serde")]
+    {
+        let json: serde_json::Value =
+            serde_json::from_str(&report.render(ReportFormat::Json)).unwrap();
+        assert_eq!(json["chains"][0]["changes"][2]["change"], "moved");
+        assert_eq!(
+            json["chains"][0]["changes"][4]["rule"],
+            "-m comment --comment \"a \\\"b\\\"\" -j Y"
+        );
+        // The rendered JSON has the shape of the derived serialization
+        assert_eq!(json, serde_json::to_value(&report).unwrap());
+        let parsed: iptables::diff::DiffReport = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, report);
+    }
+
+    desired_table.remove("FW");
+    assert!(report_table("filter", &desired_table, &live).is_empty());
+}
+
+#[test]
+fn test_chain_graph() {
+    use iptables::graph::{ChainGraph, Edge};
+
+    let runner = FakeRunner::new(
+        "-P PREROUTING ACCEPT\n-P OUTPUT ACCEPT\n-N DOCKER\n-N LOOP1\n-N LOOP2\n-N SELF\n\
+         -N ORPHAN\n-A PREROUTING -m addrtype --dst-type LOCAL -j DOCKER\n\
+         -A OUTPUT -j DNAT --to-destination 10.0.0.1\n-A OUTPUT -g DOCKER\n\
+         -A DOCKER -i docker0 -j RETURN\n-A LOOP1 -j LOOP2\n-A LOOP2 -m comment --comment \"a b\" -j LOOP1\n\
+         -A SELF -j SELF\n",
+    );
+    let graph = runner.build().graph("nat").unwrap();
+    assert_eq!(runner.take_args(), [vec!["-t", "nat", "-S"]]);
+
+    assert_eq!(graph.builtin_chains(), ["PREROUTING", "OUTPUT"]);
+    assert_eq!(
+        graph.user_chains(),
+        ["DOCKER", "LOOP1", "LOOP2", "SELF", "ORPHAN"]
+    );
+    assert_eq!(graph.edges().len(), 5);
+    assert_eq!(
+        graph.edges()[1],
+        Edge {
+            from: "OUTPUT".to_string(),
+            to: "DOCKER".to_string(),
+            position: 2,
+            goto: true,
+        }
+    );
+    assert_eq!(graph.jumps_to("DOCKER"), ["PREROUTING", "OUTPUT"]);
+    assert!(graph.jumps_from("DOCKER").is_empty());
+    assert_eq!(graph.cycles(), vec![vec!["LOOP1", "LOOP2"], vec!["SELF"]]);
+    assert_eq!(graph.unreachable(), ["LOOP1", "LOOP2", "SELF", "ORPHAN"]);
+
+    let empty = ChainGraph::from_listing(&[]);
+    assert!(empty.cycles().is_empty());
+    assert!(empty.unreachable().is_empty());
+}
+
+#[test]
+fn test_parse_listing() {
+    use iptables::list::{parse_listing, ListOptions};
+
+    let options = ListOptions {
+        verbose: true,
+        line_numbers: true,
+        ..ListOptions::default()
+    };
+    assert_eq!(options.args(), vec!["-n", "-v", "-x", "--line-numbers"]);
+
+    let output = "\
+Chain INPUT (policy DROP 12 packets, 840 bytes)
+num      pkts      bytes target     prot opt in     out     source               destination
+1        1024     65536 ACCEPT     tcp  --  eth0   *       0.0.0.0/0            0.0.0.0/0            tcp dpt:22
+2           3       180            all  --  *      *       10.0.0.0/8           0.0.0.0/0
+
+Chain MYCHAIN (1 references)
+num      pkts      bytes target     prot opt in     out     source               destination
+";
+    let chains = parse_listing(output, &options).unwrap();
+    assert_eq!(chains.len(), 2);
+    assert_eq!(chains[0].name, "INPUT");
+    assert_eq!(chains[0].policy.as_deref(), Some("DROP"));
+    assert_eq!((chains[0].packets, chains[0].bytes), (Some(12), Some(840)));
+    assert_eq!(chains[0].rules.len(), 2);
+
+    let rule = &chains[0].rules[0];
+    assert_eq!(rule.number, Some(1));
+    assert_eq!((rule.packets, rule.bytes), (Some(1024), Some(65536)));
+    assert_eq!(rule.target.as_deref(), Some("ACCEPT"));
+    assert_eq!(rule.protocol, "tcp");
+    assert_eq!(rule.in_interface.as_deref(), Some("eth0"));
+    assert_eq!(rule.source, "0.0.0.0/0");
+    assert_eq!(rule.extra, "tcp dpt:22");
+
+    let rule = &chains[0].rules[1];
+    assert_eq!(rule.target, None);
+    assert_eq!(rule.source, "10.0.0.0/8");
+
+    assert_eq!(chains[1].references, Some(1));
+    assert!(chains[1].rules.is_empty());
+
+    let plain = "\
+Chain FORWARD (policy ACCEPT)
+target     prot opt source               destination
+DROP       all  --  192.168.0.0/16       0.0.0.0/0
+";
+    let chains = parse_listing(plain, &ListOptions::default()).unwrap();
+    assert_eq!(chains[0].rules[0].target.as_deref(), Some("DROP"));
+    assert_eq!(chains[0].rules[0].in_interface, None);
+}
+
+#[test]
+fn test_list_chain_info() {
+    let runner = FakeRunner::new(
+        "\
+Chain INPUT (policy DROP 12 packets, 840 bytes)
+    pkts      bytes target     prot opt in     out     source               destination
+       3       180 MYCHAIN    all  --  *      *       0.0.0.0/0            0.0.0.0/0
+
+Chain MYCHAIN (1 references)
+    pkts      bytes target     prot opt in     out     source               destination
+",
+    );
+    let ipt = runner.build();
+    let chains = ipt.list_chain_info("filter").unwrap();
+    assert_eq!(
+        chains,
+        vec![
+            iptables::list::ChainInfo {
+                name: "INPUT".to_string(),
+                builtin: true,
+                policy: Some("DROP".to_string()),
+                references: None,
+                packets: Some(12),
+                bytes: Some(840),
+            },
+            iptables::list::ChainInfo {
+                name: "MYCHAIN".to_string(),
+                builtin: false,
+                references: Some(1),
+                ..Default::default()
+            },
+        ]
+    );
+    assert_eq!(
+        runner.take_args(),
+        vec![vec!["-t", "filter", "-L", "-n", "-v", "-x"]]
+    );
+}
+
+#[test]
+fn test_exists_normalized() {
+    use iptables::parse::canonical_rule;
+
+    assert_eq!(
+        canonical_rule("--dport 22 --protocol tcp --source 10.0.0.1 --jump ACCEPT").unwrap(),
+        "-p tcp -s 10.0.0.1/32 -m tcp --dport 22 -j ACCEPT"
+    );
+    assert_eq!(
+        canonical_rule("! -d 2001:DB8::1/64 -m comment --comment 'my app' -j DROP").unwrap(),
+        "! -d 2001:db8::/64 -m comment --comment \"my app\" -j DROP"
+    );
+    assert_eq!(
+        canonical_rule("-m multiport --dports 80,443 -m conntrack --ctstate NEW").unwrap(),
+        canonical_rule("-m conntrack --ctstate NEW -m multiport --dports 80,443").unwrap()
+    );
+    assert!(canonical_rule("--dport 22 -j ACCEPT").is_err());
+
+    let runner = FakeRunner::new(
+        "-P INPUT ACCEPT\n-A INPUT -s 10.0.0.1/32 -p tcp -m tcp --dport 22 -j ACCEPT\n",
+    );
+    let ipt = runner.build();
+    assert!(ipt
+        .exists_normalized("filter", "INPUT", "-p tcp --dport 22 -s 10.0.0.1 -j ACCEPT")
+        .unwrap());
+    assert!(!ipt
+        .exists_normalized("filter", "INPUT", "-p tcp --dport 23 -s 10.0.0.1 -j ACCEPT")
+        .unwrap());
+}
+
+#[test]
+fn test_exists_many() {
+    let runner = FakeRunner::new(
+        "-P INPUT ACCEPT\n-A INPUT -s 10.0.0.1/32 -p tcp -m tcp --dport 22 -j ACCEPT\n\
+         -A INPUT -j DROP\n-A INPUT -p udp -m udp --dport 53 -j ACCEPT\n-A INPUT -j DROP\n",
+    );
+    let ipt = runner.build();
+    let rules = [
+        "-p tcp --dport 22 -s 10.0.0.1 -j ACCEPT",
+        "-p tcp --dport 23 -j ACCEPT",
+        "-j DROP",
+    ];
+    assert_eq!(
+        ipt.exists_many("filter", "INPUT", &rules).unwrap(),
+        [true, false, true]
+    );
+    let rules = [iptables::Rule::new()
+        .protocol("udp")
+        .dport(53)
+        .jump("ACCEPT")];
+    assert_eq!(ipt.exists_many("filter", "INPUT", &rules).unwrap(), [true]);
+    assert_eq!(
+        runner.take_args(),
+        vec![vec!["-t", "filter", "-S", "INPUT"]; 2]
+    );
+
+    // The rule is repeated twice, so it is deleted twice after a single listing
+    ipt.delete_all("filter", "INPUT", "-j DROP").unwrap();
+    assert_eq!(
+        runner.take_args(),
+        [
+            vec!["-t", "filter", "-S", "INPUT"],
+            vec!["-t", "filter", "-D", "INPUT", "-j", "DROP"],
+            vec!["-t", "filter", "-D", "INPUT", "-j", "DROP"],
+        ]
+    );
+    ipt.delete_all("filter", "INPUT", "-j REJECT").unwrap();
+    assert_eq!(runner.take_args(), [vec!["-t", "filter", "-S", "INPUT"]]);
+
+    // Copies are deleted as listed, so a different order of the match modules still deletes them
+    let runner = FakeRunner::new(
+        "-A INPUT -p tcp -m tcp --dport 22 -m conntrack --ctstate NEW -j ACCEPT\n\
+         -A INPUT -m conntrack --ctstate NEW -p tcp -m tcp --dport 22 -j ACCEPT\n",
+    );
+    let ipt = runner.build();
+    ipt.delete_all(
+        "filter",
+        "INPUT",
+        "-m conntrack --ctstate NEW -p tcp -m tcp --dport 22 -j ACCEPT",
+    )
+    .unwrap();
+    let commands = runner.take_args();
+    assert_eq!(
+        commands[1..],
+        [
+            "-t filter -D INPUT -p tcp -m tcp --dport 22 -m conntrack --ctstate NEW -j ACCEPT",
+            "-t filter -D INPUT -m conntrack --ctstate NEW -p tcp -m tcp --dport 22 -j ACCEPT",
+        ]
+        .map(|command| command.split(' ').collect::<Vec<_>>())
+    );
+}
+
+#[test]
+fn test_position_of() {
+    let runner = FakeRunner::new(
+        "-P INPUT ACCEPT\n-A INPUT -i lo -j ACCEPT\n\
+         -A INPUT -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT\n\
+         -A INPUT -s 10.0.0.1/32 -p tcp -m tcp --dport 22 -j ACCEPT\n-A INPUT -j DROP\n-A INPUT -j DROP\n",
+    );
+    let ipt = runner.build();
+    assert_eq!(
+        ipt.position_of("filter", "INPUT", "-p tcp --dport 22 -s 10.0.0.1 -j ACCEPT")
+            .unwrap(),
+        Some(3)
+    );
+    assert_eq!(
+        ipt.position_of("filter", "INPUT", "-j DROP").unwrap(),
+        Some(4)
+    );
+    assert_eq!(
+        ipt.position_of("filter", "INPUT", "-j REJECT").unwrap(),
+        None
+    );
+    assert_eq!(
+        runner.take_args(),
+        vec![vec!["-t", "filter", "-S", "INPUT"]; 3]
+    );
+
+    ipt.insert_before("filter", "INPUT", "-j DROP", "-p udp -j ACCEPT")
+        .unwrap();
+    ipt.insert_after("filter", "INPUT", "-i lo -j ACCEPT", "-p icmp -j ACCEPT")
+        .unwrap();
+    assert_eq!(
+        runner.take_args(),
+        [
+            vec!["-t", "filter", "-S", "INPUT"],
+            vec!["-t", "filter", "-I", "INPUT", "4", "-p", "udp", "-j", "ACCEPT"],
+            vec!["-t", "filter", "-S", "INPUT"],
+            vec!["-t", "filter", "-I", "INPUT", "2", "-p", "icmp", "-j", "ACCEPT"],
+        ]
+    );
+    let err = ipt
+        .insert_after("filter", "INPUT", "-j REJECT", "-p icmp -j ACCEPT")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "not found: the rule \"-j REJECT\" is not in the table/chain filter/INPUT"
+    );
+}
+
+#[test]
+fn test_insert_relative() {
+    use iptables::position::Position;
+
+    assert_eq!(Position::FromEnd(1).resolve(3), 3);
+    assert_eq!(Position::FromEnd(0).resolve(3), 4);
+    assert_eq!(Position::FromEnd(5).resolve(3), 1);
+    assert_eq!(Position::FromStart(0).resolve(3), 1);
+
+    let runner = FakeRunner::new("-P INPUT ACCEPT\n-A INPUT -j ACCEPT\n-A INPUT -j REJECT\n");
+    let ipt = runner.build();
+    ipt.insert_relative("filter", "INPUT", "-j LOG", Position::FromEnd(1))
+        .unwrap();
+    ipt.insert_relative("filter", "INPUT", "-j LOG", Position::FromEnd(0))
+        .unwrap();
+    ipt.insert_relative("filter", "INPUT", "-j LOG", Position::FromStart(2))
+        .unwrap();
+
+    let args = runner.take_args();
+    assert_eq!(
+        args[1],
+        vec!["-t", "filter", "-I", "INPUT", "2", "-j", "LOG"]
+    );
+    assert_eq!(args[3], vec!["-t", "filter", "-A", "INPUT", "-j", "LOG"]);
+    assert_eq!(
+        args[5],
+        vec!["-t", "filter", "-I", "INPUT", "2", "-j", "LOG"]
+    );
+}
+
+#[test]
+fn test_list_args() {
+    let runner = FakeRunner::new(
+        "-P INPUT ACCEPT\n-A INPUT -m comment --comment \"my \\\"app\\\"\" -j ACCEPT\n",
+    );
+    let ipt = runner.build();
+    assert_eq!(
+        ipt.list_args("filter", "INPUT").unwrap(),
+        vec![
+            vec!["-P", "INPUT", "ACCEPT"],
+            vec![
+                "-A",
+                "INPUT",
+                "-m",
+                "comment",
+                "--comment",
+                "my \"app\"",
+                "-j",
+                "ACCEPT"
+            ],
+        ]
+    );
+}
+
+#[test]
+fn test_version() {
+    use iptables::Version;
+
+    let version = Version::parse("iptables v1.4.21\n").unwrap();
+    assert_eq!(version, Version::new(1, 4, 21));
+    assert_eq!(version.to_string(), "v1.4.21");
+    assert!(version.has_check() && version.has_wait());
+    assert!(!version.has_wait_timeout() && !version.has_restore_wait());
+    assert!(version < Version::new(1, 6, 0));
+
+    assert!(matches!(
+        Version::parse("iptables unknown"),
+        Err(iptables::IPTError::Version(_))
+    ));
+}
+
+#[test]
+fn test_apply_ruleset() {
+    let runner = FakeRunner::new(
+        "-N MYAPP\n\
+         -A MYAPP -s 1.1.1.1/32 -j ACCEPT\n\
+         -A MYAPP -m comment --comment myapp -j STALE\n\
+         -A MYAPP -m comment --comment myapp -j KEEP\n",
+    );
+    let ipt = runner.builder().owner_tag("myapp").build().unwrap();
+
+    let mut ruleset = iptables::ruleset::RuleSet::new();
+    ruleset
+        .append("filter", "MYAPP", "-j NEW")
+        .append("filter", "MYAPP", "-j KEEP")
+        .append_rule("filter", "MYAPP", &iptables::Rule::new().jump("LAST"));
+    assert!(ipt.apply(&ruleset).is_ok());
+
+    let tagged = |target: &'static str| vec!["-m", "comment", "--comment", "myapp", "-j", target];
+    assert_eq!(
+        runner.take_args(),
+        vec![
+            vec!["-t", "filter", "-N", "MYAPP"],
+            vec!["-t", "filter", "-S", "MYAPP"],
+            [vec!["-t", "filter", "-D", "MYAPP"], tagged("STALE")].concat(),
+            [vec!["-t", "filter", "-I", "MYAPP", "2"], tagged("NEW")].concat(),
+            [vec!["-t", "filter", "-A", "MYAPP"], tagged("LAST")].concat(),
+        ]
+    );
+}
+
+#[test]
+fn test_shared_handle() {
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+
+    let threads = (0..4)
+        .map(|thread| {
+            let ipt = ipt.clone();
+            std::thread::spawn(move || {
+                for _ in 0..10 {
+                    let rule = format!("-s 10.0.0.{} -j ACCEPT", thread);
+                    ipt.append_replace("filter", "INPUT", &rule).unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // The check, delete and append of each call are never interleaved with other threads
+    let commands = runner.take_args();
+    assert_eq!(commands.len(), 4 * 10 * 3);
+    for operation in commands.chunks(3) {
+        assert_eq!(operation[0][2], "-C");
+        assert_eq!(operation[1][2], "-D");
+        assert_eq!(operation[2][2], "-A");
+        assert!(operation
+            .iter()
+            .all(|command| command[4..] == operation[0][4..]));
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_async_shared_handle() {
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+
+    // Blocking and async calls on clones of the same instance are serialized alike
+    let thread = {
+        let ipt = ipt.clone();
+        std::thread::spawn(move || {
+            for _ in 0..10 {
+                ipt.append_replace("filter", "INPUT", "-s 10.0.0.1 -j ACCEPT")
+                    .unwrap();
+            }
+        })
+    };
+    let ipt = iptables::AsyncIPTables::from(ipt);
+    let tasks = (2..4)
+        .map(|host| {
+            let ipt = ipt.clone();
+            tokio::spawn(async move {
+                for _ in 0..10 {
+                    let rule = format!("-s 10.0.0.{} -j ACCEPT", host);
+                    ipt.append_replace("filter", "INPUT", &rule).await.unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    thread.join().unwrap();
+
+    let commands = runner.take_args();
+    assert_eq!(commands.len(), 3 * 10 * 3);
+    for operation in commands.chunks(3) {
+        assert_eq!(operation[0][2], "-C");
+        assert_eq!(operation[1][2], "-D");
+        assert_eq!(operation[2][2], "-A");
+        assert!(operation
+            .iter()
+            .all(|command| command[4..] == operation[0][4..]));
+    }
+
+    // Methods which are not mirrored run through spawn
+    let handle = ipt
+        .spawn(|ipt| ipt.append_handle("filter", "INPUT", "-j ACCEPT"))
+        .await
+        .unwrap();
+    assert_eq!(handle.chain(), "INPUT");
+    assert_eq!(runner.take_args().len(), 1);
+}
+
+#[test]
+fn test_teardown_chain() {
+    let runner = FakeRunner::new(
+        "-A INPUT -s 10.0.0.1/32 -j APPCHAIN\n-A INPUT -j ACCEPT\n-A INPUT -g APPCHAIN\n",
+    );
+    let ipt = runner.build();
+
+    assert!(ipt.teardown_chain("filter", "APPCHAIN", &["INPUT"]).is_ok());
+    assert_eq!(
+        runner.take_args(),
+        vec![
+            vec!["-t", "filter", "-S", "APPCHAIN"],
+            vec!["-t", "filter", "-S", "INPUT"],
+            vec![
+                "-t",
+                "filter",
+                "-D",
+                "INPUT",
+                "-s",
+                "10.0.0.1/32",
+                "-j",
+                "APPCHAIN"
+            ],
+            vec!["-t", "filter", "-D", "INPUT", "-g", "APPCHAIN"],
+            vec!["-t", "filter", "-F", "APPCHAIN"],
+            vec!["-t", "filter", "-X", "APPCHAIN"],
+        ]
+    );
+
+    // A missing chain is already torn down
+    assert!(ipt.teardown_chain("filter", "MISSING", &["INPUT"]).is_ok());
+    assert_eq!(runner.take_args().len(), 1);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_async() {
+    let ipt = iptables::AsyncIPTables::from(iptables::new(false).unwrap());
+
+    assert!(ipt.new_chain("filter", "ASYNCCHAIN").await.is_ok());
+    assert!(ipt
+        .append("filter", "ASYNCCHAIN", "-j ACCEPT")
+        .await
+        .is_ok());
+    assert!(ipt
+        .exists("filter", "ASYNCCHAIN", "-j ACCEPT")
+        .await
+        .unwrap());
+    assert!(ipt
+        .delete("filter", "ASYNCCHAIN", "-j ACCEPT")
+        .await
+        .is_ok());
+    assert!(!ipt
+        .exists("filter", "ASYNCCHAIN", "-j ACCEPT")
+        .await
+        .unwrap());
+    assert!(ipt.delete_chain("filter", "ASYNCCHAIN").await.is_ok());
+    assert!(!ipt.chain_exists("filter", "ASYNCCHAIN").await.unwrap());
+}
+
+#[test]
+fn test_wait_timeout() {
+    use std::time::Duration;
+
+    let ipt = iptables::IPTables::builder()
+        .wait_timeout(Duration::from_millis(1500))
+        .wait_interval(Duration::from_millis(50))
+        .build()
+        .unwrap();
+    assert_eq!(ipt.wait_timeout, Some(Duration::from_millis(1500)));
+    assert_eq!(ipt.wait_interval, Some(Duration::from_millis(50)));
+    assert!(ipt.list_table("filter").is_ok());
+}
+
+#[test]
+fn test_lock_timeout() {
+    use nix::fcntl::{flock, FlockArg};
+    use std::os::unix::io::AsRawFd;
+    use std::time::Duration;
+
+    let mut ipt = old_iptables();
+    ipt.lock_policy = iptables::lock::LockPolicy {
+        max_attempts: Some(3),
+        timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+
+    // Hold the lock of iptables versions without -w from another file description
+    let held = std::fs::File::create("/var/run/xtables_old.lock").unwrap();
+    flock(held.as_raw_fd(), FlockArg::LockExclusive).unwrap();
+
+    let err = ipt.list_table("filter").unwrap_err();
+    assert!(matches!(err, iptables::IPTError::LockTimeout));
+
+    drop(held);
+    assert!(ipt.list_table("filter").is_ok());
+}
+
+#[test]
+fn test_dual_stack() {
+    let ds = iptables::DualStack::new().unwrap();
+    assert!(ds.ensure_chain_both("filter", "DUALSTACK").is_ok());
+    assert!(ds
+        .append_both(
+            "filter",
+            "DUALSTACK",
+            "-s 10.0.0.1 -j DROP",
+            "-s fd00::1 -j DROP"
+        )
+        .is_ok());
+    assert!(ds
+        .ipv4
+        .exists("filter", "DUALSTACK", "-s 10.0.0.1/32 -j DROP")
+        .unwrap());
+    assert!(ds
+        .ipv6
+        .exists("filter", "DUALSTACK", "-s fd00::1/128 -j DROP")
+        .unwrap());
+
+    // An IPv4 address is invalid for ip6tables, only the IPv6 operation fails
+    let err = ds
+        .append_both(
+            "filter",
+            "DUALSTACK",
+            "-s 10.0.0.2 -j DROP",
+            "-s 10.0.0.2 -j DROP",
+        )
+        .unwrap_err();
+    assert!(err.ipv4.is_none());
+    assert!(err.ipv6.is_some());
+
+    assert!(ds.flush_chain_both("filter", "DUALSTACK").is_ok());
+    assert!(ds.delete_chain_both("filter", "DUALSTACK").is_ok());
+}
+
+#[test]
+fn test_ipset() {
+    let ipset = iptables::ipset::IPSet::new();
+    assert!(ipset.create("TESTSET", "hash:ip", &[]).is_ok());
+    assert!(ipset.add("TESTSET", "10.0.0.1").is_ok());
+    assert!(ipset.add_many("TESTSET", &["10.0.0.1", "10.0.0.2"]).is_ok());
+    assert!(ipset.test("TESTSET", "10.0.0.2").unwrap());
+    assert!(!ipset.test("TESTSET", "10.0.0.3").unwrap());
+    assert_eq!(ipset.list("TESTSET").unwrap().len(), 2);
+    assert!(ipset.list_sets().unwrap().contains(&"TESTSET".to_string()));
+
+    let ipt = iptables::new(false).unwrap();
+    let rule = iptables::Rule::new()
+        .match_set("TESTSET", "src")
+        .jump("DROP");
+    assert_eq!(rule.to_string(), "-m set --match-set TESTSET src -j DROP");
+    assert!(ipt.new_chain("filter", "IPSETCHAIN").is_ok());
+    assert!(ipt.append_rule("filter", "IPSETCHAIN", &rule).is_ok());
+    assert!(ipt.exists_rule("filter", "IPSETCHAIN", &rule).unwrap());
+    assert!(ipt.flush_chain("filter", "IPSETCHAIN").is_ok());
+    assert!(ipt.delete_chain("filter", "IPSETCHAIN").is_ok());
+
+    assert!(ipset.del("TESTSET", "10.0.0.1").is_ok());
+    assert!(ipset.flush("TESTSET").is_ok());
+    assert!(ipset.destroy("TESTSET").is_ok());
+}
+
+#[test]
+fn test_nat_rules() {
+    assert_eq!(
+        iptables::nat::forward_port_rule("tcp", 8080, "192.168.1.10", 80, Some("eth0")).to_string(),
+        "-i eth0 -p tcp -m tcp --dport 8080 -j DNAT --to-destination 192.168.1.10:80"
+    );
+    assert_eq!(
+        iptables::nat::forward_port_rule("udp", 53, "fd00::53", 5353, None).to_string(),
+        "-p udp -m udp --dport 53 -j DNAT --to-destination [fd00::53]:5353"
+    );
+    assert_eq!(
+        iptables::nat::masquerade_rule("eth0", Some("10.0.0.0/24")).to_string(),
+        "-s 10.0.0.0/24 -o eth0 -j MASQUERADE"
+    );
+}
+
+#[test]
+fn test_nat_helpers() {
+    let ipt = iptables::new(false).unwrap();
+    let forward = iptables::nat::forward_port_rule("tcp", 18080, "192.0.2.10", 80, None);
+
+    assert!(ipt
+        .forward_port("tcp", 18080, "192.0.2.10", 80, None)
+        .is_ok());
+    // Forwarding the same port again must not create a duplicate rule
+    assert!(ipt
+        .forward_port("tcp", 18080, "192.0.2.10", 80, None)
+        .is_ok());
+    assert!(ipt.exists_rule("nat", "PREROUTING", &forward).unwrap());
+    assert!(ipt
+        .remove_forward_port("tcp", 18080, "192.0.2.10", 80, None)
+        .is_ok());
+    assert!(!ipt.exists_rule("nat", "PREROUTING", &forward).unwrap());
+
+    assert!(ipt.masquerade("lo", Some("192.0.2.0/24")).is_ok());
+    assert!(ipt.remove_masquerade("lo", Some("192.0.2.0/24")).is_ok());
+}
+
+// Simulates a chain of the filter table to which another process appends a copy of every rule
+// added through the instance, as if it raced with the check
+#[derive(Default)]
+struct RacingRunner {
+    rules: std::sync::Mutex<Vec<String>>,
+}
+
+impl iptables::runner::CommandRunner for RacingRunner {
+    fn run(
+        &self,
+        _program: &str,
+        args: &[String],
+        _input: Option<&str>,
+    ) -> iptables::IPTResult<std::process::Output> {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut rules = self.rules.lock().unwrap();
+        let rule = args.get(4..args.len() - 1).unwrap_or_default().join(" ");
+        let mut stdout = String::new();
+        let found = match args.get(2).map(String::as_str) {
+            Some("-C") => rules.contains(&rule),
+            Some("-A") => {
+                rules.push(rule.clone());
+                rules.push(rule);
+                true
+            }
+            Some("-D") => match rules.iter().position(|live| *live == rule) {
+                Some(index) => rules.remove(index) == rule,
+                None => false,
+            },
+            Some("-S") => {
+                // Prints the rules normalized like iptables does
+                for rule in rules.iter() {
+                    let rule = rule.replace("-p tcp --dport", "-p tcp -m tcp --dport");
+                    stdout.push_str(&format!("-A INPUT {}\n", rule));
+                }
+                true
+            }
+            _ => {
+                stdout.push_str("iptables v1.8.7 (legacy)\n");
+                true
+            }
+        };
+        Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(if found { 0 } else { 1 << 8 }),
+            stdout: stdout.into_bytes(),
+            stderr: if found {
+                Vec::new()
+            } else {
+                b"iptables: Bad rule (does a matching rule exist in that chain?).\n".to_vec()
+            },
+        })
+    }
+}
+
+#[test]
+fn test_race_safe_unique() {
+    let runner = std::sync::Arc::new(RacingRunner::default());
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .build()
+        .unwrap();
+    assert!(ipt.append_unique("filter", "INPUT", "-j ACCEPT").is_ok());
+    assert_eq!(runner.rules.lock().unwrap().len(), 2);
+
+    runner.rules.lock().unwrap().clear();
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .race_safe_unique(true)
+        .build()
+        .unwrap();
+    assert!(ipt.append_unique("filter", "INPUT", "-j ACCEPT").is_ok());
+    assert_eq!(*runner.rules.lock().unwrap(), vec!["-j ACCEPT"]);
+    assert!(matches!(
+        ipt.append_unique("filter", "INPUT", "-j ACCEPT"),
+        Err(iptables::IPTError::AlreadyExists(_))
+    ));
+
+    // The copies are found even if iptables prints the rule differently than it was given
+    runner.rules.lock().unwrap().clear();
+    assert!(ipt
+        .append_unique("filter", "INPUT", "-p tcp --dport 22 -j ACCEPT")
+        .is_ok());
+    assert_eq!(
+        *runner.rules.lock().unwrap(),
+        vec!["-p tcp --dport 22 -j ACCEPT"]
+    );
+}
+
+#[test]
+fn test_rate_limit_rules() {
+    use iptables::limit::{HashLimit, HashLimitMode, Rate, RateUnit};
+
+    let rule = iptables::Rule::new()
+        .protocol("icmp")
+        .limit(Rate::per_second(10), Some(20))
+        .jump("ACCEPT");
+    assert_eq!(
+        rule.to_string(),
+        "-p icmp -m limit --limit 10/sec --limit-burst 20 -j ACCEPT"
+    );
+    let rule = iptables::Rule::new()
+        .limit(Rate::new(1, RateUnit::Hour), Some(5))
+        .jump("LOG");
+    assert_eq!(rule.to_string(), "-m limit --limit 1/hour -j LOG");
+
+    let limit = HashLimit::upto("web", Rate::per_second(100))
+        .mode(HashLimitMode::SrcIp)
+        .mode(HashLimitMode::DstPort)
+        .srcmask(24)
+        .expire_ms(60000);
+    assert_eq!(
+        limit.args().join(" "),
+        "--hashlimit-upto 100/sec --hashlimit-mode srcip,dstport --hashlimit-name web \
+         --hashlimit-srcmask 24 --hashlimit-htable-expire 60000"
+    );
+}
+
+#[test]
+fn test_conntrack_rules() {
+    use iptables::conntrack::{CtState, CtStatus};
+
+    let rule = iptables::Rule::new()
+        .ctstate(&[CtState::Established, CtState::Related])
+        .jump("ACCEPT");
+    assert_eq!(
+        rule.to_string(),
+        "-m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT"
+    );
+
+    let rule = iptables::Rule::new()
+        .protocol("tcp")
+        .dport(22)
+        .ctstatus(&[CtStatus::Assured])
+        .ctstate(&[CtState::New, CtState::New])
+        .comment("ssh")
+        .jump("ACCEPT");
+    assert_eq!(
+        rule.to_string(),
+        "-p tcp -m tcp --dport 22 -m conntrack --ctstate NEW --ctstatus ASSURED \
+         -m comment --comment ssh -j ACCEPT"
+    );
+}
+
+#[test]
+fn test_mangle_rules() {
+    let rule = iptables::Rule::new()
+        .protocol("tcp")
+        .dport(443)
+        .set_mark(0x10, None);
+    assert_eq!(
+        rule.to_string(),
+        "-p tcp -m tcp --dport 443 -j MARK --set-xmark 0x10/0xffffffff"
+    );
+    let rule = iptables::Rule::new()
+        .mark(0x1, Some(0xff))
+        .set_connmark(0x1, Some(0xf0));
+    assert_eq!(
+        rule.to_string(),
+        "-m mark --mark 0x1/0xff -j CONNMARK --set-xmark 0x1/0xf1"
+    );
+    assert_eq!(
+        iptables::Rule::new().restore_connmark().to_string(),
+        "-j CONNMARK --restore-mark --nfmask 0xffffffff --ctmask 0xffffffff"
+    );
+    assert_eq!(
+        iptables::Rule::new().set_dscp(46).to_string(),
+        "-j DSCP --set-dscp 0x2e"
+    );
+    // Padded to two digits like `iptables -S` prints it
+    assert_eq!(
+        iptables::Rule::new().set_dscp(10).to_string(),
+        "-j DSCP --set-dscp 0x0a"
+    );
+    assert!(iptables::Rule::new().set_dscp(63).check(false).is_ok());
+    assert!(matches!(
+        iptables::Rule::new().set_dscp(64).check(false),
+        Err(iptables::IPTError::Invalid(
+            iptables::validate::ValidationError::InvalidRange(_)
+        ))
+    ));
+    assert_eq!(
+        iptables::Rule::new().set_tos(0x10, Some(0x3f)).to_string(),
+        "-j TOS --set-tos 0x10/0x3f"
+    );
+
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+    let selector = iptables::Rule::new().protocol("udp").dport(53);
+    assert!(ipt
+        .set_dscp(iptables::Table::Mangle, "POSTROUTING", &selector, 46)
+        .is_ok());
+    assert_eq!(
+        runner.take_args(),
+        vec![
+            "-t mangle -C POSTROUTING -p udp -m udp --dport 53 -j DSCP --set-dscp 0x2e"
+                .split(' ')
+                .map(String::from)
+                .collect::<Vec<_>>()
+        ]
+    );
+}
+
+#[test]
+fn test_security_rules() {
+    let rule = iptables::Rule::new()
+        .protocol("tcp")
+        .dport(80)
+        .set_secmark("system_u:object_r:http_packet_t:s0");
+    assert_eq!(
+        rule.to_string(),
+        "-p tcp -m tcp --dport 80 -j SECMARK --selctx system_u:object_r:http_packet_t:s0"
+    );
+    assert_eq!(
+        iptables::Rule::new().save_connsecmark().to_string(),
+        "-j CONNSECMARK --save"
+    );
+    assert_eq!(
+        iptables::Rule::new().restore_connsecmark().to_string(),
+        "-j CONNSECMARK --restore"
+    );
+
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+    assert!(ipt
+        .append_rule(iptables::Table::Security, "INPUT", &rule)
+        .is_ok());
+    assert_eq!(
+        runner.take_args(),
+        vec![
+            "-t security -A INPUT -p tcp -m tcp --dport 80 -j SECMARK --selctx \
+             system_u:object_r:http_packet_t:s0"
+                .split(' ')
+                .map(String::from)
+                .collect::<Vec<_>>()
+        ]
+    );
+}
+
+#[test]
+fn test_ipv6_rules() {
+    use iptables::rule::HopLimit;
+
+    let rule = iptables::Rule::new()
+        .icmpv6_type("echo-request")
+        .hop_limit(HopLimit::Eq(255))
+        .jump("ACCEPT");
+    assert_eq!(
+        rule.to_string(),
+        "-p ipv6-icmp -m icmp6 --icmpv6-type echo-request -m hl --hl-eq 255 -j ACCEPT"
+    );
+    assert_eq!(
+        iptables::Rule::new()
+            .routing_type(0)
+            .jump("DROP")
+            .to_string(),
+        "-m rt --rt-type 0 -j DROP"
+    );
+    assert!(rule.check_family(true).is_ok());
+    assert!(rule.check_family(false).is_err());
+
+    let ipv4 = iptables::Rule::new()
+        .source("10.0.0.0/8")
+        .matching("ttl", &["--ttl-lt", "2"])
+        .jump("DROP");
+    assert!(ipv4.check_family(false).is_ok());
+    assert!(iptables::Rule::new()
+        .source("10.0.0.0/8")
+        .check_family(true)
+        .is_err());
+    assert!(iptables::Rule::new()
+        .protocol("icmp")
+        .check_family(true)
+        .is_err());
+    assert!(iptables::Rule::new()
+        .destination("fd00::/8")
+        .check_family(false)
+        .is_err());
+    assert!(iptables::Rule::new()
+        .source("example.com")
+        .check_family(true)
+        .is_ok());
+
+    let ipt = iptables::IPTables::builder()
+        .ipv6(true)
+        .dry_run(true)
+        .build()
+        .unwrap();
+    assert!(ipt.append_rule("filter", "INPUT", &ipv4).is_err());
+    assert!(ipt.recorded_commands().is_empty());
+    assert!(ipt.append_rule("filter", "INPUT", &rule).is_ok());
+    assert_eq!(ipt.recorded_commands().len(), 1);
+}
+
+#[test]
+fn test_validate_rule() {
+    use iptables::validate::{validate_rule, ValidationError};
+
+    for rule in [
+        "-p tcp -m tcp --dport 22 -j ACCEPT",
+        "-p tcp --dport 22 ! --syn -j DROP",
+        "-s 10.0.0.0/8 -i eth0 -m comment --comment \"my app\" -j ACCEPT",
+        "-p tcp -j REDIRECT --to-ports 8080",
+        "-m conntrack --ctstate ESTABLISHED,RELATED",
+    ] {
+        assert_eq!(validate_rule(rule, false), Ok(()), "{}", rule);
+    }
+
+    assert_eq!(validate_rule("  ", false), Err(ValidationError::Empty));
+    assert_eq!(
+        validate_rule("-m comment --comment \"ssh -j ACCEPT", false),
+        Err(ValidationError::UnbalancedQuotes)
+    );
+    assert_eq!(
+        validate_rule("-p tcp -x -j ACCEPT", false),
+        Err(ValidationError::UnknownOption("-x".to_string()))
+    );
+    assert_eq!(
+        validate_rule("--dport 22 -j ACCEPT", false),
+        Err(ValidationError::UnknownOption("--dport".to_string()))
+    );
+    assert_eq!(
+        validate_rule("-p tcp -j", false),
+        Err(ValidationError::MissingTarget)
+    );
+    assert_eq!(
+        validate_rule("-s -j ACCEPT", false),
+        Err(ValidationError::MissingValue("-s".to_string()))
+    );
+    assert_eq!(
+        validate_rule("-p tcp -s", false),
+        Err(ValidationError::MissingValue("-s".to_string()))
+    );
+    assert!(matches!(
+        validate_rule("-p icmp -m icmp --icmp-type 8 -j ACCEPT", true),
+        Err(ValidationError::WrongFamily(_))
+    ));
+    assert!(matches!(
+        validate_rule("-f -j DROP", true),
+        Err(ValidationError::WrongFamily(_))
+    ));
+    assert!(validate_rule("-f -j DROP", false).is_ok());
+
+    let ipt = iptables::IPTables::builder()
+        .dry_run(true)
+        .validate_rules(true)
+        .build()
+        .unwrap();
+    assert!(matches!(
+        ipt.append("filter", "INPUT", "-p tcp -dport 22 -j ACCEPT"),
+        Err(iptables::IPTError::Invalid(ValidationError::UnknownOption(
+            _
+        )))
+    ));
+    assert!(ipt
+        .insert("filter", "INPUT", "-s fd00::/8 -j ACCEPT", 1)
+        .is_err());
+    assert!(ipt.recorded_commands().is_empty());
+    assert!(ipt
+        .append("filter", "INPUT", "-p tcp --dport 22 -j ACCEPT")
+        .is_ok());
+    assert_eq!(ipt.recorded_commands().len(), 1);
+}
+
+#[test]
+fn test_snapshot() {
+    let runner = FakeRunner::new(
+        "# Generated by iptables-save v1.8.7\n\
+         *nat\n\
+         :PREROUTING ACCEPT [0:0]\n\
+         -A PREROUTING -p tcp -m tcp --dport 8080 -j DNAT --to-destination 10.0.0.2:80\n\
+         COMMIT\n\
+         *filter\n\
+         :INPUT DROP [12:720]\n\
+         :MYAPP - [0:0]\n\
+         [3:180] -A INPUT -j MYAPP\n\
+         -A MYAPP -s 10.0.0.1/32 -m comment --comment \"my app\" -j DROP\n\
+         -A MYAPP -p tcp -m tcp --dport 22 -j ACCEPT\n\
+         COMMIT\n",
+    );
+    let snapshot = runner.build().snapshot().unwrap();
+    assert_eq!(
+        runner.commands.lock().unwrap().last().unwrap(),
+        &vec!["iptables-save".to_string()]
+    );
+
+    assert_eq!(snapshot.tables(), vec!["nat", "filter"]);
+    let input = snapshot.chain("filter", "INPUT").unwrap();
+    assert_eq!(input.policy(), Some("DROP"));
+    assert_eq!(input.rules()[0].packets(), 3);
+    assert_eq!(snapshot.chain("filter", "MYAPP").unwrap().policy(), None);
+    assert!(snapshot.chain("filter", "MISSING").is_none());
+
+    let drop = snapshot.find(|rule| rule.target() == "DROP").unwrap();
+    assert_eq!(drop.chain(), "MYAPP");
+    assert!(drop.has_comment("my app"));
+    assert_eq!(drop.parse().unwrap().source.as_deref(), Some("10.0.0.1/32"));
+    assert_eq!(snapshot.filter(|rule| rule.table() == "filter").len(), 3);
+    assert!(snapshot.contains("filter", "MYAPP", "-p tcp -m tcp --dport 22 -j ACCEPT"));
+    assert!(!snapshot.contains("nat", "MYAPP", "-p tcp -m tcp --dport 22 -j ACCEPT"));
+}
+
+#[test]
+fn test_counters() {
+    let runner = FakeRunner::new(
+        "-P INPUT ACCEPT -c 0 0\n\
+         -A INPUT -p tcp -m tcp --dport 22 -m comment --comment ssh -c 10 600\n\
+         -A INPUT -j DROP -c 2 80\n",
+    );
+    let ipt = runner.build();
+    let counters = ipt.counters("filter", "INPUT").unwrap();
+    assert_eq!(counters.len(), 2);
+    assert_eq!(counters[0].comment().as_deref(), Some("ssh"));
+    assert_eq!(counters[0].key(), "ssh");
+    assert_eq!(counters[1].key(), "-j DROP");
+    assert_eq!((counters[1].packets, counters[1].bytes), (2, 80));
+
+    let (sender, polls) = std::sync::mpsc::channel();
+    let poller = ipt.poll_counters(
+        &[("filter", "INPUT")],
+        std::time::Duration::from_millis(10),
+        move |table, chain, counters| {
+            let _ = sender.send((
+                table.to_string(),
+                chain.to_string(),
+                counters.unwrap().len(),
+            ));
+        },
+    );
+    for _ in 0..2 {
+        let poll = polls
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(poll, ("filter".to_string(), "INPUT".to_string(), 2));
+    }
+    poller.stop();
+    while polls.try_recv().is_ok() {}
+    assert!(polls.recv().is_err());
+}
+
+#[test]
+fn test_call_options() {
+    use iptables::CallOptions;
+    use std::time::Duration;
+
+    let runner = FakeRunner::new("");
+    let ipt = runner
+        .builder()
+        .wait_timeout(Duration::from_secs(30))
+        .build()
+        .unwrap();
+    let last_command = || runner.commands.lock().unwrap().last().unwrap().join(" ");
+
+    assert!(ipt
+        .exists_with("filter", "INPUT", "-j ACCEPT", &CallOptions::no_wait())
+        .unwrap());
+    assert_eq!(last_command(), "iptables -t filter -C INPUT -j ACCEPT");
+
+    let options = CallOptions::wait(Duration::from_millis(1500));
+    assert!(ipt
+        .append_with("filter", "INPUT", "-j ACCEPT", &options)
+        .is_ok());
+    assert_eq!(
+        last_command(),
+        "iptables -t filter -A INPUT -j ACCEPT --wait 2"
+    );
+
+    assert!(ipt.delete("filter", "INPUT", "-j ACCEPT").is_ok());
+    assert_eq!(
+        last_command(),
+        "iptables -t filter -D INPUT -j ACCEPT --wait 30"
+    );
+}
+
+#[test]
+fn test_strict() {
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+    let output = ipt.execute_args(&["-S", "MISSING"]).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    let strict = runner.builder().strict(true).build().unwrap();
+    assert!(strict.execute_args(&["-S", "INPUT"]).is_ok());
+    match strict
+        .execute_table("filter", &["-S", "MISSING"])
+        .unwrap_err()
+    {
+        iptables::IPTError::CommandFailed(err) => assert_eq!(err.code, 1),
+        other => panic!("unexpected error: {}", other),
+    }
+    assert!(strict.execute("filter", "-S MISSING").is_err());
+}
+
+#[test]
+fn test_netns() {
+    let runner = FakeRunner::new("");
+    let ipt = runner.builder().netns("blue").build().unwrap();
+    assert_eq!(ipt.netns.as_deref(), Some("blue"));
+    assert!(ipt.append("filter", "INPUT", "-j ACCEPT").is_ok());
+    assert!(ipt.in_netns("red").flush_chain("filter", "INPUT").is_ok());
+
+    let commands = runner.commands.lock().unwrap();
+    assert_eq!(
+        commands[0],
+        vec!["ip", "netns", "exec", "blue", "iptables", "--version"]
+    );
+    assert_eq!(
+        commands[1].join(" "),
+        "ip netns exec blue iptables -t filter -A INPUT -j ACCEPT --wait"
+    );
+    assert_eq!(
+        commands[2].join(" "),
+        "ip netns exec red iptables -t filter -F INPUT --wait"
+    );
+    drop(commands);
+
+    let ipt = iptables::IPTables::builder()
+        .dry_run(true)
+        .netns("blue")
+        .build()
+        .unwrap();
+    assert!(ipt.save(Some("nat")).is_ok());
+    assert_eq!(
+        ipt.take_recorded_commands(),
+        vec![vec![
+            "ip",
+            "netns",
+            "exec",
+            "blue",
+            "iptables-save",
+            "-t",
+            "nat"
+        ]]
+    );
+}
+
+#[test]
+fn test_environment() {
+    let runner = FakeRunner::new("");
+    let ipt = runner
+        .builder()
+        .env_clear(true)
+        .env("PATH", "/usr/sbin:/usr/bin")
+        .modprobe("/sbin/modprobe")
+        .global_arg("-v")
+        .build()
+        .unwrap();
+    assert!(ipt.append("filter", "INPUT", "-j ACCEPT").is_ok());
+    assert!(ipt.save(None).is_ok());
+
+    let commands = runner.commands.lock().unwrap();
+    assert_eq!(
+        commands[0].join(" "),
+        "env -i PATH=/usr/sbin:/usr/bin iptables --modprobe=/sbin/modprobe --version"
+    );
+    assert_eq!(
+        commands[1].join(" "),
+        "env -i PATH=/usr/sbin:/usr/bin iptables --modprobe=/sbin/modprobe -v \
+         -t filter -A INPUT -j ACCEPT --wait"
+    );
+    assert_eq!(
+        commands[2].join(" "),
+        "env -i PATH=/usr/sbin:/usr/bin iptables-save --modprobe=/sbin/modprobe"
+    );
+}
+
+#[test]
+fn test_command_prefix() {
+    let runner = FakeRunner::new("");
+    let ipt = runner
+        .builder()
+        .command_prefix("sudo -n -u 'fw admin'")
+        .netns("blue")
+        .env("LANG", "C")
+        .build()
+        .unwrap();
+    assert_eq!(ipt.command_prefix, vec!["sudo", "-n", "-u", "fw admin"]);
+    assert!(ipt.flush_chain("filter", "INPUT").is_ok());
+
+    let commands = runner.commands.lock().unwrap();
+    assert_eq!(
+        commands[1],
+        vec![
+            "sudo", "-n", "-u", "fw admin", "ip", "netns", "exec", "blue", "env", "LANG=C",
+            "iptables", "-t", "filter", "-F", "INPUT", "--wait"
+        ]
+    );
+}
+
+// Answers the version check and denies every other command like iptables run without root
+struct DeniedRunner;
+
+impl iptables::runner::CommandRunner for DeniedRunner {
+    fn run(
+        &self,
+        _program: &str,
+        args: &[String],
+        _input: Option<&str>,
+    ) -> iptables::IPTResult<std::process::Output> {
+        use std::os::unix::process::ExitStatusExt;
+
+        let (code, stdout, stderr) = match args.last().map(String::as_str) {
+            Some("--version") => (0, "iptables v1.8.7 (nf_tables)\n", ""),
+            _ => (
+                4,
+                "",
+                "iptables v1.8.7 (nf_tables): Could not fetch rule set generation id: \
+                 Permission denied (you must be root)\n",
+            ),
+        };
+        Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        })
+    }
+}
+
+#[test]
+fn test_check_permissions() {
+    use iptables::privileges::Privileges;
+
+    let denied = iptables::IPTables::builder()
+        .runner(DeniedRunner)
+        .build()
+        .unwrap();
+    assert_eq!(denied.check_permissions().unwrap(), Privileges::default());
+
+    let runner = FakeRunner::new("");
+    let ipt = runner.builder().command_prefix("sudo -n").build().unwrap();
+    assert_eq!(
+        ipt.check_permissions().unwrap(),
+        Privileges {
+            read: true,
+            write: true
+        }
+    );
+}
+
+// Fails the first `failures` commands after the version check like a busy kernel
+struct FlakyRunner {
+    failures: std::sync::atomic::AtomicU32,
+    runs: std::sync::atomic::AtomicU32,
+}
+
+impl iptables::runner::CommandRunner for FlakyRunner {
+    fn run(
+        &self,
+        _program: &str,
+        args: &[String],
+        _input: Option<&str>,
+    ) -> iptables::IPTResult<std::process::Output> {
+        use std::os::unix::process::ExitStatusExt;
+        use std::sync::atomic::Ordering;
+
+        let (code, stdout, stderr) = if args.last().map(String::as_str) == Some("--version") {
+            (0, "iptables v1.8.7 (legacy)\n", "")
+        } else {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            let failing = self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                    left.checked_sub(1)
+                })
+                .is_ok();
+            if failing {
+                (4, "", "iptables: Resource temporarily unavailable.\n")
+            } else {
+                (0, "", "")
+            }
+        };
+        Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        })
+    }
+}
+
+#[test]
+fn test_retry_policy() {
+    use iptables::retry::RetryPolicy;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    let flaky = |failures| {
+        std::sync::Arc::new(FlakyRunner {
+            failures: std::sync::atomic::AtomicU32::new(failures),
+            runs: std::sync::atomic::AtomicU32::new(0),
+        })
+    };
+    let policy = RetryPolicy {
+        backoff: Duration::ZERO,
+        ..RetryPolicy::new(3)
+    };
+
+    let runner = flaky(2);
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .retry_policy(policy)
+        .build()
+        .unwrap();
+    assert!(ipt.append("filter", "INPUT", "-j ACCEPT").is_ok());
+    assert_eq!(runner.runs.load(Ordering::SeqCst), 3);
+
+    let runner = flaky(3);
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .retry_policy(policy)
+        .build()
+        .unwrap();
+    match ipt.append("filter", "INPUT", "-j ACCEPT").unwrap_err() {
+        iptables::IPTError::CommandFailed(err) => assert_eq!(err.code, 4),
+        other => panic!("unexpected error: {}", other),
+    }
+    assert_eq!(runner.runs.load(Ordering::SeqCst), 3);
+
+    // Commands are not retried by default
+    let runner = flaky(1);
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .build()
+        .unwrap();
+    assert!(ipt.append("filter", "INPUT", "-j ACCEPT").is_err());
+    assert_eq!(runner.runs.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_netns_path() {
+    let runner = FakeRunner::new("");
+    let ipt = runner
+        .builder()
+        .netns_path("/run/netns/blue")
+        .build()
+        .unwrap();
+    assert!(ipt.append("filter", "INPUT", "-j ACCEPT").is_ok());
+
+    let commands = runner.commands.lock().unwrap();
+    assert_eq!(commands[0], vec!["/run/netns/blue:iptables", "--version"]);
+    assert_eq!(
+        commands[1].join(" "),
+        "/run/netns/blue:iptables -t filter -A INPUT -j ACCEPT --wait"
+    );
+    drop(commands);
+
+    // The namespace is opened before iptables is spawned
+    let result = iptables::IPTables::builder()
+        .netns_path("/nonexistent/netns")
+        .search_paths::<&str>(&[])
+        .build();
+    assert!(matches!(result, Err(iptables::IPTError::Io(_))));
+}
+
+#[test]
+fn test_link_chain() {
+    assert_eq!(
+        iptables::Rule::new()
+            .protocol("tcp")
+            .goto("MYAPP")
+            .to_string(),
+        "-p tcp -g MYAPP"
+    );
+    assert_eq!(
+        iptables::Rule::new().goto("MYAPP").jump("DROP").to_string(),
+        "-j DROP"
+    );
+
+    let ipt = iptables::IPTables::builder().dry_run(true).build().unwrap();
+    // Checks succeed in dry-run mode, so the jump is considered to exist already
+    assert!(ipt.link_chain("filter", "INPUT", "MYAPP", Some(1)).is_ok());
+    assert!(ipt
+        .link_chain_goto("filter", "FORWARD", "MYAPP", None)
+        .is_ok());
+    let commands = ipt
+        .take_recorded_commands()
+        .iter()
+        .map(|command| command.join(" "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commands,
+        vec![
+            "iptables -t filter -N MYAPP --wait",
+            "iptables -t filter -C INPUT -j MYAPP --wait",
+            "iptables -t filter -N MYAPP --wait",
+            "iptables -t filter -C FORWARD -g MYAPP --wait",
+        ]
+    );
+}
+
+#[test]
+fn test_rule_handle() {
+    let ipt = iptables::IPTables::builder()
+        .dry_run(true)
+        .owner_tag("myapp")
+        .build()
+        .unwrap();
+    let handle = ipt
+        .append_handle("filter", "INPUT", "-p tcp --dport 8080 -j ACCEPT")
+        .unwrap();
+    assert_eq!(handle.table(), "filter");
+    assert_eq!(
+        handle.rule(),
+        "-p tcp --dport 8080 -m comment --comment myapp -j ACCEPT"
+    );
+    let inserted = ipt
+        .insert_handle("nat", "PREROUTING", "-j MYAPP", 1)
+        .unwrap();
+
+    {
+        let mut guard = iptables::handle::RuleGuard::new(&ipt);
+        guard.register(handle).register(inserted.clone());
+        assert_eq!(guard.handles().len(), 2);
+    }
+    let commands = ipt
+        .take_recorded_commands()
+        .iter()
+        .map(|command| command[1..].join(" "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commands,
+        vec![
+            "-t filter -A INPUT -p tcp --dport 8080 -m comment --comment myapp -j ACCEPT --wait",
+            "-t nat -I PREROUTING 1 -m comment --comment myapp -j MYAPP --wait",
+            "-t nat -D PREROUTING -m comment --comment myapp -j MYAPP --wait",
+            "-t filter -D INPUT -p tcp --dport 8080 -m comment --comment myapp -j ACCEPT --wait",
+        ]
+    );
+
+    let mut guard = iptables::handle::RuleGuard::new(&ipt);
+    guard.register(inserted);
+    assert_eq!(guard.disarm().len(), 1);
+    drop(guard);
+    assert!(ipt.take_recorded_commands().is_empty());
+}
+
+// Records the input of iptables-restore next to the command line, `-S` prints `listing` if set
+#[derive(Default)]
+struct RestoreRunner {
+    inputs: std::sync::Mutex<Vec<(String, String)>>,
+    listing: Option<&'static str>,
+}
+
+impl iptables::runner::CommandRunner for RestoreRunner {
+    fn run(
+        &self,
+        program: &str,
+        args: &[String],
+        input: Option<&str>,
+    ) -> iptables::IPTResult<std::process::Output> {
+        use std::os::unix::process::ExitStatusExt;
+
+        let stdout = match args.first().map(String::as_str) {
+            Some("--version") => "iptables v1.8.7 (legacy)\n",
+            _ if args.iter().any(|arg| arg == "-S") => {
+                self.listing.unwrap_or("-P INPUT ACCEPT\n-N EXISTING\n")
+            }
+            _ => "",
+        };
+        let mut stderr = String::new();
+        if let Some(input) = input {
+            let command = [&[program.to_string()], args].concat().join(" ");
+            self.inputs
+                .lock()
+                .unwrap()
+                .push((command, input.to_string()));
+            // Rejects lines with the target BAD like iptables-restore rejects unknown targets
+            if let Some(line) = input.lines().position(|line| line.ends_with("-j BAD")) {
+                stderr = format!("iptables-restore: line {} failed\n", line + 1);
+            }
+        }
+        Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(if stderr.is_empty() { 0 } else { 1 << 8 }),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.into_bytes(),
+        })
+    }
+}
+
+#[test]
+fn test_managed_chain() {
+    let runner = std::sync::Arc::new(RestoreRunner::default());
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .owner_tag("myapp")
+        .build()
+        .unwrap();
+
+    let chain = ipt
+        .create_managed_chain("filter", "MYAPP", &[("INPUT", 1), ("FORWARD", 2)])
+        .unwrap();
+    assert_eq!(chain.table(), "filter");
+    assert_eq!(chain.chain(), "MYAPP");
+    assert_eq!(chain.parents(), ["INPUT", "FORWARD"]);
+    ipt.create_managed_chain("filter", "EXISTING", &[("INPUT", 1)])
+        .unwrap();
+
+    let inputs = runner.inputs.lock().unwrap();
+    assert_eq!(
+        inputs[0],
+        (
+            "iptables-restore --noflush --wait".to_string(),
+            "*filter\n:MYAPP - [0:0]\n-I INPUT 1 -m comment --comment myapp -j MYAPP\n\
+             -I FORWARD 2 -m comment --comment myapp -j MYAPP\nCOMMIT\n"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        inputs[1].1,
+        "*filter\n-I INPUT 1 -m comment --comment myapp -j EXISTING\nCOMMIT\n"
+    );
+    drop(inputs);
+
+    // A line break in a name would start another command of iptables-restore
+    runner.inputs.lock().unwrap().clear();
+    assert!(ipt
+        .create_managed_chain("filter", "MYAPP\n-F INPUT", &[("INPUT", 1)])
+        .is_err());
+    assert!(ipt
+        .create_managed_chain("filter", "MYAPP", &[("INPUT 1 -j ACCEPT\r-F", 1)])
+        .is_err());
+    assert!(runner.inputs.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_append_many() {
+    let runner = std::sync::Arc::new(RestoreRunner::default());
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .build()
+        .unwrap();
+
+    ipt.append_many(
+        "filter",
+        "INPUT",
+        &["-p tcp --dport 80 -j ACCEPT", "-j DROP"],
+    )
+    .unwrap();
+    assert_eq!(
+        runner.inputs.lock().unwrap()[0],
+        (
+            "iptables-restore --noflush --wait".to_string(),
+            "*filter\n-A INPUT -p tcp --dport 80 -j ACCEPT\n-A INPUT -j DROP\nCOMMIT\n".to_string()
+        )
+    );
+
+    match ipt.append_many("filter", "INPUT", &["-j ACCEPT", "-j BAD", "-j DROP"]) {
+        Err(iptables::IPTError::RuleFailed { index, error }) => {
+            assert_eq!(index, 1);
+            assert!(matches!(*error, iptables::IPTError::CommandFailed(_)));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // Invalid rules are rejected before running iptables-restore
+    match ipt.append_many("filter", "INPUT", &["-j ACCEPT", "-j DROP\n-F INPUT"]) {
+        Err(iptables::IPTError::RuleFailed { index, .. }) => assert_eq!(index, 1),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(matches!(
+        ipt.append_many("filter", "INPUT", &[" "]),
+        Err(iptables::IPTError::RuleFailed { index: 0, .. })
+    ));
+    assert_eq!(runner.inputs.lock().unwrap().len(), 2);
+
+    let mut batch = iptables::batch::Batch::new();
+    batch.append_many("filter", "INPUT", &["-j ACCEPT", "-j DROP"]);
+    assert_eq!(
+        batch.to_restore_string(),
+        "*filter\n-A INPUT -j ACCEPT\n-A INPUT -j DROP\nCOMMIT\n"
+    );
+}
+
+#[test]
+fn test_batch_commit() {
+    let runner = std::sync::Arc::new(RestoreRunner::default());
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .owner_tag("myapp")
+        .build()
+        .unwrap();
+
+    let mut batch = iptables::batch::Batch::new();
+    batch
+        .append("filter", "INPUT", "-p tcp --dport 22 -j ACCEPT")
+        .delete_rule("filter", "INPUT", &iptables::Rule::new().jump("DROP"));
+    ipt.commit(&batch).unwrap();
+    assert_eq!(
+        runner.inputs.lock().unwrap()[0].1,
+        "*filter\n-A INPUT -p tcp --dport 22 -m comment --comment myapp -j ACCEPT\n\
+         -D INPUT -m comment --comment myapp -j DROP\nCOMMIT\n"
+    );
+    // The batch itself stays untagged
+    assert_eq!(
+        batch.to_restore_string(),
+        "*filter\n-A INPUT -p tcp --dport 22 -j ACCEPT\n-D INPUT -j DROP\nCOMMIT\n"
+    );
+
+    // A line break would inject restore commands, the batch is rejected as a whole
+    let mut batch = iptables::batch::Batch::new();
+    batch
+        .append("filter", "INPUT", "-j ACCEPT")
+        .append(
+            "filter",
+            "INPUT",
+            "-m comment --comment \"a\nCOMMIT\n*nat\" -j DROP",
+        )
+        .append_rule(
+            "filter",
+            "INPUT",
+            &iptables::Rule::new().comment("b\r-F INPUT").jump("DROP"),
+        );
+    match batch.check() {
+        Err(iptables::IPTError::RuleFailed { index, .. }) => assert_eq!(index, 1),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(
+        batch.to_restore_string(),
+        "*filter\n-A INPUT -j ACCEPT\nCOMMIT\n"
+    );
+    assert!(matches!(
+        ipt.commit(&batch),
+        Err(iptables::IPTError::RuleFailed { index: 1, .. })
+    ));
+    assert_eq!(runner.inputs.lock().unwrap().len(), 1);
+
+    // So would a line break in the name of a table or chain
+    let mut batch = iptables::batch::Batch::new();
+    batch
+        .append("filter", "INPUT", "-j ACCEPT")
+        .append("filter", "INPUT\n-F", "-j ACCEPT")
+        .delete_rule("filter\n*nat", "INPUT", &iptables::Rule::new().jump("DROP"));
+    assert!(matches!(
+        batch.check(),
+        Err(iptables::IPTError::RuleFailed { index: 1, .. })
+    ));
+    assert_eq!(
+        batch.to_restore_string(),
+        "*filter\n-A INPUT -j ACCEPT\nCOMMIT\n"
+    );
+
+    // A failed commit restores the backup of the table together with its counters
+    runner.inputs.lock().unwrap().clear();
+    let mut batch = iptables::batch::Batch::new();
+    batch.append("filter", "INPUT", "-j BAD");
+    assert!(ipt.commit(&batch).is_err());
+    let commands = runner
+        .inputs
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(command, _)| command.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commands,
+        [
+            "iptables-restore --noflush --wait",
+            "iptables-restore --counters --wait"
+        ]
+    );
+}
+
+#[test]
+fn test_restore_table() {
+    let runner = std::sync::Arc::new(RestoreRunner::default());
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .build()
+        .unwrap();
+
+    ipt.restore_table(
+        "filter",
+        &[
+            (
+                "MYAPP",
+                vec!["-p tcp --dport 22 -j ACCEPT", "[5:300] -j DROP"],
+            ),
+            ("INPUT", vec!["-j MYAPP"]),
+        ],
+        true,
+    )
+    .unwrap();
+    ipt.restore_table(
+        "filter",
+        &[
+            (
+                "EXISTING",
+                vec!["-m comment --comment 'two words' -j RETURN"],
+            ),
+            ("NEW", vec![]),
+        ],
+        false,
+    )
+    .unwrap();
+
+    let inputs = runner.inputs.lock().unwrap();
+    assert_eq!(
+        inputs[0],
+        (
+            "iptables-restore --noflush --counters --wait".to_string(),
+            "*filter\n:MYAPP - [0:0]\n-F INPUT\n-A MYAPP -p tcp --dport 22 -j ACCEPT\n\
+             [5:300] -A MYAPP -j DROP\n-A INPUT -j MYAPP\nCOMMIT\n"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        inputs[1],
+        (
+            "iptables-restore --noflush --wait".to_string(),
+            "*filter\n:NEW - [0:0]\n-A EXISTING -m comment --comment \"two words\" -j RETURN\nCOMMIT\n"
+                .to_string()
+        )
+    );
+    drop(inputs);
+
+    // Line breaks in rules or chain names would inject restore commands
+    for chains in [
+        &[("INPUT", vec!["-j ACCEPT\nCOMMIT\n*nat\n-F"])][..],
+        &[("INPUT", vec!["-m comment --comment \"a\rb\" -j ACCEPT"])],
+        &[("NEW\n-F INPUT", vec![])],
+    ] {
+        assert!(matches!(
+            ipt.restore_table("filter", chains, false),
+            Err(iptables::IPTError::Parse(_))
+        ));
+    }
+    assert_eq!(runner.inputs.lock().unwrap().len(), 2);
+
+    // iptables-restore waits for the xtables lock up to the timeout, rounded up to seconds
+    let runner = std::sync::Arc::new(RestoreRunner::default());
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .wait_timeout(std::time::Duration::from_millis(2500))
+        .build()
+        .unwrap();
+    ipt.restore_table("filter", &[("INPUT", vec!["-j ACCEPT"])], false)
+        .unwrap();
+    ipt.restore("*filter\nCOMMIT\n", true).unwrap();
+    let commands = runner
+        .inputs
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(command, _)| command.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commands,
+        [
+            "iptables-restore --noflush --wait 3",
+            "iptables-restore --wait 3"
+        ]
+    );
+}
+
+#[test]
+fn test_parse_save() {
+    let ruleset = iptables::ruleset::parse_save(
+        "# Generated by iptables-save v1.8.7\n\
+         *nat\n\
+         :POSTROUTING ACCEPT [0:0]\n\
+         -A POSTROUTING -o eth0 -j MASQUERADE\n\
+         COMMIT\n\
+         *filter\n\
+         :INPUT DROP [12:720]\n\
+         :MYAPP - [0:0]\n\
+         -A MYAPP -m comment --comment \"my app\" -j ACCEPT\n\
+         COMMIT\n",
+    )
+    .unwrap();
+    assert_eq!(ruleset.tables(), vec!["filter", "nat"]);
+    assert_eq!(ruleset.policy("filter", "MYAPP"), None);
+    assert_eq!(
+        ruleset.rules("filter", "MYAPP"),
+        ["-m comment --comment \"my app\" -j ACCEPT"]
+    );
+    assert!(ruleset.rules("filter", "INPUT").is_empty());
+
+    let ipt = iptables::IPTables::builder().dry_run(true).build().unwrap();
+    ipt.apply(&ruleset).unwrap();
+    assert!(ipt
+        .take_recorded_commands()
+        .iter()
+        .any(|command| command[1..] == ["-t", "filter", "-P", "INPUT", "DROP", "--wait"]));
+    assert!(iptables::ruleset::parse_save("-A INPUT -j DROP\n").is_err());
+}
+
+#[test]
+fn test_apply_canonical() {
+    let runner = FakeRunner::new(
+        "-P INPUT ACCEPT\n-A INPUT -p tcp -m tcp --dport 22 -j ACCEPT\n-A INPUT -j DROP\n",
+    );
+    let ipt = runner.build();
+
+    // Rules in place are kept even if iptables prints them differently than they were written
+    let mut ruleset = iptables::ruleset::RuleSet::new();
+    ruleset
+        .append("filter", "INPUT", "-p tcp --dport 22 -j ACCEPT")
+        .append("filter", "INPUT", "-p udp --dport 53 -j ACCEPT")
+        .append("filter", "INPUT", "-j DROP");
+    ipt.apply(&ruleset).unwrap();
+    let args = runner.take_args();
+    assert_eq!(args.len(), 2);
+    assert_eq!(args[0], ["-t", "filter", "-S", "INPUT"]);
+    assert_eq!(
+        args[1].join(" "),
+        "-t filter -I INPUT 2 -p udp --dport 53 -j ACCEPT"
+    );
+}
+
+#[test]
+fn test_ruleset_restore_string() {
+    let saved = "*filter\n\
+                 :FORWARD ACCEPT [0:0]\n\
+                 :INPUT DROP [0:0]\n\
+                 :MYAPP - [0:0]\n\
+                 -A INPUT -i lo -j ACCEPT\n\
+                 -A INPUT -j MYAPP\n\
+                 -A MYAPP -m comment --comment \"my app\" -j ACCEPT\n\
+                 COMMIT\n\
+                 *nat\n\
+                 :POSTROUTING ACCEPT [0:0]\n\
+                 -A POSTROUTING -o eth0 -j MASQUERADE\n\
+                 COMMIT\n";
+    let ruleset = iptables::ruleset::parse_save(saved).unwrap();
+    assert_eq!(ruleset.to_restore_string(), saved);
+    assert_eq!(
+        iptables::ruleset::parse_save(&ruleset.to_restore_string()).unwrap(),
+        ruleset
+    );
+
+    // Counters are dropped and single quotes are rendered as double quotes
+    let ruleset = iptables::ruleset::parse_save(
+        "*raw\n:PREROUTING ACCEPT [10:600]\n[1:60] -A PREROUTING -m comment --comment 'a b' -j CT --notrack\nCOMMIT\n",
+    )
+    .unwrap();
+    assert_eq!(
+        ruleset.to_restore_string(),
+        "*raw\n:PREROUTING ACCEPT [0:0]\n-A PREROUTING -m comment --comment \"a b\" -j CT --notrack\nCOMMIT\n"
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let rule = iptables::Rule::new()
+        .protocol("tcp")
+        .dport(22)
+        .comment("ssh")
+        .jump("ACCEPT");
+    let json = serde_json::to_string(&rule).unwrap();
+    assert_eq!(serde_json::from_str::<iptables::Rule>(&json).unwrap(), rule);
+    let rule: iptables::Rule =
+        serde_json::from_str(r#"{"protocol": "udp", "dport": "53", "target": "ACCEPT"}"#).unwrap();
+    assert_eq!(rule.to_string(), "-p udp -m udp --dport 53 -j ACCEPT");
+
+    assert_eq!(
+        serde_json::to_string(&iptables::Table::Nat).unwrap(),
+        "\"nat\""
+    );
+    assert_eq!(
+        serde_json::from_str::<iptables::Chain>("\"INPUT\"").unwrap(),
+        iptables::Chain::Input
+    );
+
+    let mut ruleset = iptables::ruleset::RuleSet::new();
+    ruleset
+        .set_policy("filter", "INPUT", "DROP")
+        .append("filter", "INPUT", "-i lo -j ACCEPT");
+    let json = serde_json::to_string(&ruleset).unwrap();
+    assert_eq!(
+        serde_json::from_str::<iptables::ruleset::RuleSet>(&json).unwrap(),
+        ruleset
+    );
+
+    let counters = iptables::counters::RuleCounters::parse("-A INPUT -j DROP -c 2 80").unwrap();
+    let json = serde_json::to_string(&counters).unwrap();
+    assert_eq!(
+        json,
+        r#"{"chain":"INPUT","rule":"-j DROP","packets":2,"bytes":80}"#
+    );
+}
+
+// Answers each `iptables-save` call with the next of `saves`, repeating the last one
+struct SaveRunner {
+    saves: std::sync::Mutex<Vec<&'static str>>,
+}
+
+impl iptables::runner::CommandRunner for SaveRunner {
+    fn run(
+        &self,
+        program: &str,
+        args: &[String],
+        _input: Option<&str>,
+    ) -> iptables::IPTResult<std::process::Output> {
+        use std::os::unix::process::ExitStatusExt;
+
+        let stdout = match args.last().map(String::as_str) {
+            Some("--version") => "iptables v1.8.7 (legacy)\n",
+            _ if program.ends_with("-save") => {
+                let mut saves = self.saves.lock().unwrap();
+                if saves.len() > 1 {
+                    saves.remove(0)
+                } else {
+                    saves[0]
+                }
+            }
+            _ => "",
+        };
+        Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(0),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: Vec::new(),
+        })
+    }
+}
+
+#[test]
+fn test_watch() {
+    use iptables::watch::{changes, WatchEvent, WatchOptions};
+
+    let old = iptables::snapshot::Snapshot::parse(
+        "*filter\n:INPUT ACCEPT [0:0]\n:OLD - [0:0]\n[5:300] -A INPUT -j OLD\n\
+         [1:60] -A INPUT -p tcp -j ACCEPT\n-A OLD -j DROP\nCOMMIT\n",
+    )
+    .unwrap();
+    let new = iptables::snapshot::Snapshot::parse(
+        "*filter\n:INPUT DROP [0:0]\n:NEW - [0:0]\n[2:100] -A INPUT -p tcp -j ACCEPT\n\
+         -A NEW -j LOG\nCOMMIT\n",
+    )
+    .unwrap();
+    let filter = String::from("filter");
+    assert_eq!(
+        changes(&old, &new, false),
+        vec![
+            WatchEvent::RuleRemoved {
+                table: filter.clone(),
+                chain: "OLD".to_string(),
+                rule: "-j DROP".to_string(),
+            },
+            WatchEvent::ChainRemoved {
+                table: filter.clone(),
+                chain: "OLD".to_string(),
+            },
+            WatchEvent::PolicyChanged {
+                table: filter.clone(),
+                chain: "INPUT".to_string(),
+                policy: "DROP".to_string(),
+            },
+            WatchEvent::RuleRemoved {
+                table: filter.clone(),
+                chain: "INPUT".to_string(),
+                rule: "-j OLD".to_string(),
+            },
+            WatchEvent::ChainAdded {
+                table: filter.clone(),
+                chain: "NEW".to_string(),
+            },
+            WatchEvent::RuleAdded {
+                table: filter.clone(),
+                chain: "NEW".to_string(),
+                rule: "-j LOG".to_string(),
+            },
+        ]
+    );
+    assert!(
+        changes(&old, &new, true).contains(&WatchEvent::CountersChanged {
+            table: filter.clone(),
+            chain: "INPUT".to_string(),
+            rule: "-p tcp -j ACCEPT".to_string(),
+            packets: 1,
+            bytes: 40,
+        })
+    );
+    assert!(changes(&new, &new, true).is_empty());
+
+    let runner = std::sync::Arc::new(SaveRunner {
+        saves: std::sync::Mutex::new(vec![
+            "*filter\n:INPUT ACCEPT [0:0]\nCOMMIT\n",
+            "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -s 10.0.0.1/32 -j DROP\nCOMMIT\n",
+        ]),
+    });
+    let ipt = iptables::IPTables::builder()
+        .runner(runner)
+        .build()
+        .unwrap();
+    let watcher = ipt
+        .watch(WatchOptions {
+            interval: std::time::Duration::from_millis(10),
+            ..WatchOptions::default()
+        })
+        .unwrap();
+    let events = watcher.events();
+    assert_eq!(
+        events
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap()
+            .unwrap(),
+        WatchEvent::RuleAdded {
+            table: filter,
+            chain: "INPUT".to_string(),
+            rule: "-s 10.0.0.1/32 -j DROP".to_string(),
+        }
+    );
+    // The rules do not change anymore
+    assert!(events
+        .recv_timeout(std::time::Duration::from_millis(50))
+        .is_err());
+    watcher.stop();
+}
+
+#[test]
+fn test_stats() {
+    let runner = FakeRunner::new(
+        "*nat\n\
+         :PREROUTING ACCEPT [7:420]\n\
+         [2:120] -A PREROUTING -p tcp -m tcp --dport 8080 -j DNAT --to-destination 10.0.0.2:80\n\
+         COMMIT\n\
+         *filter\n\
+         :INPUT DROP [12:720]\n\
+         :MYAPP - [0:0]\n\
+         [3:180] -A INPUT -j MYAPP\n\
+         [1:60] -A MYAPP -m comment --comment \"my \\\"app\\\"\" -j ACCEPT\n\
+         [0:0] -A MYAPP -p tcp\n\
+         COMMIT\n",
+    );
+    let ipt = runner.build();
+    let stats = ipt.stats().unwrap();
+    assert_eq!(
+        runner.commands.lock().unwrap().last().unwrap(),
+        &vec!["iptables-save".to_string(), "-c".to_string()]
+    );
+
+    assert_eq!(
+        stats.chains,
+        vec![
+            iptables::stats::ChainStats {
+                table: "nat".to_string(),
+                chain: "PREROUTING".to_string(),
+                policy: "ACCEPT".to_string(),
+                packets: 7,
+                bytes: 420,
+            },
+            iptables::stats::ChainStats {
+                table: "filter".to_string(),
+                chain: "INPUT".to_string(),
+                policy: "DROP".to_string(),
+                packets: 12,
+                bytes: 720,
+            },
+        ]
+    );
+    assert_eq!(stats.rules.len(), 4);
+    assert_eq!(stats.rules[0].target.as_deref(), Some("DNAT"));
+    assert_eq!(
+        stats.rules[2],
+        iptables::stats::RuleStats {
+            table: "filter".to_string(),
+            chain: "MYAPP".to_string(),
+            position: 1,
+            comment: Some("my \"app\"".to_string()),
+            target: Some("ACCEPT".to_string()),
+            packets: 1,
+            bytes: 60,
+        }
+    );
+    assert_eq!(stats.rules[3].position, 2);
+    assert_eq!(stats.rules[3].target, None);
+
+    let mut metrics = String::new();
+    stats.write_prometheus(&mut metrics);
+    assert!(metrics.contains(
+        "iptables_chain_policy_bytes_total{table=\"filter\",chain=\"INPUT\",policy=\"DROP\"} 720\n"
+    ));
+    assert!(metrics.contains(
+        "iptables_rule_packets_total{table=\"filter\",chain=\"MYAPP\",position=\"1\",\
+         comment=\"my \\\"app\\\"\",target=\"ACCEPT\"} 1\n"
+    ));
+
+    let mut reused = iptables::stats::Stats::default();
+    ipt.stats_into(&mut reused).unwrap();
+    ipt.stats_into(&mut reused).unwrap();
+    assert_eq!(reused, stats);
+
+    // The statistics are built from the snapshot of the same output
+    let snapshot = ipt.snapshot().unwrap();
+    let input = snapshot.chain("filter", "INPUT").unwrap();
+    assert_eq!((input.packets(), input.bytes()), (12, 720));
+    assert_eq!(
+        snapshot.chain("filter", "MYAPP").unwrap().rules()[0].comment(),
+        Some("my \"app\"")
+    );
+    assert!(iptables::stats::Stats::parse("*filter\n:INPUT DROP [12]\nCOMMIT\n").is_err());
+}
+
+#[test]
+fn test_table_handle() {
+    let runner = FakeRunner::new("-P PREROUTING ACCEPT\n-N MYAPP\n");
+    let ipt = runner.build();
+    let nat = ipt.table("nat");
+    assert_eq!(nat.table(), &iptables::Table::Nat);
+
+    assert_eq!(nat.chains().unwrap(), vec!["PREROUTING", "MYAPP"]);
+    assert!(nat.chain_exists("MYAPP").unwrap());
+    assert!(!nat.chain_exists("OTHER").unwrap());
+    // The chains are listed once
+    assert_eq!(runner.take_args().len(), 1);
+
+    let shared = nat.clone();
+    std::thread::spawn(move || shared.new_chain("OTHER").unwrap())
+        .join()
+        .unwrap();
+    nat.rename_chain("MYAPP", "RENAMED").unwrap();
+    nat.append("RENAMED", "-j ACCEPT").unwrap();
+    nat.delete_rule("RENAMED", &iptables::Rule::new().jump("ACCEPT"))
+        .unwrap();
+    nat.delete_chain("PREROUTING").unwrap();
+    assert_eq!(nat.chains().unwrap(), vec!["RENAMED", "OTHER"]);
+    assert_eq!(
+        runner.take_args(),
+        [
+            "-t nat -N OTHER",
+            "-t nat -E MYAPP RENAMED",
+            "-t nat -A RENAMED -j ACCEPT",
+            "-t nat -D RENAMED -j ACCEPT",
+            "-t nat -X PREROUTING",
+        ]
+        .iter()
+        .map(|command| command.split(' ').map(String::from).collect::<Vec<_>>())
+        .collect::<Vec<_>>()
+    );
+
+    nat.refresh().unwrap();
+    assert_eq!(nat.chains().unwrap(), vec!["PREROUTING", "MYAPP"]);
+}
+
+// Succeeds with a warning on standard error like iptables-nft next to legacy tables
+struct WarningRunner;
+
+impl iptables::runner::CommandRunner for WarningRunner {
+    fn run(
+        &self,
+        _program: &str,
+        args: &[String],
+        _input: Option<&str>,
+    ) -> iptables::IPTResult<std::process::Output> {
+        use std::os::unix::process::ExitStatusExt;
+
+        let (stdout, stderr) = match args.last().map(String::as_str) {
+            Some("--version") => ("iptables v1.8.7 (nf_tables)\n", ""),
+            _ => ("", "# Warning: iptables-legacy tables present\n"),
+        };
+        Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(0),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        })
+    }
+}
+
+#[test]
+fn test_verbose_output() {
+    let ipt = iptables::IPTables::builder()
+        .runner(std::sync::Arc::new(WarningRunner))
+        .owner_tag("myapp")
+        .validate_rules(true)
+        .build()
+        .unwrap();
+    let output = ipt
+        .append_verbose("filter", "INPUT", "-p tcp --dport 22 -j ACCEPT")
+        .unwrap();
+    assert_eq!(
+        output.argv,
+        "iptables -t filter -A INPUT -p tcp --dport 22 -m comment --comment myapp -j ACCEPT --wait"
+            .split(' ')
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(output.status, 0);
+    assert!(output.has_warnings());
+    assert_eq!(output.stderr, "# Warning: iptables-legacy tables present\n");
+    assert!(output
+        .to_string()
+        .ends_with("(code: 0)\n# Warning: iptables-legacy tables present"));
+
+    let output = ipt.insert_verbose("filter", "INPUT", "-j DROP", 2).unwrap();
+    assert_eq!(&output.argv[3..6], ["-I", "INPUT", "2"]);
+    let output = ipt.delete_verbose("filter", "INPUT", "-j DROP").unwrap();
+    assert_eq!(&output.argv[3..5], ["-D", "INPUT"]);
+    assert!(matches!(
+        ipt.append_verbose("filter", "INPUT", "-p tcp -dport 22 -j DROP"),
+        Err(iptables::IPTError::Invalid(_))
+    ));
+    assert!(matches!(
+        ipt.insert_verbose("filter", "INPUT", "-p tcp -dport 22 -j DROP", 1),
+        Err(iptables::IPTError::Invalid(_))
+    ));
+
+    let denied = iptables::IPTables::builder()
+        .runner(std::sync::Arc::new(DeniedRunner))
+        .build()
+        .unwrap();
+    assert!(matches!(
+        denied.append_verbose("filter", "INPUT", "-j DROP"),
+        Err(iptables::IPTError::CommandFailed(_))
+    ));
+}
+
+#[test]
+fn test_chain_references() {
+    let runner = FakeRunner::new(
+        "-P INPUT ACCEPT\n\
+         -N MYAPP\n\
+         -N OTHER\n\
+         -A INPUT -p tcp -j MYAPP\n\
+         -A INPUT -j ACCEPT\n\
+         -A INPUT -i eth0 -g MYAPP\n\
+         -A OTHER -m comment --comment \"-j MYAPP\" -j DROP\n\
+         -A OTHER -j MYAPP\n\
+         -A MYAPP -j MYAPPLICATION\n",
+    );
+    let ipt = runner.build();
+    assert_eq!(
+        ipt.chain_references("filter", "MYAPP").unwrap(),
+        vec![
+            ("INPUT".to_string(), 1),
+            ("INPUT".to_string(), 3),
+            ("OTHER".to_string(), 2)
+        ]
+    );
+    runner.take_args();
+
+    ipt.delete_chain_recursive("filter", "MYAPP").unwrap();
+    assert_eq!(
+        runner.take_args(),
+        [
+            "-t filter -S",
+            "-t filter -D OTHER 2",
+            "-t filter -D INPUT 3",
+            "-t filter -D INPUT 1",
+            "-t filter -F MYAPP",
+            "-t filter -X MYAPP",
+        ]
+        .iter()
+        .map(|command| command.split(' ').map(String::from).collect::<Vec<_>>())
+        .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_rule_spec() {
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+    let expected = ["-t", "filter", "-A", "INPUT"]
+        .iter()
+        .chain(&["-m", "comment", "--comment", "my app", "-j", "ACCEPT"])
+        .map(|arg| arg.to_string())
+        .collect::<Vec<_>>();
+
+    ipt.append("filter", "INPUT", "-m comment --comment 'my app' -j ACCEPT")
+        .unwrap();
+    ipt.append(
+        "filter",
+        "INPUT",
+        &["-m", "comment", "--comment", "my app", "-j", "ACCEPT"],
+    )
+    .unwrap();
+    let rule = iptables::Rule::new().comment("my app").jump("ACCEPT");
+    ipt.append("filter", "INPUT", &rule).unwrap();
+    assert_eq!(runner.take_args(), vec![expected; 3]);
+
+    let args = vec![
+        "-s".to_string(),
+        "10.0.0.1".to_string(),
+        "-j".to_string(),
+        "DROP".to_string(),
+    ];
+    ipt.delete("filter", "INPUT", &args[..]).unwrap();
+    ipt.insert("filter", "INPUT", args, 1).unwrap();
+    let commands = runner.take_args();
+    assert_eq!(
+        commands[0][2..],
+        ["-D", "INPUT", "-s", "10.0.0.1", "-j", "DROP"]
+    );
+    assert_eq!(
+        commands[1][2..],
+        ["-I", "INPUT", "1", "-s", "10.0.0.1", "-j", "DROP"]
+    );
+}
+
+#[test]
+fn test_owner_rules() {
+    assert_eq!(
+        iptables::Rule::new()
+            .uid_owner(1000)
+            .jump("ACCEPT")
+            .to_string(),
+        "-m owner --uid-owner 1000 -j ACCEPT"
+    );
+    assert_eq!(
+        iptables::Rule::new()
+            .suppl_groups()
+            .gid_owner_range(100, 199)
+            .socket_exists()
+            .uid_owner_range(1000, 1000)
+            .jump("DROP")
+            .to_string(),
+        "-m owner --socket-exists --uid-owner 1000 --gid-owner 100-199 --suppl-groups -j DROP"
+    );
+
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+    let rule = iptables::Rule::new()
+        .out_interface("eth0")
+        .gid_owner(1001)
+        .jump("REJECT");
+    ipt.append_rule("filter", "OUTPUT", &rule).unwrap();
+    assert_eq!(
+        runner.take_args(),
+        vec![
+            "-t filter -A OUTPUT -o eth0 -m owner --gid-owner 1001 -j REJECT"
+                .split(' ')
+                .map(String::from)
+                .collect::<Vec<_>>()
+        ]
+    );
+}
+
+#[test]
+fn test_port_rules() {
+    let rule = iptables::Rule::new()
+        .protocol("tcp")
+        .sport_range(1024..=65535)
+        .dports(&[80, 443, 8080])
+        .jump("ACCEPT");
+    assert_eq!(
+        rule.to_string(),
+        "-p tcp -m tcp --sport 1024:65535 -m multiport --dports 80,443,8080 -j ACCEPT"
+    );
+    assert!(rule.check(false).is_ok());
+    assert_eq!(
+        iptables::Rule::new()
+            .protocol("udp")
+            .dport_range(53..=53)
+            .sports(&[1, 2])
+            .to_string(),
+        "-p udp -m udp --dport 53 -m multiport --sports 1,2"
+    );
+
+    let invalid = |rule: iptables::Rule| match rule.check(false) {
+        Err(iptables::IPTError::Invalid(err)) => err.to_string(),
+        result => panic!("unexpected result {:?}", result),
+    };
+    assert_eq!(
+        invalid(iptables::Rule::new().dports(&[80])),
+        "ports require one of the protocols tcp, udp, udplite, sctp, dccp"
+    );
+    assert_eq!(
+        invalid(iptables::Rule::new().protocol("icmp").dport(22)),
+        "protocol icmp has no ports"
+    );
+    assert_eq!(
+        invalid(iptables::Rule::new().protocol("udplite").dport(22)),
+        "protocol udplite has no ports"
+    );
+    assert_eq!(
+        invalid(
+            iptables::Rule::new()
+                .protocol("tcp")
+                .dports(&(1..=16).collect::<Vec<_>>())
+        ),
+        "--dports accepts at most 15 ports, got 16"
+    );
+    #[allow(clippy::reversed_empty_ranges)]
+    let reversed = iptables::Rule::new()
+        .protocol("tcp")
+        .sport_range(2000..=1000);
+    assert_eq!(invalid(reversed), "port range 2000:1000 is reversed");
+
+    let ipt = FakeRunner::new("").build();
+    assert!(ipt
+        .append_rule("filter", "INPUT", &iptables::Rule::new().dport(22))
+        .is_err());
+}
+
+#[test]
+fn test_typed_addresses() {
+    use iptables::net::Cidr;
+    use iptables::validate::ValidationError;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    let rule = iptables::Rule::new()
+        .source_net("192.168.1.77/24".parse::<Cidr>().unwrap())
+        .destination_net(Ipv4Addr::new(10, 0, 0, 1))
+        .jump("ACCEPT");
+    assert_eq!(
+        rule.to_string(),
+        "-s 192.168.1.0/24 -d 10.0.0.1/32 -j ACCEPT"
+    );
+    assert!(rule.check(false).is_ok());
+    assert!(matches!(
+        rule.check(true),
+        Err(iptables::IPTError::Invalid(ValidationError::WrongFamily(_)))
+    ));
+
+    let v6 = Cidr::new(
+        IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+        32,
+    )
+    .unwrap();
+    assert_eq!(v6.to_string(), "2001:db8::/32");
+    assert!(v6.is_ipv6());
+    assert_eq!(Cidr::from(Ipv6Addr::LOCALHOST).prefix(), 128);
+    assert_eq!(
+        Cidr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 33),
+        Err(ValidationError::InvalidAddress(
+            "prefix /33 is too long for 127.0.0.1".to_string()
+        ))
+    );
+    assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+    assert!("example.com".parse::<Cidr>().is_err());
+
+    assert!(matches!(
+        iptables::validate::validate_rule("-s 10.0.0.0/40 -j DROP", false),
+        Err(ValidationError::InvalidAddress(_))
+    ));
+    assert!(iptables::validate::validate_rule("-s 10.0.0.0/255.0.0.0 -j DROP", false).is_ok());
+    assert!(iptables::validate::validate_rule("-d ::/0 -j DROP", true).is_ok());
+}
+
+#[test]
+fn test_syn_flood_protection() {
+    use iptables::rule::TcpFlag;
+
+    assert_eq!(
+        iptables::Rule::new()
+            .protocol("tcp")
+            .dport(22)
+            .syn()
+            .jump("ACCEPT")
+            .to_string(),
+        "-p tcp -m tcp --dport 22 --tcp-flags FIN,SYN,RST,ACK SYN -j ACCEPT"
+    );
+    let rule = iptables::Rule::new()
+        .protocol("tcp")
+        .tcp_flags(
+            &[TcpFlag::Syn, TcpFlag::Fin, TcpFlag::Syn],
+            &[TcpFlag::Syn, TcpFlag::Fin],
+        )
+        .jump("DROP");
+    assert_eq!(
+        rule.to_string(),
+        "-p tcp -m tcp --tcp-flags FIN,SYN FIN,SYN -j DROP"
+    );
+    assert!(rule.check(false).is_ok());
+    assert!(matches!(
+        iptables::Rule::new().protocol("udp").syn().check(false),
+        Err(iptables::IPTError::Invalid(_))
+    ));
+
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+    ipt.syn_flood_protection("SYN_FLOOD", iptables::limit::Rate::per_second(25), 50)
+        .unwrap();
+    ipt.remove_syn_flood_protection("SYN_FLOOD").unwrap();
+    assert_eq!(
+        runner.take_args(),
+        [
+            "-t filter -S SYN_FLOOD",
+            "-t filter -F SYN_FLOOD",
+            "-t filter -A SYN_FLOOD -m limit --limit 25/sec --limit-burst 50 -j RETURN",
+            "-t filter -A SYN_FLOOD -j DROP",
+            "-t filter -C INPUT -p tcp -m tcp --tcp-flags FIN,SYN,RST,ACK SYN -j SYN_FLOOD",
+            "-t filter -C INPUT -p tcp -m tcp --tcp-flags FIN,SYN,RST,ACK SYN -j SYN_FLOOD",
+            "-t filter -D INPUT -p tcp -m tcp --tcp-flags FIN,SYN,RST,ACK SYN -j SYN_FLOOD",
+            "-t filter -S SYN_FLOOD",
+            "-t filter -F SYN_FLOOD",
+            "-t filter -X SYN_FLOOD",
+        ]
+        .iter()
+        .map(|command| command.split(' ').map(String::from).collect::<Vec<_>>())
+        .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_icmp_types() {
+    use iptables::icmp::{IcmpType, Icmpv6Type};
+
+    assert_eq!(
+        iptables::Rule::new()
+            .icmp_type(IcmpType::TimeExceeded)
+            .jump("ACCEPT")
+            .to_string(),
+        "-p icmp -m icmp --icmp-type 11 -j ACCEPT"
+    );
+    assert_eq!(
+        iptables::Rule::new()
+            .icmp_type(Icmpv6Type::Other(200))
+            .jump("DROP")
+            .to_string(),
+        "-p ipv6-icmp -m icmp6 --icmpv6-type 200 -j DROP"
+    );
+    assert!(iptables::Rule::new()
+        .icmp_type(Icmpv6Type::PacketTooBig)
+        .check(false)
+        .is_err());
+
+    let rules = iptables::icmp::essential_icmpv6_rules();
+    assert_eq!(rules.len(), 10);
+    assert_eq!(
+        rules[9].to_string(),
+        "-p ipv6-icmp -m icmp6 --icmpv6-type 136 -m hl --hl-eq 255 -j ACCEPT"
+    );
+
+    let runner = FakeRunner::new("");
+    let ipt6 = runner.builder().ipv6(true).build().unwrap();
+    ipt6.allow_essential_icmpv6("INPUT").unwrap();
+    // FakeRunner reports every rule as existing
+    assert!(runner.take_args().iter().all(|command| command[2] == "-C"));
+    let ipt4 = runner.build();
+    assert!(matches!(
+        ipt4.allow_essential_icmpv6("INPUT"),
+        Err(iptables::IPTError::Invalid(_))
+    ));
+}
+
+#[test]
+fn test_log_rules() {
+    use iptables::log::LogLevel;
+
+    assert_eq!(
+        iptables::Rule::new()
+            .log("dropped: ", LogLevel::Warning)
+            .to_string(),
+        "-j LOG --log-prefix \"dropped: \""
+    );
+    assert_eq!(
+        iptables::Rule::new().log("", LogLevel::Debug).to_string(),
+        "-j LOG --log-level 7"
+    );
+    assert_eq!(
+        iptables::Rule::new().nflog(5, "app").to_string(),
+        "-j NFLOG --nflog-prefix app --nflog-group 5"
+    );
+    assert_eq!(iptables::Rule::new().nflog(0, "").to_string(), "-j NFLOG");
+
+    let long = "x".repeat(30);
+    assert!(matches!(
+        iptables::Rule::new()
+            .log(&long, LogLevel::Info)
+            .check(false),
+        Err(iptables::IPTError::Invalid(
+            iptables::validate::ValidationError::TooLong { max: 29, .. }
+        ))
+    ));
+    assert!(iptables::Rule::new().nflog(1, &long).check(false).is_ok());
+
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+    let selector = iptables::Rule::new().protocol("tcp").dport(23);
+    assert!(ipt.log_and_drop("INPUT", &selector, &long).is_err());
+    assert!(runner.take_args().is_empty());
+    ipt.remove_log_and_drop("INPUT", &selector, "telnet: ")
+        .unwrap();
+    assert_eq!(
+        runner.take_args()[1..]
+            .iter()
+            .step_by(2)
+            .map(|command| command.join(" "))
+            .collect::<Vec<_>>(),
+        [
+            "-t filter -D INPUT -p tcp -m tcp --dport 23 -j LOG --log-prefix telnet: ",
+            "-t filter -D INPUT -p tcp -m tcp --dport 23 -j DROP",
+        ]
+    );
+}
+
+#[test]
+fn test_conntrack_bypass() {
+    use iptables::conntrack::{CtEvent, CtTarget};
+
+    let selector = iptables::Rule::new().protocol("udp").dport(53);
+    assert_eq!(
+        selector.clone().notrack().to_string(),
+        "-p udp -m udp --dport 53 -j CT --notrack"
+    );
+    let target = CtTarget::new().zone(7).timeout("short").ctevents(&[
+        CtEvent::Destroy,
+        CtEvent::New,
+        CtEvent::Destroy,
+    ]);
+    assert_eq!(
+        iptables::Rule::new().ct(&target).to_string(),
+        "-j CT --timeout short --ctevents new,destroy --zone 7"
+    );
+    assert_eq!(
+        iptables::Rule::new().ct(&CtTarget::new()).to_string(),
+        "-j CT"
+    );
+
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+    ipt.enable_conntrack_for(&selector.clone().jump("ACCEPT"))
+        .unwrap();
+    assert_eq!(
+        runner.take_args()[1..]
+            .iter()
+            .step_by(2)
+            .map(|command| command.join(" "))
+            .collect::<Vec<_>>(),
+        [
+            "-t raw -D PREROUTING -p udp -m udp --dport 53 -j CT --notrack",
+            "-t raw -D OUTPUT -p udp -m udp --dport 53 -j CT --notrack",
+        ]
+    );
+}
+
+#[test]
+fn test_interfaces() {
+    use iptables::interface::validate_interface;
+    use iptables::validate::{validate_rule, ValidationError};
+
+    assert!(validate_interface("+").is_ok());
+    assert!(validate_interface("wg-vpn.100").is_ok());
+    for name in ["", "eth 0", "br/0", "e+th", "eth++", "sixteen-bytes-ok"] {
+        assert!(
+            matches!(
+                validate_interface(name),
+                Err(ValidationError::InvalidInterface(_))
+            ),
+            "{}",
+            name
+        );
+    }
+    assert!(iptables::Rule::new()
+        .in_interface("eth+")
+        .out_interface("wg0")
+        .check(false)
+        .is_ok());
+    assert!(iptables::Rule::new()
+        .out_interface("e+th")
+        .check(false)
+        .is_err());
+    assert!(validate_rule("-i eth+ -j ACCEPT", false).is_ok());
+    assert!(matches!(
+        validate_rule("-o a-very-long-interface -j ACCEPT", false),
+        Err(ValidationError::InvalidInterface(_))
+    ));
+
+    let runner = FakeRunner::new(
+        "-P FORWARD DROP\n\
+         -A FORWARD -i eth+ -o wg0 -j ACCEPT\n\
+         -A FORWARD -i eth1 -j ACCEPT\n\
+         -A FORWARD ! -o eth0 -j DROP\n\
+         -A FORWARD -m comment --comment \"-i eth0\" -j ACCEPT\n\
+         -A OUTPUT -o eth0 -j LOG\n",
+    );
+    let ipt = runner.build();
+    assert_eq!(
+        ipt.rules_for_interface("filter", "eth0").unwrap(),
+        [
+            "-A FORWARD -i eth+ -o wg0 -j ACCEPT",
+            "-A OUTPUT -o eth0 -j LOG",
+        ]
+    );
+}
+
+#[test]
+fn test_targets() {
+    use iptables::nat::{Masquerade, NatTarget};
+    use iptables::target::{RejectWith, Target};
+    use iptables::validate::{validate_rule, ValidationError};
+
+    let render = |target: Target| iptables::Rule::new().target(target).to_string();
+    assert_eq!(render(Target::Accept), "-j ACCEPT");
+    assert_eq!(render(Target::Drop), "-j DROP");
+    assert_eq!(render(Target::Return), "-j RETURN");
+    assert_eq!(render(Target::Queue { num: 3 }), "-j NFQUEUE --queue-num 3");
+    assert_eq!(render(Target::Jump("MYAPP".to_string())), "-j MYAPP");
+    assert_eq!(render(Target::Goto("MYAPP".to_string())), "-g MYAPP");
+    assert_eq!(
+        render(
+            NatTarget::from(Masquerade {
+                to_ports: None,
+                random: true,
+            })
+            .into()
+        ),
+        "-j MASQUERADE --random"
+    );
+    assert_eq!(
+        render(Target::Reject {
+            with: RejectWith::port_unreachable(true),
+        }),
+        "-j REJECT --reject-with icmp6-port-unreachable"
+    );
+
+    for (with, ipv4, ipv6) in [
+        (RejectWith::IcmpAdminProhibited, true, false),
+        (RejectWith::Icmp6AdmProhibited, false, true),
+        (RejectWith::TcpReset, true, true),
+    ] {
+        let rule = iptables::Rule::new()
+            .protocol("tcp")
+            .target(Target::Reject { with });
+        assert_eq!(rule.check(false).is_ok(), ipv4, "{}", with);
+        assert_eq!(rule.check(true).is_ok(), ipv6, "{}", with);
+    }
+    assert_eq!(
+        validate_rule("-j REJECT --reject-with icmp-host-prohibited", true),
+        Err(ValidationError::WrongFamily(
+            "reject-with icmp-host-prohibited is not available for IPv6".to_string()
+        ))
+    );
+    assert!(validate_rule("-j REJECT --reject-with icmp6-no-route", true).is_ok());
+    assert!(validate_rule("-j REJECT --reject-with icmp6-no-route", false).is_err());
+    assert!(validate_rule("-j REJECT", true).is_ok());
+}
+
+#[test]
+fn test_sanitizer() {
+    use iptables::sanitize::Sanitizer;
+    use iptables::validate::ValidationError;
+
+    let sanitizer = Sanitizer::new();
+    for rule in [
+        "-s 10.0.0.0/8 -p tcp --dport 22 -j ACCEPT",
+        "! -i eth0 -p udp -m udp ! --sport 53 -m comment --comment \"dns -j DROP\" -j DROP",
+        "-p tcp -m multiport --dports 80,443 -m conntrack --ctstate NEW -j LOG --log-prefix x",
+        "-p sctp --dport 9 -j REJECT --reject-with icmp-port-unreachable",
+    ] {
+        assert!(sanitizer.check_rule(rule).is_ok(), "{}", rule);
+    }
+    for rule in [
+        "-j ACCEPT -t nat",
+        "-j ACCEPT --modprobe=/tmp/x",
+        "-j ACCEPT --mod /tmp/x",
+        "-p tcp --dpo 22 -j ACCEPT",
+        "-m comment --comment x -j ACCEPT -F",
+        "-m string --string x -j DROP",
+        "-j MYAPP",
+        "-j DNAT --to-source 10.0.0.1",
+        "-m comment --comment \"a\tb\" -j DROP",
+        "-j DROP\n-F",
+    ] {
+        assert!(
+            matches!(
+                sanitizer.check_rule(rule),
+                Err(ValidationError::NotAllowed(_))
+            ),
+            "{}",
+            rule
+        );
+    }
+    // Values consumed by an option are checked as well
+    for args in [
+        &["-m", "comment", "--comment", "a\nCOMMIT", "-j", "DROP"][..],
+        &["-p", "tcp\nCOMMIT\n*filter\n-F INPUT", "-j", "ACCEPT"],
+        &["-m", "tcp\nCOMMIT", "-j", "ACCEPT"],
+        &["-j", "ACCEPT\nCOMMIT"],
+        &["-p", "-t", "nat", "-j", "ACCEPT"],
+        &["-m", "--modprobe=/tmp/x", "-j", "ACCEPT"],
+        &["-j", "-F"],
+    ] {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert!(
+            matches!(
+                sanitizer.check_args(&args),
+                Err(ValidationError::NotAllowed(_))
+            ),
+            "{:?}",
+            args
+        );
+    }
+
+    let custom = Sanitizer::empty()
+        .allow_chain("MYAPP")
+        .allow_match("string", &["--string", "--algo"]);
+    assert!(custom
+        .check_rule("-m string --algo bm --string x -j MYAPP")
+        .is_ok());
+    assert!(custom.check_rule("-j ACCEPT").is_err());
+
+    let runner = FakeRunner::new("");
+    let ipt = runner
+        .builder()
+        .sanitizer(Sanitizer::new())
+        .build()
+        .unwrap();
+    let err = ipt
+        .append("filter", "INPUT", "-j ACCEPT -t nat")
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "invalid rule: option \"-t\" is not allowed"
+    );
+    assert!(ipt.delete("filter", "INPUT", "-j ACCEPT -t nat").is_err());
+    assert!(ipt
+        .append_rule(
+            "filter",
+            "INPUT",
+            &iptables::Rule::new().matching("comment", &["--modprobe=/tmp/x"])
+        )
+        .is_err());
+    assert!(runner.take_args().is_empty());
+    ipt.append("filter", "INPUT", "-p tcp --dport 22 -j ACCEPT")
+        .unwrap();
+    assert_eq!(runner.take_args().len(), 1);
+
+    // Every method taking a rule is checked before anything is run
+    let bad = "-j ACCEPT -t nat";
+    let is_invalid =
+        |result: iptables::IPTResult<()>| matches!(result, Err(iptables::IPTError::Invalid(_)));
+    let is_rule_failed = |result: iptables::IPTResult<()>| {
+        matches!(
+            result,
+            Err(iptables::IPTError::RuleFailed { index: 1, error })
+                if matches!(*error, iptables::IPTError::Invalid(_))
+        )
+    };
+    assert!(is_invalid(
+        ipt.append_handle("filter", "INPUT", bad).map(|_| ())
+    ));
+    assert!(is_invalid(
+        ipt.insert_handle("filter", "INPUT", bad, 1).map(|_| ())
+    ));
+    assert!(is_invalid(
+        ipt.exists_normalized("filter", "INPUT", bad).map(|_| ())
+    ));
+    assert!(is_invalid(
+        ipt.exists_many("filter", "INPUT", &["-j ACCEPT", bad])
+            .map(|_| ())
+    ));
+    assert!(is_invalid(
+        ipt.position_of("filter", "INPUT", bad).map(|_| ())
+    ));
+    assert!(is_invalid(ipt.delete_all("filter", "INPUT", bad)));
+    assert!(is_invalid(
+        ipt.append_verbose("filter", "INPUT", bad).map(|_| ())
+    ));
+    assert!(is_invalid(
+        ipt.insert_verbose("filter", "INPUT", bad, 1).map(|_| ())
+    ));
+    assert!(is_invalid(
+        ipt.delete_verbose("filter", "INPUT", bad).map(|_| ())
+    ));
+    assert!(is_invalid(ipt.restore_table(
+        "filter",
+        &[("INPUT", vec!["[1:2] -j ACCEPT", bad])],
+        false
+    )));
+    assert!(is_rule_failed(ipt.append_many(
+        "filter",
+        "INPUT",
+        &["-j ACCEPT", bad]
+    )));
+    let mut batch = iptables::batch::Batch::new();
+    batch
+        .append("nat", "POSTROUTING", "-j MASQUERADE")
+        .append("filter", "INPUT", bad);
+    assert!(is_rule_failed(ipt.commit(&batch)));
+    let mut ruleset = iptables::ruleset::RuleSet::new();
+    ruleset.append("filter", "INPUT", bad);
+    assert!(is_invalid(ipt.apply(&ruleset)));
+    assert!(runner.take_args().is_empty());
+}
+
+#[test]
+fn test_nat_targets() {
+    use iptables::nat::{Dnat, Masquerade, NatRange, Redirect, Snat};
+    use std::net::IpAddr;
+
+    let first: IpAddr = "203.0.113.1".parse().unwrap();
+    let last: IpAddr = "203.0.113.8".parse().unwrap();
+    let snat = Snat {
+        to: NatRange {
+            addresses: Some(first..=last),
+            ports: Some(1024..=2048),
+        },
+    };
+    assert_eq!(
+        iptables::Rule::new()
+            .out_interface("eth0")
+            .protocol("tcp")
+            .nat(snat)
+            .to_string(),
+        "-o eth0 -p tcp -j SNAT --to-source 203.0.113.1-203.0.113.8:1024-2048"
+    );
+    assert_eq!(
+        iptables::Rule::new()
+            .nat(Snat {
+                to: NatRange::from(first)
+            })
+            .to_string(),
+        "-j SNAT --to-source 203.0.113.1"
+    );
+
+    let v6: IpAddr = "fd00::10".parse().unwrap();
+    assert_eq!(
+        iptables::Rule::new()
+            .nat(Dnat {
+                to: NatRange::from(v6)
+            })
+            .to_string(),
+        "-j DNAT --to-destination fd00::10"
+    );
+    assert_eq!(
+        iptables::Rule::new()
+            .protocol("tcp")
+            .nat(Dnat {
+                to: NatRange {
+                    addresses: None,
+                    ports: Some(8080..=8080),
+                }
+            })
+            .to_string(),
+        "-p tcp -j DNAT --to-destination :8080"
+    );
+
+    assert_eq!(
+        iptables::Rule::new()
+            .protocol("tcp")
+            .dport(80)
+            .nat(Redirect {
+                to_ports: Some(3128..=3128)
+            })
+            .to_string(),
+        "-p tcp -m tcp --dport 80 -j REDIRECT --to-ports 3128"
+    );
+    assert_eq!(
+        iptables::Rule::new().nat(Masquerade::default()).to_string(),
+        "-j MASQUERADE"
+    );
+}
+
+#[test]
+fn test_transparent_proxy() {
+    assert_eq!(
+        iptables::Rule::new()
+            .protocol("udp")
+            .socket(true)
+            .tproxy(53, Some("127.0.0.1".parse().unwrap()), 2, None)
+            .to_string(),
+        "-p udp -m socket --transparent -j TPROXY --on-port 53 --on-ip 127.0.0.1 \
+         --tproxy-mark 0x2/0xffffffff"
+    );
+    let rules = iptables::tproxy::transparent_proxy_rules(50080, 1)
+        .iter()
+        .map(|(chain, rule)| format!("-A {} {}", chain, rule))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rules,
+        [
+            "-A DIVERT -j MARK --set-xmark 0x1/0xffffffff",
+            "-A DIVERT -j ACCEPT",
+            "-A PREROUTING -p tcp -m socket -j DIVERT",
+            "-A PREROUTING -p tcp -j TPROXY --on-port 50080 --tproxy-mark 0x1/0x1",
+        ]
+    );
+
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+    ipt.setup_transparent_proxy(50080, 1).unwrap();
+    let commands = runner.take_args();
+    assert_eq!(commands.len(), 5);
+    assert!(commands[1..].iter().all(|command| command[2] == "-C"));
+
+    ipt.remove_transparent_proxy(50080, 1).unwrap();
+    let deleted = runner
+        .take_args()
+        .into_iter()
+        .filter(|command| command[2] == "-D" || command[2] == "-X")
+        .map(|command| command[2..4].join(" "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        deleted,
+        [
+            "-D PREROUTING",
+            "-D PREROUTING",
+            "-D DIVERT",
+            "-D DIVERT",
+            "-X DIVERT"
+        ]
+    );
+}
+
+#[test]
+fn test_host_firewall() {
+    use iptables::profiles::{HostFirewall, FORWARD_CHAIN, INPUT_CHAIN};
+
+    let firewall = HostFirewall::new()
+        .allow_tcp(22)
+        .allow_udp(53)
+        .allow(
+            iptables::Rule::new()
+                .source("10.0.0.0/8")
+                .protocol("tcp")
+                .dport(9100),
+        )
+        .allow_ping()
+        .default_drop();
+    let v4 = firewall.ruleset(false);
+    assert_eq!(v4.chains("filter"), vec![FORWARD_CHAIN, INPUT_CHAIN]);
+    assert_eq!(
+        v4.rules("filter", INPUT_CHAIN)[3..],
+        [
+            "-p icmp -m icmp --icmp-type 8 -j ACCEPT",
+            "-p tcp -m tcp --dport 22 -j ACCEPT",
+            "-p udp -m udp --dport 53 -j ACCEPT",
+            "-s 10.0.0.0/8 -p tcp -m tcp --dport 9100 -j ACCEPT",
+            "-j DROP",
+        ]
+    );
+    assert_eq!(
+        v4.rules("filter", FORWARD_CHAIN),
+        [
+            "-m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT",
+            "-j DROP"
+        ]
+    );
+    let v6 = firewall.ruleset(true);
+    assert_eq!(v6.rules("filter", INPUT_CHAIN).len(), 17);
+    assert!(!v6
+        .rules("filter", INPUT_CHAIN)
+        .iter()
+        .any(|rule| rule.contains("-p icmp ")));
+    assert_eq!(
+        HostFirewall::new()
+            .ruleset(false)
+            .rules("filter", INPUT_CHAIN)
+            .len(),
+        3
+    );
+
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+    firewall.apply(&ipt).unwrap();
+    let appended = runner
+        .take_args()
+        .into_iter()
+        .filter(|command| command[2] == "-A")
+        .count();
+    assert_eq!(appended, 10);
+
+    firewall.teardown(&ipt).unwrap();
+    let commands = runner
+        .take_args()
+        .into_iter()
+        .filter(|command| command[2] != "-C" && command[2] != "-S")
+        .map(|command| command.join(" "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commands,
+        [
+            "-t filter -D INPUT -j HOSTFW-INPUT",
+            "-t filter -F HOSTFW-INPUT",
+            "-t filter -X HOSTFW-INPUT",
+            "-t filter -D FORWARD -j HOSTFW-FORWARD",
+            "-t filter -F HOSTFW-FORWARD",
+            "-t filter -X HOSTFW-FORWARD",
+        ]
+    );
+}
+
+// Lists `listing` for every chain and reports every checked rule as missing
+struct MissingRuleRunner {
+    listing: &'static str,
+    commands: std::sync::Mutex<Vec<String>>,
+}
+
+impl iptables::runner::CommandRunner for MissingRuleRunner {
+    fn run(
+        &self,
+        _program: &str,
+        args: &[String],
+        input: Option<&str>,
+    ) -> iptables::IPTResult<std::process::Output> {
+        use std::os::unix::process::ExitStatusExt;
+
+        let (code, stdout, stderr) = match args.last().map(String::as_str) {
+            Some("--version") => (0, "iptables v1.8.7 (legacy)\n", ""),
+            _ if args.contains(&"-C".to_string()) => (
+                1,
+                "",
+                "iptables: Bad rule (does a matching rule exist in that chain?).\n",
+            ),
+            _ if args.contains(&"-S".to_string()) => (0, self.listing, ""),
+            _ => (0, "", ""),
+        };
+        if code == 0 && !args.contains(&"--version".to_string()) {
+            let args = args.iter().filter(|arg| *arg != "--wait");
+            self.commands
+                .lock()
+                .unwrap()
+                .push(args.cloned().collect::<Vec<_>>().join(" "));
+            // The input of iptables-restore follows its command
+            self.commands
+                .lock()
+                .unwrap()
+                .extend(input.map(String::from));
+        }
+        Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        })
+    }
+}
+
+#[test]
+fn test_docker_user() {
+    let runner = std::sync::Arc::new(MissingRuleRunner {
+        listing: "-N DOCKER-USER\n\
+                  -A DOCKER-USER -i eth0 -j DROP\n\
+                  -A DOCKER-USER -j RETURN\n",
+        commands: std::sync::Mutex::new(Vec::new()),
+    });
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .build()
+        .unwrap();
+    ipt.insert_docker_user(&["-s", "10.0.0.0/8", "-j", "ACCEPT"])
+        .unwrap();
+    assert_eq!(
+        *runner.commands.lock().unwrap(),
+        [
+            "-t filter -S DOCKER-USER",
+            "-t filter -I FORWARD 1 -j DOCKER-USER",
+            "-t filter -S DOCKER-USER",
+            "-t filter -S DOCKER-USER",
+            "-t filter -I DOCKER-USER 2 -s 10.0.0.0/8 -j ACCEPT",
+        ]
+    );
+
+    let runner = std::sync::Arc::new(MissingRuleRunner {
+        listing: "-N MYCHAIN\n-A MYCHAIN -j LOG\n",
+        commands: std::sync::Mutex::new(Vec::new()),
+    });
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .build()
+        .unwrap();
+    ipt.insert_before_return("filter", "MYCHAIN", "-j DROP")
+        .unwrap();
+    assert_eq!(
+        runner.commands.lock().unwrap().last().unwrap(),
+        "-t filter -A MYCHAIN -j DROP"
+    );
+}
+
+#[test]
+fn test_divert_to_queue() {
+    use iptables::queue::NfQueue;
+    use iptables::target::Target;
+
+    let queue = NfQueue {
+        queues: 2..=5,
+        bypass: true,
+        cpu_fanout: false,
+    };
+    assert_eq!(
+        iptables::Rule::new().nfqueue(&queue).to_string(),
+        "-j NFQUEUE --queue-balance 2:5 --queue-bypass"
+    );
+    assert_eq!(
+        iptables::Rule::new().nfqueue(&NfQueue::new(0)).to_string(),
+        iptables::Rule::new()
+            .target(Target::Queue { num: 0 })
+            .to_string()
+    );
+
+    let runner = std::sync::Arc::new(MissingRuleRunner {
+        listing: "",
+        commands: std::sync::Mutex::new(Vec::new()),
+    });
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .build()
+        .unwrap();
+    let selector = iptables::Rule::new().protocol("udp").jump("ACCEPT");
+    ipt.divert_to_queue(&selector, &queue).unwrap();
+    assert_eq!(
+        std::mem::take(&mut *runner.commands.lock().unwrap()),
+        [
+            "--noflush",
+            "*filter\n\
+             -I INPUT 1 -p udp -j NFQUEUE --queue-balance 2:5 --queue-bypass\n\
+             -I FORWARD 1 -p udp -j NFQUEUE --queue-balance 2:5 --queue-bypass\n\
+             -I OUTPUT 1 -p udp -j NFQUEUE --queue-balance 2:5 --queue-bypass\n\
+             COMMIT\n",
+        ]
+    );
+
+    // An empty range of queues is rejected before any rule is inserted
+    let empty = NfQueue {
+        queues: std::ops::RangeInclusive::new(5, 2),
+        ..NfQueue::new(0)
+    };
+    assert!(matches!(
+        iptables::Rule::new().nfqueue(&empty).check(false),
+        Err(iptables::IPTError::Invalid(
+            iptables::validate::ValidationError::InvalidRange(_)
+        ))
+    ));
+    assert!(ipt.divert_to_queue(&selector, &empty).is_err());
+    assert!(runner.commands.lock().unwrap().is_empty());
+
+    // The rules exist according to FakeRunner, so they are deleted
+    let runner = FakeRunner::new("");
+    let ipt = runner.build();
+    ipt.remove_queue_diversion(&selector, &NfQueue::new(1))
+        .unwrap();
+    assert_eq!(
+        runner.take_args()[1..]
+            .iter()
+            .step_by(2)
+            .map(|command| command.join(" "))
+            .collect::<Vec<_>>(),
+        [
+            "-t filter -D INPUT -p udp -j NFQUEUE --queue-num 1",
+            "-t filter -D FORWARD -p udp -j NFQUEUE --queue-num 1",
+            "-t filter -D OUTPUT -p udp -j NFQUEUE --queue-num 1",
+        ]
+    );
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_netns_sandbox() {
+    use iptables::testing::NetnsSandbox;
+
+    let sandbox = NetnsSandbox::new().unwrap();
+    let path = sandbox.path().to_path_buf();
+    assert!(path.exists());
+    let ipt = sandbox.iptables(false).unwrap();
+    ipt.new_chain("filter", "SANDBOX").unwrap();
+    assert!(ipt.chain_exists("filter", "SANDBOX").unwrap());
+    // The chain only exists inside the namespace
+    assert!(!iptables::new(false)
+        .unwrap()
+        .chain_exists("filter", "SANDBOX")
+        .unwrap());
+    drop(sandbox);
+    assert!(!path.exists());
+
+    let policy = NetnsSandbox::run(true, |ipt| ipt.get_policy("filter", "INPUT").unwrap()).unwrap();
+    assert_eq!(policy, "ACCEPT");
+}
+
+#[test]
+fn test_restore_writer() {
+    use iptables::writer::WriterOptions;
+    use iptables::{IPTError, Rule};
+
+    let runner = std::sync::Arc::new(RestoreRunner::default());
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .owner_tag("myapp")
+        .build()
+        .unwrap();
+
+    // Persistent mode falls back to one restore per commit with a custom runner
+    let options = WriterOptions {
+        max_pending: 3,
+        persistent: true,
+    };
+    let mut writer = ipt.restore_writer(options);
+    writer
+        .append("filter", "INPUT", "-p tcp --dport 22 -j ACCEPT")
+        .unwrap();
+    writer
+        .insert(
+            "nat",
+            "PREROUTING",
+            "-p tcp --dport 80 -j REDIRECT --to-ports 8080",
+            1,
+        )
+        .unwrap();
+    assert_eq!(writer.pending(), 2);
+    assert!(runner.inputs.lock().unwrap().is_empty());
+    writer
+        .delete("filter", "INPUT", "-s 10.0.0.1 -j 'DROP'")
+        .unwrap();
+    assert_eq!(writer.pending(), 0);
+    writer
+        .append(
+            "filter",
+            "INPUT",
+            Rule::new().protocol("udp").dport(53).jump("ACCEPT"),
+        )
+        .unwrap();
+    writer.finish().unwrap();
+
+    let inputs = std::mem::take(&mut *runner.inputs.lock().unwrap());
+    assert_eq!(
+        inputs,
+        vec![
+            (
+                "iptables-restore --noflush --wait".to_string(),
+                "*filter\n-A INPUT -p tcp --dport 22 -m comment --comment myapp -j ACCEPT\n\
+                 -D INPUT -s 10.0.0.1 -m comment --comment myapp -j DROP\nCOMMIT\n\
+                 *nat\n-I PREROUTING 1 -p tcp --dport 80 -m comment --comment myapp \
+                 -j REDIRECT --to-ports 8080\nCOMMIT\n"
+                    .to_string()
+            ),
+            (
+                "iptables-restore --noflush --wait".to_string(),
+                "*filter\n-A INPUT -p udp -m udp --dport 53 -m comment --comment myapp -j ACCEPT\n\
+                 COMMIT\n"
+                    .to_string()
+            ),
+        ]
+    );
+
+    let mut writer = ipt.restore_writer(WriterOptions::default());
+    writer.append("filter", "INPUT", "-j BAD").unwrap();
+    assert!(matches!(writer.commit(), Err(IPTError::CommandFailed(_))));
+    assert_eq!(writer.pending(), 0);
+    writer.finish().unwrap();
+}
+
+#[test]
+fn test_restore_writer_persistent() {
+    use iptables::writer::WriterOptions;
+    use iptables::IPTError;
+    use std::os::unix::fs::PermissionsExt;
+
+    // iptables-restore fills its standard error before reading the input, which blocks the
+    // writer unless the pipe is drained
+    let path = std::env::temp_dir().join(format!("iptables-chatty-{}", std::process::id()));
+    let script = "#!/bin/sh\ncase \"$*\" in\n*--version*) echo 'iptables v1.8.7 (legacy)';;\n\
+                  *) head -c 200000 /dev/zero | tr '\\0' x >&2; cat >/dev/null; exit 1;;\nesac\n";
+    let restore = format!("{}-restore", path.display());
+    for program in &[path.to_str().unwrap(), &restore] {
+        std::fs::write(program, script).unwrap();
+        std::fs::set_permissions(program, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let ipt = iptables::IPTables::builder()
+        .path(path.to_str().unwrap())
+        .build()
+        .unwrap();
+
+    let options = WriterOptions {
+        max_pending: 0,
+        persistent: true,
+    };
+    let mut writer = ipt.restore_writer(options);
+    for port in 10000..15000 {
+        writer
+            .append(
+                "filter",
+                "INPUT",
+                format!("-p tcp --dport {} -j ACCEPT", port),
+            )
+            .unwrap();
+    }
+    writer.commit().unwrap();
+    match writer.finish() {
+        Err(IPTError::CommandFailed(err)) => assert!(err.msg.starts_with("xxx")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // Line breaks are rejected without a sanitizer, before anything is queued
+    let mut writer = ipt.restore_writer(WriterOptions::default());
+    for rule in [
+        "-j ACCEPT\nCOMMIT\n*nat",
+        "-m comment --comment \"a\rb\" -j DROP",
+    ] {
+        assert!(matches!(
+            writer.append("filter", "INPUT", rule),
+            Err(IPTError::Parse(_))
+        ));
+        assert!(writer.delete("filter", "INPUT", rule).is_err());
+    }
+    assert!(writer
+        .insert(
+            "filter",
+            "INPUT",
+            iptables::Rule::new().comment("a\nb").jump("DROP"),
+            1
+        )
+        .is_err());
+    assert_eq!(writer.pending(), 0);
+    std::fs::remove_file(&restore).unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_dedupe_chain() {
+    let runner = std::sync::Arc::new(RestoreRunner {
+        listing: Some(
+            "-P INPUT ACCEPT\n-A INPUT -p tcp -m tcp --dport 22 -j ACCEPT\n-A INPUT -j DROP\n\
+             -A INPUT -p tcp -m tcp --dport 22 -j ACCEPT\n-A INPUT -j DROP\n\
+             -A INPUT -p tcp -m tcp --dport 22 -j ACCEPT\n",
+        ),
+        ..RestoreRunner::default()
+    });
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .build()
+        .unwrap();
+
+    assert_eq!(ipt.dedupe_chain("filter", "INPUT").unwrap(), 3);
+    assert_eq!(
+        *runner.inputs.lock().unwrap(),
+        [(
+            "iptables-restore --noflush --wait".to_string(),
+            "*filter\n-D INPUT 5\n-D INPUT 4\n-D INPUT 3\nCOMMIT\n".to_string()
+        )]
+    );
+
+    // Nothing is restored without duplicates
+    let runner = std::sync::Arc::new(RestoreRunner::default());
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .build()
+        .unwrap();
+    assert_eq!(ipt.dedupe_chain("filter", "INPUT").unwrap(), 0);
+    assert!(runner.inputs.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_set_chain_rules() {
+    let runner = std::sync::Arc::new(RestoreRunner {
+        listing: Some(
+            "-N MYAPP\n-A MYAPP -p tcp -m tcp --dport 22 -j ACCEPT\n-A MYAPP -j DROP\n\
+             -A MYAPP -p tcp -m tcp --dport 23 -j ACCEPT\n",
+        ),
+        ..RestoreRunner::default()
+    });
+    let ipt = iptables::IPTables::builder()
+        .runner(runner.clone())
+        .build()
+        .unwrap();
+
+    let rules = [
+        "-p tcp --dport 22 -j ACCEPT",
+        "-p tcp --dport 443 -j ACCEPT",
+        "-j DROP",
+        "-j LOG",
+    ];
+    ipt.set_chain_rules("filter", "MYAPP", &rules).unwrap();
+    assert_eq!(
+        std::mem::take(&mut *runner.inputs.lock().unwrap()),
+        [(
+            "iptables-restore --noflush --wait".to_string(),
+            "*filter\n-D MYAPP 3\n-I MYAPP 2 -p tcp --dport 443 -j ACCEPT\n-A MYAPP -j LOG\nCOMMIT\n"
+                .to_string()
+        )]
+    );
+
+    // Nothing is restored if the chain holds the rules already
+    let rules = [
+        "-p tcp --dport 22 -j ACCEPT",
+        "-j DROP",
+        "-p tcp -m tcp --dport 23 -j ACCEPT",
+    ];
+    ipt.set_chain_rules("filter", "MYAPP", &rules).unwrap();
+    assert!(runner.inputs.lock().unwrap().is_empty());
+
+    // Reordering deletes the rules which are out of place and inserts them again
+    let rules = ["-j DROP", "-p tcp --dport 22 -j ACCEPT"];
+    ipt.set_chain_rules("filter", "MYAPP", &rules).unwrap();
+    assert_eq!(
+        runner.inputs.lock().unwrap()[0].1,
+        "*filter\n-D MYAPP 3\n-D MYAPP 1\n-A MYAPP -p tcp --dport 22 -j ACCEPT\nCOMMIT\n"
+    );
+
+    // A line break in the chain or in a rule would start another command of iptables-restore
+    runner.inputs.lock().unwrap().clear();
+    let is_line_break = |result: iptables::IPTResult<()>| matches!(result, Err(iptables::IPTError::Parse(message)) if message.contains("line break"));
+    assert!(is_line_break(ipt.set_chain_rules(
+        "filter",
+        "MYAPP\n-F INPUT",
+        &["-j DROP"]
+    )));
+    assert!(is_line_break(ipt.set_chain_rules(
+        "filter",
+        "MYAPP",
+        &[vec!["-j".to_string(), "DROP\n-F INPUT".to_string()]]
+    )));
+    assert!(runner.inputs.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_lazy_detection() {
+    let runner = FakeRunner::new("");
+    let ipt = runner.builder().lazy(true).build().unwrap();
+    assert!(runner.commands.lock().unwrap().is_empty());
+
+    // The first command detects the version, the clones share the result
+    ipt.clone().append("filter", "INPUT", "-j ACCEPT").unwrap();
+    ipt.delete("filter", "INPUT", "-j ACCEPT").unwrap();
+    let commands = std::mem::take(&mut *runner.commands.lock().unwrap());
+    assert_eq!(commands.len(), 3);
+    assert_eq!(commands[0], ["iptables", "--version"]);
+    assert_eq!(commands[2].last().unwrap(), "--wait");
+    assert_eq!(ipt.detect().unwrap(), iptables::Version::new(1, 8, 7));
+    assert!(ipt.supports_restore_wait());
+    assert!(runner.commands.lock().unwrap().is_empty());
+
+    // A missing binary only fails once it is used
+    let ipt = iptables::IPTables::builder()
+        .path("/nonexistent/iptables")
+        .lazy(true)
+        .build()
+        .unwrap();
+    assert!(matches!(ipt.detect(), Err(iptables::IPTError::Io(_))));
+    assert!(ipt.append("filter", "INPUT", "-j ACCEPT").is_err());
+}
+
+#[test]
+fn test_binary_search() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("iptables-search-{}", std::process::id()));
+    let empty = dir.join("empty");
+    std::fs::create_dir_all(&empty).unwrap();
+    let legacy = dir.join("iptables-legacy");
+    std::fs::write(&legacy, "#!/bin/sh\necho 'iptables v1.8.7 (legacy)'\n").unwrap();
+    std::fs::set_permissions(&legacy, std::fs::Permissions::from_mode(0o755)).unwrap();
+    // Files which are not executable are skipped
+    std::fs::write(dir.join("iptables"), "").unwrap();
+
+    let ipt = iptables::IPTables::builder()
+        .search_paths(&[&empty, &dir])
+        .build()
+        .unwrap();
+    assert_eq!(ipt.cmd, legacy.display().to_string());
+    assert_eq!(ipt.backend, iptables::Backend::Legacy);
+
+    let err = iptables::IPTables::builder()
+        .ipv6(true)
+        .backend(iptables::Backend::Nft)
+        .search_paths(&[&empty, &dir])
+        .build()
+        .err()
+        .unwrap();
+    let searched = vec![
+        empty.join("ip6tables-nft").display().to_string(),
+        dir.join("ip6tables-nft").display().to_string(),
+    ];
+    assert_eq!(
+        err.to_string(),
+        format!("iptables binary not found, tried: {}", searched.join(", "))
+    );
+    assert!(
+        matches!(err, iptables::IPTError::BinaryNotFound { searched: tried } if tried == searched)
+    );
+
+    // Lazy instances and instances without search paths use the name as is
+    let ipt = iptables::IPTables::builder()
+        .search_paths(&[&empty])
+        .lazy(true)
+        .build()
+        .unwrap();
+    assert_eq!(ipt.cmd, "iptables");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Creates an iptables and an iptables-restore which only answer --version and hang on every
+// other command
+fn hanging_iptables(name: &str) -> std::path::PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let script = "#!/bin/sh\ncase \"$*\" in\n*--version*) echo 'iptables v1.8.7 (legacy)';;\n*) exec sleep 10;;\nesac\n";
+    let restore = format!("{}-restore", path.display());
+    for program in &[path.to_str().unwrap(), &restore] {
+        std::fs::write(program, script).unwrap();
+        std::fs::set_permissions(program, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    path
+}
+
+#[test]
+fn test_command_timeout() {
+    use std::time::Duration;
+
+    let path = hanging_iptables("iptables-timeout");
+    let ipt = iptables::IPTables::builder()
+        .path(path.to_str().unwrap())
+        .command_timeout(Duration::from_millis(200))
+        .build()
+        .unwrap();
+    assert_eq!(ipt.command_timeout, Some(Duration::from_millis(200)));
+
+    let started = std::time::Instant::now();
+    let err = ipt.flush_chain("filter", "INPUT").unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    match &err {
+        iptables::IPTError::Timeout { command, timeout } => {
+            assert!(command.starts_with(path.to_str().unwrap()));
+            assert!(command.contains("-F INPUT"));
+            assert_eq!(*timeout, Duration::from_millis(200));
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+    assert!(err
+        .to_string()
+        .ends_with("did not finish within 200ms and was killed"));
+
+    // Input is written without blocking on a child which never reads it
+    let started = std::time::Instant::now();
+    let err = ipt
+        .restore(&"# comment\n".repeat(100_000), false)
+        .unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(matches!(err, iptables::IPTError::Timeout { .. }));
+    std::fs::remove_file(format!("{}-restore", path.display())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_async_command_timeout() {
+    use std::time::Duration;
+
+    let path = hanging_iptables("iptables-async-timeout");
+    let ipt = iptables::IPTables::builder()
+        .path(path.to_str().unwrap())
+        .command_timeout(Duration::from_millis(200))
+        .build()
+        .unwrap();
+    let ipt = iptables::AsyncIPTables::from(ipt);
+
+    let started = std::time::Instant::now();
+    let err = ipt.flush_chain("filter", "INPUT").await.unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(matches!(err, iptables::IPTError::Timeout { .. }));
+    std::fs::remove_file(format!("{}-restore", path.display())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+}